@@ -1,95 +1,107 @@
-use criterion::{criterion_group, criterion_main, Bencher, Criterion, ParameterizedBenchmark};
-use lacbd::SimpleFinder;
+use criterion::{
+    criterion_group, criterion_main, Bencher, Benchmark, Criterion, ParameterizedBenchmark,
+};
+use lacbd::{SimpleFinder, SimpleFinderBuilder, WordBoundarySplitter};
 use regex::RegexSet;
 use std::ops::Range;
 
-// fn random_words(len: usize) -> String {
-//     use rand::prelude::*;
-
-//     let mut rng = SmallRng::from_seed([0; 16]);
-
-//     let mut random_word = || {
-//         (0..)
-//             .map(|_| rng.gen::<char>())
-//             .filter(|c| c.is_alphanumeric())
-//             .take(6)
-//             .collect::<String>()
-//     };
-
-//     (0..len)
-//         .map(|_| random_word())
-//         .collect::<Vec<_>>()
-//         .join(" ")
-// }
-
-// fn string_word_ranges(words: &str) -> Vec<Range<usize>> {
-//     fn has_alphanumeric(s: &str) -> bool {
-//         s.chars().any(char::is_alphanumeric)
-//     }
-
-//     words
-//         // .split_word_bound_indices() TODO: reimplement
-//         .filter(|(_, s)| has_alphanumeric(s))
-//         .map(|(idx, s)| idx..(idx + s.len()))
-//         .collect()
-// }
-
-// fn select_word_ranges(words: &str, num: usize) -> Vec<&str> {
-//     use rand::prelude::*;
-
-//     let split_words = string_word_ranges(words);
-
-//     let mut rng = SmallRng::from_seed([0; 16]);
-
-//     fn random_sentence<'a>(
-//         rng: &mut SmallRng,
-//         split_words: &[Range<usize>],
-//         words: &'a str,
-//     ) -> &'a str {
-//         let len = rng.gen_range(3, 7);
-//         let first_idx = rng.gen_range(0, split_words.len());
-//         let last_idx = if first_idx + len >= split_words.len() {
-//             split_words.len() - 1
-//         } else {
-//             first_idx + len
-//         };
-
-//         let first_byte = split_words[first_idx].start;
-//         let last_byte = split_words[last_idx].end;
-//         &words[first_byte..last_byte]
-//     };
-
-//     (0..num)
-//         .map(|_| random_sentence(&mut rng, &split_words, words))
-//         .collect()
-// }
-
-// fn do_simple_finder(word_len: usize, set_len: usize, b: &mut Bencher) {
-//     let words = random_words(word_len);
-//     let sentences = select_word_ranges(&words, set_len);
-//     let searcher = SimpleFinder::new(sentences.iter().enumerate().map(|(i, s)| (*s, i)));
-//     b.iter(|| searcher.find_all_unique(&words));
-// }
-
-// fn do_regex(word_len: usize, set_len: usize, b: &mut Bencher) {
-//     let words = random_words(word_len);
-//     let sentences = select_word_ranges(&words, set_len);
-
-//     let r = RegexSet::new(
-//         sentences
-//             .iter()
-//             .map(|s| format!(r"(?i)\b(?:{})\b", regex::escape(s))),
-//     )
-//     .unwrap();
-
-//     b.iter(|| r.matches(&words));
-// }
+/// A few thousand common words, so that dictionaries and haystacks can be
+/// made up without a system word list.
+const WORDS: &str = include_str!("words.txt");
 
-fn do_cracklib_finder(b: &mut Bencher) {
+/// A haystack of `len` words picked at random from `WORDS`.
+fn random_words(len: usize) -> String {
+    use rand::prelude::*;
+
+    let words: Vec<&str> = WORDS.lines().collect();
+    let mut rng = SmallRng::from_seed([0; 16]);
+
+    (0..len)
+        .map(|_| words[rng.gen_range(0, words.len())])
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn string_word_ranges(words: &str) -> Vec<Range<usize>> {
+    // the words are ASCII, so their char offsets are byte offsets
+    words
+        .unicode_words_and_syms_indices()
+        .map(|(idx, s)| idx as usize..(idx as usize + s.len()))
+        .collect()
+}
+
+/// Picks `num` runs of three to six consecutive words of `words`.
+fn select_word_ranges(words: &str, num: usize) -> Vec<&str> {
+    use rand::prelude::*;
+
+    let split_words = string_word_ranges(words);
+
+    let mut rng = SmallRng::from_seed([0; 16]);
+
+    fn random_sentence<'a>(
+        rng: &mut SmallRng,
+        split_words: &[Range<usize>],
+        words: &'a str,
+    ) -> &'a str {
+        let len = rng.gen_range(3, 7);
+        let first_idx = rng.gen_range(0, split_words.len());
+        let last_idx = if first_idx + len >= split_words.len() {
+            split_words.len() - 1
+        } else {
+            first_idx + len
+        };
+
+        let first_byte = split_words[first_idx].start;
+        let last_byte = split_words[last_idx].end;
+        &words[first_byte..last_byte]
+    }
+
+    (0..num)
+        .map(|_| random_sentence(&mut rng, &split_words, words))
+        .collect()
+}
+
+fn do_simple_finder(word_len: usize, set_len: usize, b: &mut Bencher) {
+    let words = random_words(word_len);
+    let sentences = select_word_ranges(&words, set_len);
+    let searcher =
+        SimpleFinder::new_case_insensitive(sentences.iter().enumerate().map(|(i, s)| (*s, i)));
+    b.iter(|| searcher.find_all_unique(&words));
+}
+
+fn do_regex(word_len: usize, set_len: usize, b: &mut Bencher) {
+    let words = random_words(word_len);
+    let sentences = select_word_ranges(&words, set_len);
+
+    let r = RegexSet::new(
+        sentences
+            .iter()
+            .map(|s| format!(r"(?i)\b(?:{})\b", regex::escape(s))),
+    )
+    .unwrap();
+
+    b.iter(|| r.matches(&words));
+}
+
+/// The path of the cracklib word list, which the `cracklib` benches are
+/// skipped without. This can be set with `CRACKLIB_WORDS`.
+fn cracklib_path() -> Option<String> {
+    let path = std::env::var("CRACKLIB_WORDS")
+        .unwrap_or_else(|_| String::from("/usr/share/dict/cracklib-small"));
+    if std::path::Path::new(&path).exists() {
+        Some(path)
+    } else {
+        eprintln!("{} doesn't exist, skipping the cracklib benches", path);
+        None
+    }
+}
+
+fn cracklib_words() -> Vec<String> {
     use std::fs::File;
     use std::io::{prelude::*, BufReader};
 
-    let f = File::open("/usr/share/dict/cracklib-small").expect("cracklib-small exists");
+    let path = cracklib_path().expect("the cracklib word list exists");
+    let f = File::open(path).expect("the cracklib word list can be read");
     let reader = BufReader::new(f);
 
     let mut lines = Vec::new();
@@ -98,42 +110,313 @@ fn do_cracklib_finder(b: &mut Bencher) {
         lines.push(line.unwrap());
     }
 
-    let finder = SimpleFinder::new(lines.iter().map(|s| (s.as_ref(), ())));
+    lines
+}
+
+fn cracklib_finder() -> SimpleFinder<()> {
+    SimpleFinder::new_owned(cracklib_words().into_iter().map(|s| (s, ())).collect())
+}
+
+fn do_cracklib_build(b: &mut Bencher) {
+    let lines = cracklib_words();
+
+    b.iter(|| SimpleFinder::new(lines.iter().map(|s| (s.as_ref(), ()))));
+}
+
+fn do_cracklib_finder(b: &mut Bencher) {
+    let finder = cracklib_finder();
 
     b.iter(|| finder.find_all_unique("cafécafé café café"));
 }
 
-// fn bench_set_length(c: &mut Criterion) {
-//     c.bench(
-//         "set_length",
-//         ParameterizedBenchmark::new(
-//             "SimpleFinder",
-//             |b, &len| do_simple_finder(100, len, b),
-//             (10..10000).step_by(1000),
-//         )
-//         .with_function("Regex", |b, &len| do_regex(1000, len, b)),
-//     );
-// }
-
-// fn bench_haystack_length(c: &mut Criterion) {
-//     c.bench(
-//         "haystack_length",
-//         ParameterizedBenchmark::new(
-//             "SimpleFinder",
-//             |b, &len| do_simple_finder(len, 10, b),
-//             (10..10000).step_by(1000),
-//         )
-//         .with_function("Regex", |b, &len| do_regex(len, 100, b)),
-//     );
-// }
+fn do_cracklib_finder_ascii(b: &mut Bencher) {
+    let finder = cracklib_finder();
+
+    b.iter(|| finder.find_all_unique("cafecafe cafe cafe"));
+}
+
+fn bench_set_length(c: &mut Criterion) {
+    c.bench(
+        "set_length",
+        ParameterizedBenchmark::new(
+            "SimpleFinder",
+            |b, &len| do_simple_finder(1000, len, b),
+            vec![10, 100, 1000],
+        )
+        .with_function("Regex", |b, &len| do_regex(1000, len, b))
+        // the larger regex sets take a while to search
+        .sample_size(10),
+    );
+}
+
+fn bench_haystack_length(c: &mut Criterion) {
+    c.bench(
+        "haystack_length",
+        ParameterizedBenchmark::new(
+            "SimpleFinder",
+            |b, &len| do_simple_finder(len, 100, b),
+            vec![100, 1000, 10_000],
+        )
+        .with_function("Regex", |b, &len| do_regex(len, 100, b))
+        .sample_size(10),
+    );
+}
+
+/// Every word of `WORDS` searched for in a haystack of them, standing in for
+/// the cracklib benches.
+fn bench_words(c: &mut Criterion) {
+    let words: Vec<&str> = WORDS.lines().collect();
+    let finder = SimpleFinder::new(words.iter().map(|&w| (w, ())));
+    let haystack = random_words(10_000);
+
+    c.bench_function("words_build", move |b| {
+        b.iter(|| SimpleFinder::new(words.iter().map(|&w| (w, ()))))
+    });
+    c.bench_function("words_find_all", move |b| {
+        b.iter(|| finder.find_all(&haystack).count())
+    });
+}
 
 fn bench_cracklib(c: &mut Criterion) {
+    if cracklib_path().is_none() {
+        return;
+    }
     c.bench_function(
         "cracklib_bench",
         do_cracklib_finder
     );
+    c.bench_function("cracklib_bench_ascii", do_cracklib_finder_ascii);
+    c.bench_function("cracklib_build", do_cracklib_build);
+}
+
+/// The cracklib words searched for in a longer haystack, by the NFA or the
+/// DFA.
+fn do_cracklib_automaton(dfa: bool, b: &mut Bencher) {
+    let lines = cracklib_words();
+    let finder = SimpleFinderBuilder::new()
+        .dfa(dfa)
+        .build(lines.iter().map(|s| (s.as_ref(), ())));
+    let haystack = "the quick brown fox jumps over the lazy dog café ".repeat(100);
+
+    b.iter(|| finder.find_all(&haystack).count());
 }
 
-// criterion_group!(benches, bench_set_length, bench_haystack_length);
-criterion_group!(benches, bench_cracklib);
+fn bench_dfa(c: &mut Criterion) {
+    if cracklib_path().is_none() {
+        return;
+    }
+    c.bench(
+        "cracklib_automaton",
+        Benchmark::new("nfa", |b| do_cracklib_automaton(false, b))
+            .with_function("dfa", |b| do_cracklib_automaton(true, b)),
+    );
+}
+
+/// The cracklib words searched for case-insensitively in a longer haystack
+/// of mixed case.
+fn do_cracklib_case_insensitive(b: &mut Bencher) {
+    let lines = cracklib_words();
+    let finder = SimpleFinderBuilder::new()
+        .case_insensitive(true)
+        .build(lines.iter().map(|s| (s.as_ref(), ())));
+    let haystack = "The Quick brown FOX jumps over the Lazy dog Café ".repeat(100);
+
+    b.iter(|| finder.find_all(&haystack).count());
+}
+
+fn bench_case_insensitive(c: &mut Criterion) {
+    if cracklib_path().is_none() {
+        return;
+    }
+    c.bench_function("cracklib_case_insensitive", do_cracklib_case_insensitive);
+}
+
+/// The cracklib words searched for in a longer haystack, with states up to
+/// `dense_depth` tokens deep hashing their transitions.
+fn do_cracklib_dense_depth(dense_depth: usize, b: &mut Bencher) {
+    let lines = cracklib_words();
+    let finder = SimpleFinderBuilder::new()
+        .dense_depth(dense_depth)
+        .build(lines.iter().map(|s| (s.as_ref(), ())));
+    let haystack = "the quick brown fox jumps over the lazy dog café ".repeat(100);
+
+    b.iter(|| finder.find_all(&haystack).count());
+}
+
+fn bench_dense_depth(c: &mut Criterion) {
+    if cracklib_path().is_none() {
+        return;
+    }
+    c.bench(
+        "cracklib_dense_depth",
+        ParameterizedBenchmark::new(
+            "find_all",
+            |b, &depth| do_cracklib_dense_depth(depth, b),
+            vec![0, 1, 2, 4, 8],
+        ),
+    );
+}
+
+/// Random phrases searched for in a haystack of some of them, with or without
+/// checking every transition.
+fn do_checked_search(checked: bool, b: &mut Bencher) {
+    let patterns = phrases(10_000);
+    let finder = SimpleFinder::new(patterns.iter().map(|p| (p.as_str(), ())));
+    let haystack = patterns[..1_000].join(" ");
+
+    if checked {
+        b.iter(|| finder.find_checked(&haystack).count());
+    } else {
+        b.iter(|| finder.find_all(&haystack).count());
+    }
+}
+
+fn bench_checked_search(c: &mut Criterion) {
+    c.bench(
+        "checked_search",
+        Benchmark::new("unchecked", |b| do_checked_search(false, b))
+            .with_function("checked", |b| do_checked_search(true, b)),
+    );
+}
+
+/// Phrases that all continue from the same two words, so that the state after
+/// them has a transition for every third word and is searched for each one.
+fn do_sparse_transitions(b: &mut Bencher) {
+    let words: Vec<String> = (0..26 * 26)
+        .map(|i| {
+            let letter = |n: usize| (b'a' + n as u8) as char;
+            format!("{}{}", letter(i / 26), letter(i % 26))
+        })
+        .collect();
+    let patterns: Vec<String> = words
+        .iter()
+        .step_by(2)
+        .map(|w| format!("of the {}", w))
+        .collect();
+    let finder = SimpleFinder::new(patterns.iter().map(|p| (p.as_str(), ())));
+    let haystack: String = words.iter().map(|w| format!("of the {} ", w)).collect();
+
+    b.iter(|| finder.find_all(&haystack).count());
+}
+
+fn bench_sparse_transitions(c: &mut Criterion) {
+    c.bench_function("sparse_transitions", do_sparse_transitions);
+}
+
+/// A long haystack with a match right at the start.
+fn early_match_haystack() -> String {
+    let mut haystack = String::from("hello world ");
+    for _ in 0..10_000 {
+        haystack.push_str("lorem ipsum dolor sit amet ");
+    }
+    haystack
+}
+
+fn bench_is_match(c: &mut Criterion) {
+    let patterns = || vec![("hello world", ()), ("sit amet consectetur", ())];
+
+    let finder = SimpleFinder::new(patterns());
+    let haystack = early_match_haystack();
+    let first_finder = SimpleFinder::new(patterns());
+    let first_haystack = early_match_haystack();
+    let iter_finder = SimpleFinder::new(patterns());
+    let iter_haystack = early_match_haystack();
+
+    c.bench(
+        "early_match",
+        Benchmark::new("is_match", move |b| b.iter(|| finder.is_match(&haystack)))
+            .with_function("find_first", move |b| {
+                b.iter(|| first_finder.find_first(&first_haystack).is_some())
+            })
+            .with_function("find_all_next", move |b| {
+                b.iter(|| iter_finder.find_all(&iter_haystack).next().is_some())
+            }),
+    );
+}
+
+/// A haystack where no token starts a pattern, searched with and without the
+/// prefilter.
+fn bench_prefilter(c: &mut Criterion) {
+    let finder = |prefilter| {
+        SimpleFinderBuilder::new()
+            .prefilter(prefilter)
+            .build(vec![("quick brown fox", ()), ("lazy dog", ())])
+    };
+
+    let with = finder(true);
+    let without = finder(false);
+    let haystack = early_match_haystack();
+    let haystack_without = haystack.clone();
+
+    c.bench(
+        "prefilter",
+        Benchmark::new("with", move |b| b.iter(|| with.find_all(&haystack).count()))
+            .with_function("without", move |b| {
+                b.iter(|| without.find_all(&haystack_without).count())
+            }),
+    );
+}
+
+/// Phrases of two to four made up words, as a stand in for a large
+/// dictionary.
+fn phrases(num: usize) -> Vec<String> {
+    use rand::prelude::*;
+
+    let mut rng = SmallRng::from_seed([0; 16]);
+    let words: Vec<String> = (0..20_000)
+        .map(|_| {
+            let len = rng.gen_range(2, 9);
+            (0..len).map(|_| rng.gen_range(b'a', b'z' + 1) as char).collect()
+        })
+        .collect();
+    (0..num)
+        .map(|_| {
+            let len = rng.gen_range(2, 5);
+            let phrase: Vec<_> = (0..len)
+                .map(|_| words[rng.gen_range(0, words.len())].as_str())
+                .collect();
+            phrase.join(" ")
+        })
+        .collect()
+}
+
+#[cfg(feature = "rayon")]
+fn bench_parallel_build(c: &mut Criterion) {
+    let build = |parallel| {
+        let patterns = phrases(100_000);
+        move |b: &mut Bencher| {
+            b.iter(|| {
+                SimpleFinderBuilder::new()
+                    .parallel_build(parallel)
+                    .build(patterns.iter().map(|p| (p.as_str(), ())))
+            })
+        }
+    };
+
+    c.bench(
+        "build_100k",
+        Benchmark::new("serial", build(false))
+            .with_function("parallel", build(true))
+            .sample_size(10),
+    );
+}
+
+#[cfg(not(feature = "rayon"))]
+fn bench_parallel_build(_: &mut Criterion) {}
+
+criterion_group!(
+    benches,
+    bench_set_length,
+    bench_haystack_length,
+    bench_words,
+    bench_cracklib,
+    bench_dfa,
+    bench_dense_depth,
+    bench_case_insensitive,
+    bench_checked_search,
+    bench_sparse_transitions,
+    bench_is_match,
+    bench_prefilter,
+    bench_parallel_build
+);
 criterion_main!(benches);
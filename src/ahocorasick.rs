@@ -1,23 +1,646 @@
+use alloc::{borrow::Cow, boxed::Box, string::String, vec::Vec};
+#[cfg(feature = "rayon")]
+use core::cmp;
+use core::ops::ControlFlow;
+
 use crate::{
-    automaton::Automaton,
-    nfa::{self, NFA},
+    automaton::{Automaton, TokenSource},
+    collections::HashMap,
+    error::BuildError,
+    nfa::{AnyAutomaton, NfaOptions},
+    normalize::Normalizer,
+    parts::AutomatonParts,
     state_id::StateID,
-    Match,
+    tokenizer::SharedTokenizer,
+    Cursor, Match, MatchKind,
+};
+#[cfg(feature = "serialize")]
+use crate::{
+    error::SerializeError,
+    normalize::Normalization,
+    serialize::{Reader, Writer},
+    tokenizer::DefaultTokenizer,
 };
+#[cfg(feature = "trace")]
+use crate::{state_id::dead_id, trace::TraceStep};
+#[cfg(feature = "trace")]
+use alloc::string::ToString;
+#[cfg(feature = "serialize")]
+use alloc::sync::Arc;
+
+/// The fewest tokens that a parallel search gives each thread, below which
+/// it isn't worth the overhead of splitting the search.
+#[cfg(feature = "rayon")]
+const MIN_PARALLEL_PART_LEN: usize = 4096;
 
 #[derive(Clone)]
-pub(crate) struct AhoCorasick<S: StateID = usize> {
-    imp: NFA<S>,
+pub(crate) struct AhoCorasick {
+    pub(crate) imp: AnyAutomaton,
+    tokenizer: SharedTokenizer,
+    normalizer: Normalizer,
+    /// The options the automaton was built with, which patterns added to it
+    /// are built with too.
+    options: NfaOptions,
+    /// The tokens of the patterns that only match with the same case, by
+    /// pattern ID, normalized without folding case.
+    ///
+    /// The automaton matches these patterns whatever their case, and
+    /// searches skip the matches whose tokens differ from these.
+    case_sensitive: CaseSensitivePatterns,
 }
 
-impl<S: StateID> AhoCorasick<S> {
+pub(crate) type CaseSensitivePatterns = HashMap<usize, Box<[Box<str>]>>;
+
+impl AhoCorasick {
+    /// Returns an iterator of overlapping matches in the given haystack.
+    ///
+    /// This panics when this automaton's match kind is not
+    /// `MatchKind::Standard`, as leftmost automata don't keep the failure
+    /// transitions needed to find every match.
     pub(crate) fn find_overlapping_iter<'a: 'b, 'b>(
         &'a self,
         haystack: &'b str,
-    ) -> FindOverlappingIter<'a, 'b, S> {
+    ) -> FindOverlappingIter<'a, 'b> {
         FindOverlappingIter::new(self, haystack)
     }
 
+    /// Like `find_overlapping_iter`, but continues the search that `cursor`
+    /// was saved from, rather than starting at the first token.
+    ///
+    /// A cursor that can't have been saved from a search of this haystack by
+    /// this automaton, as it's past the last token, or its state doesn't
+    /// exist or is deeper than the tokens consumed, continues a search that
+    /// finds nothing.
+    ///
+    /// This panics when this automaton's match kind is not
+    /// `MatchKind::Standard`.
+    pub(crate) fn resume_overlapping<'a: 'b, 'b>(
+        &'a self,
+        haystack: &'b str,
+        cursor: Cursor,
+    ) -> FindOverlappingIter<'a, 'b> {
+        let mut iter = FindOverlappingIter::new(self, haystack);
+        // the cursor may have come from another automaton or haystack
+        let len = iter.haystack.tokens.len();
+        let fits = cursor.pos <= len
+            && self.imp.is_valid(cursor.state_id)
+            && self.imp.state_depth(cursor.state_id) <= cursor.pos;
+        if !fits {
+            iter.pos = len;
+            return iter;
+        }
+        iter.pos = cursor.pos;
+        iter.state_id = cursor.state_id;
+        iter.match_index = cursor.match_index;
+        iter
+    }
+
+    /// Like `find_overlapping_iter`, but the returned iterator owns the
+    /// tokens of the haystack, so doesn't borrow it.
+    ///
+    /// This panics when this automaton's match kind is not
+    /// `MatchKind::Standard`.
+    pub(crate) fn find_overlapping_iter_owned(
+        &self,
+        haystack: &str,
+    ) -> FindOverlappingIter<'_, 'static> {
+        FindOverlappingIter::with_haystack(
+            self,
+            TokenizedHaystack::new(self, haystack).into_owned(),
+        )
+    }
+
+    /// Returns an iterator of overlapping matches in an already tokenized
+    /// haystack, skipping tokenization.
+    ///
+    /// Each token is still normalized, but the reported matches are in
+    /// terms of token indices: a match of the tokens `tokens[i..j]` has its
+    /// char and byte offsets both set to `i..j`.
+    ///
+    /// This panics when this automaton's match kind is not
+    /// `MatchKind::Standard`.
+    pub(crate) fn find_overlapping_iter_tokens<'a: 'b, 'b>(
+        &'a self,
+        tokens: &[&'b str],
+    ) -> FindOverlappingIter<'a, 'b> {
+        FindOverlappingIter::with_haystack(self, TokenizedHaystack::from_tokens(self, tokens))
+    }
+
+    /// Like `find_overlapping_iter`, but only reports the longest of the
+    /// matches ending at each token, or all of them if several are equally
+    /// long.
+    ///
+    /// This panics when this automaton's match kind is not
+    /// `MatchKind::Standard`.
+    pub(crate) fn find_longest_per_end_iter<'a: 'b, 'b>(
+        &'a self,
+        haystack: &'b str,
+    ) -> FindOverlappingIter<'a, 'b> {
+        let mut iter = FindOverlappingIter::new(self, haystack);
+        iter.longest_per_end = true;
+        iter
+    }
+
+    /// Searches `haystack` with this automaton's match kind, reporting
+    /// overlapping matches if `overlapping` is set and the match kind allows
+    /// it.
+    pub(crate) fn matches<'a: 'b, 'b>(
+        &'a self,
+        haystack: &'b str,
+        overlapping: bool,
+    ) -> Matches<'a, 'b> {
+        if self.options.match_kind.is_leftmost() {
+            Matches::Leftmost(self.find_leftmost_iter(haystack))
+        } else if overlapping {
+            Matches::Overlapping(self.find_overlapping_iter(haystack))
+        } else {
+            Matches::NonOverlapping(self.find_iter(haystack))
+        }
+    }
+
+    /// Returns the matches in an already tokenized haystack, found by the
+    /// search selected by `overlapping` and this automaton's match kind, as
+    /// `find_reverse_iter` selects it.
+    ///
+    /// As with `find_overlapping_iter_tokens`, the matches are in terms of
+    /// token indices.
+    pub(crate) fn find_in_tokens(&self, tokens: &[&str], overlapping: bool) -> Vec<Match> {
+        let haystack = TokenizedHaystack::from_tokens(self, tokens);
+        let matches = if self.options.match_kind.is_leftmost() {
+            Matches::Leftmost(FindLeftmostIter::with_haystack(self, haystack))
+        } else if overlapping {
+            Matches::Overlapping(FindOverlappingIter::with_haystack(self, haystack))
+        } else {
+            Matches::NonOverlapping(FindIter::with_haystack(self, haystack))
+        };
+        matches.collect()
+    }
+
+    /// Walks the automaton over the tokens of the given haystack, recording
+    /// the states each token moves it through.
+    ///
+    /// Every match state reached is reported, as in an overlapping search.
+    /// For leftmost match kinds, the walk starts over from the start state
+    /// after reaching the dead state, which a search would do after
+    /// reporting a match.
+    #[cfg(feature = "trace")]
+    pub(crate) fn explain(&self, haystack: &str) -> Vec<TraceStep> {
+        let haystack = TokenizedHaystack::new(self, haystack);
+        let mut state_id = self.imp.start_state();
+        let mut steps = Vec::with_capacity(haystack.tokens.len());
+        for (idx, token) in haystack.tokens.iter().enumerate() {
+            let from = if state_id == dead_id::<usize>() {
+                self.imp.start_state()
+            } else {
+                state_id
+            };
+            let mut failures = Vec::new();
+            state_id = self
+                .imp
+                .traced_next_state(from, self.imp.token_id(token), &mut failures);
+            let matches = (0..self.imp.match_count(state_id))
+                .filter_map(|i| self.imp.get_match(state_id, i, idx + 1))
+                .map(|m| haystack.to_haystack_offsets(m))
+                .collect();
+            let (start, end) = haystack.word_char_idx_map[idx];
+            let (byte_start, byte_end) = haystack.word_byte_idx_map[idx];
+            steps.push(TraceStep {
+                token: token.to_string(),
+                start: start as usize,
+                end: end as usize,
+                byte_start,
+                byte_end,
+                from,
+                failures,
+                to: state_id,
+                depth: self.imp.state_depth(state_id),
+                matches,
+            });
+        }
+        steps
+    }
+
+    /// Returns an iterator of non-overlapping matches in the given haystack,
+    /// reporting the first match state reached.
+    ///
+    /// This panics when this automaton's match kind is not
+    /// `MatchKind::Standard`.
+    pub(crate) fn find_iter<'a: 'b, 'b>(&'a self, haystack: &'b str) -> FindIter<'a, 'b> {
+        FindIter::new(self, haystack)
+    }
+
+    /// Returns an iterator of non-overlapping matches in the given haystack,
+    /// resolved according to this automaton's leftmost match kind.
+    ///
+    /// This panics when this automaton's match kind is `MatchKind::Standard`.
+    pub(crate) fn find_leftmost_iter<'a: 'b, 'b>(
+        &'a self,
+        haystack: &'b str,
+    ) -> FindLeftmostIter<'a, 'b> {
+        FindLeftmostIter::new(self, haystack)
+    }
+
+    /// Returns an iterator of the same matches as `find_overlapping_iter`,
+    /// `find_iter`, or `find_leftmost_iter`, depending on `overlapping` and
+    /// this automaton's match kind, but that only looks up states with
+    /// `Automaton::next_state_checked`.
+    ///
+    /// Rather than panicking, the search ends early if it reaches an invalid
+    /// state or a match that doesn't fit in the haystack.
+    pub(crate) fn find_checked_iter<'a: 'b, 'b>(
+        &'a self,
+        haystack: &'b str,
+        overlapping: bool,
+    ) -> FindCheckedIter<'a, 'b> {
+        FindCheckedIter {
+            fsm: &self.imp,
+            case_sensitive: &self.case_sensitive,
+            haystack: TokenizedHaystack::new(self, haystack),
+            pos: 0,
+            state_id: self.imp.start_state(),
+            match_index: 0,
+            leftmost: self.options.match_kind.is_leftmost(),
+            overlapping,
+        }
+    }
+
+    /// Returns an iterator of the matches in the given haystack, consuming
+    /// its tokens from the end, for an automaton built by
+    /// `build_aho_corasick_reverse`.
+    ///
+    /// Matches are found by the token they start at, so they're reported in
+    /// descending order of their start. The search is overlapping, if
+    /// `overlapping` is set and the match kind allows it.
+    pub(crate) fn find_reverse_iter<'a: 'b, 'b>(
+        &'a self,
+        haystack: &'b str,
+        overlapping: bool,
+    ) -> Matches<'a, 'b> {
+        let haystack = TokenizedHaystack::new(self, haystack).reverse();
+        if self.options.match_kind.is_leftmost() {
+            Matches::Leftmost(FindLeftmostIter::with_haystack(self, haystack))
+        } else if overlapping {
+            Matches::Overlapping(FindOverlappingIter::with_haystack(self, haystack))
+        } else {
+            Matches::NonOverlapping(FindIter::with_haystack(self, haystack))
+        }
+    }
+
+    /// Returns true if and only if any pattern occurs in the given haystack.
+    ///
+    /// This stops tokenizing the haystack at the first match state reached,
+    /// and never computes the offsets of the match. For leftmost match
+    /// kinds, a match state is always reached before the search could stop
+    /// at the dead state, so this works for every match kind.
+    pub(crate) fn is_match(&self, haystack: &str) -> bool {
+        if self.has_case_sensitive() {
+            return self.find_iter(haystack).next().is_some();
+        }
+        let haystack = self.normalizer.normalize_text(haystack);
+        let tokens = NormalizedTokens::new(self, haystack.as_str(), None);
+        self.imp.first_match_in(tokens).is_some()
+    }
+
+    /// Returns the earliest ending match in the given haystack, tokenizing
+    /// no more of it than is needed to find it.
+    ///
+    /// For leftmost match kinds, this is instead the first match that a
+    /// leftmost search reports, which may end after other matches.
+    pub(crate) fn find_first(&self, haystack: &str) -> Option<Match> {
+        if self.options.match_kind.is_leftmost() {
+            return self.find_leftmost_iter(haystack).next();
+        }
+        if self.has_case_sensitive() {
+            return self.find_iter(haystack).next();
+        }
+
+        let normalized = self.normalizer.normalize_text(haystack);
+        let text = normalized.as_str();
+        let mut consumed = Vec::new();
+        let tokens = NormalizedTokens::new(self, text, Some(&mut consumed));
+        let m = self.imp.first_match_in(tokens)?;
+
+        // only the offsets of the tokens of the match are needed
+        let mut graphemes = self.normalizer.grapheme_counter(haystack);
+        let (char_spans, byte_spans): (Vec<_>, Vec<_>) = consumed[m.end - m.len..m.end]
+            .iter()
+            .map(|&(idx, byte_idx, s)| {
+                let char_len = s.chars().count() as u32;
+                let (char_span, byte_span) =
+                    normalized.to_original((idx, idx + char_len), (byte_idx, byte_idx + s.len()));
+                match &mut graphemes {
+                    Some(graphemes) => (graphemes.span(byte_span), byte_span),
+                    None => (char_span, byte_span),
+                }
+            })
+            .unzip();
+        let m = Match {
+            token_end: m.end,
+            ..remap_offsets(&char_spans, &byte_spans, Match { end: m.len, ..m })
+        };
+        Some(m)
+    }
+
+    /// Returns the match spanning all of the given haystack, if its tokens
+    /// are exactly those of a pattern.
+    ///
+    /// The search starts at the start state and gives up at the first token
+    /// with no transition, without following failure transitions.
+    pub(crate) fn find_anchored(&self, haystack: &str) -> Option<Match> {
+        let haystack = TokenizedHaystack::new(self, haystack);
+        let m = self.imp.anchored_find(&haystack.tokens, |m| {
+            haystack.matches_case(&self.case_sensitive, m)
+        })?;
+        Some(haystack.to_haystack_offsets(m))
+    }
+
+    pub(crate) fn start_state(&self) -> usize {
+        self.imp.start_state()
+    }
+
+    /// Returns true if searches can be advanced one token at a time with
+    /// `step`, which needs the failure transitions of a standard automaton,
+    /// and can't look back at the case of the tokens of a match.
+    pub(crate) fn steppable(&self) -> bool {
+        !self.options.match_kind.is_leftmost() && !self.has_case_sensitive()
+    }
+
+    /// Advances a search in `state_id` by one token, which ends the
+    /// `end`th token of the haystack, returning every match ending there.
+    ///
+    /// A stop word is skipped, leaving `state_id` and `end` as they are.
+    ///
+    /// The search must have started in the start state of this automaton,
+    /// which must be `steppable`. This panics if `state_id` isn't a state of
+    /// this automaton.
+    pub(crate) fn step(
+        &self,
+        state_id: &mut usize,
+        end: &mut usize,
+        token: &str,
+    ) -> impl Iterator<Item = Match> + '_ {
+        // the state may have come from another automaton
+        assert!(
+            self.imp.is_valid(*state_id),
+            "{} is not a valid state ID",
+            state_id
+        );
+
+        let token = self.normalizer.normalize(token);
+        let match_count = if self.normalizer.keeps(&token) {
+            *end += 1;
+            let token = self.imp.token_id(&token);
+            *state_id = unsafe { self.imp.next_state_unchecked_no_fail(*state_id, token) };
+            self.imp.match_count(*state_id)
+        } else {
+            0
+        };
+        let (state_id, end) = (*state_id, *end);
+        (0..match_count)
+            .filter_map(move |match_index| self.imp.get_match(state_id, match_index, end))
+    }
+
+    /// Calls `f` with the pattern of every match in the given haystack, as
+    /// the search selected by `overlapping` and this automaton's match kind
+    /// would report them, without mapping the matches back to haystack
+    /// offsets.
+    pub(crate) fn for_each_match_pattern<F>(&self, haystack: &str, overlapping: bool, mut f: F)
+    where
+        F: FnMut(usize),
+    {
+        if self.has_case_sensitive() {
+            self.matches(haystack, overlapping)
+                .for_each(|m| f(m.pattern()));
+            return;
+        }
+        let haystack = self.normalizer.normalize_text(haystack);
+        let tokens: Vec<_> = self
+            .tokenizer
+            .tokenize(haystack.as_str())
+            .map(|(_, token)| self.normalizer.normalize(token))
+            .filter(|token| self.normalizer.keeps(token))
+            .collect();
+        let mut pos = 0;
+
+        if self.options.match_kind.is_leftmost() {
+            while pos <= tokens.len() {
+                let m = match self.imp.leftmost_find_at(&tokens, pos) {
+                    Some(m) => m,
+                    None => break,
+                };
+                pos = core::cmp::max(m.end(), pos + 1);
+                f(m.pattern());
+            }
+        } else if overlapping {
+            let mut state_id = self.imp.start_state();
+            let mut match_index = 0;
+            while let Some(m) =
+                self.imp
+                    .overlapping_find_at(&tokens, pos, &mut state_id, &mut match_index)
+            {
+                pos = m.end();
+                f(m.pattern());
+            }
+        } else {
+            loop {
+                let mut state_id = self.imp.start_state();
+                let m = match self.imp.standard_find_at(&tokens, pos, &mut state_id) {
+                    Some(m) => m,
+                    None => break,
+                };
+                pos = m.end();
+                f(m.pattern());
+            }
+        }
+    }
+
+    /// Calls `f` with every overlapping match in the given haystack, in the
+    /// same order as `find_overlapping_iter`, until it returns `Break`.
+    ///
+    /// This panics when this automaton's match kind is not
+    /// `MatchKind::Standard`.
+    pub(crate) fn try_for_each_overlapping<F>(&self, haystack: &str, mut f: F) -> ControlFlow<()>
+    where
+        F: FnMut(Match) -> ControlFlow<()>,
+    {
+        assert!(
+            !self.options.match_kind.is_leftmost(),
+            "overlapping searches are not supported with leftmost match kinds"
+        );
+        let haystack = TokenizedHaystack::new(self, haystack);
+        let mut state_id = self.imp.start_state();
+        let mut match_index = 0;
+        let mut pos = 0;
+        while let Some(m) =
+            self.imp
+                .overlapping_find_at(&haystack.tokens, pos, &mut state_id, &mut match_index)
+        {
+            pos = m.end();
+            if haystack.matches_case(&self.case_sensitive, &m) {
+                f(haystack.to_haystack_offsets(m))?;
+            }
+        }
+        ControlFlow::Continue(())
+    }
+
+    /// Adds patterns to this automaton, numbering them after the patterns
+    /// it already has, as if they had been given after them when it was
+    /// built.
+    ///
+    /// Only the new patterns are added to the trie, but every failure
+    /// transition is recomputed, so this takes time proportional to the
+    /// size of the whole automaton, rather than to the new patterns. If
+    /// adding them fails, the automaton is left as it was.
+    pub(crate) fn add_patterns<'p, I>(&mut self, patterns: I) -> Result<(), BuildError>
+    where
+        I: IntoIterator<Item = &'p str>,
+    {
+        let patterns =
+            tokenize_patterns(patterns, &self.tokenizer, &self.normalizer, self.options)?;
+        self.imp = self.imp.clone().extend(&patterns, self.options)?;
+        Ok(())
+    }
+
+    /// Adds the patterns of `other` to this automaton, numbering them after
+    /// the patterns it already has. `other` must tokenize and normalize text
+    /// the same way as this automaton does.
+    ///
+    /// If adding them fails, the automaton is left as it was.
+    pub(crate) fn merge(&mut self, other: &AhoCorasick) -> Result<(), BuildError> {
+        if !self.normalizer.normalizes_like(&other.normalizer) {
+            return Err(BuildError::NormalizerMismatch);
+        }
+        let offset = self.imp.pattern_count();
+        self.imp = self.imp.clone().merge(&other.imp, self.options)?;
+        self.case_sensitive.extend(
+            other
+                .case_sensitive
+                .iter()
+                .map(|(&id, tokens)| (id + offset, tokens.clone())),
+        );
+        Ok(())
+    }
+
+    pub(crate) fn match_kind(&self) -> MatchKind {
+        self.options.match_kind
+    }
+
+    pub(crate) fn first_tokens(&self) -> impl Iterator<Item = &str> {
+        self.imp.start_tokens()
+    }
+
+    /// Returns the ID and length in tokens of every pattern, in order of
+    /// their IDs.
+    ///
+    /// Patterns merged into an earlier one by `dedup_patterns` are left out,
+    /// as they are never reported.
+    pub(crate) fn patterns_iter(&self) -> impl Iterator<Item = (usize, usize)> {
+        let mut patterns: Vec<_> = self.imp.pattern_lens().collect();
+        patterns.sort_unstable();
+        patterns.into_iter()
+    }
+
+    /// Makes the given patterns only match haystack tokens of the same
+    /// case, given their IDs and text.
+    ///
+    /// This does nothing unless the automaton folds case. It returns an
+    /// error if the automaton has a leftmost match kind, as a leftmost search
+    /// can't skip a match without missing the one it was chosen over.
+    pub(crate) fn keep_case_of<'p, I>(&mut self, patterns: I) -> Result<(), BuildError>
+    where
+        I: IntoIterator<Item = (usize, &'p str)>,
+    {
+        if !self.normalizer.case_insensitive {
+            return Ok(());
+        }
+        for (id, pattern) in patterns {
+            if self.options.match_kind.is_leftmost() {
+                return Err(BuildError::case_sensitive_leftmost(id));
+            }
+            let normalized = self.normalizer.normalize_text(pattern);
+            let tokens = self
+                .tokenizer
+                .tokenize(normalized.as_str())
+                .map(|(_, token)| token)
+                .filter(|token| self.normalizer.keeps(&self.normalizer.normalize(token)))
+                .map(|token| self.normalizer.normalize_keeping_case(token).into())
+                .collect();
+            self.case_sensitive.insert(id, tokens);
+        }
+        Ok(())
+    }
+
+    /// Returns true if any pattern only matches with the same case, so
+    /// that searches must compare the case of the tokens they match.
+    pub(crate) fn has_case_sensitive(&self) -> bool {
+        !self.case_sensitive.is_empty()
+    }
+
+    /// Panics if any pattern is case-sensitive, for searches that are fed
+    /// one token at a time, and can't look back at the case of the tokens
+    /// of a match.
+    pub(crate) fn assert_no_case_sensitive(&self, search: &str) {
+        assert!(
+            !self.has_case_sensitive(),
+            "{} are not supported with case-sensitive patterns",
+            search
+        );
+    }
+
+    pub(crate) fn tokenizer(&self) -> &SharedTokenizer {
+        &self.tokenizer
+    }
+
+    pub(crate) fn normalizer(&self) -> &Normalizer {
+        &self.normalizer
+    }
+
+    pub(crate) fn max_pattern_len(&self) -> usize {
+        self.imp.max_pattern_len()
+    }
+
+    /// Returns the fraction of the tokens of `haystack` that are part of
+    /// any match, or `0.0` if it has no tokens.
+    ///
+    /// Every overlapping match counts, unless the match kind is leftmost,
+    /// in which case only the leftmost matches do.
+    pub(crate) fn coverage(&self, haystack: &str) -> f64 {
+        let haystack = TokenizedHaystack::new(self, haystack);
+        let token_count = haystack.tokens.len();
+        if token_count == 0 {
+            return 0.0;
+        }
+
+        let mut covered = alloc::vec![false; token_count];
+        let matches = if self.options.match_kind.is_leftmost() {
+            Matches::Leftmost(FindLeftmostIter::with_haystack(self, haystack))
+        } else {
+            Matches::Overlapping(FindOverlappingIter::with_haystack(self, haystack))
+        };
+        for m in matches {
+            covered[m.token_start()..m.token_end()].fill(true);
+        }
+        covered.iter().filter(|&&c| c).count() as f64 / token_count as f64
+    }
+
+    /// Returns true if the start state is a match state, which is only the
+    /// case when a pattern has no tokens, and so matches everywhere.
+    pub(crate) fn matches_empty(&self) -> bool {
+        self.imp.is_match_state(self.imp.start_state())
+    }
+
+    pub(crate) fn state_count(&self) -> usize {
+        self.imp.state_count()
+    }
+
+    pub(crate) fn dense_state_count(&self) -> usize {
+        self.imp.dense_state_count()
+    }
+
+    pub(crate) fn max_out_degree(&self) -> usize {
+        self.imp.max_out_degree()
+    }
+
     pub(crate) fn pattern_count(&self) -> usize {
         self.imp.pattern_count()
     }
@@ -25,71 +648,918 @@ impl<S: StateID> AhoCorasick<S> {
     pub(crate) fn heap_bytes(&self) -> usize {
         self.imp.heap_bytes()
     }
+
+    /// Frees the spare capacity of the automaton's states and their
+    /// transitions, which building leaves behind.
+    pub(crate) fn shrink_to_fit(&mut self) {
+        self.imp.shrink_to_fit();
+    }
+
+    /// Returns the automaton as plain data, to be restored with
+    /// `aho_corasick_from_parts`.
+    pub(crate) fn into_parts(self) -> AutomatonParts {
+        self.assert_no_case_sensitive("automaton parts");
+        self.imp.into_parts(self.options)
+    }
 }
 
-pub(crate) struct FindOverlappingIter<'a, 'b, S: 'a + StateID> {
-    fsm: &'a NFA<S>,
-    word_char_idx_map: Vec<u32>,
-    haystack: Vec<&'b str>,
-    pos: usize,
-    state_id: S,
-    match_index: usize,
+#[cfg(feature = "rayon")]
+impl AhoCorasick {
+    /// Returns every overlapping match in the given haystack, in the same
+    /// order as `find_overlapping_iter`, searching parts of the haystack in
+    /// parallel.
+    ///
+    /// The haystack is tokenized once and split into parts, each of which
+    /// reports the matches ending within it. A part is searched from
+    /// `max_pattern_len - 1` tokens before its start, so that matches
+    /// straddling its start are found by exactly one part.
+    ///
+    /// This panics when this automaton's match kind is not
+    /// `MatchKind::Standard`.
+    pub(crate) fn find_overlapping_parallel(&self, haystack: &str) -> Vec<Match> {
+        use rayon::prelude::*;
+
+        assert!(
+            !self.options.match_kind.is_leftmost(),
+            "overlapping searches are not supported with leftmost match kinds"
+        );
+        let haystack = TokenizedHaystack::new(self, haystack);
+        let tokens = &haystack.tokens[..];
+        let overlap = self.max_pattern_len().saturating_sub(1);
+        // a few parts per thread, so that a slow part doesn't hold up the
+        // whole search
+        let part_len = cmp::max(
+            tokens.len() / (rayon::current_num_threads() * 4),
+            cmp::max(MIN_PARALLEL_PART_LEN, overlap),
+        );
+
+        let parts: Vec<Vec<Match>> = (0..tokens.len())
+            .step_by(part_len)
+            .collect::<Vec<_>>()
+            .into_par_iter()
+            .map(|start| {
+                let end = cmp::min(start + part_len, tokens.len());
+                let from = start.saturating_sub(overlap);
+                let part = &tokens[from..end];
+
+                let mut matches = Vec::new();
+                let mut state_id = self.imp.start_state();
+                let mut match_index = 0;
+                let mut pos = 0;
+                while let Some(mut m) =
+                    self.imp
+                        .overlapping_find_at(part, pos, &mut state_id, &mut match_index)
+                {
+                    pos = m.end;
+                    // matches ending before the start of this part belong to
+                    // the part before it
+                    m.end += from;
+                    if m.end > start && haystack.matches_case(&self.case_sensitive, &m) {
+                        matches.push(haystack.to_haystack_offsets(m));
+                    }
+                }
+                matches
+            })
+            .collect();
+        parts.into_iter().flatten().collect()
+    }
+}
+
+#[cfg(feature = "serialize")]
+impl AhoCorasick {
+    /// Writes this automaton and the configuration needed to search with it.
+    ///
+    /// Only the `DefaultTokenizer` can be written, and only without a custom
+    /// word character classifier or a token transform.
+    pub(crate) fn write_to(&self, w: &mut Writer) -> Result<(), SerializeError> {
+        let tokenizer = match &self.tokenizer {
+            SharedTokenizer::Default(tokenizer) if tokenizer.is_word.is_none() => tokenizer,
+            _ => return Err(SerializeError::CustomTokenizer),
+        };
+        if self.normalizer.transform.is_some() {
+            return Err(SerializeError::TokenTransform);
+        }
+        w.write_bool(tokenizer.keep_whitespace);
+        w.write_bool(tokenizer.join_apostrophes);
+        w.write_bool(tokenizer.join_hyphens);
+        w.write_bool(tokenizer.join_digit_groups);
+        w.write_bool(tokenizer.skip_symbols);
+
+        w.write_bool(self.normalizer.fold_width);
+        w.write_bool(self.normalizer.case_insensitive);
+        w.write_u8(match self.normalizer.form {
+            Normalization::None => 0,
+            Normalization::Nfc => 1,
+        });
+        w.write_bool(self.normalizer.graphemes);
+        // sorted so that the same finder always serializes the same
+        let mut stop_words: Vec<_> = self.normalizer.stop_words.iter().collect();
+        stop_words.sort();
+        w.write_usize(stop_words.len());
+        for word in stop_words {
+            w.write_str(word);
+        }
+        // already in a fixed order, longest first
+        w.write_usize(self.normalizer.strip_symbols.len());
+        for symbol in self.normalizer.strip_symbols.iter() {
+            w.write_str(symbol);
+        }
+        w.write_u8(match self.options.match_kind {
+            MatchKind::Standard => 0,
+            MatchKind::LeftmostLongest => 1,
+            MatchKind::LeftmostFirst => 2,
+        });
+        w.write_bool(self.options.dedup_patterns);
+        w.write_usize(self.options.dense_depth);
+        w.write_bool(self.options.reject_symbol_only);
+        match self.options.max_match_tokens {
+            Some(max) => {
+                w.write_bool(true);
+                w.write_usize(max);
+            }
+            None => w.write_bool(false),
+        }
+        // sorted so that the same finder always serializes the same
+        let mut case_sensitive: Vec<_> = self.case_sensitive.iter().collect();
+        case_sensitive.sort();
+        w.write_usize(case_sensitive.len());
+        for (&id, tokens) in case_sensitive {
+            w.write_usize(id);
+            w.write_usize(tokens.len());
+            for token in tokens.iter() {
+                w.write_str(token);
+            }
+        }
+
+        self.imp.write_to(w);
+        Ok(())
+    }
+
+    pub(crate) fn read_from(r: &mut Reader<'_>) -> Result<AhoCorasick, SerializeError> {
+        let mut tokenizer = DefaultTokenizer {
+            keep_whitespace: r.read_bool()?,
+            is_word: None,
+            join_apostrophes: r.read_bool()?,
+            join_hyphens: r.read_bool()?,
+            join_digit_groups: r.read_bool()?,
+            skip_symbols: r.read_bool()?,
+            strip_symbols: Default::default(),
+        };
+
+        let mut normalizer = Normalizer {
+            fold_width: r.read_bool()?,
+            case_insensitive: r.read_bool()?,
+            form: match r.read_u8()? {
+                0 => Normalization::None,
+                1 => Normalization::Nfc,
+                _ => return Err(SerializeError::InvalidFormat),
+            },
+            graphemes: r.read_bool()?,
+            ..Normalizer::default()
+        };
+        for _ in 0..r.read_len(8)? {
+            normalizer.stop_words.insert(r.read_str()?.into());
+        }
+        let strip_symbols: Arc<[Box<str>]> = (0..r.read_len(8)?)
+            .map(|_| Ok(r.read_str()?.into()))
+            .collect::<Result<_, SerializeError>>()?;
+        normalizer.strip_symbols = strip_symbols.clone();
+        tokenizer.strip_symbols = strip_symbols;
+        let tokenizer = SharedTokenizer::Default(tokenizer);
+        let match_kind = match r.read_u8()? {
+            0 => MatchKind::Standard,
+            1 => MatchKind::LeftmostLongest,
+            2 => MatchKind::LeftmostFirst,
+            _ => return Err(SerializeError::InvalidFormat),
+        };
+        let dedup_patterns = r.read_bool()?;
+        let dense_depth = r.read_usize()?;
+        let reject_symbol_only = r.read_bool()?;
+        let max_match_tokens = if r.read_bool()? {
+            Some(r.read_usize()?)
+        } else {
+            None
+        };
+        let mut case_sensitive = CaseSensitivePatterns::default();
+        for _ in 0..r.read_len(16)? {
+            let id = r.read_usize()?;
+            let tokens = (0..r.read_len(8)?)
+                .map(|_| Ok(r.read_str()?.into()))
+                .collect::<Result<_, SerializeError>>()?;
+            case_sensitive.insert(id, tokens);
+        }
+
+        let imp = AnyAutomaton::read_from(r)?;
+        let options = NfaOptions {
+            match_kind,
+            dedup_patterns,
+            dense_depth,
+            reject_symbol_only,
+            max_match_tokens,
+            prefilter: imp.prefilter().is_some(),
+            dfa: imp.is_dfa(),
+            ..NfaOptions::default()
+        };
+        Ok(AhoCorasick {
+            imp,
+            tokenizer,
+            normalizer,
+            options,
+            case_sensitive,
+        })
+    }
+}
+
+/// A haystack split into normalized tokens, along with the char and byte
+/// offsets spanned by each token.
+pub(crate) struct TokenizedHaystack<'b> {
+    word_char_idx_map: Vec<(u32, u32)>,
+    word_byte_idx_map: Vec<(usize, usize)>,
+    tokens: Vec<Cow<'b, str>>,
+    /// Whether the tokens are in reverse order, to be searched by an
+    /// automaton built from reversed patterns. The offset maps are always
+    /// in the order of the haystack.
+    reversed: bool,
+    /// The tokens normalized without folding case, to compare with the
+    /// tokens of case-sensitive patterns. This is only filled in when the
+    /// automaton has any.
+    exact_tokens: Vec<Cow<'b, str>>,
+}
+
+/// The normalized tokens of a text, read one at a time and normalized into
+/// the same buffer, for searches that don't keep them.
+struct NormalizedTokens<'a, 't> {
+    normalizer: &'a Normalizer,
+    tokens: Box<dyn Iterator<Item = (u32, usize, &'t str)> + 't>,
+    scratch: String,
+    /// Where to record the offsets of each token read, if they're needed.
+    consumed: Option<&'a mut Vec<(u32, usize, &'t str)>>,
 }
 
-impl<'a, 'b, S: StateID> FindOverlappingIter<'a, 'b, S> {
-    fn new(ac: &'a AhoCorasick<S>, haystack_str: &'b str) -> FindOverlappingIter<'a, 'b, S> {
-        use crate::word_split_trait::WordBoundarySplitter;
+impl<'a, 't> NormalizedTokens<'a, 't> {
+    fn new(
+        ac: &'a AhoCorasick,
+        text: &'t str,
+        consumed: Option<&'a mut Vec<(u32, usize, &'t str)>>,
+    ) -> Self {
+        NormalizedTokens {
+            normalizer: &ac.normalizer,
+            tokens: Box::new(ac.tokenizer.tokenize_spans(text)),
+            scratch: String::new(),
+            consumed,
+        }
+    }
+}
+
+impl<'a, 't> TokenSource<str> for NormalizedTokens<'a, 't> {
+    fn next_token(&mut self) -> Option<&str> {
+        // only whether each token was written to `scratch` is kept between
+        // iterations, so that skipping a stop word can overwrite it
+        let ((idx, byte_idx, s), in_scratch) = loop {
+            let span = self.tokens.next()?;
+            let in_scratch = self.normalizer.normalize_into(span.2, &mut self.scratch);
+            let token = if in_scratch { &self.scratch } else { span.2 };
+            if self.normalizer.keeps(token) {
+                break (span, in_scratch);
+            }
+        };
+        if let Some(consumed) = &mut self.consumed {
+            consumed.push((idx, byte_idx, s));
+        }
+        Some(if in_scratch { &self.scratch } else { s })
+    }
+}
+
+impl<'b> TokenizedHaystack<'b> {
+    fn new(ac: &AhoCorasick, haystack: &'b str) -> TokenizedHaystack<'b> {
+        let mut word_char_idx_map = Vec::new();
+        let mut word_byte_idx_map = Vec::new();
+        let mut tokens = Vec::new();
+        let mut exact_tokens = Vec::new();
+        // chars are bytes in ASCII text, so they needn't be counted
+        let is_ascii = haystack.is_ascii();
+        let normalized = ac.normalizer.normalize_text(haystack);
+        let text = normalized.as_str();
+        let mut graphemes = ac.normalizer.grapheme_counter(haystack);
+
+        // each token is mapped to the offsets of its start and end, so that a
+        // match spanning tokens `i..j` covers `map[i].0..map[j - 1].1`
+        for (idx, byte_idx, s) in ac.tokenizer.tokenize_spans(text) {
+            // borrow the token from the haystack when normalizing left it be
+            let token = match normalized.unchanged() {
+                Some(haystack) => ac
+                    .normalizer
+                    .normalize(&haystack[byte_idx..byte_idx + s.len()]),
+                None => Cow::Owned(ac.normalizer.normalize(s).into_owned()),
+            };
+            // a stop word has no entry in the maps, so a match spanning it
+            // covers it through the tokens either side
+            if !ac.normalizer.keeps(&token) {
+                continue;
+            }
+            tokens.push(token);
+            if ac.has_case_sensitive() {
+                exact_tokens.push(match normalized.unchanged() {
+                    Some(haystack) => ac
+                        .normalizer
+                        .normalize_keeping_case(&haystack[byte_idx..byte_idx + s.len()]),
+                    None => Cow::Owned(ac.normalizer.normalize_keeping_case(s).into_owned()),
+                });
+            }
 
-        let input_len = haystack_str.chars().count() + 1;
+            let char_len = if is_ascii { s.len() } else { s.chars().count() };
+            let (char_span, byte_span) = normalized
+                .to_original((idx, idx + char_len as u32), (byte_idx, byte_idx + s.len()));
+            let char_span = match &mut graphemes {
+                Some(graphemes) => graphemes.span(byte_span),
+                None => char_span,
+            };
+            word_char_idx_map.push(char_span);
+            word_byte_idx_map.push(byte_span);
+        }
+
+        TokenizedHaystack {
+            word_char_idx_map,
+            word_byte_idx_map,
+            tokens,
+            reversed: false,
+            exact_tokens,
+        }
+    }
 
-        let (mut word_char_idx_map, haystack): (Vec<_>, Vec<_>) =
-            haystack_str.unicode_words_and_syms_indices().unzip();
+    /// Copies the tokens that were borrowed from the haystack, so that this
+    /// no longer borrows it.
+    fn into_owned(self) -> TokenizedHaystack<'static> {
+        TokenizedHaystack {
+            word_char_idx_map: self.word_char_idx_map,
+            word_byte_idx_map: self.word_byte_idx_map,
+            tokens: self
+                .tokens
+                .into_iter()
+                .map(|token| Cow::Owned(token.into_owned()))
+                .collect(),
+            reversed: self.reversed,
+            exact_tokens: self
+                .exact_tokens
+                .into_iter()
+                .map(|token| Cow::Owned(token.into_owned()))
+                .collect(),
+        }
+    }
 
-        word_char_idx_map.push(input_len as u32);
+    /// Reverses the order of the tokens, so that a search consumes them from
+    /// the end of the haystack.
+    fn reverse(mut self) -> TokenizedHaystack<'b> {
+        self.tokens.reverse();
+        self.exact_tokens.reverse();
+        self.reversed = !self.reversed;
+        self
+    }
 
+    /// A haystack that has already been split into tokens, where the offsets
+    /// of each token are its index.
+    fn from_tokens(ac: &AhoCorasick, tokens: &[&'b str]) -> TokenizedHaystack<'b> {
+        let normalizer = &ac.normalizer;
+        let mut haystack = TokenizedHaystack {
+            word_char_idx_map: Vec::with_capacity(tokens.len()),
+            word_byte_idx_map: Vec::with_capacity(tokens.len()),
+            tokens: Vec::with_capacity(tokens.len()),
+            reversed: false,
+            exact_tokens: Vec::new(),
+        };
+        for (i, &token) in tokens.iter().enumerate() {
+            let normalized = normalizer.normalize(token);
+            if normalizer.keeps(&normalized) {
+                haystack.word_char_idx_map.push((i as u32, i as u32 + 1));
+                haystack.word_byte_idx_map.push((i, i + 1));
+                haystack.tokens.push(normalized);
+                if ac.has_case_sensitive() {
+                    haystack
+                        .exact_tokens
+                        .push(normalizer.normalize_keeping_case(token));
+                }
+            }
+        }
+        haystack
+    }
+
+    /// Returns true unless `m`, which is in terms of token indices, is of a
+    /// case-sensitive pattern whose tokens differ in case from those it
+    /// spans.
+    #[inline]
+    fn matches_case(&self, case_sensitive: &CaseSensitivePatterns, m: &Match) -> bool {
+        if case_sensitive.is_empty() {
+            return true;
+        }
+        let pattern = match case_sensitive.get(&m.pattern) {
+            Some(pattern) => pattern,
+            None => return true,
+        };
+        let tokens = self.exact_tokens[m.end - m.len..m.end].iter();
+        if self.reversed {
+            tokens.rev().map(|t| &**t).eq(pattern.iter().map(|t| &**t))
+        } else {
+            tokens.map(|t| &**t).eq(pattern.iter().map(|t| &**t))
+        }
+    }
+
+    /// Converts a match reported by the automaton, which is in terms of token
+    /// indices, to one in terms of char and byte offsets.
+    fn to_haystack_offsets(&self, mut m: Match) -> Match {
+        if self.reversed {
+            // the tokens `i..j` of the reversed haystack are the tokens
+            // `n - j..n - i` of the haystack
+            m.end = self.tokens.len() - (m.end - m.len);
+        }
+        remap_offsets(&self.word_char_idx_map, &self.word_byte_idx_map, m)
+    }
+}
+
+/// Converts a match in terms of token indices to one in terms of the char and
+/// byte offsets spanned by those tokens.
+pub(crate) fn remap_offsets(
+    word_char_idx_map: &[(u32, u32)],
+    word_byte_idx_map: &[(usize, usize)],
+    mut m: Match,
+) -> Match {
+    // empty patterns are rejected when building, so every match spans at
+    // least one token
+    debug_assert!(m.len > 0, "matches must span at least one token");
+    let (first, last) = (m.end - m.len, m.end - 1);
+    m.token_len = m.len;
+    m.token_end = m.end;
+
+    let start_idx = word_char_idx_map[first].0;
+    let end_idx = word_char_idx_map[last].1;
+    m.len = (end_idx - start_idx) as usize;
+    m.end = end_idx as usize;
+
+    let byte_start_idx = word_byte_idx_map[first].0;
+    let byte_end_idx = word_byte_idx_map[last].1;
+    m.byte_len = byte_end_idx - byte_start_idx;
+    m.byte_end = byte_end_idx;
+    m
+}
+
+pub(crate) struct FindOverlappingIter<'a, 'b> {
+    fsm: &'a AnyAutomaton,
+    case_sensitive: &'a CaseSensitivePatterns,
+    haystack: TokenizedHaystack<'b>,
+    pos: usize,
+    state_id: usize,
+    match_index: usize,
+    /// Whether to skip the matches shorter than the first of each match
+    /// state, which are the matches of its suffixes.
+    longest_per_end: bool,
+    /// The end and length of the last match reported, when only the longest
+    /// matches ending at each token are.
+    longest: Option<(usize, usize)>,
+}
+
+impl<'a, 'b> FindOverlappingIter<'a, 'b> {
+    fn new(ac: &'a AhoCorasick, haystack_str: &'b str) -> FindOverlappingIter<'a, 'b> {
+        let haystack = TokenizedHaystack::new(ac, haystack_str);
+        FindOverlappingIter::with_haystack(ac, haystack)
+    }
+
+    fn with_haystack(
+        ac: &'a AhoCorasick,
+        haystack: TokenizedHaystack<'b>,
+    ) -> FindOverlappingIter<'a, 'b> {
+        assert!(
+            !ac.options.match_kind.is_leftmost(),
+            "overlapping searches are not supported with leftmost match kinds"
+        );
         FindOverlappingIter {
             fsm: &ac.imp,
-            word_char_idx_map,
+            case_sensitive: &ac.case_sensitive,
             haystack,
             pos: 0,
             state_id: ac.imp.start_state(),
             match_index: 0,
+            longest_per_end: false,
+            longest: None,
+        }
+    }
+
+    /// Returns where the search is, so that it can be continued later with
+    /// `AhoCorasick::resume_overlapping`.
+    pub(crate) fn cursor(&self) -> Cursor {
+        Cursor {
+            pos: self.pos,
+            state_id: self.state_id,
+            match_index: self.match_index,
+        }
+    }
+}
+
+/// Filters a stream of matches ordered by their end position down to those
+/// that don't overlap an earlier one.
+///
+/// This keeps the earliest ending match and skips any later match that
+/// starts before the end of the last one yielded.
+pub(crate) fn non_overlapping<I>(iter: I) -> impl Iterator<Item = Match>
+where
+    I: Iterator<Item = Match>,
+{
+    let mut last_end = 0;
+    iter.filter(move |m| {
+        if m.start() < last_end {
+            return false;
+        }
+        last_end = m.end();
+        true
+    })
+}
+
+/// Resolves a set of possibly overlapping matches to a non-overlapping set,
+/// preferring the leftmost match and, of those starting at the same
+/// position, the longest.
+///
+/// The returned matches are ordered by their start position.
+pub(crate) fn leftmost_longest(mut matches: Vec<Match>) -> Vec<Match> {
+    matches.sort_by(|a, b| a.start().cmp(&b.start()).then(b.end().cmp(&a.end())));
+
+    let mut last_end = 0;
+    matches.retain(|m| {
+        if m.start() < last_end {
+            return false;
+        }
+        last_end = m.end();
+        true
+    });
+    matches
+}
+
+impl<'a, 'b> Iterator for FindOverlappingIter<'a, 'b> {
+    type Item = Match;
+
+    fn next(&mut self) -> Option<Match> {
+        loop {
+            let m = self.fsm.overlapping_find_at(
+                &self.haystack.tokens,
+                self.pos,
+                &mut self.state_id,
+                &mut self.match_index,
+            )?;
+            self.pos = m.end();
+            // the matches of a state's own patterns come first, and are all
+            // as long as it is deep, while the rest are copied from the
+            // states it fails to, which are its suffixes
+            match self.longest {
+                Some((end, len)) if self.longest_per_end && m.end == end && m.len < len => {
+                    self.match_index = usize::MAX;
+                    continue;
+                }
+                _ => {}
+            }
+            if !self.haystack.matches_case(self.case_sensitive, &m) {
+                continue;
+            }
+            if self.longest_per_end {
+                self.longest = Some((m.end, m.len));
+            }
+            return Some(self.haystack.to_haystack_offsets(m));
+        }
+    }
+}
+
+pub(crate) struct FindIter<'a, 'b> {
+    fsm: &'a AnyAutomaton,
+    case_sensitive: &'a CaseSensitivePatterns,
+    haystack: TokenizedHaystack<'b>,
+    pos: usize,
+}
+
+impl<'a, 'b> FindIter<'a, 'b> {
+    fn new(ac: &'a AhoCorasick, haystack_str: &'b str) -> FindIter<'a, 'b> {
+        FindIter::with_haystack(ac, TokenizedHaystack::new(ac, haystack_str))
+    }
+
+    fn with_haystack(ac: &'a AhoCorasick, haystack: TokenizedHaystack<'b>) -> FindIter<'a, 'b> {
+        assert!(
+            !ac.options.match_kind.is_leftmost(),
+            "use a leftmost search with leftmost match kinds"
+        );
+
+        FindIter {
+            fsm: &ac.imp,
+            case_sensitive: &ac.case_sensitive,
+            haystack,
+            pos: 0,
         }
     }
 }
 
-impl<'a, 'b, S: StateID> Iterator for FindOverlappingIter<'a, 'b, S> {
+impl<'a, 'b> Iterator for FindIter<'a, 'b> {
     type Item = Match;
 
     fn next(&mut self) -> Option<Match> {
-        let result = self.fsm.overlapping_find_at(
-            &self.haystack,
-            self.pos,
-            &mut self.state_id,
-            &mut self.match_index,
+        // each search restarts from the start state at the end of the
+        // previous match, so that no two matches can overlap
+        let mut state_id = self.fsm.start_state();
+        let mut pos = self.pos;
+        loop {
+            let m = self
+                .fsm
+                .standard_find_at(&self.haystack.tokens, pos, &mut state_id)?;
+            pos = m.end();
+            let m = accepted_match(self.fsm, &self.haystack, self.case_sensitive, state_id, m);
+            if let Some(m) = m {
+                self.pos = m.end();
+                return Some(self.haystack.to_haystack_offsets(m));
+            }
+        }
+    }
+}
+
+pub(crate) struct FindLeftmostIter<'a, 'b> {
+    fsm: &'a AnyAutomaton,
+    haystack: TokenizedHaystack<'b>,
+    pos: usize,
+}
+
+impl<'a, 'b> FindLeftmostIter<'a, 'b> {
+    fn new(ac: &'a AhoCorasick, haystack_str: &'b str) -> FindLeftmostIter<'a, 'b> {
+        FindLeftmostIter::with_haystack(ac, TokenizedHaystack::new(ac, haystack_str))
+    }
+
+    fn with_haystack(
+        ac: &'a AhoCorasick,
+        haystack: TokenizedHaystack<'b>,
+    ) -> FindLeftmostIter<'a, 'b> {
+        assert!(
+            ac.options.match_kind.is_leftmost(),
+            "leftmost searches need a leftmost match kind"
         );
-        match result {
-            None => None,
-            Some(mut m) => {
+
+        FindLeftmostIter {
+            fsm: &ac.imp,
+            haystack,
+            pos: 0,
+        }
+    }
+}
+
+impl<'a, 'b> Iterator for FindLeftmostIter<'a, 'b> {
+    type Item = Match;
+
+    fn next(&mut self) -> Option<Match> {
+        if self.pos > self.haystack.tokens.len() {
+            return None;
+        }
+        let m = self.fsm.leftmost_find_at(&self.haystack.tokens, self.pos)?;
+        // an empty match ends where the search started, so step past it to
+        // avoid finding it again
+        self.pos = core::cmp::max(m.end(), self.pos + 1);
+        Some(self.haystack.to_haystack_offsets(m))
+    }
+}
+
+/// A search that never uses unchecked state lookups, created by
+/// `AhoCorasick::find_checked_iter`.
+pub(crate) struct FindCheckedIter<'a, 'b> {
+    fsm: &'a AnyAutomaton,
+    case_sensitive: &'a CaseSensitivePatterns,
+    haystack: TokenizedHaystack<'b>,
+    pos: usize,
+    state_id: usize,
+    match_index: usize,
+    leftmost: bool,
+    overlapping: bool,
+}
+
+impl<'a, 'b> Iterator for FindCheckedIter<'a, 'b> {
+    type Item = Match;
+
+    fn next(&mut self) -> Option<Match> {
+        let tokens = &self.haystack.tokens;
+        loop {
+            let m = if self.leftmost {
+                let m = self.fsm.checked_leftmost_find_at(tokens, self.pos)?;
+                self.pos = core::cmp::max(m.end(), self.pos + 1);
+                m
+            } else if self.overlapping {
+                let m = self.fsm.checked_overlapping_find_at(
+                    tokens,
+                    self.pos,
+                    &mut self.state_id,
+                    &mut self.match_index,
+                )?;
+                self.pos = m.end();
+                m
+            } else {
+                // the state is only carried past a match that is skipped
+                let m = self
+                    .fsm
+                    .checked_find_at(tokens, self.pos, &mut self.state_id)?;
                 self.pos = m.end();
+                m
+            };
 
-                let start_idx = self.word_char_idx_map[m.end - m.len];
-                let end_idx = self.word_char_idx_map[m.end - 1];
+            // the offsets of a match of no tokens, or of more tokens than
+            // come before its end, can't be looked up
+            if m.len == 0 || m.len > m.end || m.end > tokens.len() {
+                return None;
+            }
+            let m = if self.overlapping {
+                Some(m).filter(|m| self.haystack.matches_case(self.case_sensitive, m))
+            } else {
+                accepted_match(
+                    self.fsm,
+                    &self.haystack,
+                    self.case_sensitive,
+                    self.state_id,
+                    m,
+                )
+            };
+            if let Some(m) = m {
+                if !self.overlapping {
+                    self.state_id = self.fsm.start_state();
+                }
+                return Some(self.haystack.to_haystack_offsets(m));
+            }
+        }
+    }
+}
+
+/// Returns `m`, the first match of the state `state_id`, unless it is of a
+/// case-sensitive pattern in another case, in which case this returns the
+/// first of the other matches of the state that isn't, if any.
+///
+/// This lets a search that reports the first match state it reaches skip a
+/// match without skipping the matches found along with it.
+fn accepted_match(
+    fsm: &AnyAutomaton,
+    haystack: &TokenizedHaystack<'_>,
+    case_sensitive: &CaseSensitivePatterns,
+    state_id: usize,
+    m: Match,
+) -> Option<Match> {
+    if haystack.matches_case(case_sensitive, &m) {
+        return Some(m);
+    }
+    (1..fsm.match_count(state_id))
+        .filter_map(|match_index| fsm.get_match(state_id, match_index, m.end))
+        .find(|m| haystack.matches_case(case_sensitive, m))
+}
+
+/// A search configured by `SimpleFinderBuilder::overlapping` and
+/// `SimpleFinderBuilder::match_kind`.
+pub(crate) enum Matches<'a, 'b> {
+    Overlapping(FindOverlappingIter<'a, 'b>),
+    NonOverlapping(FindIter<'a, 'b>),
+    Leftmost(FindLeftmostIter<'a, 'b>),
+    Checked(FindCheckedIter<'a, 'b>),
+}
+
+impl<'a, 'b> Iterator for Matches<'a, 'b> {
+    type Item = Match;
+
+    fn next(&mut self) -> Option<Match> {
+        match self {
+            Matches::Overlapping(it) => it.next(),
+            Matches::NonOverlapping(it) => it.next(),
+            Matches::Leftmost(it) => it.next(),
+            Matches::Checked(it) => it.next(),
+        }
+    }
+}
+
+/// Splits each pattern into normalized tokens, in the same way as every
+/// haystack will be.
+///
+/// Patterns without any tokens are rejected, as a match of no tokens has no
+/// span in the haystack to report.
+fn tokenize_patterns<'p, I>(
+    patterns: I,
+    tokenizer: &SharedTokenizer,
+    normalizer: &Normalizer,
+    options: NfaOptions,
+) -> Result<Vec<Vec<Cow<'p, str>>>, BuildError>
+where
+    I: IntoIterator<Item = &'p str>,
+{
+    let patterns: Vec<Vec<Cow<'p, str>>> = patterns
+        .into_iter()
+        .map(|p| {
+            let normalized = normalizer.normalize_text(p);
+            match normalized.unchanged() {
+                Some(p) => tokenizer
+                    .tokenize(p)
+                    .map(|(_, t)| normalizer.normalize(t))
+                    .filter(|t| normalizer.keeps(t))
+                    .collect(),
+                None => tokenizer
+                    .tokenize(normalized.as_str())
+                    .map(|(_, t)| Cow::Owned(normalizer.normalize(t).into_owned()))
+                    .filter(|t| normalizer.keeps(t))
+                    .collect(),
+            }
+        })
+        .collect();
 
-                let len = end_idx - start_idx;
-                m.len = len as usize;
-                m.end = end_idx as usize;
-                Some(m)
+    for (i, pattern) in patterns.iter().enumerate() {
+        if pattern.is_empty() {
+            return Err(BuildError::empty_pattern(i));
+        }
+        if options.reject_symbol_only && !pattern.iter().any(|t| tokenizer.is_word(t)) {
+            return Err(BuildError::symbol_only_pattern(i));
+        }
+        match options.max_match_tokens {
+            Some(max) if pattern.len() > max => {
+                return Err(BuildError::pattern_too_long(i, pattern.len(), max));
             }
+            _ => {}
         }
     }
+    Ok(patterns)
 }
 
-pub(crate) fn build_aho_corasick<'p, I>(patterns: I) -> AhoCorasick
+pub(crate) fn build_aho_corasick<'p, I>(
+    patterns: I,
+    tokenizer: SharedTokenizer,
+    normalizer: Normalizer,
+    options: NfaOptions,
+) -> Result<AhoCorasick, BuildError>
 where
     I: IntoIterator<Item = &'p str>,
 {
-    AhoCorasick {
-        imp: nfa::build_nfa(patterns).unwrap(),
+    let patterns = tokenize_patterns(patterns, &tokenizer, &normalizer, options)?;
+    let imp = AnyAutomaton::build(&patterns, options)?;
+
+    Ok(AhoCorasick {
+        imp,
+        tokenizer,
+        normalizer,
+        options,
+        case_sensitive: CaseSensitivePatterns::default(),
+    })
+}
+
+/// Builds an automaton from the parts returned by `AhoCorasick::into_parts`,
+/// which must have been built with the same tokenizer, normalizer, and
+/// match kind to find the same matches.
+pub(crate) fn aho_corasick_from_parts(
+    parts: AutomatonParts,
+    tokenizer: SharedTokenizer,
+    normalizer: Normalizer,
+    options: NfaOptions,
+) -> Result<AhoCorasick, BuildError> {
+    let imp = AnyAutomaton::from_parts(parts, options)?;
+
+    Ok(AhoCorasick {
+        imp,
+        tokenizer,
+        normalizer,
+        options,
+        case_sensitive: CaseSensitivePatterns::default(),
+    })
+}
+
+/// Builds an automaton from the patterns with their tokens reversed, to be
+/// searched with `AhoCorasick::find_reverse_iter`.
+pub(crate) fn build_aho_corasick_reverse<'p, I>(
+    patterns: I,
+    tokenizer: SharedTokenizer,
+    normalizer: Normalizer,
+    options: NfaOptions,
+) -> Result<AhoCorasick, BuildError>
+where
+    I: IntoIterator<Item = &'p str>,
+{
+    let mut patterns = tokenize_patterns(patterns, &tokenizer, &normalizer, options)?;
+    for pattern in &mut patterns {
+        pattern.reverse();
     }
+    let imp = AnyAutomaton::build(&patterns, options)?;
+
+    Ok(AhoCorasick {
+        imp,
+        tokenizer,
+        normalizer,
+        options,
+        case_sensitive: CaseSensitivePatterns::default(),
+    })
+}
+
+pub(crate) fn build_aho_corasick_with_state_id<'p, I, S: StateID>(
+    patterns: I,
+    tokenizer: SharedTokenizer,
+    normalizer: Normalizer,
+    options: NfaOptions,
+) -> Result<AhoCorasick, BuildError>
+where
+    I: IntoIterator<Item = &'p str>,
+{
+    let patterns = tokenize_patterns(patterns, &tokenizer, &normalizer, options)?;
+    let imp = AnyAutomaton::build_with_state_id::<S>(&patterns, options)?;
+
+    Ok(AhoCorasick {
+        imp,
+        tokenizer,
+        normalizer,
+        options,
+        case_sensitive: CaseSensitivePatterns::default(),
+    })
 }
@@ -1,4 +1,44 @@
-use crate::{Match, state_id::{StateID, fail_id}};
+use alloc::borrow::{Cow, ToOwned};
+#[cfg(feature = "trace")]
+use alloc::vec::Vec;
+
+use crate::{
+    Match,
+    nfa::{Token, TokenID},
+    prefilter::Prefilter,
+    state_id::{StateID, dead_id, fail_id},
+};
+
+/// The tokens of a haystack, read one at a time.
+///
+/// Unlike an `Iterator`, each token only lives until the next is read, so a
+/// source can normalize every token into the same buffer.
+pub(crate) trait TokenSource<T: ?Sized> {
+    fn next_token(&mut self) -> Option<&T>;
+}
+
+/// A `TokenSource` of the tokens yielded by an iterator.
+pub(crate) struct IterTokens<'t, T: ?Sized + ToOwned, I> {
+    tokens: I,
+    current: Option<Cow<'t, T>>,
+}
+
+impl<'t, T: ?Sized + ToOwned, I: Iterator<Item = Cow<'t, T>>> IterTokens<'t, T, I> {
+    pub(crate) fn new(tokens: I) -> Self {
+        IterTokens { tokens, current: None }
+    }
+}
+
+impl<'t, T, I> TokenSource<T> for IterTokens<'t, T, I>
+where
+    T: ?Sized + ToOwned,
+    I: Iterator<Item = Cow<'t, T>>,
+{
+    fn next_token(&mut self) -> Option<&T> {
+        self.current = self.tokens.next();
+        self.current.as_deref()
+    }
+}
 
 pub(crate) trait Automaton {
     /// The representation used for state identifiers in this automaton.
@@ -6,6 +46,9 @@ pub(crate) trait Automaton {
     /// Typically, this is one of `u8`, `u16`, `u32`, `u64` or `usize`.
     type ID: StateID;
 
+    /// The type of the tokens that haystacks are split into.
+    type Token: Token + ?Sized;
+
     /// Return the identifier of this automaton's start state.
     fn start_state(&self) -> Self::ID;
 
@@ -42,8 +85,35 @@ pub(crate) trait Automaton {
     /// The state ID given must be valid, or else implementors must panic.
     fn match_count(&self, id: Self::ID) -> usize;
 
-    /// Given the current state that this automaton is in and the next input
-    /// byte, this method returns the identifier of the next state. The
+    /// Returns the identifier that transitions on `token` are labelled with.
+    ///
+    /// Tokens that don't appear in any pattern all share an identifier that
+    /// no state has a transition on.
+    fn token_id(&self, token: &Self::Token) -> TokenID;
+
+    /// Returns the prefilter used to skip tokens in the start state, if any.
+    fn prefilter(&self) -> Option<&Prefilter>;
+
+    /// Returns the state reached from the start state on `token`.
+    ///
+    /// This is the same state as `next_state_unchecked` reaches from the
+    /// start state on the token's identifier, but implementors may find it
+    /// without looking up the identifier first.
+    fn start_next_state(&self, token: &Self::Token) -> Self::ID;
+
+    /// Returns true if the given token can be skipped in the given state
+    /// without changing state, as it certainly doesn't start a pattern.
+    #[inline(always)]
+    fn skippable(&self, state_id: Self::ID, token: &Self::Token) -> bool {
+        match self.prefilter() {
+            Some(prefilter) => state_id == self.start_state() && !prefilter.may_start(token.as_bytes()),
+            None => false,
+        }
+    }
+
+    /// Given the current state that this automaton is in and the identifier
+    /// of the next input token, this method returns the identifier of the
+    /// next state. The
     /// identifier returned must always be valid and may never correspond to
     /// the fail state. The returned identifier may, however, point to the
     /// dead state.
@@ -52,19 +122,50 @@ pub(crate) trait Automaton {
     /// without memory safety checks such as bounds checks. As such, callers
     /// must ensure that the given identifier corresponds to a valid automaton
     /// state. Implementors must, in turn, ensure that this routine is safe for
-    /// all valid state identifiers and for all possible `u8` values.
+    /// all valid state identifiers and for all possible token identifiers.
     unsafe fn next_state_unchecked(
         &self,
         current: Self::ID,
-        input: &str,
+        input: TokenID,
     ) -> Self::ID;
 
+    /// Like `next_state_unchecked`, but safe for any state identifier,
+    /// returning `None` if `current` or a state reached from it through its
+    /// failure transitions isn't valid.
+    fn next_state_checked(&self, current: Self::ID, input: TokenID) -> Option<Self::ID>;
+
+    /// Like `next_state_unchecked`, but returns `None` when neither
+    /// `current` nor any state it fails to has a transition on `input`,
+    /// rather than staying in the start or dead state at the end of its
+    /// failure chain.
+    ///
+    /// The state ID given must be valid, or else implementors must panic.
+    fn next_state_or_none(&self, current: Self::ID, input: TokenID) -> Option<Self::ID>;
+
+    /// Like `next_state_checked`, but pushes each state jumped to through a
+    /// failure transition onto `failures`.
+    ///
+    /// The state ID given must be valid, or else implementors must panic.
+    #[cfg(feature = "trace")]
+    fn traced_next_state(
+        &self,
+        current: Self::ID,
+        input: TokenID,
+        failures: &mut Vec<Self::ID>,
+    ) -> Self::ID;
+
+    /// Returns the number of transitions from the start state to the given
+    /// state.
+    ///
+    /// The state ID given must be valid, or else implementors must panic.
+    fn state_depth(&self, id: Self::ID) -> usize;
+
     /// Like next_state_unchecked, but debug_asserts that the underlying
     /// implementation never returns a `fail_id()` for the next state.
     unsafe fn next_state_unchecked_no_fail(
         &self,
         current: Self::ID,
-        input: &str,
+        input: TokenID,
     ) -> Self::ID {
         let next = self.next_state_unchecked(current, input);
         // We should never see a transition to the failure state.
@@ -75,13 +176,12 @@ pub(crate) trait Automaton {
         next
     }
 
-    // It's important for this to always be inlined. Namely, it's only caller
-    // is standard_find_at, and the inlining should remove the case analysis
-    // for prefilter scanning when there is no prefilter available.
+    // It's important for this to always be inlined, as it's the inner loop of
+    // every search.
     #[inline(always)]
     fn standard_find_at(
         &self,
-        haystack: &[&str],
+        haystack: &[Cow<'_, Self::Token>],
         at: usize,
         state_id: &mut Self::ID,
     ) -> Option<Match> {
@@ -95,7 +195,39 @@ pub(crate) trait Automaton {
         );
 
         for (idx, elem) in haystack[at..].iter().enumerate() {
-            *state_id = unsafe { self.next_state_unchecked_no_fail(*state_id, elem) };
+            if self.skippable(*state_id, elem) {
+                continue;
+            }
+            *state_id = if *state_id == self.start_state() {
+                self.start_next_state(elem)
+            } else {
+                let token = self.token_id(elem);
+                unsafe { self.next_state_unchecked_no_fail(*state_id, token) }
+            };
+            if let Some(m) = self.get_match(*state_id, 0, idx + at + 1) {
+                return Some(m);
+            }
+        }
+        None
+    }
+
+    /// Like `standard_find_at`, but only looks up states with
+    /// `next_state_checked`, returning `None` rather than panicking if
+    /// `state_id` or a state reached from it isn't valid, or if `at` is past
+    /// the end of the haystack.
+    #[inline(always)]
+    fn checked_find_at(
+        &self,
+        haystack: &[Cow<'_, Self::Token>],
+        at: usize,
+        state_id: &mut Self::ID,
+    ) -> Option<Match> {
+        for (idx, elem) in haystack.get(at..)?.iter().enumerate() {
+            if self.skippable(*state_id, elem) {
+                continue;
+            }
+            let token = self.token_id(elem);
+            *state_id = self.next_state_checked(*state_id, token)?;
             if let Some(m) = self.get_match(*state_id, 0, idx + at + 1) {
                 return Some(m);
             }
@@ -103,6 +235,130 @@ pub(crate) trait Automaton {
         None
     }
 
+    /// Returns the first match reached in the given tokens, which is the
+    /// earliest ending match, stopping as soon as it's reached.
+    ///
+    /// Unlike the other searches, this consumes tokens lazily, so none of
+    /// the haystack after the first match needs to be tokenized, and each
+    /// token can be dropped once the next is read.
+    #[inline(always)]
+    fn first_match_in<S>(&self, mut tokens: S) -> Option<Match>
+    where
+        S: TokenSource<Self::Token>,
+    {
+        let mut state_id = self.start_state();
+        let mut idx = 0;
+        while let Some(token) = tokens.next_token() {
+            idx += 1;
+            if self.skippable(state_id, token) {
+                continue;
+            }
+            state_id = if state_id == self.start_state() {
+                self.start_next_state(token)
+            } else {
+                let token = self.token_id(token);
+                unsafe { self.next_state_unchecked_no_fail(state_id, token) }
+            };
+            if self.is_match_state(state_id) {
+                return self.get_match(state_id, 0, idx);
+            }
+        }
+        None
+    }
+
+    /// Returns the first match accepted by `accept` spanning all of the
+    /// given tokens, if they are exactly the tokens of a pattern.
+    ///
+    /// This only follows transitions from the start state, stopping as soon
+    /// as a token has no transition, rather than following failure
+    /// transitions to look for matches starting later.
+    #[inline(always)]
+    fn anchored_find<F>(&self, haystack: &[Cow<'_, Self::Token>], accept: F) -> Option<Match>
+    where
+        F: FnMut(&Match) -> bool,
+    {
+        let mut state_id = self.start_state();
+        for (depth, elem) in haystack.iter().enumerate() {
+            let token = self.token_id(elem);
+            // a state any shallower was reached through a failure
+            // transition, and so by a match starting after the first token
+            state_id = match self.next_state_or_none(state_id, token) {
+                Some(next) if self.state_depth(next) == depth + 1 => next,
+                _ => return None,
+            };
+        }
+        // the matches of patterns ending in a state come before those
+        // copied from its suffixes
+        (0..self.match_count(state_id))
+            .filter_map(|match_index| self.get_match(state_id, match_index, haystack.len()))
+            .take_while(|m| m.len == haystack.len())
+            .find(accept)
+    }
+
+    /// Execute a leftmost search starting at the token `at`, returning the
+    /// leftmost match. Of the matches starting at the leftmost position, this
+    /// is the longest or the earliest added pattern, depending on whether the
+    /// automaton was compiled for leftmost-longest or leftmost-first
+    /// semantics.
+    ///
+    /// This only gives meaningful results for automata compiled with a
+    /// leftmost match kind, where the failure transitions out of states that
+    /// follow a match lead to the dead state.
+    #[inline(always)]
+    fn leftmost_find_at(
+        &self,
+        haystack: &[Cow<'_, Self::Token>],
+        at: usize,
+    ) -> Option<Match> {
+        let mut state_id = self.start_state();
+        let mut last_match = self.get_match(state_id, 0, at);
+        for (idx, elem) in haystack[at..].iter().enumerate() {
+            if self.skippable(state_id, elem) {
+                continue;
+            }
+            state_id = if state_id == self.start_state() {
+                self.start_next_state(elem)
+            } else {
+                let token = self.token_id(elem);
+                unsafe { self.next_state_unchecked_no_fail(state_id, token) }
+            };
+            if state_id == dead_id() {
+                return last_match;
+            }
+            if let Some(m) = self.get_match(state_id, 0, idx + at + 1) {
+                last_match = Some(m);
+            }
+        }
+        last_match
+    }
+
+    /// Like `leftmost_find_at`, but only looks up states with
+    /// `next_state_checked`, returning `None` rather than panicking if a
+    /// state isn't valid, or if `at` is past the end of the haystack.
+    #[inline(always)]
+    fn checked_leftmost_find_at(
+        &self,
+        haystack: &[Cow<'_, Self::Token>],
+        at: usize,
+    ) -> Option<Match> {
+        let mut state_id = self.start_state();
+        let mut last_match = self.get_match(state_id, 0, at);
+        for (idx, elem) in haystack.get(at..)?.iter().enumerate() {
+            if self.skippable(state_id, elem) {
+                continue;
+            }
+            let token = self.token_id(elem);
+            state_id = self.next_state_checked(state_id, token)?;
+            if state_id == dead_id() {
+                return last_match;
+            }
+            if let Some(m) = self.get_match(state_id, 0, idx + at + 1) {
+                last_match = Some(m);
+            }
+        }
+        last_match
+    }
+
     /// Execute an overlapping search.
     ///
     /// When executing an overlapping match, the previous state ID in addition
@@ -112,7 +368,7 @@ pub(crate) trait Automaton {
     #[inline(always)]
     fn overlapping_find_at(
         &self,
-        haystack: &[&str],
+        haystack: &[Cow<'_, Self::Token>],
         at: usize,
         state_id: &mut Self::ID,
         match_index: &mut usize,
@@ -140,4 +396,30 @@ pub(crate) trait Automaton {
             }
         }
     }
+
+    /// Like `overlapping_find_at`, but only looks up states with
+    /// `next_state_checked`, returning `None` rather than panicking if
+    /// `state_id` or a state reached from it isn't valid.
+    #[inline(always)]
+    fn checked_overlapping_find_at(
+        &self,
+        haystack: &[Cow<'_, Self::Token>],
+        at: usize,
+        state_id: &mut Self::ID,
+        match_index: &mut usize,
+    ) -> Option<Match> {
+        if !self.is_valid(*state_id) {
+            return None;
+        }
+        if *match_index < self.match_count(*state_id) {
+            let result = self.get_match(*state_id, *match_index, at);
+            *match_index += 1;
+            return result;
+        }
+
+        *match_index = 0;
+        let m = self.checked_find_at(haystack, at, state_id)?;
+        *match_index = 1;
+        Some(m)
+    }
 }
@@ -0,0 +1,271 @@
+use alloc::{borrow::Cow, vec::Vec};
+
+use crate::{
+    automaton::{Automaton, IterTokens},
+    collections::HashMap,
+    error::BuildError,
+    nfa::{AnyAutomaton, NfaOptions},
+    Match, MatchKind,
+};
+
+/// A finder that searches byte strings, such as Latin-1 text, created by
+/// `SimpleFinderBuilder::build_bytes`.
+///
+/// Patterns and haystacks are split into tokens byte by byte: a run of word
+/// bytes, as `is_latin1_word_byte` classifies them, is a word, ASCII
+/// whitespace separates tokens, and every other byte is a symbol of its own.
+/// So word characters of Latin-1, such as `é` (`0xE9`), are part of words,
+/// while other bytes above ASCII, such as `0xA0`, are symbols rather than
+/// whitespace. Case-insensitive finders fold the case of Latin-1 letters.
+/// Text that is valid UTF-8 should be searched as a `str` instead, as each
+/// byte of a multi-byte char is read on its own.
+///
+/// Only the case sensitivity, match kind, overlapping and automaton options
+/// of the builder apply; its tokenizer and the rest of its normalization are
+/// for text.
+///
+/// The offsets of the matches are byte offsets, and `Match::start` and
+/// `Match::end` are the same as `Match::byte_start` and `Match::byte_end`.
+pub struct BytesFinder<D> {
+    pub(crate) imp: AnyAutomaton<[u8]>,
+    pub(crate) match_kind: MatchKind,
+    pub(crate) case_insensitive: bool,
+    pub(crate) data: HashMap<usize, D>,
+    pub(crate) overlapping: bool,
+}
+
+impl<D> BytesFinder<D> {
+    /// Builds a finder of the given patterns, tokenized and folded as its
+    /// haystacks will be.
+    pub(crate) fn new(
+        patterns: Vec<&[u8]>,
+        datas: Vec<D>,
+        case_insensitive: bool,
+        overlapping: bool,
+        options: NfaOptions,
+    ) -> Result<BytesFinder<D>, BuildError> {
+        let patterns: Vec<Vec<Cow<'_, [u8]>>> = patterns
+            .into_iter()
+            .map(|pattern| {
+                tokenize(pattern)
+                    .map(|(_, token)| fold(token, case_insensitive))
+                    .collect()
+            })
+            .collect();
+        for (i, pattern) in patterns.iter().enumerate() {
+            if pattern.is_empty() {
+                return Err(BuildError::empty_pattern(i));
+            }
+            if options.reject_symbol_only && !pattern.iter().any(|t| is_latin1_word_byte(t[0])) {
+                return Err(BuildError::symbol_only_pattern(i));
+            }
+            match options.max_match_tokens {
+                Some(max) if pattern.len() > max => {
+                    return Err(BuildError::pattern_too_long(i, pattern.len(), max));
+                }
+                _ => {}
+            }
+        }
+
+        let imp = AnyAutomaton::build(&patterns, options)?;
+        Ok(BytesFinder {
+            data: (0..imp.pattern_count()).zip(datas).collect(),
+            imp,
+            match_kind: options.match_kind,
+            case_insensitive,
+            overlapping,
+        })
+    }
+
+    /// Find all matches in `haystack`.
+    ///
+    /// Unless the finder was built with `SimpleFinderBuilder::overlapping`
+    /// disabled, or with a leftmost `MatchKind`, this reports overlapping
+    /// matches, in the same order as `SimpleFinder::find_all`.
+    ///
+    /// ```
+    /// use lacbd::SimpleFinderBuilder;
+    ///
+    /// let finder = SimpleFinderBuilder::new()
+    ///     .case_insensitive(true)
+    ///     .build_bytes(vec![(&b"caf\xe9 noir"[..], 0)]);
+    /// let matches: Vec<_> = finder.find_all(b"un CAF\xc9 noir").collect();
+    /// assert_eq!(matches[0].0.byte_start(), 3);
+    /// assert_eq!(matches[0].0.byte_end(), 12);
+    /// ```
+    pub fn find_all<'a: 'b, 'b>(
+        &'a self,
+        haystack: &'b [u8],
+    ) -> impl Iterator<Item = (Match, &'a D)> + 'b {
+        let haystack = BytesHaystack::new(haystack, self.case_insensitive);
+        let search = if self.match_kind.is_leftmost() {
+            Search::Leftmost
+        } else if self.overlapping {
+            Search::Overlapping {
+                state_id: self.imp.start_state(),
+                match_index: 0,
+            }
+        } else {
+            Search::NonOverlapping
+        };
+        BytesMatches {
+            imp: &self.imp,
+            haystack,
+            pos: 0,
+            search,
+        }
+        .filter_map(move |m| {
+            let data = self.data.get(&m.pattern)?;
+
+            Some((m, data))
+        })
+    }
+
+    /// Returns true if any pattern matches in `haystack`.
+    ///
+    /// This stops tokenizing the haystack at the first match state reached.
+    pub fn is_match(&self, haystack: &[u8]) -> bool {
+        let tokens = tokenize(haystack).map(|(_, token)| fold(token, self.case_insensitive));
+        self.imp.first_match_in(IterTokens::new(tokens)).is_some()
+    }
+
+    /// Returns the number of patterns that the finder was built from.
+    pub fn pattern_count(&self) -> usize {
+        self.imp.pattern_count()
+    }
+
+    /// Returns the approximate amount of heap memory used by the finder's
+    /// automaton, in bytes.
+    pub fn heap_bytes(&self) -> usize {
+        self.imp.heap_bytes()
+    }
+}
+
+/// Returns true if `b` is a word character of Latin-1, using the same
+/// definition of `\w` as `is_word_character` does for the char with the
+/// value of `b`.
+pub(crate) fn is_latin1_word_byte(b: u8) -> bool {
+    matches!(
+        b,
+        b'_' | b'0'..=b'9'
+            | b'a'..=b'z'
+            | b'A'..=b'Z'
+            | 0xAA
+            | 0xB5
+            | 0xBA
+            | 0xC0..=0xD6
+            | 0xD8..=0xF6
+            | 0xF8..=0xFF
+    )
+}
+
+/// Splits `bytes` into tokens, along with the offset of each.
+///
+/// A run of word bytes is a word, and every other byte is a symbol of its
+/// own, apart from ASCII whitespace, which only separates tokens.
+fn tokenize(bytes: &[u8]) -> impl Iterator<Item = (usize, &[u8])> {
+    let mut pos = 0;
+    core::iter::from_fn(move || {
+        while bytes.get(pos)?.is_ascii_whitespace() {
+            pos += 1;
+        }
+        let start = pos;
+        pos += if is_latin1_word_byte(bytes[pos]) {
+            bytes[pos..]
+                .iter()
+                .take_while(|&&b| is_latin1_word_byte(b))
+                .count()
+        } else {
+            1
+        };
+        Some((start, &bytes[start..pos]))
+    })
+}
+
+/// Lowercases the letters of `token` as Latin-1, if `case_insensitive`.
+fn fold(token: &[u8], case_insensitive: bool) -> Cow<'_, [u8]> {
+    let is_upper = |b: u8| b.is_ascii_uppercase() || matches!(b, 0xC0..=0xDE if b != 0xD7);
+    if !case_insensitive || !token.iter().any(|&b| is_upper(b)) {
+        return Cow::Borrowed(token);
+    }
+    Cow::Owned(
+        token
+            .iter()
+            .map(|&b| if is_upper(b) { b + 0x20 } else { b })
+            .collect(),
+    )
+}
+
+/// A byte string split into folded tokens, along with the offsets spanned
+/// by each token.
+struct BytesHaystack<'b> {
+    tokens: Vec<Cow<'b, [u8]>>,
+    spans: Vec<(usize, usize)>,
+}
+
+impl<'b> BytesHaystack<'b> {
+    fn new(bytes: &'b [u8], case_insensitive: bool) -> BytesHaystack<'b> {
+        let (mut tokens, mut spans) = (Vec::new(), Vec::new());
+        for (start, token) in tokenize(bytes) {
+            spans.push((start, start + token.len()));
+            tokens.push(fold(token, case_insensitive));
+        }
+        BytesHaystack { tokens, spans }
+    }
+
+    /// Converts a match reported by the automaton, which is in terms of token
+    /// indices, to one in terms of byte offsets.
+    fn to_byte_offsets(&self, m: Match) -> Match {
+        let start = self.spans[m.end - m.len].0;
+        let end = self.spans[m.end - 1].1;
+        Match {
+            len: end - start,
+            end,
+            byte_len: end - start,
+            byte_end: end,
+            token_len: m.len,
+            token_end: m.end,
+            ..m
+        }
+    }
+}
+
+/// The search that a `BytesFinder` runs, selected as `AhoCorasick::matches`
+/// selects it.
+enum Search {
+    Overlapping { state_id: usize, match_index: usize },
+    NonOverlapping,
+    Leftmost,
+}
+
+/// The matches in a byte string, found as they're asked for.
+struct BytesMatches<'a, 'b> {
+    imp: &'a AnyAutomaton<[u8]>,
+    haystack: BytesHaystack<'b>,
+    pos: usize,
+    search: Search,
+}
+
+impl<'a, 'b> Iterator for BytesMatches<'a, 'b> {
+    type Item = Match;
+
+    fn next(&mut self) -> Option<Match> {
+        let tokens = &self.haystack.tokens;
+        let m = match &mut self.search {
+            Search::Overlapping {
+                state_id,
+                match_index,
+            } => self
+                .imp
+                .overlapping_find_at(tokens, self.pos, state_id, match_index)?,
+            Search::NonOverlapping => {
+                // restart from the start state so that no two matches overlap
+                let mut state_id = self.imp.start_state();
+                self.imp.standard_find_at(tokens, self.pos, &mut state_id)?
+            }
+            Search::Leftmost => self.imp.leftmost_find_at(tokens, self.pos)?,
+        };
+        self.pos = m.end();
+        Some(self.haystack.to_byte_offsets(m))
+    }
+}
@@ -0,0 +1,37 @@
+use alloc::vec::Vec;
+
+use crate::{stream::StreamFinder, Match, SimpleFinder};
+
+/// A search over a haystack that arrives in chunks, such as from a network
+/// stream, created by `SimpleFinder::chunked_search`.
+///
+/// Chunks may be split anywhere, including in the middle of a word; the last
+/// token of each chunk is held back until the next chunk shows whether it
+/// continues. Each match is reported exactly once, including those spanning
+/// a chunk boundary.
+///
+/// Match offsets are char and byte offsets from the start of the stream, not
+/// the start of the chunk. This is a `StreamFinder` under its original name.
+pub struct ChunkedSearch<'a, D> {
+    stream: StreamFinder<'a, D>,
+}
+
+impl<'a, D> ChunkedSearch<'a, D> {
+    pub(crate) fn new(finder: &'a SimpleFinder<D>) -> Self {
+        ChunkedSearch {
+            stream: StreamFinder::new(finder),
+        }
+    }
+
+    /// Feed the next chunk of the stream, returning the matches that it
+    /// completes.
+    pub fn push_chunk(&mut self, chunk: &str) -> Vec<(Match, &'a D)> {
+        self.stream.push(chunk)
+    }
+
+    /// Signal the end of the stream, returning any matches ending in the
+    /// final token.
+    pub fn finish(self) -> Vec<(Match, &'a D)> {
+        self.stream.finish()
+    }
+}
@@ -0,0 +1,10 @@
+// `hashbrown` is what the standard library's maps are built on, so this only
+// changes which type is exposed, not how the maps behave.
+#[cfg(feature = "std")]
+pub(crate) use std::collections::{HashMap, HashSet};
+
+#[cfg(not(feature = "std"))]
+pub(crate) use hashbrown::{HashMap, HashSet};
+
+#[cfg(not(any(feature = "std", feature = "hashbrown")))]
+compile_error!("either the `std` or the `hashbrown` feature must be enabled");
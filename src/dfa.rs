@@ -0,0 +1,529 @@
+use alloc::{boxed::Box, vec, vec::Vec};
+use core::mem::{self, size_of};
+
+use crate::{
+    automaton::Automaton,
+    collections::HashMap,
+    nfa::{
+        self, clone_token_map, tokens_heap_bytes, PatternID, PatternLength, StartTransitions,
+        Token, TokenID, Transitions, NFA, UNKNOWN_TOKEN,
+    },
+    prefilter::Prefilter,
+    state_id::{dead_id, fail_id, StateID},
+    Match,
+};
+#[cfg(feature = "serialize")]
+use crate::{
+    error::SerializeError,
+    nfa::{read_tokens, write_tokens, TokenMap},
+    serialize::{Reader, Writer},
+};
+
+/// An automaton that never follows failure transitions.
+///
+/// The token alphabet is open ended, so states can't hold a transition for
+/// every token. Instead, each state copies the transitions of every state on
+/// its failure chain apart from the start state, and falls back to the start
+/// or dead state at the end of the chain for any other token. So the next
+/// state is always found by looking up the token in the current state, and
+/// then in the state it falls back to.
+pub(crate) struct DFA<S, T: Token + ?Sized = str> {
+    start_id: S,
+    max_pattern_len: usize,
+    pattern_count: usize,
+    heap_bytes: usize,
+    tokens: HashMap<Box<T>, TokenID>,
+    prefilter: Option<Prefilter>,
+    start_transitions: Option<StartTransitions<S, T>>,
+    states: Vec<State<S>>,
+}
+
+impl<S: StateID, T: Token + ?Sized> Clone for DFA<S, T> {
+    fn clone(&self) -> DFA<S, T> {
+        DFA {
+            start_id: self.start_id,
+            max_pattern_len: self.max_pattern_len,
+            pattern_count: self.pattern_count,
+            heap_bytes: self.heap_bytes,
+            tokens: clone_token_map(&self.tokens),
+            prefilter: self.prefilter.clone(),
+            start_transitions: self.start_transitions.clone(),
+            states: self.states.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct State<S> {
+    /// The transitions of this state in the NFA, along with those it would
+    /// reach by following failure transitions as far as its fallback.
+    trans: Transitions<S>,
+    /// The start or dead state, whichever its failure chain ends at, used
+    /// for any token that this state has no transition for. The start and
+    /// dead states fall back to themselves.
+    fallback: S,
+    /// The number of transitions from the start state to this state, which
+    /// tells the transitions of this state apart from inherited ones.
+    depth: usize,
+    matches: Vec<(PatternID, PatternLength)>,
+}
+
+impl<S: StateID> State<S> {
+    fn heap_bytes(&self) -> usize {
+        self.trans.heap_bytes() + self.matches.capacity() * size_of::<(PatternID, PatternLength)>()
+    }
+}
+
+impl<S: StateID, T: Token + ?Sized> DFA<S, T> {
+    /// Converts an NFA to a DFA, resolving every failure chain.
+    pub(crate) fn new(mut nfa: NFA<S, T>) -> DFA<S, T> {
+        let (start, dead) = (nfa.start_id, dead_id());
+
+        // a state fails to a shallower one, whose transitions must be
+        // resolved before its own
+        let mut order: Vec<usize> = (0..nfa.states.len()).collect();
+        order.sort_by_key(|&id| nfa.states[id].depth);
+
+        let mut states: Vec<State<S>> = vec![
+            State {
+                trans: Transitions::Sparse(vec![]),
+                fallback: dead,
+                depth: 0,
+                matches: vec![],
+            };
+            nfa.states.len()
+        ];
+        for id in order {
+            let state = &mut nfa.states[id];
+            let mut trans = mem::replace(&mut state.trans, Transitions::Sparse(vec![]));
+            let fail = &states[state.fail.to_usize()];
+            let fallback = if id == start.to_usize() || id == dead.to_usize() {
+                S::from_usize(id)
+            } else if state.fail == start || state.fail == dead {
+                state.fail
+            } else {
+                // the state it fails to has already copied the transitions of
+                // the rest of the chain
+                for (token, next) in fail.trans.iter() {
+                    if trans.next_state(token) == fail_id() {
+                        trans.set_next_state(token, next);
+                    }
+                }
+                fail.fallback
+            };
+            if let Transitions::Sparse(ref mut sparse) = trans {
+                sparse.shrink_to_fit();
+            }
+            states[id] = State {
+                trans,
+                fallback,
+                depth: state.depth,
+                matches: mem::take(&mut state.matches),
+            };
+        }
+
+        let mut dfa = DFA {
+            start_id: start,
+            max_pattern_len: nfa.max_pattern_len,
+            pattern_count: nfa.pattern_count,
+            heap_bytes: 0,
+            tokens: nfa.tokens,
+            prefilter: nfa.prefilter,
+            // the start state has no failure transitions to copy, so keeps
+            // the same transitions
+            start_transitions: nfa.start_transitions,
+            states,
+        };
+        dfa.heap_bytes = dfa.calculate_heap_bytes();
+        dfa
+    }
+
+    /// Converts this back to an NFA of just the trie of its patterns, with
+    /// no failure transitions, keeping only the transitions and matches of
+    /// each state that it didn't inherit.
+    pub(crate) fn into_trie(self) -> NFA<S, T> {
+        let start_id = self.start_id;
+        let depths: Vec<usize> = self.states.iter().map(|state| state.depth).collect();
+        let states = self
+            .states
+            .into_iter()
+            .map(|state| {
+                let depth = state.depth;
+                let own = |next: S| depths[next.to_usize()] == depth + 1;
+                let trans = match state.trans {
+                    Transitions::Dense(dense) => Transitions::Dense(
+                        dense.into_iter().filter(|&(_, next)| own(next)).collect(),
+                    ),
+                    Transitions::Sparse(sparse) => Transitions::Sparse(
+                        sparse.into_iter().filter(|&(_, next)| own(next)).collect(),
+                    ),
+                };
+                nfa::State {
+                    trans,
+                    fail: start_id,
+                    depth,
+                    matches: state.matches,
+                }
+            })
+            .collect();
+
+        NFA {
+            start_id,
+            max_pattern_len: self.max_pattern_len,
+            pattern_count: self.pattern_count,
+            heap_bytes: 0,
+            tokens: self.tokens,
+            prefilter: None,
+            start_transitions: None,
+            states,
+        }
+    }
+
+    pub(crate) fn heap_bytes(&self) -> usize {
+        self.heap_bytes
+    }
+
+    pub(crate) fn max_pattern_len(&self) -> usize {
+        self.max_pattern_len
+    }
+
+    /// Like the NFA's `follow_failures`, looking the token up in the state
+    /// that `current` falls back to instead of following a chain.
+    ///
+    /// `current` must be a valid state ID.
+    #[inline(always)]
+    unsafe fn follow_fallback(&self, current: S, input: TokenID) -> Result<S, S> {
+        let state = self.states.get_unchecked(current.to_usize());
+        let next = state.trans.next_state(input);
+        if next != fail_id() {
+            return Ok(next);
+        }
+        let fallback = self.states.get_unchecked(state.fallback.to_usize());
+        match fallback.trans.next_state(input) {
+            next if next != fail_id() => Ok(next),
+            _ => Err(state.fallback),
+        }
+    }
+
+    pub(crate) fn pattern_count(&self) -> usize {
+        self.pattern_count
+    }
+
+    pub(crate) fn state_count(&self) -> usize {
+        self.states.len()
+    }
+
+    pub(crate) fn max_out_degree(&self) -> usize {
+        self.states
+            .iter()
+            .map(|state| state.trans.len())
+            .max()
+            .unwrap_or(0)
+    }
+
+    pub(crate) fn dense_state_count(&self) -> usize {
+        self.states
+            .iter()
+            .filter(|state| matches!(state.trans, Transitions::Dense(_)))
+            .count()
+    }
+
+    /// Returns the ID and length of every pattern that can match, as
+    /// `NFA::pattern_lens` does.
+    pub(crate) fn pattern_lens(&self) -> impl Iterator<Item = (PatternID, PatternLength)> + '_ {
+        self.states.iter().flat_map(|state| {
+            state
+                .matches
+                .iter()
+                .copied()
+                .filter(move |&(_, len)| len == state.depth)
+        })
+    }
+
+    /// Returns the tokens that label the transitions out of the start state,
+    /// i.e. the first token of every pattern.
+    pub(crate) fn start_tokens(&self) -> impl Iterator<Item = &T> {
+        let start = &self.states[self.start_id.to_usize()];
+        self.tokens
+            .iter()
+            .filter(move |&(_, &id)| start.trans.next_state(id) != fail_id())
+            .map(|(token, _)| &**token)
+    }
+
+    fn calculate_heap_bytes(&self) -> usize {
+        tokens_heap_bytes(&self.tokens)
+            + self
+                .start_transitions
+                .as_ref()
+                .map_or(0, StartTransitions::heap_bytes)
+            + self.states.capacity() * size_of::<State<S>>()
+            + self.states.iter().map(State::heap_bytes).sum::<usize>()
+    }
+
+    /// Frees the spare capacity left over from building this DFA.
+    pub(crate) fn shrink_to_fit(&mut self) {
+        self.tokens.shrink_to_fit();
+        if let Some(start_transitions) = &mut self.start_transitions {
+            start_transitions.shrink_to_fit();
+        }
+        self.states.shrink_to_fit();
+        for state in &mut self.states {
+            state.trans.shrink_to_fit();
+            state.matches.shrink_to_fit();
+        }
+        self.heap_bytes = self.calculate_heap_bytes();
+    }
+}
+
+impl<S: StateID, T: Token + ?Sized> Automaton for DFA<S, T> {
+    type ID = S;
+    type Token = T;
+
+    fn start_state(&self) -> S {
+        self.start_id
+    }
+
+    fn is_valid(&self, id: S) -> bool {
+        id.to_usize() < self.states.len()
+    }
+
+    fn is_match_state(&self, id: S) -> bool {
+        !self.states[id.to_usize()].matches.is_empty()
+    }
+
+    fn get_match(&self, id: S, match_index: usize, end: usize) -> Option<Match> {
+        let state = self.states.get(id.to_usize())?;
+        state.matches.get(match_index).map(|&(id, len)| Match {
+            pattern: id,
+            len,
+            end,
+            byte_len: len,
+            byte_end: end,
+            token_len: len,
+            token_end: end,
+        })
+    }
+
+    fn match_count(&self, id: S) -> usize {
+        self.states[id.to_usize()].matches.len()
+    }
+
+    fn token_id(&self, token: &T) -> TokenID {
+        self.tokens.get(token).copied().unwrap_or(UNKNOWN_TOKEN)
+    }
+
+    fn prefilter(&self) -> Option<&Prefilter> {
+        self.prefilter.as_ref()
+    }
+
+    fn start_next_state(&self, token: &T) -> S {
+        match &self.start_transitions {
+            Some(start) => start.next_state(token),
+            None => unsafe { self.next_state_unchecked(self.start_id, self.token_id(token)) },
+        }
+    }
+
+    fn next_state_checked(&self, current: S, input: TokenID) -> Option<S> {
+        let state = self.states.get(current.to_usize())?;
+        let next = match state.trans.next_state(input) {
+            next if next != fail_id() => next,
+            _ => match self
+                .states
+                .get(state.fallback.to_usize())?
+                .trans
+                .next_state(input)
+            {
+                next if next != fail_id() => next,
+                _ => state.fallback,
+            },
+        };
+        Some(next).filter(|&next| self.is_valid(next))
+    }
+
+    unsafe fn next_state_unchecked(&self, current: S, input: TokenID) -> S {
+        self.follow_fallback(current, input)
+            .unwrap_or_else(|end| end)
+    }
+
+    fn next_state_or_none(&self, current: S, input: TokenID) -> Option<S> {
+        assert!(
+            self.is_valid(current),
+            "{} is not a valid state ID",
+            current.to_usize()
+        );
+        unsafe { self.follow_fallback(current, input) }.ok()
+    }
+
+    #[cfg(feature = "trace")]
+    fn traced_next_state(&self, current: S, input: TokenID, failures: &mut Vec<S>) -> S {
+        let state = &self.states[current.to_usize()];
+        let next = state.trans.next_state(input);
+        if next != fail_id() {
+            return next;
+        }
+        if state.fallback == current {
+            return current;
+        }
+        failures.push(state.fallback);
+        match self.states[state.fallback.to_usize()]
+            .trans
+            .next_state(input)
+        {
+            next if next != fail_id() => next,
+            _ => state.fallback,
+        }
+    }
+
+    fn state_depth(&self, id: S) -> usize {
+        self.states[id.to_usize()].depth
+    }
+}
+
+#[cfg(feature = "serialize")]
+impl<S: StateID> DFA<S> {
+    pub(crate) fn write_to(&self, w: &mut Writer) {
+        w.write_usize(self.start_id.to_usize());
+        w.write_usize(self.max_pattern_len);
+        w.write_usize(self.pattern_count);
+        w.write_bool(self.prefilter.is_some());
+        write_tokens(w, &self.tokens);
+
+        w.write_usize(self.states.len());
+        for state in &self.states {
+            w.write_usize(state.fallback.to_usize());
+            w.write_usize(state.depth);
+
+            // sorted so that the same automaton always serializes the same
+            let mut trans: Vec<_> = state.trans.iter().collect();
+            if let Transitions::Dense(_) = state.trans {
+                trans.sort();
+            }
+            w.write_bool(matches!(state.trans, Transitions::Dense(_)));
+            w.write_usize(trans.len());
+            for (token, next) in trans {
+                w.write_usize(token as usize);
+                w.write_usize(next.to_usize());
+            }
+
+            w.write_usize(state.matches.len());
+            for &(pattern, len) in &state.matches {
+                w.write_usize(pattern);
+                w.write_usize(len);
+            }
+        }
+    }
+
+    /// Reads a DFA written by `DFA::write_to`, checking that every state ID
+    /// it refers to exists and can be represented by `S`.
+    pub(crate) fn read_from(r: &mut Reader<'_>) -> Result<DFA<S>, SerializeError> {
+        let start_id = r.read_usize()?;
+        let max_pattern_len = r.read_usize()?;
+        let pattern_count = r.read_usize()?;
+        let prefilter = r.read_bool()?;
+        let tokens = read_tokens(r)?;
+
+        // each state is at least its four integers
+        let num_states = r.read_len(32)?;
+        if num_states < 3 || start_id != 2 || num_states - 1 > S::max_id() {
+            return Err(SerializeError::InvalidFormat);
+        }
+        let valid = |id: usize| {
+            if id < num_states {
+                Ok(S::from_usize(id))
+            } else {
+                Err(SerializeError::InvalidFormat)
+            }
+        };
+
+        let mut states = Vec::with_capacity(num_states);
+        for _ in 0..num_states {
+            let fallback = valid(r.read_usize()?)?;
+            let depth = r.read_usize()?;
+
+            let mut trans = if r.read_bool()? {
+                Transitions::Dense(TokenMap::default())
+            } else {
+                Transitions::Sparse(vec![])
+            };
+            let num_trans = r.read_len(16)?;
+            for _ in 0..num_trans {
+                let token = r.read_usize()?;
+                if token >= tokens.len() {
+                    return Err(SerializeError::InvalidFormat);
+                }
+                trans.set_next_state(token as TokenID, valid(r.read_usize()?)?);
+            }
+
+            let num_matches = r.read_len(16)?;
+            let mut matches = Vec::with_capacity(num_matches);
+            for _ in 0..num_matches {
+                let (pattern, len) = (r.read_usize()?, r.read_usize()?);
+                // searches keep no more tokens than the longest pattern spans
+                if pattern >= pattern_count || len > max_pattern_len {
+                    return Err(SerializeError::InvalidFormat);
+                }
+                matches.push((pattern, len));
+            }
+
+            states.push(State {
+                trans,
+                fallback,
+                depth,
+                matches,
+            });
+        }
+
+        // every state must fall back to the start or dead state, which fall
+        // back to themselves, as searches never look further
+        let (dead, start) = (dead_id::<usize>(), start_id);
+        if states[dead].fallback.to_usize() != dead || states[start].fallback.to_usize() != start {
+            return Err(SerializeError::InvalidFormat);
+        }
+        if states
+            .iter()
+            .any(|state| state.fallback.to_usize() != start && state.fallback.to_usize() != dead)
+        {
+            return Err(SerializeError::InvalidFormat);
+        }
+
+        // nor may a match start before the haystack, as it could if a state
+        // were deeper than the tokens consumed to reach it
+        if states[start].depth != 0 || states[dead].depth != 0 {
+            return Err(SerializeError::InvalidFormat);
+        }
+        for state in &states {
+            // inherited transitions lead to shallower states than its own
+            if state
+                .trans
+                .iter()
+                .any(|(_, next)| states[next.to_usize()].depth > state.depth + 1)
+            {
+                return Err(SerializeError::InvalidFormat);
+            }
+            if state
+                .matches
+                .iter()
+                .any(|&(_, len)| len == 0 || len > state.depth)
+            {
+                return Err(SerializeError::InvalidFormat);
+            }
+        }
+
+        let mut dfa = DFA {
+            start_id: S::from_usize(start_id),
+            max_pattern_len,
+            pattern_count,
+            heap_bytes: 0,
+            tokens,
+            prefilter: None,
+            start_transitions: None,
+            states,
+        };
+        if prefilter {
+            dfa.prefilter = Some(Prefilter::new(dfa.start_tokens().map(str::as_bytes)));
+        }
+        dfa.start_transitions = Some(StartTransitions::new(&dfa, &dfa.tokens));
+        dfa.heap_bytes = dfa.calculate_heap_bytes();
+        Ok(dfa)
+    }
+}
@@ -0,0 +1,171 @@
+use core::fmt;
+
+use crate::state_id::StateID;
+
+/// An error that occurred while building a `SimpleFinder`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum BuildError {
+    /// The automaton needed more states than the chosen `StateID`
+    /// representation can identify.
+    StateIdOverflow {
+        /// The largest state identifier the representation supports.
+        max: usize,
+    },
+    /// The patterns contained more distinct tokens than can be identified.
+    TooManyTokens {
+        /// The largest number of distinct tokens supported.
+        max: usize,
+    },
+    /// A pattern contained no tokens, such as an empty or all whitespace
+    /// pattern, so it would match between every pair of tokens.
+    EmptyPattern {
+        /// The identifier of the empty pattern.
+        pattern: usize,
+    },
+    /// A pattern contained only symbols, such as `"@"`, and the finder was
+    /// built with `SimpleFinderBuilder::reject_symbol_only_patterns`.
+    SymbolOnlyPattern {
+        /// The identifier of the pattern without any words.
+        pattern: usize,
+    },
+    /// A pattern contained more tokens than allowed by
+    /// `SimpleFinderBuilder::max_match_tokens`.
+    PatternTooLong {
+        /// The identifier of the pattern that is too long.
+        pattern: usize,
+        /// The number of tokens in the pattern.
+        len: usize,
+        /// The largest number of tokens allowed in a pattern.
+        max: usize,
+    },
+    /// A pattern was case-sensitive in a case-insensitive finder with a
+    /// leftmost `MatchKind`, whose searches can't drop a match of a pattern
+    /// without missing the match it was chosen over.
+    CaseSensitiveLeftmost {
+        /// The identifier of the case-sensitive pattern.
+        pattern: usize,
+    },
+    /// The finders given to `SimpleFinder::try_merge` normalize tokens
+    /// differently, so the patterns of one wouldn't match as they did.
+    NormalizerMismatch,
+    /// The parts given to `SimpleFinderBuilder::try_build_from_parts` don't
+    /// describe a valid automaton.
+    InvalidParts {
+        /// What is wrong with the parts.
+        reason: &'static str,
+    },
+}
+
+impl BuildError {
+    pub(crate) fn state_id_overflow<S: StateID>() -> BuildError {
+        BuildError::StateIdOverflow { max: S::max_id() }
+    }
+
+    pub(crate) fn empty_pattern(pattern: usize) -> BuildError {
+        BuildError::EmptyPattern { pattern }
+    }
+
+    pub(crate) fn symbol_only_pattern(pattern: usize) -> BuildError {
+        BuildError::SymbolOnlyPattern { pattern }
+    }
+
+    pub(crate) fn pattern_too_long(pattern: usize, len: usize, max: usize) -> BuildError {
+        BuildError::PatternTooLong { pattern, len, max }
+    }
+
+    pub(crate) fn case_sensitive_leftmost(pattern: usize) -> BuildError {
+        BuildError::CaseSensitiveLeftmost { pattern }
+    }
+
+    pub(crate) fn invalid_parts(reason: &'static str) -> BuildError {
+        BuildError::InvalidParts { reason }
+    }
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BuildError::StateIdOverflow { max } => write!(
+                f,
+                "building the automaton requires more states than the \
+                 state ID representation supports (max ID {})",
+                max
+            ),
+            BuildError::TooManyTokens { max } => {
+                write!(f, "the patterns contain more than {} distinct tokens", max)
+            }
+            BuildError::EmptyPattern { pattern } => {
+                write!(f, "pattern {} contains no tokens", pattern)
+            }
+            BuildError::SymbolOnlyPattern { pattern } => {
+                write!(f, "pattern {} contains no words", pattern)
+            }
+            BuildError::PatternTooLong { pattern, len, max } => write!(
+                f,
+                "pattern {} contains {} tokens, more than the maximum of {}",
+                pattern, len, max
+            ),
+            BuildError::CaseSensitiveLeftmost { pattern } => write!(
+                f,
+                "pattern {} is case-sensitive, which leftmost match kinds don't support",
+                pattern
+            ),
+            BuildError::NormalizerMismatch => {
+                write!(f, "the finders normalize tokens differently")
+            }
+            BuildError::InvalidParts { reason } => {
+                write!(f, "the automaton parts are invalid: {}", reason)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for BuildError {}
+
+/// An error that occurred while serializing or deserializing a
+/// `SimpleFinder`.
+#[cfg(feature = "serialize")]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum SerializeError {
+    /// The finder splits text with a custom `Tokenizer` or word character
+    /// classifier, which can't be serialized.
+    CustomTokenizer,
+    /// The finder transforms tokens with a function given to
+    /// `SimpleFinderBuilder::token_transform`, which can't be serialized.
+    TokenTransform,
+    /// The bytes don't hold a serialized finder, or were written by an
+    /// incompatible version of this crate.
+    InvalidFormat,
+    /// The data given to `SimpleFinder::from_bytes` isn't one item for each
+    /// pattern of the serialized finder.
+    DataCountMismatch {
+        /// The number of patterns of the serialized finder.
+        patterns: usize,
+        /// The number of items of data given.
+        data: usize,
+    },
+}
+
+#[cfg(feature = "serialize")]
+impl fmt::Display for SerializeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SerializeError::CustomTokenizer => {
+                write!(f, "finders with a custom tokenizer can't be serialized")
+            }
+            SerializeError::TokenTransform => {
+                write!(f, "finders with a token transform can't be serialized")
+            }
+            SerializeError::InvalidFormat => write!(f, "the bytes don't hold a serialized finder"),
+            SerializeError::DataCountMismatch { patterns, data } => write!(
+                f,
+                "{} items of data were given for {} patterns",
+                data, patterns
+            ),
+        }
+    }
+}
+
+#[cfg(all(feature = "serialize", feature = "std"))]
+impl std::error::Error for SerializeError {}
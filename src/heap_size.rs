@@ -0,0 +1,123 @@
+use alloc::{boxed::Box, string::String, vec::Vec};
+use core::mem::size_of;
+
+use crate::collections::HashMap;
+
+/// Reports the heap memory owned by a value, for
+/// `SimpleFinder::heap_bytes_with_data`.
+///
+/// Only memory the value owns through pointers is counted, not the value
+/// itself, which is already accounted for by whatever holds it. Implement
+/// this for your own data types by summing the `heap_bytes` of their fields.
+pub trait HeapSize {
+    /// Returns the number of bytes of heap memory owned by this value.
+    fn heap_bytes(&self) -> usize;
+}
+
+macro_rules! no_heap {
+    ($($ty:ty),*) => {
+        $(
+            impl HeapSize for $ty {
+                fn heap_bytes(&self) -> usize {
+                    0
+                }
+            }
+        )*
+    };
+}
+
+no_heap!(
+    (),
+    bool,
+    char,
+    u8,
+    u16,
+    u32,
+    u64,
+    u128,
+    usize,
+    i8,
+    i16,
+    i32,
+    i64,
+    i128,
+    isize,
+    f32,
+    f64
+);
+
+/// Borrowed data is owned elsewhere, so doesn't count.
+impl<T: ?Sized> HeapSize for &T {
+    fn heap_bytes(&self) -> usize {
+        0
+    }
+}
+
+impl HeapSize for String {
+    fn heap_bytes(&self) -> usize {
+        self.capacity()
+    }
+}
+
+impl HeapSize for Box<str> {
+    fn heap_bytes(&self) -> usize {
+        self.len()
+    }
+}
+
+impl<T: HeapSize> HeapSize for Box<T> {
+    fn heap_bytes(&self) -> usize {
+        size_of::<T>() + (**self).heap_bytes()
+    }
+}
+
+impl<T: HeapSize> HeapSize for Box<[T]> {
+    fn heap_bytes(&self) -> usize {
+        self.len() * size_of::<T>() + self.iter().map(T::heap_bytes).sum::<usize>()
+    }
+}
+
+impl<T: HeapSize> HeapSize for Vec<T> {
+    fn heap_bytes(&self) -> usize {
+        self.capacity() * size_of::<T>() + self.iter().map(T::heap_bytes).sum::<usize>()
+    }
+}
+
+impl<T: HeapSize> HeapSize for Option<T> {
+    fn heap_bytes(&self) -> usize {
+        self.as_ref().map_or(0, T::heap_bytes)
+    }
+}
+
+impl<A: HeapSize, B: HeapSize> HeapSize for (A, B) {
+    fn heap_bytes(&self) -> usize {
+        self.0.heap_bytes() + self.1.heap_bytes()
+    }
+}
+
+impl<K: HeapSize, V: HeapSize, H> HeapSize for HashMap<K, V, H> {
+    fn heap_bytes(&self) -> usize {
+        map_heap_bytes(self)
+            + self
+                .iter()
+                .map(|(k, v)| k.heap_bytes() + v.heap_bytes())
+                .sum::<usize>()
+    }
+}
+
+/// Estimates the memory allocated by a `HashMap`'s table, not counting
+/// anything its keys and values own.
+///
+/// The table has a power of two number of buckets, of which it fills at most
+/// seven eighths, and a control byte per bucket plus a group's worth more.
+pub(crate) fn map_heap_bytes<K, V, H>(map: &HashMap<K, V, H>) -> usize {
+    const GROUP_WIDTH: usize = 16;
+
+    let buckets = match map.capacity() {
+        0 => return 0,
+        capacity if capacity < 4 => 4,
+        capacity if capacity < 8 => 8,
+        capacity => (capacity * 8 / 7).next_power_of_two(),
+    };
+    buckets * size_of::<(K, V)>() + buckets + GROUP_WIDTH
+}
@@ -1,14 +1,90 @@
-use std::collections::{HashMap, HashSet};
+#![no_std]
+
+extern crate alloc;
+#[cfg(any(feature = "std", test))]
+extern crate std;
+
+use alloc::{
+    borrow::Cow,
+    boxed::Box,
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::{
+    convert::TryFrom,
+    fmt,
+    num::TryFromIntError,
+    ops::{ControlFlow, Range},
+};
+
+use collections::{HashMap, HashSet};
+use nfa::NfaOptions;
+use normalize::Normalizer;
+use tokenizer::SharedTokenizer;
+
+pub use bytes::BytesFinder;
+pub use chunked::ChunkedSearch;
+pub use error::BuildError;
+#[cfg(feature = "serialize")]
+pub use error::SerializeError;
+pub use heap_size::HeapSize;
+pub use normalize::Normalization;
+pub use parts::{AutomatonParts, StateParts};
+pub use reverse::ReverseFinder;
+pub use state_id::StateID;
+pub use stream::StreamFinder;
+pub use tokenizer::{DefaultTokenizer, Tokenizer};
+#[cfg(feature = "trace")]
+pub use trace::TraceStep;
+pub use word_split_trait::{
+    is_word_character, UnicodeWordsAndSyms, UnicodeWordsAndSymsIndices,
+    UnicodeWordsSymsAndSpacesIndices, WordBoundarySplitter,
+};
 
 mod ahocorasick;
 mod automaton;
+mod bytes;
+mod chunked;
+mod collections;
+mod dfa;
+mod error;
+mod heap_size;
 mod nfa;
+mod normalize;
+mod parts;
+mod prefilter;
+mod reverse;
+#[cfg(feature = "serialize")]
+mod serialize;
 mod state_id;
-mod word_split_trait;
+mod stream;
+mod tokenizer;
+#[cfg(feature = "trace")]
+mod trace;
 mod unicode_tables;
+mod word_split_trait;
 
-
+/// A match of a pattern in a haystack.
+///
+/// The span of a match is available in three coordinate systems:
+///
+/// * char offsets, through `start` and `end`, counting the chars of the
+///   haystack before each end of the match.
+/// * byte offsets, through `byte_start` and `byte_end`. Only these can be
+///   used to slice the haystack.
+/// * token indices, through `token_start` and `token_end`, counting the
+///   tokens of the haystack before each end of the match, so that a match of
+///   the third to fifth tokens spans `2..5`.
+///
+/// With the `serde` feature enabled, a match is serialized with its `start`,
+/// `end`, `byte_start`, `byte_end`, `token_start`, and `token_end` offsets,
+/// and deserializing checks that each span doesn't end before it starts.
 #[derive(Clone, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(into = "MatchRepr", try_from = "MatchRepr")
+)]
 pub struct Match {
     /// The pattern id.
     pattern: usize,
@@ -22,6 +98,14 @@ pub struct Match {
     len: usize,
     /// The end offset of the match, exclusive.
     end: usize,
+    /// The length of this match in bytes.
+    byte_len: usize,
+    /// The end offset of the match in bytes, exclusive.
+    byte_end: usize,
+    /// The number of tokens spanned by this match.
+    token_len: usize,
+    /// The index of the token after the match.
+    token_end: usize,
 }
 
 impl Match {
@@ -36,180 +120,6065 @@ impl Match {
         self.pattern
     }
 
-    /// The starting position of the match.
+    /// The starting position of the match, as a char offset.
+    ///
+    /// This counts grapheme clusters instead of chars when the finder was
+    /// built with `SimpleFinderBuilder::count_graphemes`.
     #[inline]
     pub fn start(&self) -> usize {
         self.end - self.len
     }
 
-    /// The ending position of the match.
+    /// The ending position of the match, as a char offset.
     #[inline]
     pub fn end(&self) -> usize {
         self.end
     }
 
+    /// The starting position of the match, as a byte offset.
+    #[inline]
+    pub fn byte_start(&self) -> usize {
+        self.byte_end - self.byte_len
+    }
+
+    /// The ending position of the match, as a byte offset.
+    #[inline]
+    pub fn byte_end(&self) -> usize {
+        self.byte_end
+    }
+
+    /// The index of the first token of the match.
+    ///
+    /// Tokens dropped as stop words, set with
+    /// `SimpleFinderBuilder::stop_words`, aren't counted.
+    #[inline]
+    pub fn token_start(&self) -> usize {
+        self.token_end - self.token_len
+    }
+
+    /// The index of the token after the match, so that the match spans the
+    /// tokens `token_start()..token_end()`.
+    #[inline]
+    pub fn token_end(&self) -> usize {
+        self.token_end
+    }
+
     /// Returns true if and only if this match is empty. That is, when
     /// `start() == end()`.
     ///
-    /// An empty match can only be returned when the empty string was among
-    /// the patterns used to build the Aho-Corasick automaton.
+    /// Patterns without any tokens are rejected with
+    /// `BuildError::EmptyPattern`, so matches are only empty when a custom
+    /// `Tokenizer` yields empty tokens.
     #[inline]
     pub fn is_empty(&self) -> bool {
         self.len == 0
     }
+
+    /// Wraps this match so that it compares and hashes by its pattern alone,
+    /// for collecting the distinct patterns that matched.
+    #[inline]
+    pub fn by_pattern(self) -> PatternMatch {
+        PatternMatch(self)
+    }
 }
 
-pub struct SimpleFinder<D> {
-    aho: ahocorasick::AhoCorasick,
-    data: HashMap<usize, D>,
+/// A `Match` that is equal to any other match of the same pattern, wherever
+/// it is, created by `Match::by_pattern`.
+///
+/// Collecting these into a `HashSet` keeps the first match of each pattern.
+/// The set can be queried by pattern id too, as a `PatternMatch` borrows as
+/// its pattern's id.
+///
+/// ```
+/// use lacbd::SimpleFinder;
+/// use std::collections::HashSet;
+///
+/// let finder = SimpleFinder::new(vec![("foo", ()), ("bar", ())]);
+/// let matched: HashSet<_> = finder
+///     .find_all("foo bar foo")
+///     .map(|(m, _)| m.by_pattern())
+///     .collect();
+///
+/// assert_eq!(matched.len(), 2);
+/// assert_eq!(matched.get(&0).unwrap().0.start(), 0);
+/// ```
+#[derive(Clone, Debug)]
+pub struct PatternMatch(pub Match);
+
+impl PartialEq for PatternMatch {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.pattern == other.0.pattern
+    }
 }
 
-pub struct SimpleFinderIter<'a, 'b, D> {
-    finder: &'a SimpleFinder<D>,
-    iter: ahocorasick::FindOverlappingIter<'a, 'b, usize>,
+impl Eq for PatternMatch {}
+
+impl core::hash::Hash for PatternMatch {
+    fn hash<H: core::hash::Hasher>(&self, state: &mut H) {
+        self.0.pattern.hash(state);
+    }
 }
 
-impl<'a, 'b, D> Iterator for SimpleFinderIter<'a, 'b, D> {
-    type Item = (Match, &'a D);
+impl core::borrow::Borrow<usize> for PatternMatch {
+    fn borrow(&self) -> &usize {
+        &self.0.pattern
+    }
+}
 
-    fn next(&mut self) -> Option<Self::Item> {
-        let next = self.iter.next()?;
-        let data = &self.finder.data.get(&next.pattern)?;
+/// The serialized form of a `Match`, which uses offsets rather than lengths
+/// so that it reads naturally as JSON.
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct MatchRepr {
+    pattern: usize,
+    start: usize,
+    end: usize,
+    byte_start: usize,
+    byte_end: usize,
+    /// Missing from matches serialized before token indices were added.
+    #[serde(default)]
+    token_start: usize,
+    #[serde(default)]
+    token_end: usize,
+}
 
-        Some((next, data))
+#[cfg(feature = "serde")]
+impl From<Match> for MatchRepr {
+    fn from(m: Match) -> Self {
+        MatchRepr {
+            pattern: m.pattern(),
+            start: m.start(),
+            end: m.end(),
+            byte_start: m.byte_start(),
+            byte_end: m.byte_end(),
+            token_start: m.token_start(),
+            token_end: m.token_end(),
+        }
     }
 }
 
-impl<D> SimpleFinder<D> {
-    pub fn new<'p, I>(patterns: I) -> Self
-    where
-        I: IntoIterator<Item = (&'p str, D)>,
-    {
-        let (patterns, datas): (Vec<_>, Vec<_>) = patterns.into_iter().unzip();
+#[cfg(feature = "serde")]
+impl core::convert::TryFrom<MatchRepr> for Match {
+    type Error = String;
 
-        let aho = ahocorasick::build_aho_corasick(patterns);
+    fn try_from(m: MatchRepr) -> Result<Self, Self::Error> {
+        if m.end < m.start || m.byte_end < m.byte_start || m.token_end < m.token_start {
+            return Err(alloc::format!(
+                "match span {}..{} (bytes {}..{}, tokens {}..{}) ends before it starts",
+                m.start,
+                m.end,
+                m.byte_start,
+                m.byte_end,
+                m.token_start,
+                m.token_end
+            ));
+        }
 
-        let data = (0..aho.pattern_count()).zip(datas.into_iter()).collect();
+        Ok(Match {
+            pattern: m.pattern,
+            len: m.end - m.start,
+            end: m.end,
+            byte_len: m.byte_end - m.byte_start,
+            byte_end: m.byte_end,
+            token_len: m.token_end - m.token_start,
+            token_end: m.token_end,
+        })
+    }
+}
+
+/// A compact form of `Match` that uses `u32` fields, taking 12 bytes rather
+/// than 24 on 64-bit targets.
+///
+/// Offsets are char offsets, as with `Match`, so this can only represent
+/// matches that end within the first `u32::MAX` chars of a haystack, of
+/// patterns whose ids fit in a `u32`. Converting any other `Match` fails.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct CompactMatch {
+    /// The pattern id.
+    pattern: u32,
+    /// The starting position of the match.
+    start: u32,
+    /// The end offset of the match, exclusive.
+    end: u32,
+}
+
+impl CompactMatch {
+    /// Returns the identifier of the pattern that matched.
+    #[inline]
+    pub fn pattern(&self) -> usize {
+        self.pattern as usize
+    }
 
-        SimpleFinder { aho, data }
+    /// The starting position of the match.
+    #[inline]
+    pub fn start(&self) -> usize {
+        self.start as usize
     }
 
-    pub fn find_all<'a: 'b, 'b>(&'a self, haystack: &'b str) -> SimpleFinderIter<'a, 'b, D> {
-        SimpleFinderIter {
-            finder: self,
-            iter: self.aho.find_overlapping_iter(haystack),
+    /// The ending position of the match.
+    #[inline]
+    pub fn end(&self) -> usize {
+        self.end as usize
+    }
+
+    /// Expands this into a full `Match`, recovering its byte offsets from the
+    /// haystack it was found in.
+    ///
+    /// This counts the chars of the haystack up to the end of the match, so
+    /// it takes time proportional to the match's offset. Use
+    /// `CompactMatch::to_matches` to expand many matches of a haystack in
+    /// one pass. Offsets past the end of the haystack, which a match found
+    /// in another haystack may have, are taken to be its end.
+    ///
+    /// A compact match doesn't keep the token indices of the match, so
+    /// `token_start` and `token_end` of the returned match are both zero.
+    pub fn to_match(&self, haystack: &str) -> Match {
+        let byte_offset = |char_idx| {
+            haystack
+                .char_indices()
+                .map(|(idx, _)| idx)
+                .chain(Some(haystack.len()))
+                .nth(char_idx)
+                .unwrap_or(haystack.len())
+        };
+        self.with_byte_offsets(byte_offset(self.start()), byte_offset(self.end()))
+    }
+
+    /// Expands each of `matches` into a full `Match`, as `to_match` does,
+    /// but counting the chars of `haystack` only once for all of them.
+    pub fn to_matches(matches: &[CompactMatch], haystack: &str) -> Vec<Match> {
+        // the char offsets are visited in order, so that each is found by
+        // carrying on from the one before
+        let mut char_offsets: Vec<usize> =
+            matches.iter().flat_map(|m| [m.start(), m.end()]).collect();
+        char_offsets.sort_unstable();
+        char_offsets.dedup();
+
+        let mut chars = haystack
+            .char_indices()
+            .map(|(idx, _)| idx)
+            .chain(Some(haystack.len()))
+            .enumerate();
+        let byte_offsets: Vec<usize> = char_offsets
+            .iter()
+            .map(|&char_idx| {
+                chars
+                    .find(|&(idx, _)| idx == char_idx)
+                    .map_or(haystack.len(), |(_, byte_idx)| byte_idx)
+            })
+            .collect();
+        // every offset of the matches was looked up
+        let byte_offset = |char_idx| byte_offsets[char_offsets.binary_search(&char_idx).unwrap()];
+
+        matches
+            .iter()
+            .map(|m| m.with_byte_offsets(byte_offset(m.start()), byte_offset(m.end())))
+            .collect()
+    }
+
+    fn with_byte_offsets(&self, byte_start: usize, byte_end: usize) -> Match {
+        Match {
+            pattern: self.pattern(),
+            len: self.end() - self.start(),
+            end: self.end(),
+            byte_len: byte_end - byte_start,
+            byte_end,
+            token_len: 0,
+            token_end: 0,
         }
     }
+}
 
-    pub fn pattern_count(&self) -> usize {
-        self.aho.pattern_count()
+impl TryFrom<&Match> for CompactMatch {
+    type Error = TryFromIntError;
+
+    fn try_from(m: &Match) -> Result<Self, TryFromIntError> {
+        Ok(CompactMatch {
+            pattern: u32::try_from(m.pattern())?,
+            start: u32::try_from(m.start())?,
+            end: u32::try_from(m.end())?,
+        })
     }
+}
 
-    pub fn heap_bytes(&self) -> usize {
-        self.aho.heap_bytes() + self.data.capacity() * (std::mem::size_of::<(usize, D)>())
+/// Statistics about the size and shape of a finder's automaton, returned by
+/// `SimpleFinder::stats`.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[non_exhaustive]
+pub struct Stats {
+    /// The number of patterns the finder was built from.
+    pub pattern_count: usize,
+    /// The number of states, including the start state and the two states
+    /// every automaton has for failing.
+    pub state_count: usize,
+    /// The number of states whose transitions are kept in a hash map rather
+    /// than a list. These are the states closest to the start state, which
+    /// tend to have the most transitions.
+    pub dense_state_count: usize,
+    /// The number of tokens in the longest pattern.
+    pub max_pattern_len: usize,
+    /// The most transitions out of any one state. This is usually the start
+    /// state, which every token of a haystack is looked up in until it
+    /// begins a match. With `SimpleFinderBuilder::dfa`, this includes the
+    /// transitions a state copies from the states it would fail to.
+    pub max_out_degree: usize,
+    /// The approximate heap memory used by the finder, in bytes, as returned
+    /// by `SimpleFinder::heap_bytes`.
+    pub heap_bytes: usize,
+}
+
+/// The state of a search that's driven one token at a time, created by
+/// `SimpleFinder::start_state` and advanced by `SimpleFinder::step`.
+///
+/// This is `Copy`, so a search can be snapshotted and resumed later from the
+/// same point, such as before the last word typed into a text box.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct SearchState {
+    state_id: usize,
+    /// The number of tokens consumed so far.
+    pos: usize,
+}
+
+impl SearchState {
+    /// Returns the number of tokens that the search has consumed.
+    pub fn tokens_consumed(&self) -> usize {
+        self.pos
     }
+}
 
-    pub fn data(&self) -> &HashMap<usize, D> {
-        &self.data
+/// Where an overlapping search is in its haystack, saved by
+/// `FindOverlapping::cursor` and continued by
+/// `SimpleFinder::resume_overlapping`.
+///
+/// A cursor is only meaningful for the haystack and finder it was saved
+/// from: it records a position in the haystack's tokens and a state of the
+/// finder's automaton. With the `serde` feature enabled, it can be
+/// serialized, such as to resume paging through the matches of a document
+/// in a later request.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Cursor {
+    /// The number of tokens consumed so far.
+    pos: usize,
+    state_id: usize,
+    /// The number of the matches of the current state already reported.
+    match_index: usize,
+}
+
+impl Cursor {
+    /// Returns the number of tokens that the search has consumed.
+    pub fn tokens_consumed(&self) -> usize {
+        self.pos
     }
 }
 
-impl<'p, D: std::hash::Hash + std::cmp::Eq + Copy> SimpleFinder<D> {
-    pub fn find_all_unique<'a, 'b>(&'a self, haystack: &'b str) -> HashSet<D> {
-        self.find_all(haystack).map(|(_, d)| *d).collect()
+/// How a `SimpleFinder` chooses which matches to report when they overlap.
+///
+/// Matches are measured in tokens, so a match can only start at a token
+/// boundary, and one match is longer than another when it spans more tokens.
+/// The reported offsets are still char and byte offsets in the haystack.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum MatchKind {
+    /// Report every match, or only the first match state reached when
+    /// `SimpleFinderBuilder::overlapping` is disabled.
+    ///
+    /// This is the default.
+    #[default]
+    Standard,
+    /// Report the leftmost match, picking the longest of the matches that
+    /// start at the same token, and then resume the search after it.
+    ///
+    /// Given the patterns `"bar"` and `"bar baz"`, only `"bar baz"` is
+    /// reported in `"bar baz"`. This is like a regex alternation that prefers
+    /// longer matches.
+    LeftmostLongest,
+    /// Report the leftmost match, picking the pattern given first of the
+    /// matches that start at the same token, and then resume the search
+    /// after it.
+    ///
+    /// Given the patterns `"bar"` and `"bar baz"`, in that order, only
+    /// `"bar"` is reported in `"bar baz"`; a pattern that starts with an
+    /// earlier pattern can never match. This is how a regex alternation
+    /// behaves, and lets the order of the patterns encode their precedence.
+    LeftmostFirst,
+}
+
+impl MatchKind {
+    pub(crate) fn is_leftmost(&self) -> bool {
+        *self != MatchKind::Standard
+    }
+
+    pub(crate) fn is_leftmost_first(&self) -> bool {
+        *self == MatchKind::LeftmostFirst
     }
 }
 
-#[cfg(test)]
-mod test {
-    use super::*;
+/// The order that `SimpleFinder::find_all_sorted` reports matches in.
+///
+/// Every key orders matches completely, breaking ties by the offsets and
+/// pattern ID of the matches, so two finders reporting the same matches
+/// sort them the same way.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum SortKey {
+    /// Order matches by where they start, putting the shorter of two
+    /// matches starting at the same offset first.
+    Start,
+    /// Order matches by where they end, putting the longer of two matches
+    /// ending at the same offset first. This is the order that `find_all`
+    /// reports overlapping matches in, save for ties.
+    End,
+    /// Order matches by their pattern ID, and then by where they start.
+    Pattern,
+}
 
-    #[test]
-    fn test_iguess() {
-        let finder = SimpleFinder::new(vec![
-            ("foo", 123),
-            ("bar", 234),
-            ("baz", 345),
-            ("bar baz", 456),
-        ]);
+impl SortKey {
+    fn key(self, m: &Match) -> (usize, usize, usize) {
+        match self {
+            SortKey::Start => (m.start(), m.end(), m.pattern()),
+            SortKey::End => (m.end(), m.start(), m.pattern()),
+            SortKey::Pattern => (m.pattern(), m.start(), m.end()),
+        }
+    }
+}
 
-        let results: Vec<u64> = finder
-            .find_all("foo bar baz foobar foo'bar foo,bar")
-            .map(|(_, k)| k)
-            .cloned()
-            .collect();
+/// Options for a single pattern, given to
+/// `SimpleFinderBuilder::build_with_options`.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct PatternOptions {
+    case_sensitive: bool,
+}
 
-        assert_eq!(
-            results.len(),
-            8,
-            "Results {:?} was not 8 in length?",
-            results
-        );
-        assert!(results.contains(&123));
-        assert!(results.contains(&234));
-        assert!(results.contains(&345));
-        assert!(results.contains(&456));
+impl PatternOptions {
+    /// Create options that treat the pattern like any other.
+    pub fn new() -> Self {
+        Self::default()
     }
 
-    #[test]
-    fn test_unique() {
-        let finder = SimpleFinder::new(vec![
-            ("foo", 123),
-            ("bar", 234),
-            ("baz", 345),
-            ("bar baz", 456),
-        ]);
+    /// Match the pattern only where the haystack has the same case, even in
+    /// a finder built with `SimpleFinderBuilder::case_insensitive`.
+    ///
+    /// This has no effect in a case-sensitive finder.
+    pub fn case_sensitive(mut self, yes: bool) -> Self {
+        self.case_sensitive = yes;
+        self
+    }
+}
 
-        let results = finder.find_all_unique("foo bar baz foobar foo'bar foo,bar");
+/// A span of a haystack to highlight, returned by `SimpleFinder::highlights`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Highlight<'a, D> {
+    /// The byte offset of the start of the span.
+    pub byte_start: usize,
+    /// The byte offset of the end of the span, exclusive.
+    pub byte_end: usize,
+    /// The ID of the pattern that matched the span.
+    pub pattern: usize,
+    /// The data associated with the pattern.
+    pub data: &'a D,
+}
 
-        assert_eq!(
-            results.len(),
-            4,
-            "Results {:?} was not 4 in length?",
-            results
-        );
-        assert!(results.contains(&123));
-        assert!(results.contains(&234));
-        assert!(results.contains(&345));
-        assert!(results.contains(&456));
+/// A builder for configuring how a `SimpleFinder` tokenizes and compares its
+/// patterns and haystacks, and how it searches.
+#[derive(Clone, Debug)]
+pub struct SimpleFinderBuilder {
+    tokenizer: Option<SharedTokenizer>,
+    default_tokenizer: DefaultTokenizer,
+    normalizer: Normalizer,
+    stop_words: Vec<String>,
+    overlapping: bool,
+    match_kind: MatchKind,
+    dedup_patterns: bool,
+    dense_depth: usize,
+    reject_symbol_only_patterns: bool,
+    max_match_tokens: Option<usize>,
+    prefilter: bool,
+    dfa: bool,
+    #[cfg(feature = "rayon")]
+    parallel_build: bool,
+    store_patterns: bool,
+}
+
+impl Default for SimpleFinderBuilder {
+    fn default() -> Self {
+        SimpleFinderBuilder {
+            tokenizer: None,
+            default_tokenizer: DefaultTokenizer::default(),
+            normalizer: Normalizer::default(),
+            stop_words: Vec::new(),
+            overlapping: true,
+            match_kind: MatchKind::default(),
+            dedup_patterns: false,
+            dense_depth: nfa::DEFAULT_DENSE_DEPTH,
+            reject_symbol_only_patterns: false,
+            max_match_tokens: None,
+            prefilter: false,
+            dfa: false,
+            #[cfg(feature = "rayon")]
+            parallel_build: false,
+            store_patterns: true,
+        }
     }
+}
 
-    #[test]
-    fn test_loops() {
-        let finder = SimpleFinder::new(vec![
-            ("lol lol_", 0),
-            ("lol lol", 2),
-        ]);
+impl SimpleFinderBuilder {
+    /// Create a new builder with the default configuration.
+    pub fn new() -> Self {
+        Self::default()
+    }
 
-        let results: Vec<_> = finder.find_all("lol lol lol lol_").collect();
-        assert_eq!(
-            results,
-            vec![
-                (
-                    Match {
-                        pattern: 1,
-                        len: 7,
-                        end: 7
-                    },
-                    &2
-                ),
-                (
-                    Match {
-                        pattern: 1,
-                        len: 7,
-                        end: 11
-                    },
-                    &2
-                ),
-                (
-                    Match {
-                        pattern: 0,
-                        len: 8,
-                        end: 16
-                    },
-                    &0
-                )
-            ]
-        );
+    /// Build a `SimpleFinder` from the given patterns and their associated
+    /// data, using this configuration.
+    ///
+    /// # Panics
+    ///
+    /// This panics if building the automaton fails. Use `try_build` to
+    /// handle the error instead.
+    pub fn build<'p, I, D>(&self, patterns: I) -> SimpleFinder<D>
+    where
+        I: IntoIterator<Item = (&'p str, D)>,
+    {
+        match self.try_build(patterns) {
+            Ok(finder) => finder,
+            Err(err) => panic!("failed to build finder: {}", err),
+        }
+    }
+
+    /// Build a `SimpleFinder` from the given patterns and their associated
+    /// data, using this configuration, returning an error if the automaton
+    /// could not be built.
+    pub fn try_build<'p, I, D>(&self, patterns: I) -> Result<SimpleFinder<D>, BuildError>
+    where
+        I: IntoIterator<Item = (&'p str, D)>,
+    {
+        let (patterns, datas): (Vec<_>, Vec<_>) = patterns.into_iter().unzip();
+
+        let aho = ahocorasick::build_aho_corasick(
+            patterns.iter().copied(),
+            self.shared_tokenizer(),
+            self.normalizer(),
+            self.nfa_options(),
+        )?;
+
+        Ok(self.finder(aho, &patterns, datas))
+    }
+
+    /// Build a `SimpleFinder` that uses `S` to represent the states of its
+    /// automaton.
+    ///
+    /// By default, the narrowest representation that can identify every
+    /// state is chosen. This forces a particular representation, returning
+    /// an error if the patterns need more states than `S` can identify.
+    pub fn build_with_state_id<'p, S, I, D>(
+        &self,
+        patterns: I,
+    ) -> Result<SimpleFinder<D>, BuildError>
+    where
+        S: StateID,
+        I: IntoIterator<Item = (&'p str, D)>,
+    {
+        let (patterns, datas): (Vec<_>, Vec<_>) = patterns.into_iter().unzip();
+
+        let aho = ahocorasick::build_aho_corasick_with_state_id::<_, S>(
+            patterns.iter().copied(),
+            self.shared_tokenizer(),
+            self.normalizer(),
+            self.nfa_options(),
+        )?;
+
+        Ok(self.finder(aho, &patterns, datas))
+    }
+
+    /// Like `build`, but with `PatternOptions` for each pattern.
+    ///
+    /// # Panics
+    ///
+    /// This panics if building the automaton fails, or if any pattern is
+    /// case-sensitive in a case-insensitive finder with a leftmost
+    /// `MatchKind`. Use `try_build_with_options` to handle the error
+    /// instead.
+    ///
+    /// ```
+    /// use lacbd::{PatternOptions, SimpleFinderBuilder};
+    ///
+    /// let finder = SimpleFinderBuilder::new().case_insensitive(true).build_with_options(vec![
+    ///     ("US", "country", PatternOptions::new().case_sensitive(true)),
+    ///     ("usa", "country", PatternOptions::new()),
+    /// ]);
+    /// assert!(finder.is_match("made in the US"));
+    /// assert!(finder.is_match("made in the USA"));
+    /// assert!(!finder.is_match("all of us"));
+    /// ```
+    pub fn build_with_options<'p, I, D>(&self, patterns: I) -> SimpleFinder<D>
+    where
+        I: IntoIterator<Item = (&'p str, D, PatternOptions)>,
+    {
+        match self.try_build_with_options(patterns) {
+            Ok(finder) => finder,
+            Err(err) => panic!("failed to build finder: {}", err),
+        }
+    }
+
+    /// Like `try_build`, but with `PatternOptions` for each pattern.
+    ///
+    /// Case-sensitive patterns are matched case-insensitively like the rest,
+    /// and then their matches are dropped unless the tokens they span have
+    /// the same case as the pattern. Searches that are fed one token at a
+    /// time can't check this: `SimpleFinder::start_state` returns `None`,
+    /// and `SimpleFinder::stream` and `SimpleFinder::chunked_search` panic,
+    /// if any pattern is case-sensitive.
+    ///
+    /// This returns `BuildError::CaseSensitiveLeftmost` if any pattern is
+    /// case-sensitive in a case-insensitive finder with a leftmost
+    /// `MatchKind`, as a leftmost search can't drop a match without missing
+    /// the one it was chosen over.
+    pub fn try_build_with_options<'p, I, D>(
+        &self,
+        patterns: I,
+    ) -> Result<SimpleFinder<D>, BuildError>
+    where
+        I: IntoIterator<Item = (&'p str, D, PatternOptions)>,
+    {
+        let (patterns, rest): (Vec<_>, Vec<_>) = patterns
+            .into_iter()
+            .map(|(pattern, data, options)| (pattern, (data, options)))
+            .unzip();
+        let (datas, options): (Vec<_>, Vec<PatternOptions>) = rest.into_iter().unzip();
+
+        let mut aho = ahocorasick::build_aho_corasick(
+            patterns.iter().copied(),
+            self.shared_tokenizer(),
+            self.normalizer(),
+            self.nfa_options(),
+        )?;
+        aho.keep_case_of(
+            patterns
+                .iter()
+                .copied()
+                .enumerate()
+                .filter(|&(id, _)| options[id].case_sensitive),
+        )?;
+
+        Ok(self.finder(aho, &patterns, datas))
+    }
+
+    /// Build a `ReverseFinder` from the given patterns and their associated
+    /// data, using this configuration, which searches haystacks from their
+    /// end.
+    ///
+    /// # Panics
+    ///
+    /// This panics if building the automaton fails. Use `try_build_reverse`
+    /// to handle the error instead.
+    pub fn build_reverse<'p, I, D>(&self, patterns: I) -> ReverseFinder<D>
+    where
+        I: IntoIterator<Item = (&'p str, D)>,
+    {
+        match self.try_build_reverse(patterns) {
+            Ok(finder) => finder,
+            Err(err) => panic!("failed to build finder: {}", err),
+        }
+    }
+
+    /// Build a `ReverseFinder` from the given patterns and their associated
+    /// data, using this configuration, returning an error if the automaton
+    /// could not be built.
+    pub fn try_build_reverse<'p, I, D>(&self, patterns: I) -> Result<ReverseFinder<D>, BuildError>
+    where
+        I: IntoIterator<Item = (&'p str, D)>,
+    {
+        let (patterns, datas): (Vec<_>, Vec<_>) = patterns.into_iter().unzip();
+
+        let aho = ahocorasick::build_aho_corasick_reverse(
+            patterns,
+            self.shared_tokenizer(),
+            self.normalizer(),
+            self.nfa_options(),
+        )?;
+
+        Ok(ReverseFinder {
+            data: (0..aho.pattern_count()).zip(datas).collect(),
+            aho,
+            overlapping: self.overlapping,
+        })
+    }
+
+    /// Build a `BytesFinder` from the given byte string patterns and their
+    /// associated data, using this configuration, for haystacks that aren't
+    /// valid UTF-8.
+    ///
+    /// # Panics
+    ///
+    /// This panics if building the automaton fails. Use `try_build_bytes`
+    /// to handle the error instead.
+    pub fn build_bytes<'p, I, D>(&self, patterns: I) -> BytesFinder<D>
+    where
+        I: IntoIterator<Item = (&'p [u8], D)>,
+    {
+        match self.try_build_bytes(patterns) {
+            Ok(finder) => finder,
+            Err(err) => panic!("failed to build finder: {}", err),
+        }
+    }
+
+    /// Build a `BytesFinder` from the given byte string patterns and their
+    /// associated data, using this configuration, returning an error if the
+    /// automaton could not be built.
+    pub fn try_build_bytes<'p, I, D>(&self, patterns: I) -> Result<BytesFinder<D>, BuildError>
+    where
+        I: IntoIterator<Item = (&'p [u8], D)>,
+    {
+        let (patterns, datas): (Vec<_>, Vec<_>) = patterns.into_iter().unzip();
+
+        BytesFinder::new(
+            patterns,
+            datas,
+            self.normalizer.case_insensitive,
+            self.overlapping,
+            self.nfa_options(),
+        )
+    }
+
+    /// Build a `SimpleFinder` from the parts returned by
+    /// `SimpleFinder::into_parts`, pairing each pattern with the data in the
+    /// same position of `data`, as when building it.
+    ///
+    /// The parts only hold the automaton, so the finder is configured by
+    /// this builder, which must tokenize, normalize and match as the one
+    /// that built the parts did for the finder to find the same matches.
+    /// Patterns aren't stored, whatever `store_patterns` is set to.
+    ///
+    /// This returns an error if the parts refer to states, tokens or
+    /// patterns that don't exist, have failure transitions that could loop
+    /// forever, or have transitions or matches that don't fit the depths of
+    /// their states, or if `data` isn't one item for each pattern.
+    ///
+    /// ```
+    /// use lacbd::SimpleFinderBuilder;
+    ///
+    /// let builder = SimpleFinderBuilder::new();
+    /// let finder = builder.build(vec![("foo bar", 0), ("bar", 1)]);
+    /// let (parts, data) = finder.into_parts();
+    ///
+    /// let finder = builder.try_build_from_parts(parts, data).unwrap();
+    /// assert_eq!(finder.find_all("foo bar").count(), 2);
+    /// ```
+    pub fn try_build_from_parts<I, D>(
+        &self,
+        parts: AutomatonParts,
+        data: I,
+    ) -> Result<SimpleFinder<D>, BuildError>
+    where
+        I: IntoIterator<Item = D>,
+    {
+        let aho = ahocorasick::aho_corasick_from_parts(
+            parts,
+            self.shared_tokenizer(),
+            self.normalizer(),
+            self.nfa_options(),
+        )?;
+
+        let data: Vec<D> = data.into_iter().collect();
+        if data.len() != aho.pattern_count() {
+            return Err(BuildError::invalid_parts(
+                "the data isn't one item for each pattern",
+            ));
+        }
+        Ok(SimpleFinder {
+            data: (0..aho.pattern_count()).zip(data).collect(),
+            aho,
+            patterns: None,
+            overlapping: self.overlapping,
+        })
+    }
+
+    fn finder<D>(
+        &self,
+        aho: ahocorasick::AhoCorasick,
+        patterns: &[&str],
+        datas: Vec<D>,
+    ) -> SimpleFinder<D> {
+        let data = (0..aho.pattern_count()).zip(datas.into_iter()).collect();
+        let patterns = if self.store_patterns {
+            Some(patterns.iter().map(|p| p.to_string()).collect())
+        } else {
+            None
+        };
+
+        SimpleFinder {
+            aho,
+            data,
+            patterns,
+            overlapping: self.overlapping,
+        }
+    }
+
+    /// Split patterns and haystacks into tokens with `tokenizer` instead of
+    /// the `DefaultTokenizer`.
+    ///
+    /// The same tokenizer is used for the patterns and for every haystack,
+    /// so that they're always split consistently.
+    pub fn tokenizer<T: Tokenizer + 'static>(&mut self, tokenizer: T) -> &mut Self {
+        self.tokenizer = Some(SharedTokenizer::new(tokenizer));
+        self
+    }
+
+    /// Keep each run of whitespace as a token, rather than discarding it, so
+    /// that a pattern only matches text with the same spacing.
+    ///
+    /// With this enabled, `"foo  bar"` no longer matches `"foo bar"`. This
+    /// configures the `DefaultTokenizer`, and has no effect when a custom
+    /// tokenizer is given.
+    ///
+    /// This is disabled by default.
+    pub fn keep_whitespace(&mut self, yes: bool) -> &mut Self {
+        self.default_tokenizer.keep_whitespace = yes;
+        self
+    }
+
+    /// Keep words joined by an apostrophe as one token, so that `"don't"` is
+    /// a single token rather than `"don"`, `"'"`, and `"t"`, as a reader
+    /// would expect of a dictionary of words.
+    ///
+    /// Both `'` and the typographic `’` join words, but only when directly
+    /// between two words, so `"rock'n'roll"` is one token while `"'tis"` and
+    /// `"dogs'"` still split off their apostrophes. This configures the
+    /// `DefaultTokenizer`, and has no effect when a custom tokenizer is
+    /// given.
+    ///
+    /// This is disabled by default.
+    pub fn join_apostrophes(&mut self, yes: bool) -> &mut Self {
+        self.default_tokenizer.join_apostrophes = yes;
+        self
+    }
+
+    /// Keep words joined by a hyphen as one token, so that a pattern
+    /// `"state-of-the-art"` matches it directly rather than as seven tokens.
+    ///
+    /// Both `-` and the Unicode hyphen `‐` join words, but only when directly
+    /// between two words, so `"-foo"`, `"foo-"`, and `"foo - bar"` are split
+    /// as before. As digits are word characters, a range such as
+    /// `"2020-2021"` becomes one token too, which `"2020"` alone won't match.
+    /// This configures the `DefaultTokenizer`, and has no effect when a
+    /// custom tokenizer is given.
+    ///
+    /// This is disabled by default.
+    pub fn join_hyphens(&mut self, yes: bool) -> &mut Self {
+        self.default_tokenizer.join_hyphens = yes;
+        self
+    }
+
+    /// Keep numbers grouped with commas or with a decimal point as one token,
+    /// so that `"1,000.50"` is a single token rather than five, for matching
+    /// amounts or phone numbers from a dictionary.
+    ///
+    /// Runs of ASCII digits are joined by a `,` or `.` directly between them,
+    /// however many digits they have, so a list such as `"1,2,3"` becomes one
+    /// token too, while the full stop of `"3.14."` is still split off. This
+    /// configures the `DefaultTokenizer`, and has no effect when a custom
+    /// tokenizer is given.
+    ///
+    /// This is disabled by default.
+    pub fn join_digit_groups(&mut self, yes: bool) -> &mut Self {
+        self.default_tokenizer.join_digit_groups = yes;
+        self
+    }
+
+    /// Drop every symbol token from patterns and haystacks, as whitespace
+    /// is dropped, so that a pattern `"foo bar"` matches `"foo,bar"`,
+    /// `"foo - bar"`, and `"(foo) bar"`.
+    ///
+    /// Symbols are runs of characters that are neither word characters nor
+    /// whitespace. A match spans from the start of its first word to the end
+    /// of its last, so its offsets cover the symbols skipped between them,
+    /// but never those before or after it: `"foo bar"` in `"(foo, bar)"`
+    /// spans `"foo, bar"`. Symbols are dropped after words are joined, so
+    /// `"don't"` is still one token with `SimpleFinderBuilder::join_apostrophes`.
+    /// Patterns made up of only symbols have no tokens left, and are
+    /// rejected with `BuildError::EmptyPattern`. This configures the
+    /// `DefaultTokenizer`, and has no effect when a custom tokenizer is given.
+    ///
+    /// This is disabled by default.
+    pub fn skip_symbols(&mut self, yes: bool) -> &mut Self {
+        self.default_tokenizer.skip_symbols = yes;
+        self
+    }
+
+    /// Strip the symbol tokens `symbols` from patterns and haystacks, so that
+    /// with `"-"` and `"."` stripped, `"e-mail"` matches `"email"` and
+    /// `"U.S.A."` matches `"USA"`.
+    ///
+    /// Only whole symbol tokens are stripped: stripping `"-"` leaves `"--"`
+    /// as it is. A stripped symbol directly between two words, with no
+    /// whitespace either side of it, joins them into one token, which is
+    /// compared with the symbol removed, so `"e-mail"` becomes `"email"`
+    /// and `"U.S.A"` becomes `"USA"`. Anywhere else, such as the last
+    /// `"."` of `"U.S.A."` or the `"-"` of `"e - mail"`, the symbol is
+    /// dropped like a stop word, leaving the words either side as tokens of
+    /// their own, so `"e - mail"` is still `"e"` and `"mail"`. The offsets
+    /// of a match cover the stripped symbols within it.
+    ///
+    /// Joining words configures the `DefaultTokenizer`, so with a custom
+    /// tokenizer, stripped symbols are only dropped. Each call replaces the
+    /// symbols given before.
+    ///
+    /// ```
+    /// use lacbd::SimpleFinderBuilder;
+    ///
+    /// let finder = SimpleFinderBuilder::new()
+    ///     .strip_symbols(vec!["-", "."])
+    ///     .build(vec![("e-mail", 0), ("USA", 1)]);
+    /// assert!(finder.is_match("send an email"));
+    /// assert!(finder.is_match("made in the U.S.A."));
+    /// assert!(!finder.is_match("e - mail"));
+    /// ```
+    pub fn strip_symbols<'s, I>(&mut self, symbols: I) -> &mut Self
+    where
+        I: IntoIterator<Item = &'s str>,
+    {
+        let mut symbols: Vec<&str> = symbols.into_iter().filter(|s| !s.is_empty()).collect();
+        symbols.sort_by(|a, b| b.len().cmp(&a.len()).then(a.cmp(b)));
+        symbols.dedup();
+        let symbols: alloc::sync::Arc<[Box<str>]> = symbols.into_iter().map(Box::from).collect();
+        self.default_tokenizer.strip_symbols = symbols.clone();
+        self.normalizer.strip_symbols = symbols;
+        self
+    }
+
+    /// Use `is_word` to decide which characters are word characters, rather
+    /// than `is_word_character`.
+    ///
+    /// Runs of word characters become a single token, so a classifier that
+    /// also accepts `'#'` makes `"#foo"` one token rather than `"#"` and
+    /// `"foo"`. This configures the `DefaultTokenizer`, and has no effect
+    /// when a custom tokenizer is given.
+    pub fn word_characters<F>(&mut self, is_word: F) -> &mut Self
+    where
+        F: Fn(char) -> bool + Send + Sync + 'static,
+    {
+        self.default_tokenizer.is_word = Some(alloc::sync::Arc::new(is_word));
+        self
+    }
+
+    fn nfa_options(&self) -> NfaOptions {
+        NfaOptions {
+            match_kind: self.match_kind,
+            dedup_patterns: self.dedup_patterns,
+            dense_depth: self.dense_depth,
+            reject_symbol_only: self.reject_symbol_only_patterns,
+            max_match_tokens: self.max_match_tokens,
+            prefilter: self.prefilter,
+            dfa: self.dfa,
+            #[cfg(feature = "rayon")]
+            parallel: self.parallel_build,
+        }
+    }
+
+    fn shared_tokenizer(&self) -> SharedTokenizer {
+        match &self.tokenizer {
+            Some(tokenizer) => tokenizer.clone(),
+            None => SharedTokenizer::Default(self.default_tokenizer.clone()),
+        }
+    }
+
+    /// The normalizer for the finder, with its stop words normalized the
+    /// same as the tokens they're compared with.
+    fn normalizer(&self) -> Normalizer {
+        let mut normalizer = self.normalizer.clone();
+        normalizer.stop_words = self
+            .stop_words
+            .iter()
+            .map(|word| self.normalizer.normalize(word).into())
+            .collect();
+        normalizer
+    }
+
+    /// Fold fullwidth and halfwidth character variants to a common width
+    /// before comparing tokens, so that `"ＡＢＣ"` matches `"ABC"`.
+    ///
+    /// This maps the fullwidth forms of ASCII and the ideographic space to
+    /// ASCII, and halfwidth katakana and CJK punctuation to their fullwidth
+    /// forms. Match offsets still refer to the original haystack.
+    ///
+    /// This is disabled by default.
+    pub fn fold_width(&mut self, yes: bool) -> &mut Self {
+        self.normalizer.fold_width = yes;
+        self
+    }
+
+    /// Convert patterns and haystacks to the normalization form `form`
+    /// before tokenizing them, so that text matches regardless of how its
+    /// characters are composed.
+    ///
+    /// With `Normalization::Nfc`, a pattern `"café"` written with a
+    /// precomposed `'é'` matches a haystack that spells it `"cafe\u{301}"`.
+    /// Match offsets still refer to the original haystack.
+    ///
+    /// A `StreamFinder` converts each token on its own, so it misses the rare
+    /// compositions that join a symbol with the combining mark after it.
+    ///
+    /// This is `Normalization::None` by default.
+    pub fn normalize(&mut self, form: Normalization) -> &mut Self {
+        self.normalizer.form = form;
+        self
+    }
+
+    /// Ignore case when comparing tokens, so that a pattern `"foo"` matches
+    /// `"Foo"`, `"FOO"`, and `"foo"`.
+    ///
+    /// Tokens are folded with `str::to_lowercase`, which may change their
+    /// length, but match offsets always refer to the original haystack.
+    ///
+    /// This is disabled by default.
+    pub fn case_insensitive(&mut self, yes: bool) -> &mut Self {
+        self.normalizer.case_insensitive = yes;
+        self
+    }
+
+    /// Transform each token with `transform` before it's matched, such as
+    /// with a stemmer so that a pattern `"run"` matches `"running"`.
+    ///
+    /// The transform is applied to the tokens of patterns and haystacks
+    /// alike, after they're normalized as configured, and to stop words.
+    /// Match offsets always refer to the original haystack. A finder with a
+    /// transform can't be serialized.
+    pub fn token_transform<F>(&mut self, transform: F) -> &mut Self
+    where
+        F: Fn(&str) -> Cow<'_, str> + Send + Sync + 'static,
+    {
+        self.normalizer.transform = Some(alloc::sync::Arc::new(transform));
+        self
+    }
+
+    /// Count the char offsets of matches, `Match::start` and `Match::end`, in
+    /// extended grapheme clusters rather than chars, so that they agree with
+    /// what a user sees as a character. An emoji sequence such as
+    /// `"👨\u{200d}👩\u{200d}👧"` is then one character instead of five.
+    ///
+    /// This segments every haystack into grapheme clusters as it's
+    /// searched, which makes searches slower, so it is disabled by default.
+    /// A match starting or ending inside a cluster is widened to cover it.
+    /// Byte offsets are unchanged, but `CompactMatch::to_match` expects char
+    /// offsets, so it can't be used with these matches.
+    pub fn count_graphemes(&mut self, yes: bool) -> &mut Self {
+        self.normalizer.graphemes = yes;
+        self
+    }
+
+    /// Drop tokens that are one of `words` from patterns and haystacks, so
+    /// that with the stop word `"the"`, a pattern `"quick fox"` matches
+    /// `"quick the fox"`.
+    ///
+    /// Stop words are compared with whole tokens, after both are normalized
+    /// as configured. A match can span a stop word, and its offsets then
+    /// cover it, but never starts or ends with one. Stop words aren't
+    /// counted by `Match::token_start` and `Match::token_end`. A pattern made
+    /// up of only stop words is rejected with `BuildError::EmptyPattern`.
+    ///
+    /// This replaces any stop words given before. There are none by default.
+    pub fn stop_words<'w, I>(&mut self, words: I) -> &mut Self
+    where
+        I: IntoIterator<Item = &'w str>,
+    {
+        self.stop_words = words.into_iter().map(String::from).collect();
+        self
+    }
+
+    /// Whether `SimpleFinder::find_all` reports overlapping matches, or only
+    /// the matches that `SimpleFinder::find_all_nonoverlapping` would.
+    ///
+    /// This is enabled by default.
+    pub fn overlapping(&mut self, yes: bool) -> &mut Self {
+        self.overlapping = yes;
+        self
+    }
+
+    /// Set the semantics used to choose between overlapping matches.
+    ///
+    /// With a leftmost match kind, `SimpleFinder::find_all` only reports
+    /// non-overlapping matches, regardless of
+    /// `SimpleFinderBuilder::overlapping`, and chunked searches are not
+    /// supported.
+    ///
+    /// This is `MatchKind::Standard` by default.
+    pub fn match_kind(&mut self, kind: MatchKind) -> &mut Self {
+        self.match_kind = kind;
+        self
+    }
+
+    /// Whether patterns that split into the same tokens as an earlier
+    /// pattern are merged into it, so that a match is only reported once.
+    ///
+    /// A match of merged patterns is reported with the ID and data of the
+    /// first of them. The later patterns still count towards
+    /// `SimpleFinder::pattern_count`, but are never reported.
+    ///
+    /// This is disabled by default, reporting a match of each duplicate.
+    pub fn dedup_patterns(&mut self, yes: bool) -> &mut Self {
+        self.dedup_patterns = yes;
+        self
+    }
+
+    /// Whether a pattern made up of only symbols, such as `"@"` or `"..."`,
+    /// is rejected with `BuildError::SymbolOnlyPattern`.
+    ///
+    /// Such a pattern matches every occurrence of its symbols in a
+    /// haystack, which is rarely wanted of a dictionary of phrases. Which
+    /// tokens are words is decided by `SimpleFinderBuilder::word_characters`
+    /// for the default tokenizer, and by the first char of each token
+    /// otherwise. This applies to patterns added with `SimpleFinder::extend`
+    /// too.
+    ///
+    /// This is disabled by default.
+    pub fn reject_symbol_only_patterns(&mut self, yes: bool) -> &mut Self {
+        self.reject_symbol_only_patterns = yes;
+        self
+    }
+
+    /// The most tokens a pattern may have, rejecting longer patterns with
+    /// `BuildError::PatternTooLong`.
+    ///
+    /// As a match spans the tokens of one pattern, this bounds how long a
+    /// match can be, for finders built from patterns that aren't trusted.
+    /// `SimpleFinder::max_pattern_len` is then at most `n`, which also
+    /// bounds how much consecutive chunks of a haystack need to overlap.
+    /// Tokens dropped as stop words don't count. This applies to patterns
+    /// added with `SimpleFinder::extend` too.
+    ///
+    /// There is no limit by default.
+    pub fn max_match_tokens(&mut self, n: usize) -> &mut Self {
+        self.max_match_tokens = Some(n);
+        self
+    }
+
+    /// How many tokens deep into the trie of the patterns states keep their
+    /// transitions in a hash map, rather than a sorted list.
+    ///
+    /// States near the start state tend to have many transitions, which are
+    /// quicker to hash than to search a list of, while deeper states usually
+    /// have only a few, which take less memory in a list. Raising this
+    /// trades memory for speed on dictionaries whose patterns share long
+    /// prefixes, and `0` keeps every state in a list.
+    ///
+    /// This is `2` by default, so the start state and the states one token
+    /// from it are hashed.
+    pub fn dense_depth(&mut self, depth: usize) -> &mut Self {
+        self.dense_depth = depth;
+        self
+    }
+
+    /// Whether searches skip over tokens that can't start any pattern,
+    /// judged by their first byte and length, without looking them up.
+    ///
+    /// This never changes the matches found, and speeds up searches of
+    /// haystacks where few tokens start a pattern, at a small cost to
+    /// searches of haystacks where many do.
+    ///
+    /// This is disabled by default.
+    pub fn prefilter(&mut self, yes: bool) -> &mut Self {
+        self.prefilter = yes;
+        self
+    }
+
+    /// Whether the automaton is compiled to a DFA, which resolves failure
+    /// transitions ahead of time, so that a search looks each token up in
+    /// at most two states rather than following a whole chain of them.
+    ///
+    /// Each state copies the transitions of the states it would fail to, so
+    /// the DFA can be much larger than the NFA when failure chains are long.
+    ///
+    /// This only pays off when patterns often share a suffix with the prefix
+    /// of another, and failure chains are long. Tokenizing the haystack
+    /// usually costs far more than following failure transitions, so for
+    /// most sets of patterns searches are no faster, and the build is a
+    /// little slower.
+    ///
+    /// This is disabled by default.
+    pub fn dfa(&mut self, yes: bool) -> &mut Self {
+        self.dfa = yes;
+        self
+    }
+
+    /// Whether the trie of the patterns is built on several threads.
+    ///
+    /// The patterns are split up by their first token, and the tries of each
+    /// group are merged afterwards. The merge adds work, so this only speeds
+    /// up building large sets of patterns with several cores to spare. The
+    /// automaton built is the same either way.
+    ///
+    /// This is disabled by default.
+    #[cfg(feature = "rayon")]
+    pub fn parallel_build(&mut self, yes: bool) -> &mut Self {
+        self.parallel_build = yes;
+        self
+    }
+
+    /// Whether to keep a copy of the original patterns, so that they can be
+    /// looked up by ID with `SimpleFinder::pattern`.
+    ///
+    /// This is enabled by default. Disabling it saves memory when the
+    /// patterns aren't needed after building.
+    pub fn store_patterns(&mut self, yes: bool) -> &mut Self {
+        self.store_patterns = yes;
+        self
+    }
+}
+
+/// Finds word boundary delimited patterns in haystacks, along with the data
+/// associated with each pattern.
+///
+/// The automaton's states are identified using the narrowest integer type
+/// that can represent all of them, so a finder with a few hundred patterns
+/// uses `u16` state IDs, saving memory over `usize` on every transition.
+pub struct SimpleFinder<D> {
+    aho: ahocorasick::AhoCorasick,
+    data: HashMap<usize, D>,
+    patterns: Option<Vec<String>>,
+    overlapping: bool,
+}
+
+pub struct SimpleFinderIter<'a, 'b, D> {
+    finder: &'a SimpleFinder<D>,
+    haystack: &'b str,
+    iter: ahocorasick::Matches<'a, 'b>,
+}
+
+impl<'a, 'b, D> Iterator for SimpleFinderIter<'a, 'b, D> {
+    type Item = (Match, &'a D);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let next = self.iter.next()?;
+        let data = &self.finder.data.get(&next.pattern)?;
+
+        Some((next, data))
+    }
+}
+
+impl<'a, 'b, D> SimpleFinderIter<'a, 'b, D> {
+    /// Converts this iterator into one that only yields non-overlapping
+    /// matches, keeping the earliest ending match whenever two overlap.
+    pub fn non_overlapping(self) -> impl Iterator<Item = (Match, &'a D)> + 'b
+    where
+        'a: 'b,
+    {
+        let finder = self.finder;
+        ahocorasick::non_overlapping(self.iter).filter_map(move |m| {
+            let data = finder.data.get(&m.pattern)?;
+
+            Some((m, data))
+        })
+    }
+
+    /// Converts this iterator into one that also yields the text of the
+    /// haystack covered by each match.
+    ///
+    /// The text is sliced from the original haystack, so it keeps its
+    /// original case and width even when the finder normalizes tokens.
+    pub fn with_text(self) -> impl Iterator<Item = (Match, &'a D, &'b str)> {
+        let haystack = self.haystack;
+        self.map(move |(m, data)| {
+            let text = &haystack[m.byte_start()..m.byte_end()];
+            (m, data, text)
+        })
+    }
+}
+
+/// An iterator of the overlapping matches in a haystack that it owns,
+/// created by `SimpleFinder::find_overlapping_owned`.
+///
+/// Since it only borrows the finder, this can be sent to another thread,
+/// along with its haystack, as long as the finder's data is `Sync`. It is
+/// `Send` and `Sync` whenever `D` is `Sync`, as is `SimpleFinderIter`.
+pub struct FindOverlappingIntoIter<'a, D> {
+    finder: &'a SimpleFinder<D>,
+    haystack: String,
+    iter: ahocorasick::FindOverlappingIter<'a, 'static>,
+}
+
+impl<'a, D> Iterator for FindOverlappingIntoIter<'a, D> {
+    type Item = (Match, &'a D);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let next = self.iter.next()?;
+        let data = self.finder.data.get(&next.pattern)?;
+
+        Some((next, data))
+    }
+}
+
+impl<'a, D> FindOverlappingIntoIter<'a, D> {
+    /// Returns the haystack being searched, which the byte offsets of each
+    /// match can slice.
+    pub fn haystack(&self) -> &str {
+        &self.haystack
+    }
+
+    /// Returns the haystack, dropping the rest of the search.
+    pub fn into_haystack(self) -> String {
+        self.haystack
+    }
+}
+
+/// An iterator of the overlapping matches in a haystack, created by
+/// `SimpleFinder::find_overlapping` and `SimpleFinder::resume_overlapping`,
+/// which can be paused and resumed.
+pub struct FindOverlapping<'a, 'b, D> {
+    finder: &'a SimpleFinder<D>,
+    iter: ahocorasick::FindOverlappingIter<'a, 'b>,
+}
+
+impl<'a, 'b, D> Iterator for FindOverlapping<'a, 'b, D> {
+    type Item = (Match, &'a D);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let next = self.iter.next()?;
+        let data = self.finder.data.get(&next.pattern)?;
+
+        Some((next, data))
+    }
+}
+
+impl<'a, 'b, D> FindOverlapping<'a, 'b, D> {
+    /// Returns where the search is, so that the matches that haven't been
+    /// yielded yet can be found later with `SimpleFinder::resume_overlapping`,
+    /// without searching the tokens before them again.
+    pub fn cursor(&self) -> Cursor {
+        self.iter.cursor()
+    }
+}
+
+impl<D> SimpleFinder<D> {
+    /// Create a finder for the given patterns and their associated data.
+    ///
+    /// # Panics
+    ///
+    /// This panics if building the automaton fails. Use `try_new` to handle
+    /// the error instead.
+    pub fn new<'p, I>(patterns: I) -> Self
+    where
+        I: IntoIterator<Item = (&'p str, D)>,
+    {
+        SimpleFinderBuilder::new().build(patterns)
+    }
+
+    /// Create a finder for the given patterns and their associated data,
+    /// returning an error if the automaton could not be built.
+    pub fn try_new<'p, I>(patterns: I) -> Result<Self, BuildError>
+    where
+        I: IntoIterator<Item = (&'p str, D)>,
+    {
+        SimpleFinderBuilder::new().try_build(patterns)
+    }
+
+    /// Like `new`, but takes the patterns as owned strings, such as those
+    /// read from a file.
+    ///
+    /// A finder never borrows its patterns, keeping its own copy of each
+    /// token, so the strings are dropped once it's built, unless they're
+    /// kept for `SimpleFinder::pattern`.
+    ///
+    /// ```
+    /// use lacbd::SimpleFinder;
+    ///
+    /// let words = "apple\nbanana split\n";
+    /// let finder = SimpleFinder::new_owned(words.lines().map(|w| (w.to_owned(), ())).collect());
+    /// assert!(finder.is_match("a banana split"));
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// This panics if building the automaton fails.
+    pub fn new_owned(patterns: Vec<(String, D)>) -> Self {
+        let (patterns, datas): (Vec<String>, Vec<D>) = patterns.into_iter().unzip();
+        SimpleFinder::new(patterns.iter().map(String::as_str).zip(datas))
+    }
+
+    /// Create a finder that ignores case when comparing tokens, so that a
+    /// pattern `"foo"` matches `"Foo"`, `"FOO"`, and `"foo"`.
+    ///
+    /// Tokens are folded with `str::to_lowercase`, which may change their
+    /// length, but match offsets always refer to the original haystack.
+    pub fn new_case_insensitive<'p, I>(patterns: I) -> Self
+    where
+        I: IntoIterator<Item = (&'p str, D)>,
+    {
+        SimpleFinderBuilder::new()
+            .case_insensitive(true)
+            .build(patterns)
+    }
+
+    /// Create a finder that uses `S` to represent the states of its
+    /// automaton, returning an error if the patterns need more states than
+    /// `S` can identify.
+    ///
+    /// See `SimpleFinderBuilder::build_with_state_id`.
+    pub fn new_with_state_id<'p, S, I>(patterns: I) -> Result<Self, BuildError>
+    where
+        S: StateID,
+        I: IntoIterator<Item = (&'p str, D)>,
+    {
+        SimpleFinderBuilder::new().build_with_state_id::<S, _, _>(patterns)
+    }
+
+    /// Find all matches in `haystack`.
+    ///
+    /// Unless the finder was built with `SimpleFinderBuilder::overlapping`
+    /// disabled, or with a leftmost `MatchKind`, this reports overlapping
+    /// matches.
+    pub fn find_all<'a: 'b, 'b>(&'a self, haystack: &'b str) -> SimpleFinderIter<'a, 'b, D> {
+        SimpleFinderIter {
+            finder: self,
+            haystack,
+            iter: self.matches(haystack, self.overlapping),
+        }
+    }
+
+    /// Like `find_all`, but looks up every transition with bounds checks,
+    /// for fuzzing and other untrusted uses.
+    ///
+    /// `find_all` checks the state a search starts in, and then relies on
+    /// the automaton being valid to follow transitions without checking
+    /// them. This search never panics, even if the automaton were invalid,
+    /// and instead stops early. It reports the same matches as `find_all`,
+    /// at a modest cost measured by the `checked_search` benchmark.
+    pub fn find_checked<'a: 'b, 'b>(&'a self, haystack: &'b str) -> SimpleFinderIter<'a, 'b, D> {
+        SimpleFinderIter {
+            finder: self,
+            haystack,
+            iter: ahocorasick::Matches::Checked(
+                self.aho.find_checked_iter(haystack, self.overlapping),
+            ),
+        }
+    }
+
+    /// Find all overlapping matches in `haystack`, taking ownership of it so
+    /// that the returned iterator only borrows the finder.
+    ///
+    /// This reports every match, like `find_all` with
+    /// `SimpleFinderBuilder::overlapping` enabled, whether or not it was.
+    /// The haystack is tokenized up front, copying each of its tokens.
+    ///
+    /// # Panics
+    ///
+    /// This panics if the finder was built with a leftmost `MatchKind`.
+    pub fn find_overlapping_owned(&self, haystack: String) -> FindOverlappingIntoIter<'_, D> {
+        let iter = self.aho.find_overlapping_iter_owned(&haystack);
+        FindOverlappingIntoIter {
+            finder: self,
+            haystack,
+            iter,
+        }
+    }
+
+    /// Find all overlapping matches in `haystack`, whether or not the finder
+    /// was built with `SimpleFinderBuilder::overlapping`, with an iterator
+    /// whose position can be saved with `FindOverlapping::cursor`.
+    ///
+    /// # Panics
+    ///
+    /// This panics if the finder was built with a leftmost `MatchKind`.
+    pub fn find_overlapping<'a: 'b, 'b>(&'a self, haystack: &'b str) -> FindOverlapping<'a, 'b, D> {
+        FindOverlapping {
+            finder: self,
+            iter: self.aho.find_overlapping_iter(haystack),
+        }
+    }
+
+    /// Continues the overlapping search of `haystack` that `cursor` was
+    /// saved from, yielding the matches that it hadn't yet.
+    ///
+    /// The haystack is tokenized again, but the automaton isn't run over
+    /// the tokens before the cursor. `haystack` must be the same haystack,
+    /// searched by the same finder, as when the cursor was saved, or the
+    /// matches reported are meaningless. A cursor that can't have been saved
+    /// from this search, such as one past the end of the haystack, finds no
+    /// more matches.
+    ///
+    /// ```
+    /// use lacbd::SimpleFinder;
+    ///
+    /// let finder = SimpleFinder::new(vec![("a", 0), ("a b", 1), ("c", 2)]);
+    /// let haystack = "a b c a";
+    /// let mut first_page = finder.find_overlapping(haystack);
+    /// assert_eq!(first_page.by_ref().take(2).count(), 2);
+    /// let cursor = first_page.cursor();
+    ///
+    /// let rest: Vec<_> = finder
+    ///     .resume_overlapping(haystack, cursor)
+    ///     .map(|(m, &d)| (m.start(), d))
+    ///     .collect();
+    /// assert_eq!(rest, vec![(4, 2), (6, 0)]);
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// This panics if the finder was built with a leftmost `MatchKind`.
+    pub fn resume_overlapping<'a: 'b, 'b>(
+        &'a self,
+        haystack: &'b str,
+        cursor: Cursor,
+    ) -> FindOverlapping<'a, 'b, D> {
+        FindOverlapping {
+            finder: self,
+            iter: self.aho.resume_overlapping(haystack, cursor),
+        }
+    }
+
+    /// Find matches in `haystack` that don't overlap each other.
+    ///
+    /// Matches are taken from the overlapping matches in the order they end,
+    /// keeping each one that doesn't overlap a match already kept. Unlike a
+    /// leftmost `MatchKind`, this prefers the match that ends first over one
+    /// that starts first, and works whether or not the finder was built with
+    /// `SimpleFinderBuilder::overlapping` enabled.
+    pub fn find_all_disjoint<'a: 'b, 'b>(
+        &'a self,
+        haystack: &'b str,
+    ) -> impl Iterator<Item = (Match, &'a D)> + 'b {
+        SimpleFinderIter {
+            finder: self,
+            haystack,
+            iter: self.matches(haystack, true),
+        }
+        .non_overlapping()
+    }
+
+    /// Find the longest match ending at each token of `haystack`.
+    ///
+    /// This is an overlapping search, whether or not the finder was built
+    /// with `SimpleFinderBuilder::overlapping` enabled, except that of the
+    /// matches ending at the same token, only the longest is reported. The
+    /// shorter matches of its suffixes are left out, so that with the
+    /// patterns `"apple pie"` and `"pie"`, only `"apple pie"` is found in
+    /// `"apple pie"`, though `"pie"` alone still is. Patterns that split into
+    /// the same tokens are equally long, so unless they're merged with
+    /// `SimpleFinderBuilder::dedup_patterns`, each of them is reported.
+    ///
+    /// # Panics
+    ///
+    /// This panics if the finder was built with a leftmost `MatchKind`.
+    pub fn find_all_longest_per_end<'a: 'b, 'b>(
+        &'a self,
+        haystack: &'b str,
+    ) -> SimpleFinderIter<'a, 'b, D> {
+        SimpleFinderIter {
+            finder: self,
+            haystack,
+            iter: ahocorasick::Matches::Overlapping(self.aho.find_longest_per_end_iter(haystack)),
+        }
+    }
+
+    /// Searches `haystack` with the finder's match kind, reporting
+    /// overlapping matches if `overlapping` is set and the match kind allows
+    /// it.
+    fn matches<'a: 'b, 'b>(
+        &'a self,
+        haystack: &'b str,
+        overlapping: bool,
+    ) -> ahocorasick::Matches<'a, 'b> {
+        self.aho.matches(haystack, overlapping)
+    }
+
+    /// Like `find_all`, but splits the haystack into parts that are searched
+    /// in parallel, which is faster for very large haystacks.
+    ///
+    /// The haystack is still tokenized on the calling thread. The matches
+    /// reported are the same, in the same order, as those of `find_all`.
+    ///
+    /// Finders built with a leftmost `MatchKind` search the haystack on the
+    /// calling thread instead, as a leftmost match can't be chosen until the
+    /// tokens before it have been searched.
+    #[cfg(feature = "rayon")]
+    pub fn find_all_parallel(&self, haystack: &str) -> Vec<(Match, &D)> {
+        if self.aho.match_kind().is_leftmost() {
+            return self.find_all(haystack).collect();
+        }
+        let matches = self.aho.find_overlapping_parallel(haystack).into_iter();
+        // the first match state reached from the end of the last match is
+        // the first match ending after it that doesn't overlap it
+        let matches: Box<dyn Iterator<Item = Match>> = if self.overlapping {
+            Box::new(matches)
+        } else {
+            Box::new(ahocorasick::non_overlapping(matches))
+        };
+        matches
+            .filter_map(|m| {
+                let data = self.data.get(&m.pattern)?;
+
+                Some((m, data))
+            })
+            .collect()
+    }
+
+    /// Returns true if and only if any pattern occurs in `haystack`.
+    ///
+    /// This stops at the first match, without tokenizing the rest of the
+    /// haystack, so it's faster than `find_all(haystack).next().is_some()`.
+    pub fn is_match(&self, haystack: &str) -> bool {
+        self.aho.is_match(haystack)
+    }
+
+    /// Adds patterns and their associated data to the finder, numbering them
+    /// after the patterns it already has.
+    ///
+    /// Only the new patterns are added to the trie of the automaton, but
+    /// every failure transition has to be recomputed, so this takes time
+    /// proportional to the size of the whole automaton rather than to the
+    /// new patterns. Adding patterns in batches, rather than one at a time,
+    /// keeps this cost low.
+    ///
+    /// The automaton is built with the same options as before, although
+    /// it may switch to a wider state ID representation to fit the new
+    /// states, even if a narrower one was forced with
+    /// `SimpleFinderBuilder::build_with_state_id`.
+    ///
+    /// # Panics
+    ///
+    /// This panics if adding the patterns fails. Use `try_extend` to handle
+    /// the error instead.
+    pub fn extend<'p, I>(&mut self, patterns: I)
+    where
+        I: IntoIterator<Item = (&'p str, D)>,
+    {
+        if let Err(err) = self.try_extend(patterns) {
+            panic!("failed to extend finder: {}", err);
+        }
+    }
+
+    /// Like `extend`, but returns an error if the patterns could not be
+    /// added, leaving the finder unchanged.
+    pub fn try_extend<'p, I>(&mut self, patterns: I) -> Result<(), BuildError>
+    where
+        I: IntoIterator<Item = (&'p str, D)>,
+    {
+        let (patterns, datas): (Vec<_>, Vec<_>) = patterns.into_iter().unzip();
+        let first_id = self.aho.pattern_count();
+
+        self.aho.add_patterns(patterns.iter().copied())?;
+        self.data.extend((first_id..).zip(datas));
+        if let Some(stored) = &mut self.patterns {
+            stored.extend(patterns.iter().map(|p| p.to_string()));
+        }
+        Ok(())
+    }
+
+    /// Combines this finder with `other` into a finder of the patterns of
+    /// both, with the patterns of `other` numbered after those of this one.
+    ///
+    /// The patterns of `other` are read back from its automaton, so they
+    /// needn't have been stored, but they keep the tokens that `other` split
+    /// them into. Both finders should be built with the same tokenizer, or
+    /// the patterns of `other` may not match as they did. The combined finder
+    /// is otherwise configured like this one, and only knows the text of its
+    /// patterns if both finders stored them.
+    ///
+    /// # Panics
+    ///
+    /// This panics if the finders normalize tokens differently, or if the
+    /// combined automaton can't be built, such as when the finders have too
+    /// many distinct tokens between them. Use `try_merge` to handle the
+    /// error instead.
+    pub fn merge(self, other: SimpleFinder<D>) -> SimpleFinder<D> {
+        match self.try_merge(other) {
+            Ok(merged) => merged,
+            Err(err) => panic!("failed to merge finders: {}", err),
+        }
+    }
+
+    /// Like `merge`, but returns an error if the finders normalize tokens
+    /// differently, with `BuildError::NormalizerMismatch`, or if the
+    /// combined automaton can't be built.
+    pub fn try_merge(mut self, other: SimpleFinder<D>) -> Result<SimpleFinder<D>, BuildError> {
+        let offset = self.aho.pattern_count();
+        self.aho.merge(&other.aho)?;
+
+        self.data
+            .extend(other.data.into_iter().map(|(id, data)| (offset + id, data)));
+        self.patterns = match (self.patterns, other.patterns) {
+            (Some(mut patterns), Some(other)) => {
+                patterns.extend(other);
+                Some(patterns)
+            }
+            _ => None,
+        };
+        Ok(self)
+    }
+
+    /// Returns the state of a search that hasn't consumed any tokens yet, to
+    /// be advanced with `step`.
+    ///
+    /// This returns `None` if the finder was built with a leftmost
+    /// `MatchKind`, whose searches need to look ahead of a match before
+    /// reporting it, or with case-sensitive patterns through
+    /// `build_with_options`, whose matches need the case of every token
+    /// they span.
+    pub fn start_state(&self) -> Option<SearchState> {
+        if !self.aho.steppable() {
+            return None;
+        }
+        Some(SearchState {
+            state_id: self.aho.start_state(),
+            pos: 0,
+        })
+    }
+
+    /// Advances a search by one token, returning every match ending at it,
+    /// including overlapping ones.
+    ///
+    /// This lets callers drive a search themselves, such as when text is
+    /// typed one word at a time. Tokens must be split the same way as this
+    /// finder splits haystacks, or patterns won't match; they're still
+    /// normalized as configured. As with `find_all_tokens`, the offsets of
+    /// the matches are token indices: a match of the last two tokens after
+    /// stepping through five has `start() == 3` and `end() == 5`.
+    ///
+    /// # Panics
+    ///
+    /// This panics if `state` was created by a different finder with fewer
+    /// states.
+    pub fn step<'a>(
+        &'a self,
+        state: &mut SearchState,
+        token: &str,
+    ) -> impl Iterator<Item = (Match, &'a D)> + 'a {
+        self.aho
+            .step(&mut state.state_id, &mut state.pos, token)
+            .filter_map(move |m| {
+                let data = self.data.get(&m.pattern)?;
+
+                Some((m, data))
+            })
+    }
+
+    /// Returns the match of the pattern that `haystack` consists of, if its
+    /// tokens are exactly those of a pattern.
+    ///
+    /// Unlike `find_all`, this never reports a pattern that only occurs
+    /// within the haystack, and gives up at the first token that doesn't
+    /// continue a pattern, so it's a quick way to check an input against a
+    /// dictionary of phrases. With `MatchKind::LeftmostFirst`, patterns that
+    /// begin with an earlier pattern can never match, so they're never found
+    /// here either.
+    pub fn find_anchored(&self, haystack: &str) -> Option<(Match, &D)> {
+        let m = self.aho.find_anchored(haystack)?;
+        let data = self.data.get(&m.pattern)?;
+
+        Some((m, data))
+    }
+
+    /// Like `find_all`, but yields only the data of each match.
+    ///
+    /// Unlike `find_all_unique`, this is lazy and doesn't need the data to
+    /// be `Copy` or hashable, but yields the data of a pattern as many times
+    /// as it matches.
+    pub fn find_all_data<'a: 'b, 'b>(
+        &'a self,
+        haystack: &'b str,
+    ) -> impl Iterator<Item = &'a D> + 'b {
+        self.find_all(haystack).map(|(_, data)| data)
+    }
+
+    /// Returns the earliest ending match in `haystack`, if any.
+    ///
+    /// Like `is_match`, this stops at the first match, without tokenizing
+    /// the rest of the haystack, so it's faster than
+    /// `find_all(haystack).next()`. With a leftmost `MatchKind`, this is the
+    /// first match that `find_all` reports instead, which needs the whole
+    /// haystack to be tokenized.
+    pub fn find_first(&self, haystack: &str) -> Option<(Match, &D)> {
+        let m = self.aho.find_first(haystack)?;
+        let data = self.data.get(&m.pattern)?;
+
+        Some((m, data))
+    }
+
+    /// Calls `f` with every match `find_all` would report in `haystack`, in
+    /// the same order, until it returns `ControlFlow::Break`, returning
+    /// whether it did.
+    ///
+    /// An overlapping search is driven directly, without an iterator, and
+    /// stops as soon as `f` breaks, though the whole haystack is tokenized
+    /// first. This allows stopping on conditions `find_first` can't express,
+    /// such as after a number of distinct patterns have matched.
+    ///
+    /// ```
+    /// use core::ops::ControlFlow;
+    /// use lacbd::SimpleFinder;
+    ///
+    /// let finder = SimpleFinder::new(vec![("foo", 0), ("bar", 1), ("baz", 2)]);
+    /// let mut seen = Vec::new();
+    /// finder.for_each_match("foo foo bar baz", |_, &d| {
+    ///     if !seen.contains(&d) {
+    ///         seen.push(d);
+    ///     }
+    ///     if seen.len() == 2 {
+    ///         ControlFlow::Break(())
+    ///     } else {
+    ///         ControlFlow::Continue(())
+    ///     }
+    /// });
+    /// assert_eq!(seen, vec![0, 1]);
+    /// ```
+    pub fn for_each_match<F>(&self, haystack: &str, mut f: F) -> ControlFlow<()>
+    where
+        F: FnMut(Match, &D) -> ControlFlow<()>,
+    {
+        let f = |m: Match| match self.data.get(&m.pattern) {
+            Some(data) => f(m, data),
+            None => ControlFlow::Continue(()),
+        };
+        if self.overlapping && !self.aho.match_kind().is_leftmost() {
+            self.aho.try_for_each_overlapping(haystack, f)
+        } else {
+            self.matches(haystack, self.overlapping).try_for_each(f)
+        }
+    }
+
+    /// Like `find_all`, but also yields the text of the haystack covered by
+    /// each match.
+    pub fn find_all_with_text<'a: 'b, 'b>(
+        &'a self,
+        haystack: &'b str,
+    ) -> impl Iterator<Item = (Match, &'a D, &'b str)> + 'b {
+        self.find_all(haystack).with_text()
+    }
+
+    /// Find all non-overlapping matches in `haystack`.
+    ///
+    /// Matches are found by scanning from the left, reporting the first match
+    /// state reached and then resuming the search after the end of that
+    /// match, so `"bar"` and `"bar baz"` will not both be reported for the
+    /// same text.
+    ///
+    /// With a leftmost `MatchKind`, matches are instead resolved according to
+    /// that kind.
+    pub fn find_all_nonoverlapping<'a: 'b, 'b>(
+        &'a self,
+        haystack: &'b str,
+    ) -> impl Iterator<Item = (Match, &'a D)> + 'b {
+        self.matches(haystack, false).filter_map(move |m| {
+            let data = self.data.get(&m.pattern)?;
+
+            Some((m, data))
+        })
+    }
+
+    /// Find all overlapping matches in a haystack that has already been
+    /// split into tokens, such as by another segmenter, skipping the
+    /// finder's own tokenization.
+    ///
+    /// The tokens are still normalized as configured, so that they compare
+    /// equal to the tokens of the patterns. The offsets of the matches are
+    /// token indices rather than char offsets: a match of `tokens[i..j]` has
+    /// `start() == byte_start() == i` and `end() == byte_end() == j`.
+    ///
+    /// # Panics
+    ///
+    /// This panics if the finder was built with a leftmost `MatchKind`.
+    pub fn find_all_tokens<'a: 'b, 'b>(
+        &'a self,
+        tokens: &[&'b str],
+    ) -> impl Iterator<Item = (Match, &'a D)> + 'b {
+        self.aho
+            .find_overlapping_iter_tokens(tokens)
+            .filter_map(move |m| {
+                let data = self.data.get(&m.pattern)?;
+
+                Some((m, data))
+            })
+    }
+
+    /// Find the matches in a haystack that has already been split into
+    /// tokens, returning them without their data.
+    ///
+    /// Like `find_all_tokens`, the tokens are normalized as configured, and
+    /// the offsets of the matches are token indices. Unlike it, this
+    /// searches as `find_all` does, following the finder's `MatchKind` and
+    /// whether it was built with `SimpleFinderBuilder::overlapping`.
+    ///
+    /// ```
+    /// use lacbd::{MatchKind, SimpleFinderBuilder};
+    ///
+    /// let finder = SimpleFinderBuilder::new()
+    ///     .match_kind(MatchKind::LeftmostLongest)
+    ///     .build(vec![("new", ()), ("new york", ())]);
+    /// let matches = finder.find_in_tokens(&["in", "new", "york"]);
+    ///
+    /// assert_eq!(matches.len(), 1);
+    /// assert_eq!((matches[0].token_start(), matches[0].token_end()), (1, 3));
+    /// ```
+    pub fn find_in_tokens(&self, tokens: &[&str]) -> Vec<Match> {
+        self.aho.find_in_tokens(tokens, self.overlapping)
+    }
+
+    /// Like `find_all`, but only yields matches that lie entirely within
+    /// `range`.
+    ///
+    /// `range` is given in char offsets, the same units as `Match::start` and
+    /// `Match::end`. The whole haystack is still tokenized, so matches are the
+    /// same as those `find_all` would report; a match straddling either end of
+    /// the range is dropped.
+    pub fn find_in_range<'a: 'b, 'b>(
+        &'a self,
+        haystack: &'b str,
+        range: Range<usize>,
+    ) -> impl Iterator<Item = (Match, &'a D)> + 'b {
+        self.find_all(haystack)
+            .filter(move |(m, _)| range.start <= m.start() && m.end() <= range.end)
+    }
+
+    /// Like `find_all`, but yields `CompactMatch`es without the associated
+    /// data, for when a large number of matches is being collected.
+    ///
+    /// Compact matches only hold char offsets; the byte offsets can be
+    /// recovered with `CompactMatch::to_matches`.
+    ///
+    /// A match that a `CompactMatch` can't represent, such as one ending
+    /// after the first `u32::MAX` chars of the haystack, is reported as an
+    /// error in its place.
+    pub fn find_all_compact<'a: 'b, 'b>(
+        &'a self,
+        haystack: &'b str,
+    ) -> impl Iterator<Item = Result<CompactMatch, TryFromIntError>> + 'b {
+        self.matches(haystack, self.overlapping)
+            .map(|m| CompactMatch::try_from(&m))
+    }
+
+    /// Returns a copy of `haystack` with each match replaced by the output of
+    /// `replacement`.
+    ///
+    /// Overlapping matches are resolved by taking the leftmost match, and the
+    /// longest of those starting at the same position, so every replaced span
+    /// is disjoint. With a leftmost `MatchKind`, the matches that kind
+    /// reports are replaced instead.
+    pub fn replace_all<F>(&self, haystack: &str, mut replacement: F) -> String
+    where
+        F: FnMut(&Match, &D) -> String,
+    {
+        let matches = ahocorasick::leftmost_longest(self.matches(haystack, true).collect());
+
+        let mut out = String::with_capacity(haystack.len());
+        let mut last_end = 0;
+        for m in matches {
+            let data = match self.data.get(&m.pattern) {
+                Some(data) => data,
+                None => continue,
+            };
+            out.push_str(&haystack[last_end..m.byte_start()]);
+            out.push_str(&replacement(&m, data));
+            last_end = m.byte_end();
+        }
+        out.push_str(&haystack[last_end..]);
+        out
+    }
+
+    /// Returns the spans of `haystack` to highlight, in order and without
+    /// overlapping, ready to be rendered.
+    ///
+    /// Matches are chosen as `replace_all` chooses the matches it replaces,
+    /// so the spans are the leftmost matches, and the longest of those
+    /// starting at the same position.
+    ///
+    /// ```
+    /// use lacbd::SimpleFinder;
+    ///
+    /// let finder = SimpleFinder::new(vec![("new", 0), ("new york", 1), ("york city", 2)]);
+    /// let haystack = "new york city";
+    /// let spans: Vec<_> = finder
+    ///     .highlights(haystack)
+    ///     .iter()
+    ///     .map(|h| &haystack[h.byte_start..h.byte_end])
+    ///     .collect();
+    /// assert_eq!(spans, vec!["new york"]);
+    /// ```
+    pub fn highlights(&self, haystack: &str) -> Vec<Highlight<'_, D>> {
+        ahocorasick::leftmost_longest(self.matches(haystack, true).collect())
+            .into_iter()
+            .filter_map(|m| {
+                Some(Highlight {
+                    byte_start: m.byte_start(),
+                    byte_end: m.byte_end(),
+                    pattern: m.pattern,
+                    data: self.data.get(&m.pattern)?,
+                })
+            })
+            .collect()
+    }
+
+    /// Start a search over a haystack that will be fed in chunks.
+    ///
+    /// # Panics
+    ///
+    /// This panics if the finder was built with a leftmost `MatchKind`, as a
+    /// leftmost match can't be chosen until the tokens after it are seen, or
+    /// with case-sensitive patterns through `build_with_options`.
+    pub fn chunked_search(&self) -> ChunkedSearch<'_, D> {
+        assert!(
+            !self.aho.match_kind().is_leftmost(),
+            "chunked searches are not supported with leftmost match kinds"
+        );
+        self.aho.assert_no_case_sensitive("chunked searches");
+        ChunkedSearch::new(self)
+    }
+
+    /// Start a search over a haystack that arrives as a stream of chunks,
+    /// carrying the state of the automaton from one chunk to the next.
+    ///
+    /// # Panics
+    ///
+    /// This panics if the finder was built with a leftmost `MatchKind`, as a
+    /// leftmost match can't be chosen until the tokens after it are seen, or
+    /// with case-sensitive patterns through `build_with_options`.
+    pub fn stream(&self) -> StreamFinder<'_, D> {
+        assert!(
+            !self.aho.match_kind().is_leftmost(),
+            "streaming searches are not supported with leftmost match kinds"
+        );
+        self.aho.assert_no_case_sensitive("streaming searches");
+        StreamFinder::new(self)
+    }
+
+    /// Returns the set of distinct tokens that any pattern starts with.
+    ///
+    /// Tokens are returned after normalization, so a haystack containing none
+    /// of these tokens (once tokenized with the same options) cannot contain
+    /// a match.
+    pub fn first_tokens(&self) -> HashSet<&str> {
+        self.aho.first_tokens().collect()
+    }
+
+    /// Returns the steps the automaton takes over each token of `haystack`,
+    /// for finding out why a pattern did or didn't match.
+    ///
+    /// Each step records the token as it was normalized, the states followed
+    /// through failure transitions before one had a transition on it, and
+    /// how many tokens of a pattern the automaton has matched afterwards.
+    /// Stop words are left out, as they are by searches.
+    ///
+    /// This is much slower than searching, and is only available with the
+    /// `trace` feature.
+    ///
+    /// ```
+    /// use lacbd::SimpleFinder;
+    ///
+    /// let finder = SimpleFinder::new(vec![("new york city", ())]);
+    /// let steps = finder.explain("new-york town");
+    /// let depths: Vec<_> = steps.iter().map(|step| step.depth()).collect();
+    /// // "-" is a token of its own, which the pattern doesn't have
+    /// assert_eq!(depths, vec![1, 0, 0, 0]);
+    /// ```
+    #[cfg(feature = "trace")]
+    pub fn explain(&self, haystack: &str) -> Vec<TraceStep> {
+        self.aho.explain(haystack)
+    }
+
+    /// Returns the ID, length in tokens and data of every pattern in the
+    /// finder, in order of their IDs.
+    ///
+    /// Patterns merged into an earlier one by
+    /// `SimpleFinderBuilder::dedup_patterns` are left out, as they are never
+    /// reported.
+    ///
+    /// ```
+    /// use lacbd::SimpleFinder;
+    ///
+    /// let finder = SimpleFinder::new(vec![("new york", 'a'), ("paris", 'b')]);
+    /// let patterns: Vec<_> = finder.patterns_iter().collect();
+    /// assert_eq!(patterns, vec![(0, 2, &'a'), (1, 1, &'b')]);
+    /// ```
+    pub fn patterns_iter(&self) -> impl Iterator<Item = (usize, usize, &D)> {
+        self.aho
+            .patterns_iter()
+            .filter_map(move |(id, len)| Some((id, len, self.data.get(&id)?)))
+    }
+
+    /// Returns the number of matches `find_all` would report in `haystack`.
+    ///
+    /// This skips computing the offsets of each match and looking up its
+    /// data.
+    pub fn count_matches(&self, haystack: &str) -> usize {
+        let mut count = 0;
+        self.aho
+            .for_each_match_pattern(haystack, self.overlapping, |_| count += 1);
+        count
+    }
+
+    /// Returns the fraction of the tokens of `haystack` that are covered by
+    /// a match, from `0.0` when nothing matches to `1.0` when every token
+    /// is part of some match, for scoring how relevant a haystack is.
+    ///
+    /// Every overlapping match counts, whether or not the finder was built
+    /// with `SimpleFinderBuilder::overlapping`, unless it was built with a
+    /// leftmost `MatchKind`, in which case the matches that `find_all`
+    /// reports do. Tokens dropped as stop words aren't counted, and neither
+    /// is whitespace, unless it's kept as tokens. A haystack without any
+    /// tokens has a coverage of `0.0`.
+    ///
+    /// ```
+    /// use lacbd::SimpleFinder;
+    ///
+    /// let finder = SimpleFinder::new(vec![("new york", 0), ("york city", 1)]);
+    /// assert_eq!(finder.coverage("new york city"), 1.0);
+    /// assert_eq!(finder.coverage("a new york bagel"), 0.5);
+    /// ```
+    pub fn coverage(&self, haystack: &str) -> f64 {
+        self.aho.coverage(haystack)
+    }
+
+    /// Like `count_matches`, but counts the matches of each pattern
+    /// separately, keyed by pattern ID. Patterns that don't match are left
+    /// out.
+    pub fn count_matches_per_pattern(&self, haystack: &str) -> HashMap<usize, usize> {
+        let mut counts = HashMap::new();
+        self.aho
+            .for_each_match_pattern(haystack, self.overlapping, |pattern| {
+                *counts.entry(pattern).or_insert(0) += 1
+            });
+        counts
+    }
+
+    /// Returns the matches `find_all` would report in `haystack`, sorted by
+    /// `key`.
+    ///
+    /// ```
+    /// use lacbd::{SimpleFinder, SortKey};
+    ///
+    /// let finder = SimpleFinder::new(vec![("new york", 0), ("york", 1), ("new", 2)]);
+    /// let matches = finder.find_all_sorted("new york", SortKey::Pattern);
+    /// let patterns: Vec<_> = matches.iter().map(|(m, _)| m.pattern()).collect();
+    /// assert_eq!(patterns, vec![0, 1, 2]);
+    /// ```
+    pub fn find_all_sorted(&self, haystack: &str, key: SortKey) -> Vec<(Match, &D)> {
+        let mut matches: Vec<_> = self.find_all(haystack).collect();
+        matches.sort_unstable_by_key(|(m, _)| key.key(m));
+        matches
+    }
+
+    /// Returns the set of keys derived by `key` from the data of every
+    /// pattern matching in `haystack`.
+    ///
+    /// This is `find_all_unique` for data that isn't `Copy`. The keys may
+    /// borrow from the data, so a finder with `String` data can collect
+    /// `&str` keys without cloning.
+    ///
+    /// ```
+    /// use lacbd::SimpleFinder;
+    ///
+    /// let finder = SimpleFinder::new(vec![
+    ///     ("colour", String::from("color")),
+    ///     ("color", String::from("color")),
+    /// ]);
+    /// let matched = finder.find_all_unique_by("colour or color", String::as_str);
+    ///
+    /// assert_eq!(matched.len(), 1);
+    /// assert!(matched.contains("color"));
+    /// ```
+    pub fn find_all_unique_by<'a, K, F>(&'a self, haystack: &str, key: F) -> HashSet<K>
+    where
+        K: core::hash::Hash + Eq,
+        F: Fn(&'a D) -> K,
+    {
+        self.find_all(haystack).map(|(_, d)| key(d)).collect()
+    }
+
+    /// Returns which patterns match in `haystack`, as a `Vec` of length
+    /// `pattern_count` that is true at the ID of each pattern that matched.
+    ///
+    /// This collects the same patterns as `find_all`, without hashing them,
+    /// which is quicker than collecting a set when there aren't many
+    /// patterns.
+    pub fn matched_pattern_set(&self, haystack: &str) -> Vec<bool> {
+        let mut matched = alloc::vec![false; self.pattern_count()];
+        self.aho
+            .for_each_match_pattern(haystack, self.overlapping, |pattern| {
+                matched[pattern] = true
+            });
+        matched
+    }
+
+    /// Returns how many distinct patterns match in `haystack`.
+    ///
+    /// Patterns are counted by their ID rather than their data, so two
+    /// patterns with the same data that both match count twice, unlike with
+    /// `find_all_unique`. The patterns counted are those `find_all` reports,
+    /// each marked in a bitset as the search finds it, and nothing is hashed.
+    ///
+    /// ```
+    /// use lacbd::SimpleFinder;
+    ///
+    /// let finder = SimpleFinder::new(vec![("colour", "color"), ("color", "color")]);
+    ///
+    /// assert_eq!(finder.distinct_pattern_count("colour, color, or color"), 2);
+    /// ```
+    pub fn distinct_pattern_count(&self, haystack: &str) -> usize {
+        let mut matched = alloc::vec![0u64; self.pattern_count().div_ceil(64)];
+        self.aho
+            .for_each_match_pattern(haystack, self.overlapping, |pattern| {
+                matched[pattern / 64] |= 1 << (pattern % 64)
+            });
+        matched.iter().map(|bits| bits.count_ones() as usize).sum()
+    }
+
+    pub fn pattern_count(&self) -> usize {
+        self.aho.pattern_count()
+    }
+
+    /// Returns the number of tokens in the longest pattern.
+    ///
+    /// When searching a haystack in chunks, a match can start up to
+    /// `max_pattern_len() - 1` tokens before the chunk it ends in, so each
+    /// chunk should overlap the previous one by that many tokens.
+    pub fn max_pattern_len(&self) -> usize {
+        self.aho.max_pattern_len()
+    }
+
+    /// Returns true if the finder's automaton matches the empty haystack,
+    /// because its start state is a match state.
+    ///
+    /// This is always false, as patterns without any tokens are rejected with
+    /// `BuildError::EmptyPattern`, and `SimpleFinder::from_bytes` rejects
+    /// automata with empty matches.
+    pub fn matches_empty(&self) -> bool {
+        self.aho.matches_empty()
+    }
+
+    /// Returns the number of states in the finder's automaton, including the
+    /// start state and the two states every automaton has for failing.
+    pub fn state_count(&self) -> usize {
+        self.aho.state_count()
+    }
+
+    /// Returns statistics about the size and shape of the finder's
+    /// automaton.
+    pub fn stats(&self) -> Stats {
+        Stats {
+            pattern_count: self.pattern_count(),
+            state_count: self.state_count(),
+            dense_state_count: self.aho.dense_state_count(),
+            max_pattern_len: self.aho.max_pattern_len(),
+            max_out_degree: self.aho.max_out_degree(),
+            heap_bytes: self.heap_bytes(),
+        }
+    }
+
+    /// Returns the original text of the pattern with the given ID, as
+    /// reported by `Match::pattern`.
+    ///
+    /// This returns `None` if there is no such pattern, or if the finder was
+    /// built with `SimpleFinderBuilder::store_patterns` disabled.
+    pub fn pattern(&self, id: usize) -> Option<&str> {
+        self.patterns.as_ref()?.get(id).map(String::as_str)
+    }
+
+    /// Returns the approximate amount of heap memory used by the finder, in
+    /// bytes.
+    ///
+    /// This counts the automaton, the stored patterns, and the table mapping
+    /// pattern IDs to data, including the space for each `D` in it. Anything
+    /// that the data owns on the heap, such as the contents of a `String`,
+    /// isn't counted; use `heap_bytes_with_data` to include it.
+    pub fn heap_bytes(&self) -> usize {
+        self.aho.heap_bytes() + heap_size::map_heap_bytes(&self.data) + self.patterns.heap_bytes()
+    }
+
+    /// Frees the spare capacity left in the finder's automaton, pattern
+    /// table and stored patterns, so that `heap_bytes` is as small as it
+    /// can be.
+    ///
+    /// Building a finder, or extending it, leaves room to spare in the
+    /// vectors and maps it grows. A finder that is kept around for a long
+    /// time can reclaim it with this, which costs about as much as copying
+    /// the automaton.
+    pub fn shrink_to_fit(&mut self) {
+        self.aho.shrink_to_fit();
+        self.data.shrink_to_fit();
+        if let Some(patterns) = &mut self.patterns {
+            patterns.shrink_to_fit();
+        }
+    }
+
+    pub fn data(&self) -> &HashMap<usize, D> {
+        &self.data
+    }
+
+    /// Splits the finder into its automaton, as plain data that can be
+    /// stored in any format, and the data of its patterns in order of their
+    /// IDs, to be put back together with
+    /// `SimpleFinderBuilder::try_build_from_parts`.
+    ///
+    /// A finder built as a DFA has its NFA built again from its patterns, as
+    /// the DFA doesn't keep the failure transitions of the NFA.
+    ///
+    /// # Panics
+    ///
+    /// This panics if the finder was built with case-sensitive patterns
+    /// through `build_with_options`, whose case the parts can't hold.
+    pub fn into_parts(self) -> (AutomatonParts, Vec<D>) {
+        let mut data: Vec<_> = self.data.into_iter().collect();
+        data.sort_unstable_by_key(|&(pattern, _)| pattern);
+        (
+            self.aho.into_parts(),
+            data.into_iter().map(|(_, data)| data).collect(),
+        )
+    }
+}
+
+#[cfg(feature = "serialize")]
+impl<D> SimpleFinder<D> {
+    /// Serializes the compiled automaton, so that it can be restored with
+    /// `SimpleFinder::from_bytes` without building it again.
+    ///
+    /// The configuration from the builder and the stored patterns are
+    /// included, but the data isn't. This returns an error if the finder uses
+    /// a custom `Tokenizer` or word character classifier.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, SerializeError> {
+        let mut w = serialize::Writer::new();
+        self.aho.write_to(&mut w)?;
+        w.write_bool(self.overlapping);
+
+        match &self.patterns {
+            Some(patterns) => {
+                w.write_bool(true);
+                w.write_usize(patterns.len());
+                for pattern in patterns {
+                    w.write_str(pattern);
+                }
+            }
+            None => w.write_bool(false),
+        }
+        Ok(w.into_bytes())
+    }
+
+    /// Restores a finder serialized with `SimpleFinder::to_bytes`, pairing
+    /// each pattern with the data in the same position of `data`, as when
+    /// building it.
+    ///
+    /// This returns `SerializeError::DataCountMismatch` unless `data` has
+    /// exactly one item for each pattern.
+    pub fn from_bytes<I>(bytes: &[u8], data: I) -> Result<Self, SerializeError>
+    where
+        I: IntoIterator<Item = D>,
+    {
+        let mut r = serialize::Reader::new(bytes)?;
+        let aho = ahocorasick::AhoCorasick::read_from(&mut r)?;
+        let overlapping = r.read_bool()?;
+
+        let patterns = if r.read_bool()? {
+            let len = r.read_len(8)?;
+            let patterns = (0..len).map(|_| r.read_str()).collect::<Result<_, _>>()?;
+            Some(patterns)
+        } else {
+            None
+        };
+        r.finish()?;
+
+        let data: Vec<D> = data.into_iter().collect();
+        if data.len() != aho.pattern_count() {
+            return Err(SerializeError::DataCountMismatch {
+                patterns: aho.pattern_count(),
+                data: data.len(),
+            });
+        }
+        let data = (0..aho.pattern_count()).zip(data).collect();
+        Ok(SimpleFinder {
+            aho,
+            data,
+            patterns,
+            overlapping,
+        })
+    }
+}
+
+impl<D: Clone> SimpleFinder<D> {
+    /// Clears `buf` and fills it with the matches `find_all` would report in
+    /// `haystack`, along with a clone of their data.
+    ///
+    /// Reusing the same buffer across many searches saves allocating a new
+    /// collection for each of them, once it has grown to fit the most
+    /// matches of any haystack. Tokenizing the haystack still allocates.
+    ///
+    /// ```
+    /// use lacbd::SimpleFinder;
+    ///
+    /// let finder = SimpleFinder::new(vec![("foo", 'f'), ("bar", 'b')]);
+    /// let mut buf = Vec::new();
+    /// for haystack in &["foo bar", "bar"] {
+    ///     finder.find_all_into(haystack, &mut buf);
+    ///     assert_eq!(buf.last().map(|(_, d)| *d), Some('b'));
+    /// }
+    /// assert_eq!(buf.len(), 1);
+    /// ```
+    pub fn find_all_into(&self, haystack: &str, buf: &mut Vec<(Match, D)>) {
+        buf.clear();
+        buf.extend(self.find_all(haystack).map(|(m, d)| (m, d.clone())));
+    }
+}
+
+impl<'p, D: core::hash::Hash + core::cmp::Eq + Copy> SimpleFinder<D> {
+    /// Returns the set of data of every pattern matching in `haystack`.
+    ///
+    /// The haystack is normalized as the patterns were, so a finder built
+    /// with `SimpleFinderBuilder::case_insensitive` collects the data of
+    /// `"foo"` from `"FOO"` too.
+    pub fn find_all_unique<'a, 'b>(&'a self, haystack: &'b str) -> HashSet<D> {
+        self.find_all(haystack).map(|(_, d)| *d).collect()
+    }
+}
+
+impl SimpleFinder<usize> {
+    /// Create a finder for a list of literal patterns, whose data is the
+    /// index of each pattern in the list, as `RegexSet` numbers its patterns.
+    ///
+    /// This eases moving from a `regex::RegexSet` of escaped literals.
+    /// `RegexSet::matches` and `find_all_unique` both return the indices of
+    /// the matching patterns, though not in the same collection.
+    ///
+    /// A pattern here always matches whole tokens, so the `\b` word
+    /// boundaries usually wrapped around each literal aren't needed. Tokens
+    /// are the words and symbols of the haystack, so a pattern `"foo"` won't
+    /// match inside `"foobar"`, but will match the `"foo"` in `"foo-bar"`.
+    /// Whitespace only separates tokens, so `"foo bar"` also matches
+    /// `"foo \t bar"`. A literal starting or ending with a symbol, such as
+    /// `"c++"`, matches where `\b` around it would fail. For `(?i)`, build
+    /// with `SimpleFinderBuilder::case_insensitive` instead.
+    ///
+    /// ```
+    /// use lacbd::SimpleFinder;
+    ///
+    /// // Replaces `RegexSet::new([r"\bfoo\b", r"\bbar baz\b"])`.
+    /// let finder = SimpleFinder::from_literals(&["foo", "bar baz"]);
+    /// let matched = finder.find_all_unique("a bar baz, then foo");
+    ///
+    /// assert!(matched.contains(&0) && matched.contains(&1));
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// This panics if building the automaton fails.
+    pub fn from_literals<I, P>(literals: I) -> Self
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<str>,
+    {
+        let literals: Vec<P> = literals.into_iter().collect();
+        SimpleFinder::new(literals.iter().enumerate().map(|(i, l)| (l.as_ref(), i)))
+    }
+}
+
+/// Collects patterns and their associated data into a finder with the
+/// default configuration, as `SimpleFinder::new` does.
+///
+/// ```
+/// use lacbd::SimpleFinder;
+///
+/// let finder: SimpleFinder<_> = vec!["foo", "bar baz"]
+///     .into_iter()
+///     .enumerate()
+///     .map(|(id, pattern)| (pattern, id))
+///     .collect();
+/// assert_eq!(finder.find_all_data("foo bar baz").count(), 2);
+/// ```
+///
+/// # Panics
+///
+/// This panics if building the automaton fails.
+impl<'p, D> core::iter::FromIterator<(&'p str, D)> for SimpleFinder<D> {
+    fn from_iter<I>(patterns: I) -> Self
+    where
+        I: IntoIterator<Item = (&'p str, D)>,
+    {
+        SimpleFinder::new(patterns)
+    }
+}
+
+impl<D: HeapSize> SimpleFinder<D> {
+    /// Returns the approximate amount of heap memory used by the finder, in
+    /// bytes, including the memory owned by the data of each pattern.
+    pub fn heap_bytes_with_data(&self) -> usize {
+        self.heap_bytes() + self.data.values().map(D::heap_bytes).sum::<usize>()
+    }
+}
+
+/// Summarizes the size of the finder's automaton, without listing its
+/// patterns or data, so that it stays short however large the finder is.
+impl<D> fmt::Debug for SimpleFinder<D> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SimpleFinder")
+            .field("pattern_count", &self.pattern_count())
+            .field("state_count", &self.state_count())
+            .field("heap_bytes", &self.heap_bytes())
+            .field("max_pattern_len", &self.aho.max_pattern_len())
+            .field("match_kind", &self.aho.match_kind())
+            .finish()
+    }
+}
+
+impl<D> fmt::Display for SimpleFinder<D> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "finder of {} patterns of up to {} tokens, in {} states using {} bytes",
+            self.pattern_count(),
+            self.aho.max_pattern_len(),
+            self.state_count(),
+            self.heap_bytes()
+        )
+    }
+}
+
+impl<D> SimpleFinder<Vec<D>> {
+    /// Like `find_all`, but for patterns with several data values, yielding
+    /// each match once for every value of its pattern, in order.
+    ///
+    /// A pattern with no data values is never reported.
+    pub fn find_all_flat<'a: 'b, 'b>(
+        &'a self,
+        haystack: &'b str,
+    ) -> impl Iterator<Item = (Match, &'a D)> + 'b {
+        self.find_all(haystack)
+            .flat_map(|(m, datas)| datas.iter().map(move |data| (m.clone(), data)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::{format, prelude::v1::*, vec};
+
+    #[test]
+    fn test_iguess() {
+        let finder = SimpleFinder::new(vec![
+            ("foo", 123),
+            ("bar", 234),
+            ("baz", 345),
+            ("bar baz", 456),
+        ]);
+
+        let results: Vec<u64> = finder
+            .find_all("foo bar baz foobar foo'bar foo,bar")
+            .map(|(_, k)| k)
+            .cloned()
+            .collect();
+
+        assert_eq!(
+            results.len(),
+            8,
+            "Results {:?} was not 8 in length?",
+            results
+        );
+        assert!(results.contains(&123));
+        assert!(results.contains(&234));
+        assert!(results.contains(&345));
+        assert!(results.contains(&456));
+    }
+
+    #[test]
+    fn test_find_all_flat() {
+        let finder = SimpleFinder::new(vec![
+            ("foo", vec!["noun", "slang"]),
+            ("bar baz", vec!["place"]),
+            ("baz", vec![]),
+        ]);
+
+        let found: Vec<_> = finder
+            .find_all_flat("foo bar baz")
+            .map(|(m, &tag)| (m.start(), m.end(), tag))
+            .collect();
+        assert_eq!(
+            found,
+            vec![(0, 3, "noun"), (0, 3, "slang"), (4, 11, "place")]
+        );
+    }
+
+    #[test]
+    fn test_find_all_data() {
+        let finder = SimpleFinder::new(vec![
+            ("foo", "a foo".to_string()),
+            ("bar", "a bar".to_string()),
+        ]);
+
+        let mut data = finder.find_all_data("bar foo bar");
+        assert_eq!(data.next().map(String::as_str), Some("a bar"));
+        let rest: Vec<_> = data.collect();
+        assert_eq!(rest, vec!["a foo", "a bar"]);
+        // the data is borrowed from the finder, not cloned
+        assert!(std::ptr::eq(rest[1], &finder.data()[&1]));
+    }
+
+    #[test]
+    fn test_patterns_iter() {
+        let patterns = vec![
+            ("foo bar baz", "a"),
+            ("bar", "b"),
+            ("Foo, bar", "c"),
+            ("bar baz", "d"),
+            ("qux", "e"),
+        ];
+        for &dfa in &[false, true] {
+            let mut finder = SimpleFinderBuilder::new().dfa(dfa).build(patterns.clone());
+            let found: Vec<_> = finder.patterns_iter().collect();
+            assert_eq!(
+                found,
+                vec![
+                    (0, 3, &"a"),
+                    (1, 1, &"b"),
+                    (2, 3, &"c"),
+                    (3, 2, &"d"),
+                    (4, 1, &"e")
+                ]
+            );
+
+            finder.extend(vec![("baz", "f")]);
+            let found: Vec<_> = finder
+                .patterns_iter()
+                .map(|(id, len, _)| (id, len))
+                .collect();
+            assert_eq!(found.len(), finder.pattern_count());
+            assert_eq!(found.last(), Some(&(5, 1)));
+
+            // the second "bar" never matches
+            let mut duplicated = patterns.clone();
+            duplicated.push(("bar", "f"));
+            let finder = SimpleFinderBuilder::new()
+                .dfa(dfa)
+                .dedup_patterns(true)
+                .build(duplicated);
+            let ids: Vec<_> = finder.patterns_iter().map(|(id, _, _)| id).collect();
+            assert_eq!(ids, vec![0, 1, 2, 3, 4]);
+        }
+    }
+
+    #[test]
+    fn test_find_all_sorted() {
+        let patterns = vec![
+            ("lol lol_", 0),
+            ("lol lol", 2),
+            ("lol", 3),
+            ("lol lol lol", 4),
+        ];
+        let finder = SimpleFinder::new(patterns);
+        let haystack = "lol lol lol lol_";
+        let sorted = |key| -> Vec<_> {
+            finder
+                .find_all_sorted(haystack, key)
+                .into_iter()
+                .map(|(m, &d)| (m.pattern(), m.start(), m.end(), d))
+                .collect()
+        };
+
+        assert_eq!(
+            sorted(SortKey::Start),
+            vec![
+                (2, 0, 3, 3),
+                (1, 0, 7, 2),
+                (3, 0, 11, 4),
+                (2, 4, 7, 3),
+                (1, 4, 11, 2),
+                (2, 8, 11, 3),
+                (0, 8, 16, 0),
+            ]
+        );
+        assert_eq!(
+            sorted(SortKey::End),
+            vec![
+                (2, 0, 3, 3),
+                (1, 0, 7, 2),
+                (2, 4, 7, 3),
+                (3, 0, 11, 4),
+                (1, 4, 11, 2),
+                (2, 8, 11, 3),
+                (0, 8, 16, 0),
+            ]
+        );
+        assert_eq!(
+            sorted(SortKey::Pattern),
+            vec![
+                (0, 8, 16, 0),
+                (1, 0, 7, 2),
+                (1, 4, 11, 2),
+                (2, 0, 3, 3),
+                (2, 4, 7, 3),
+                (2, 8, 11, 3),
+                (3, 0, 11, 4),
+            ]
+        );
+
+        let mut unsorted: Vec<_> = finder.find_all(haystack).collect();
+        unsorted.sort_by_key(|(m, _)| (m.end(), m.start(), m.pattern()));
+        assert_eq!(unsorted, finder.find_all_sorted(haystack, SortKey::End));
+    }
+
+    #[test]
+    fn test_find_all_into() {
+        let finder = SimpleFinder::new(vec![
+            ("foo bar", String::from("a")),
+            ("bar", String::from("b")),
+            ("baz", String::from("c")),
+        ]);
+        let owned = |haystack| -> Vec<_> {
+            finder
+                .find_all(haystack)
+                .map(|(m, d)| (m, d.clone()))
+                .collect()
+        };
+
+        let mut buf = Vec::new();
+        finder.find_all_into("foo bar baz bar", &mut buf);
+        assert_eq!(buf, owned("foo bar baz bar"));
+        assert_eq!(buf.len(), 4);
+        let capacity = buf.capacity();
+
+        finder.find_all_into("baz", &mut buf);
+        assert_eq!(buf, owned("baz"));
+        assert_eq!(buf.capacity(), capacity);
+
+        finder.find_all_into("qux", &mut buf);
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn test_bytes_finder() {
+        let patterns: Vec<(&[u8], _)> = vec![
+            (b"caf\xe9", 0),
+            (b"na\xefve", 1),
+            (b"\xff\xfe", 2),
+            (b"new york", 3),
+        ];
+        // not valid UTF-8
+        let haystack = b"un caf\xe9 na\xefve \xff\xfe in new york, caf\xe9s";
+
+        let finder = SimpleFinderBuilder::new().build_bytes(patterns.clone());
+        let results: Vec<_> = finder
+            .find_all(haystack)
+            .map(|(m, &d)| (&haystack[m.byte_start()..m.byte_end()], m.start(), d))
+            .collect();
+        assert_eq!(
+            results,
+            vec![
+                (&b"caf\xe9"[..], 3, 0),
+                (&b"na\xefve"[..], 8, 1),
+                (&b"\xff\xfe"[..], 14, 2),
+                (&b"new york"[..], 20, 3),
+            ]
+        );
+        // both bytes are Latin-1 letters, so they only match as one word
+        assert!(finder.is_match(b"x \xff\xfe"));
+        assert!(!finder.is_match(b"\xff \xfe"));
+        assert!(!finder.is_match(b"cafe caf\xe9s"));
+        assert_eq!(finder.pattern_count(), 4);
+
+        let finder = SimpleFinderBuilder::new()
+            .case_insensitive(true)
+            .match_kind(MatchKind::LeftmostLongest)
+            .build_bytes(patterns);
+        let results: Vec<_> = finder
+            .find_all(b"CAF\xc9 NEW YORK")
+            .map(|(m, &d)| (m.byte_start(), m.byte_end(), d))
+            .collect();
+        assert_eq!(results, vec![(0, 4, 0), (5, 13, 3)]);
+
+        let result = SimpleFinderBuilder::new().try_build_bytes(vec![(&b"  "[..], 0)]);
+        assert_eq!(result.err(), Some(BuildError::EmptyPattern { pattern: 0 }));
+
+        // bytes that are whitespace as Unicode chars are symbols, not
+        // separators
+        let finder = SimpleFinderBuilder::new().build_bytes(vec![(&b"a b"[..], 0)]);
+        assert!(finder.is_match(b"a\tb"));
+        assert!(!finder.is_match(b"a\xa0b"));
+        assert!(!finder.is_match(b"a\x85b"));
+        let finder = SimpleFinderBuilder::new().build_bytes(vec![(&b"a\xa0b"[..], 0)]);
+        let results: Vec<_> = finder
+            .find_all(b"x a\xa0b")
+            .map(|(m, _)| (m.byte_start(), m.byte_end(), m.token_start(), m.token_end()))
+            .collect();
+        assert_eq!(results, vec![(2, 5, 1, 4)]);
+
+        for b in 0..=255u8 {
+            assert_eq!(
+                bytes::is_latin1_word_byte(b),
+                is_word_character(char::from(b)),
+                "{:#x}",
+                b
+            );
+        }
+    }
+
+    #[test]
+    fn test_pattern_options() {
+        let patterns = vec![
+            ("US", 0, PatternOptions::new().case_sensitive(true)),
+            ("usa", 1, PatternOptions::new()),
+            ("us army", 2, PatternOptions::new()),
+        ];
+        let haystack = "Us and the USA, not us but the US and the us Army";
+        let finder = SimpleFinderBuilder::new()
+            .case_insensitive(true)
+            .build_with_options(patterns.clone());
+        let spans = |results: Vec<(Match, &usize)>| -> Vec<_> {
+            results
+                .into_iter()
+                .map(|(m, &d)| (&haystack[m.byte_start()..m.byte_end()], d))
+                .collect()
+        };
+        let expected = vec![("USA", 1), ("US", 0), ("us Army", 2)];
+        assert_eq!(spans(finder.find_all(haystack).collect()), expected);
+        assert_eq!(spans(finder.find_checked(haystack).collect()), expected);
+        assert_eq!(finder.count_matches(haystack), 3);
+        assert_eq!(
+            spans(finder.find_first(haystack).into_iter().collect()),
+            vec![("USA", 1)]
+        );
+        assert!(finder.is_match("the US"));
+        assert!(!finder.is_match("Us and us"));
+        assert_eq!(finder.find_anchored("US").map(|(_, &d)| d), Some(0));
+        assert_eq!(finder.find_anchored("us"), None);
+
+        let finder = SimpleFinderBuilder::new()
+            .case_insensitive(true)
+            .overlapping(false)
+            .build_with_options(patterns.clone());
+        assert_eq!(spans(finder.find_all(haystack).collect()), expected);
+
+        // "us" shares its state with "US", and still matches in any case
+        let finder = SimpleFinderBuilder::new()
+            .case_insensitive(true)
+            .build_with_options(vec![
+                ("US", 0, PatternOptions::new().case_sensitive(true)),
+                ("us", 1, PatternOptions::new()),
+            ]);
+        let results: Vec<_> = finder
+            .find_all("Us US")
+            .map(|(m, &d)| (m.start(), d))
+            .collect();
+        assert_eq!(results, vec![(0, 1), (3, 0), (3, 1)]);
+
+        // without case folding, every pattern is case-sensitive anyway
+        let finder = SimpleFinderBuilder::new().build_with_options(patterns.clone());
+        assert!(finder.is_match("us army"));
+        assert!(!finder.is_match("us Army"));
+
+        #[cfg(feature = "serialize")]
+        {
+            let finder = SimpleFinderBuilder::new()
+                .case_insensitive(true)
+                .build_with_options(patterns);
+            let loaded = SimpleFinder::from_bytes(&finder.to_bytes().unwrap(), [0, 1, 2]).unwrap();
+            assert_eq!(spans(loaded.find_all(haystack).collect()), expected);
+        }
+    }
+
+    #[test]
+    fn test_pattern_options_leftmost() {
+        let result = SimpleFinderBuilder::new()
+            .case_insensitive(true)
+            .match_kind(MatchKind::LeftmostLongest)
+            .try_build_with_options(vec![
+                ("usa", 0, PatternOptions::new()),
+                ("US", 1, PatternOptions::new().case_sensitive(true)),
+            ]);
+        assert_eq!(
+            result.err(),
+            Some(BuildError::CaseSensitiveLeftmost { pattern: 1 })
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "streaming searches are not supported")]
+    fn test_pattern_options_stream() {
+        let finder = SimpleFinderBuilder::new()
+            .case_insensitive(true)
+            .build_with_options(vec![("US", 0, PatternOptions::new().case_sensitive(true))]);
+        finder.stream();
+    }
+
+    #[test]
+    fn test_matches_empty() {
+        for &dfa in &[false, true] {
+            let finder = SimpleFinderBuilder::new().dfa(dfa).build(vec![("a b", 0)]);
+            assert!(!finder.matches_empty());
+
+            let empty = SimpleFinderBuilder::new()
+                .dfa(dfa)
+                .try_build(vec![("", 0), ("a", 1)]);
+            assert_eq!(empty.err(), Some(BuildError::EmptyPattern { pattern: 0 }));
+        }
+    }
+
+    #[test]
+    fn test_max_match_tokens() {
+        let patterns = vec![("new york", 0), ("the big apple", 1), ("nyc", 2)];
+
+        let mut builder = SimpleFinderBuilder::new();
+        builder.max_match_tokens(2);
+        let result = builder.try_build(patterns.clone());
+        assert_eq!(
+            result.err(),
+            Some(BuildError::PatternTooLong {
+                pattern: 1,
+                len: 3,
+                max: 2
+            })
+        );
+
+        // stop words don't count towards the limit
+        builder.stop_words(vec!["the"]);
+        let finder = builder.build(patterns);
+        assert_eq!(finder.max_pattern_len(), 2);
+        assert_eq!(finder.count_matches("the big apple is new york"), 2);
+
+        #[cfg(feature = "serialize")]
+        {
+            let mut loaded =
+                SimpleFinder::from_bytes(&finder.to_bytes().unwrap(), [0, 1, 2]).unwrap();
+            let result = loaded.try_extend(vec![("statue of liberty", 3)]);
+            assert_eq!(
+                result.err(),
+                Some(BuildError::PatternTooLong {
+                    pattern: 0,
+                    len: 3,
+                    max: 2
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn test_resume_overlapping() {
+        let finder = SimpleFinder::new(vec![
+            ("new", 0),
+            ("new york", 1),
+            ("york", 2),
+            ("new york city", 3),
+            ("city", 4),
+        ]);
+        let haystack = "new york city, not new york";
+        let all: Vec<_> = finder.find_overlapping(haystack).collect();
+        assert_eq!(all.len(), 8);
+
+        for split in 0..=all.len() {
+            let mut iter = finder.find_overlapping(haystack);
+            let mut results: Vec<_> = iter.by_ref().take(split).collect();
+            let cursor = iter.cursor();
+            results.extend(finder.resume_overlapping(haystack, cursor));
+            assert_eq!(results, all);
+        }
+
+        let mut iter = finder.find_overlapping(haystack);
+        iter.by_ref().take(5).for_each(drop);
+        let cursor = iter.cursor();
+        assert_eq!(cursor.tokens_consumed(), 3);
+        #[cfg(feature = "serde")]
+        {
+            let json = serde_json::to_string(&cursor).unwrap();
+            let cursor: Cursor = serde_json::from_str(&json).unwrap();
+            assert_eq!(finder.resume_overlapping(haystack, cursor).count(), 3);
+        }
+
+        // a cursor from a longer haystack, or a larger finder, finds nothing
+        let mut iter = finder.find_overlapping(haystack);
+        iter.by_ref().for_each(drop);
+        assert_eq!(
+            finder.resume_overlapping("new york", iter.cursor()).count(),
+            0
+        );
+        let cursor = Cursor {
+            pos: 1,
+            state_id: 1000,
+            match_index: 0,
+        };
+        assert_eq!(finder.resume_overlapping(haystack, cursor).count(), 0);
+        // nor does one in a state deeper than the tokens it has consumed
+        let mut iter = finder.find_overlapping(haystack);
+        iter.by_ref().take(3).for_each(drop);
+        let cursor = Cursor {
+            pos: 0,
+            ..iter.cursor()
+        };
+        assert_eq!(finder.resume_overlapping(haystack, cursor).count(), 0);
+    }
+
+    #[test]
+    fn test_coverage() {
+        let patterns = vec![("quick brown", 0), ("brown fox", 1), ("lazy", 2)];
+        let haystack = "the quick brown fox jumps over the lazy dog";
+
+        // overlapping matches cover "quick brown fox" and "lazy"
+        let finder = SimpleFinder::new(patterns.clone());
+        assert_eq!(finder.coverage(haystack), 4.0 / 9.0);
+        assert_eq!(finder.coverage("no matches here"), 0.0);
+        assert_eq!(finder.coverage(" , "), 0.0);
+        assert_eq!(finder.coverage(""), 0.0);
+
+        let finder = SimpleFinderBuilder::new()
+            .overlapping(false)
+            .build(patterns.clone());
+        assert_eq!(finder.coverage(haystack), 4.0 / 9.0);
+
+        // "brown fox" is skipped, as it overlaps the leftmost match
+        let finder = SimpleFinderBuilder::new()
+            .match_kind(MatchKind::LeftmostLongest)
+            .build(patterns.clone());
+        assert_eq!(finder.coverage(haystack), 3.0 / 9.0);
+
+        let finder = SimpleFinderBuilder::new()
+            .stop_words(vec!["the", "over"])
+            .build(patterns);
+        assert_eq!(finder.coverage(haystack), 4.0 / 6.0);
+    }
+
+    #[test]
+    fn test_strip_symbols() {
+        let patterns = vec![("e-mail", 0), ("USA", 1), ("fast", 2)];
+        let haystack = "email from the U.S.A, by e-mail--fast";
+
+        let finder = SimpleFinder::new(patterns.clone());
+        let results: Vec<_> = finder.find_all(haystack).map(|(_, &d)| d).collect();
+        assert_eq!(results, vec![0, 2]);
+
+        let finder = SimpleFinderBuilder::new()
+            .strip_symbols(vec!["-", "."])
+            .build(patterns.clone());
+        let results: Vec<_> = finder
+            .find_all(haystack)
+            .map(|(m, &d)| (&haystack[m.byte_start()..m.byte_end()], d))
+            .collect();
+        assert_eq!(
+            results,
+            vec![("email", 0), ("U.S.A", 1), ("e-mail", 0), ("fast", 2)]
+        );
+        // symbols not between two words are dropped
+        assert!(finder.is_match("made in the U.S.A."));
+        assert!(finder.is_match(".USA"));
+        assert!(!finder.is_match("e - mail"));
+        assert!(finder.is_match("e - mail - fast"));
+
+        // the patterns are stripped too
+        let finder = SimpleFinderBuilder::new()
+            .case_insensitive(true)
+            .strip_symbols(vec!["."])
+            .build(vec![("U.S.A", 0), ("e.g. this", 1)]);
+        assert!(finder.is_match("usa"));
+        assert!(finder.is_match("eg this"));
+        assert!(finder.is_match("e.g. this"));
+        // ".," is a symbol token of its own, which isn't stripped
+        assert!(!finder.is_match("e.g., this"));
+
+        let finder = SimpleFinderBuilder::new()
+            .strip_symbols(vec!["-"])
+            .build(vec![("a b", 0), ("a--b", 1)]);
+        assert_eq!(finder.find_all("a-b").count(), 0);
+        assert_eq!(finder.find_all("a--b").count(), 1);
+        assert_eq!(finder.find_all("a - b").count(), 1);
+
+        #[cfg(feature = "serialize")]
+        {
+            let finder = SimpleFinderBuilder::new()
+                .strip_symbols(vec!["-", "."])
+                .build(patterns);
+            let loaded = SimpleFinder::from_bytes(&finder.to_bytes().unwrap(), [0, 1, 2]).unwrap();
+            let results: Vec<_> = loaded.find_all(haystack).map(|(_, &d)| d).collect();
+            assert_eq!(results, vec![0, 1, 0, 2]);
+        }
+    }
+
+    #[test]
+    fn test_new_owned() {
+        let patterns = vec![(String::from("new york"), 0), (String::from("york"), 1)];
+        let finder = SimpleFinder::new_owned(patterns);
+        let results: Vec<_> = finder
+            .find_all("new york")
+            .map(|(m, &d)| (m.start(), d))
+            .collect();
+        assert_eq!(results, vec![(0, 0), (4, 1)]);
+        assert_eq!(finder.pattern(0), Some("new york"));
+
+        let finder = {
+            let text = String::from("a b\nc");
+            SimpleFinder::new_owned(text.lines().map(|l| (l.to_string(), ())).collect())
+        };
+        assert_eq!(finder.count_matches("a b c"), 2);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    fn test_debug_validate() {
+        // the patterns of `test_loops`
+        let patterns: Vec<Vec<Cow<'_, str>>> = vec![
+            vec!["lol".into(), "lol_".into()],
+            vec!["lol".into(), "lol".into()],
+        ];
+        let match_kinds = [
+            MatchKind::Standard,
+            MatchKind::LeftmostLongest,
+            MatchKind::LeftmostFirst,
+        ];
+        for &match_kind in &match_kinds {
+            let options = NfaOptions {
+                match_kind,
+                ..NfaOptions::default()
+            };
+            nfa::build_nfa::<usize, str>(&patterns, options)
+                .unwrap()
+                .debug_validate();
+        }
+
+        // "'" doesn't start a pattern, so the start state has no transition
+        // on it for the state after "é" to fail to
+        let patterns = vec![vec!["é".into(), "'".into()]];
+        let nfa = nfa::build_nfa::<usize, str>(&patterns, NfaOptions::default()).unwrap();
+        nfa.debug_validate();
+        assert_eq!(nfa.states[4].fail, nfa.start_id);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "which isn't shallower")]
+    fn test_debug_validate_invalid() {
+        let patterns = vec![vec!["a".into(), "b".into()], vec!["b".into()]];
+        let mut nfa = nfa::build_nfa::<usize, str>(&patterns, NfaOptions::default()).unwrap();
+        // the state after "a" fails to the state after "a b"
+        nfa.states[3].fail = 4;
+        nfa.debug_validate();
+    }
+
+    #[test]
+    fn test_highlights() {
+        let finder = SimpleFinder::new(vec![
+            ("quick", "adj"),
+            ("quick brown fox", "animal"),
+            ("brown", "colour"),
+            ("lazy dog", "animal"),
+            ("dog", "animal"),
+        ]);
+        let haystack = "The quick brown fox jumps over the lazy dog, and a brown dog.";
+        let highlights = finder.highlights(haystack);
+        let spans: Vec<_> = highlights
+            .iter()
+            .map(|h| (&haystack[h.byte_start..h.byte_end], h.pattern, *h.data))
+            .collect();
+        assert_eq!(
+            spans,
+            vec![
+                ("quick brown fox", 1, "animal"),
+                ("lazy dog", 3, "animal"),
+                ("brown", 2, "colour"),
+                ("dog", 4, "animal"),
+            ]
+        );
+        for pair in highlights.windows(2) {
+            assert!(pair[0].byte_end <= pair[1].byte_start);
+        }
+
+        assert!(finder.highlights("nothing to see").is_empty());
+    }
+
+    #[test]
+    fn test_skip_symbols() {
+        let patterns = vec![("foo bar", 0), ("don't stop", 1)];
+
+        let finder = SimpleFinder::new(patterns.clone());
+        assert!(!finder.is_match("foo,bar"));
+
+        let finder = SimpleFinderBuilder::new()
+            .skip_symbols(true)
+            .join_apostrophes(true)
+            .build(patterns.clone());
+        for (haystack, found) in [
+            ("foo,bar", "foo,bar"),
+            ("foo - bar", "foo - bar"),
+            ("foo/bar", "foo/bar"),
+            ("(foo... bar)", "foo... bar"),
+            ("\"foo\", \"bar\"", "foo\", \"bar"),
+            ("foo bar", "foo bar"),
+        ] {
+            let results: Vec<_> = finder
+                .find_all(haystack)
+                .map(|(m, &d)| (&haystack[m.byte_start()..m.byte_end()], d))
+                .collect();
+            assert_eq!(results, vec![(found, 0)]);
+        }
+        // char offsets cover the skipped symbols too
+        let (m, _) = finder.find_all("é, foo — bar!").next().unwrap();
+        assert_eq!((m.start(), m.end()), (3, 12));
+        // words are joined before symbols are skipped
+        assert!(finder.is_match("don't, stop"));
+        assert!(!finder.is_match("don, t stop"));
+
+        let finder = SimpleFinderBuilder::new()
+            .skip_symbols(true)
+            .build(vec![("foo bar", 0)]);
+        let mut stream = finder.stream();
+        let mut results = Vec::new();
+        for chunk in ["foo", " -", "- ", "bar"] {
+            results.extend(stream.push(chunk));
+        }
+        results.extend(stream.finish());
+        let results: Vec<_> = results
+            .into_iter()
+            .map(|(m, &d)| (m.byte_start(), m.byte_end(), d))
+            .collect();
+        assert_eq!(results, vec![(0, 10, 0)]);
+
+        let result = SimpleFinderBuilder::new()
+            .skip_symbols(true)
+            .try_build(vec![("...", 0)]);
+        assert!(matches!(result, Err(BuildError::EmptyPattern { .. })));
+    }
+
+    #[test]
+    fn test_distinct_pattern_count() {
+        let patterns = vec![("foo", 0), ("foo bar", 1), ("bar", 0), ("qux", 1)];
+        let haystack = "foo bar baz foo bar";
+
+        let finder = SimpleFinder::new(patterns.clone());
+        assert_eq!(finder.find_all_unique(haystack).len(), 2);
+        assert_eq!(finder.distinct_pattern_count(haystack), 3);
+        assert_eq!(finder.distinct_pattern_count("qux"), 1);
+        assert_eq!(finder.distinct_pattern_count("baz"), 0);
+
+        let finder = SimpleFinderBuilder::new()
+            .match_kind(MatchKind::LeftmostLongest)
+            .build(patterns);
+        assert_eq!(finder.distinct_pattern_count(haystack), 1);
+
+        // more patterns than fit in one word of the bitset
+        let words: Vec<_> = (0..150).map(|i| format!("w{}", i)).collect();
+        let finder = SimpleFinder::new(words.iter().map(|w| (w.as_str(), ())).collect::<Vec<_>>());
+        assert_eq!(finder.distinct_pattern_count("w0 w63 w64 w149 w64 w0"), 4);
+    }
+
+    #[test]
+    fn test_parts() {
+        let patterns = vec![
+            ("foo", 'a'),
+            ("foo bar", 'b'),
+            ("bar", 'c'),
+            ("baz qux", 'd'),
+        ];
+        let haystack = "foo bar baz qux foo baz";
+
+        for &(match_kind, dfa) in &[
+            (MatchKind::Standard, false),
+            (MatchKind::Standard, true),
+            (MatchKind::LeftmostLongest, false),
+            (MatchKind::LeftmostFirst, true),
+        ] {
+            let mut builder = SimpleFinderBuilder::new();
+            builder.match_kind(match_kind).dfa(dfa);
+            let finder = builder.build(patterns.clone());
+            let expected: Vec<_> = finder.find_all(haystack).map(|(m, &d)| (m, d)).collect();
+
+            let (parts, data) = finder.into_parts();
+            assert_eq!(data, vec!['a', 'b', 'c', 'd']);
+            assert_eq!(parts.pattern_count, 4);
+            assert_eq!(parts.max_pattern_len, 2);
+            #[cfg(feature = "serde")]
+            let parts = {
+                let json = serde_json::to_string(&parts).unwrap();
+                serde_json::from_str::<AutomatonParts>(&json).unwrap()
+            };
+            let finder = builder.try_build_from_parts(parts, data).unwrap();
+            let results: Vec<_> = finder.find_all(haystack).map(|(m, &d)| (m, d)).collect();
+            assert_eq!(results, expected);
+        }
+
+        let finder = SimpleFinder::new(patterns.clone());
+        let (parts, data) = finder.into_parts();
+        // an NFA's parts are the same after a round trip
+        let finder = SimpleFinderBuilder::new()
+            .try_build_from_parts(parts.clone(), data.clone())
+            .unwrap();
+        assert_eq!(finder.into_parts().0, parts);
+
+        // each pattern needs exactly one item of data
+        let mismatched = Some(BuildError::invalid_parts(
+            "the data isn't one item for each pattern",
+        ));
+        for data in [vec!['a', 'b', 'c'], vec!['a', 'b', 'c', 'd', 'e']] {
+            let finder = SimpleFinderBuilder::new().try_build_from_parts(parts.clone(), data);
+            assert_eq!(finder.err(), mismatched);
+        }
+
+        let invalid = |f: fn(&mut AutomatonParts)| {
+            let mut parts = parts.clone();
+            f(&mut parts);
+            let result = SimpleFinderBuilder::new().try_build_from_parts(parts, data.clone());
+            result.err()
+        };
+        assert_eq!(
+            invalid(|parts| parts.start_id = 3),
+            Some(BuildError::invalid_parts("the start state must be state 2"))
+        );
+        assert_eq!(
+            invalid(|parts| parts.states[2].transitions[0].1 = 100),
+            Some(BuildError::invalid_parts(
+                "a transition leads to a missing state"
+            ))
+        );
+        assert_eq!(
+            invalid(|parts| parts.states[2].transitions[0].1 = 0),
+            Some(BuildError::invalid_parts(
+                "a transition leads to a missing state"
+            ))
+        );
+        assert_eq!(
+            invalid(|parts| parts.states[2].transitions[0].0 = 100),
+            Some(BuildError::invalid_parts(
+                "a transition is labelled with a missing token"
+            ))
+        );
+        assert_eq!(
+            invalid(|parts| parts.states[3].fail = 100),
+            Some(BuildError::invalid_parts(
+                "a failure transition leads to a missing state"
+            ))
+        );
+        assert_eq!(
+            invalid(|parts| {
+                let last = parts.states.len() - 1;
+                parts.states[3].fail = last;
+            }),
+            Some(BuildError::invalid_parts(
+                "a failure transition leads to a state that isn't shallower"
+            ))
+        );
+        assert_eq!(
+            invalid(|parts| parts.pattern_count = 1),
+            Some(BuildError::invalid_parts("a match is of a missing pattern"))
+        );
+        assert_eq!(
+            invalid(|parts| parts.max_pattern_len = 1),
+            Some(BuildError::invalid_parts(
+                "a match is longer than the patterns"
+            ))
+        );
+        assert_eq!(
+            invalid(|parts| parts.tokens.push(String::from("foo"))),
+            Some(BuildError::invalid_parts("a token is listed twice"))
+        );
+        assert_eq!(
+            invalid(|parts| parts.states[2].depth = 1),
+            Some(BuildError::invalid_parts(
+                "the start and dead states must have depth 0"
+            ))
+        );
+        assert_eq!(
+            invalid(|parts| {
+                let last = parts.states.len() - 1;
+                parts.states[2].transitions[0].1 = last;
+            }),
+            Some(BuildError::invalid_parts("a transition skips a depth"))
+        );
+        assert_eq!(
+            invalid(|parts| parts.states[3].matches[0].1 = 0),
+            Some(BuildError::invalid_parts("a match is empty"))
+        );
+    }
+
+    #[test]
+    fn test_for_each_match() {
+        let patterns = vec![("foo", 0), ("foo bar", 1), ("bar", 2), ("baz", 3)];
+        let haystack = "foo bar baz foo";
+
+        for &(match_kind, overlapping) in &[
+            (MatchKind::Standard, true),
+            (MatchKind::Standard, false),
+            (MatchKind::LeftmostLongest, true),
+        ] {
+            let finder = SimpleFinderBuilder::new()
+                .match_kind(match_kind)
+                .overlapping(overlapping)
+                .build(patterns.clone());
+            let expected: Vec<_> = finder.find_all(haystack).map(|(m, &d)| (m, d)).collect();
+
+            let mut results = Vec::new();
+            let flow = finder.for_each_match(haystack, |m, &d| {
+                results.push((m, d));
+                ControlFlow::Continue(())
+            });
+            assert_eq!(flow, ControlFlow::Continue(()));
+            assert_eq!(results, expected);
+
+            let mut visited = 0;
+            let flow = finder.for_each_match(haystack, |_, _| {
+                visited += 1;
+                ControlFlow::Break(())
+            });
+            assert_eq!(flow, ControlFlow::Break(()));
+            assert_eq!(visited, 1);
+        }
+
+        let finder = SimpleFinder::new(patterns);
+        let flow = finder.for_each_match("qux", |_, _| ControlFlow::Break(()));
+        assert_eq!(flow, ControlFlow::Continue(()));
+    }
+
+    #[cfg(feature = "serialize")]
+    #[test]
+    fn test_from_corrupted_bytes() {
+        let patterns = vec![("foo bar", 0), ("bar", 1), ("bar baz qux", 2)];
+        let haystack = "foo bar baz qux bar foo";
+
+        for &(kind, dfa) in &[
+            (MatchKind::Standard, false),
+            (MatchKind::LeftmostLongest, false),
+            (MatchKind::Standard, true),
+        ] {
+            let finder = SimpleFinderBuilder::new()
+                .match_kind(kind)
+                .dfa(dfa)
+                .build(patterns.clone());
+            let bytes = finder.to_bytes().unwrap();
+
+            // whatever a byte is changed to, the bytes are either rejected or
+            // load a finder whose searches report matches within the haystack
+            for i in 0..bytes.len() {
+                for &b in &[0, 1, 2, 3, 0xFF] {
+                    let mut corrupted = bytes.clone();
+                    corrupted[i] = b;
+                    if let Ok(loaded) = SimpleFinder::from_bytes(&corrupted, 0..3) {
+                        for (m, _) in loaded.find_all(haystack) {
+                            assert!(m.start() <= m.end() && m.end() <= haystack.len());
+                        }
+                        loaded.is_match(haystack);
+                    }
+                }
+            }
+        }
+    }
+
+    #[cfg(feature = "serialize")]
+    #[test]
+    fn test_from_bytes_understated_max_pattern_len() {
+        for &dfa in &[false, true] {
+            let finder = SimpleFinderBuilder::new()
+                .dfa(dfa)
+                .build(vec![("a b c", 0)]);
+            let bytes = finder.to_bytes().unwrap();
+
+            // the start state ID, the longest pattern's length, and the
+            // number of patterns begin the automaton
+            let header: Vec<u8> = [2u64, 3, 1].iter().flat_map(|n| n.to_le_bytes()).collect();
+            let at = bytes
+                .windows(header.len())
+                .position(|window| window == &header[..])
+                .unwrap();
+            let mut crafted = bytes.clone();
+            crafted[at + 8] = 1;
+
+            let loaded = SimpleFinder::from_bytes(&crafted, vec![0]);
+            assert_eq!(loaded.err(), Some(SerializeError::InvalidFormat));
+        }
+    }
+
+    #[test]
+    fn test_shrink_to_fit() {
+        let patterns: Vec<_> = (0..200)
+            .map(|i| (format!("foo{} bar{} baz", i % 50, i), i))
+            .collect();
+        let patterns = || patterns.iter().map(|(p, d)| (p.as_str(), *d));
+        let haystack = "foo7 bar107 baz foo7 bar57 baz";
+
+        for &dfa in &[false, true] {
+            let mut finder = SimpleFinderBuilder::new()
+                .dfa(dfa)
+                .store_patterns(true)
+                .build(patterns());
+            finder.extend(vec![("qux", 200)]);
+            let expected = finder.find_all(haystack).count();
+
+            let before = finder.heap_bytes();
+            finder.shrink_to_fit();
+            let after = finder.heap_bytes();
+            assert!(after <= before);
+            assert_eq!(after, finder.stats().heap_bytes);
+            assert_eq!(finder.find_all(haystack).count(), expected);
+
+            finder.shrink_to_fit();
+            assert_eq!(finder.heap_bytes(), after);
+        }
+
+        let mut reverse = SimpleFinderBuilder::new().build_reverse(patterns());
+        let before = reverse.heap_bytes();
+        reverse.shrink_to_fit();
+        assert!(reverse.heap_bytes() <= before);
+    }
+
+    #[test]
+    fn test_heap_bytes_with_data() {
+        let long = "x".repeat(10_000);
+        let finder = SimpleFinder::new(vec![("foo", long.clone()), ("bar", String::new())]);
+
+        // the contents of the strings are only counted with the data
+        assert!(finder.heap_bytes() < long.len());
+        assert!(finder.heap_bytes_with_data() >= finder.heap_bytes() + long.len());
+
+        let unit = SimpleFinder::new(vec![("foo", ()), ("bar", ())]);
+        assert_eq!(unit.heap_bytes_with_data(), unit.heap_bytes());
+
+        // the table holds at least a `(usize, String)` per pattern
+        let table = 2 * std::mem::size_of::<(usize, String)>();
+        let without_table = SimpleFinder::new(Vec::<(&str, String)>::new());
+        assert!(finder.heap_bytes() >= without_table.heap_bytes() + table);
+    }
+
+    #[test]
+    fn test_debug_display() {
+        let finder = SimpleFinder::new(vec![("foo", 1), ("bar baz", 2)]);
+        assert_eq!(finder.state_count(), 6);
+
+        let bytes = finder.heap_bytes();
+        assert_eq!(
+            format!("{:?}", finder),
+            format!(
+                "SimpleFinder {{ pattern_count: 2, state_count: 6, heap_bytes: {}, \
+                 max_pattern_len: 2, match_kind: Standard }}",
+                bytes
+            )
+        );
+        assert_eq!(
+            finder.to_string(),
+            format!(
+                "finder of 2 patterns of up to 2 tokens, in 6 states using {} bytes",
+                bytes
+            )
+        );
+
+        // the patterns aren't listed, however many there are
+        let patterns: Vec<_> = (0..1000).map(|i| format!("foo{}", i)).collect();
+        let finder: SimpleFinder<_> = patterns.iter().map(|p| (p.as_str(), ())).collect();
+        assert!(format!("{:?}", finder).len() < 200);
+    }
+
+    #[test]
+    fn test_stats() {
+        let finder = SimpleFinder::new(vec![
+            ("foo", 1),
+            ("foo bar", 2),
+            ("baz", 3),
+            ("qux quux", 4),
+        ]);
+
+        let stats = finder.stats();
+        assert_eq!(stats.pattern_count, 4);
+        // fail, dead, start, foo, bar, baz, qux and quux
+        assert_eq!(stats.state_count, 8);
+        assert_eq!(stats.dense_state_count, 6);
+        assert_eq!(stats.max_pattern_len, 2);
+        // the start state, to foo, baz and qux
+        assert_eq!(stats.max_out_degree, 3);
+        assert_eq!(stats.heap_bytes, finder.heap_bytes());
+    }
+
+    #[test]
+    fn test_sparse_transitions_out_of_order() {
+        // the single token patterns number the tokens, which are then added
+        // to the sparse state after `a b` in descending order
+        let word = |i: u8| format!("q{}{}", (b'a' + i / 10) as char, (b'a' + i % 10) as char);
+        let singles: Vec<_> = (0..50).map(word).collect();
+        let triples: Vec<_> = (0..50).rev().map(|i| format!("a b {}", word(i))).collect();
+        let patterns: Vec<_> = singles.iter().chain(&triples).map(String::as_str).collect();
+
+        for &dfa in &[false, true] {
+            let finder = SimpleFinderBuilder::new()
+                .dfa(dfa)
+                .build(patterns.iter().map(|&p| (p, p)));
+
+            for triple in &triples {
+                let found: Vec<_> = finder.find_all(triple).map(|(_, &d)| d).collect();
+                assert_eq!(found, vec![triple.as_str(), &triple[4..]]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_unique() {
+        let finder = SimpleFinder::new(vec![
+            ("foo", 123),
+            ("bar", 234),
+            ("baz", 345),
+            ("bar baz", 456),
+        ]);
+
+        let results = finder.find_all_unique("foo bar baz foobar foo'bar foo,bar");
+
+        assert_eq!(
+            results.len(),
+            4,
+            "Results {:?} was not 4 in length?",
+            results
+        );
+        assert!(results.contains(&123));
+        assert!(results.contains(&234));
+        assert!(results.contains(&345));
+        assert!(results.contains(&456));
+    }
+
+    #[test]
+    fn test_loops() {
+        let finder = SimpleFinder::new(vec![
+            ("lol lol_", 0),
+            ("lol lol", 2),
+        ]);
+
+        let results: Vec<_> = finder.find_all("lol lol lol lol_").collect();
+        assert_eq!(
+            results,
+            vec![
+                (
+                    Match {
+                        pattern: 1,
+                        len: 7,
+                        end: 7,
+                        byte_len: 7,
+                        byte_end: 7,
+                        token_len: 2,
+                        token_end: 2
+                    },
+                    &2
+                ),
+                (
+                    Match {
+                        pattern: 1,
+                        len: 7,
+                        end: 11,
+                        byte_len: 7,
+                        byte_end: 11,
+                        token_len: 2,
+                        token_end: 3
+                    },
+                    &2
+                ),
+                (
+                    Match {
+                        pattern: 0,
+                        len: 8,
+                        end: 16,
+                        byte_len: 8,
+                        byte_end: 16,
+                        token_len: 2,
+                        token_end: 4
+                    },
+                    &0
+                )
+            ]
+        );
+    }
+
+    #[test]
+    fn test_find_all_disjoint() {
+        let patterns = vec![("lol lol_", 0), ("lol lol", 2)];
+        let haystack = "lol lol lol lol_";
+
+        for &overlapping in &[true, false] {
+            let finder = SimpleFinderBuilder::new()
+                .overlapping(overlapping)
+                .build(patterns.clone());
+
+            // "lol lol" at 4..11 overlaps the one kept at 0..7
+            let results: Vec<_> = finder
+                .find_all_disjoint(haystack)
+                .map(|(m, &d)| (m.start(), m.end(), d))
+                .collect();
+            assert_eq!(results, vec![(0, 7, 2), (8, 16, 0)]);
+        }
+    }
+
+    #[test]
+    fn test_token_offsets() {
+        let patterns = vec![("brown fox", 0), ("quick", 1)];
+        // punctuation is a token, but the whitespace between tokens isn't
+        let haystack = "the quick, brown fox";
+        let spans = |m: Match| (m.start(), m.end(), m.token_start(), m.token_end());
+
+        let finder = SimpleFinder::new(patterns.clone());
+        let results: Vec<_> = finder.find_all(haystack).map(|(m, _)| spans(m)).collect();
+        assert_eq!(results, vec![(4, 9, 1, 2), (11, 20, 3, 5)]);
+        assert_eq!(
+            finder.find_first("a brown fox").map(|(m, _)| spans(m)),
+            Some((2, 11, 1, 3))
+        );
+
+        let leftmost = SimpleFinderBuilder::new()
+            .match_kind(MatchKind::LeftmostLongest)
+            .build(patterns.clone());
+        let results: Vec<_> = leftmost.find_all(haystack).map(|(m, _)| spans(m)).collect();
+        assert_eq!(results, vec![(4, 9, 1, 2), (11, 20, 3, 5)]);
+
+        let reverse = SimpleFinderBuilder::new().build_reverse(patterns);
+        let results: Vec<_> = reverse.find_all(haystack).map(|(m, _)| spans(m)).collect();
+        assert_eq!(results, vec![(11, 20, 3, 5), (4, 9, 1, 2)]);
+    }
+
+    #[test]
+    fn test_stop_words() {
+        let mut builder = SimpleFinderBuilder::new();
+        builder.case_insensitive(true).stop_words(["the", "A"]);
+        let finder = builder.build(vec![("quick fox", 0)]);
+        let spans = |m: Match| (m.start(), m.end(), m.token_start(), m.token_end());
+
+        // the match covers the stop words between its tokens, but not those
+        // around it, which aren't counted as tokens either
+        let haystack = "the quick The a fox the";
+        let results: Vec<_> = finder.find_all(haystack).map(|(m, _)| spans(m)).collect();
+        assert_eq!(results, vec![(4, 19, 0, 2)]);
+        assert_eq!(
+            finder.find_first(haystack).map(|(m, _)| spans(m)),
+            Some((4, 19, 0, 2))
+        );
+        assert!(finder.is_match("Quick a fox"));
+        assert!(!finder.is_match("quick brown fox"));
+
+        let mut stream = finder.stream();
+        let mut results = Vec::new();
+        for chunk in ["the quick ", "the f", "ox"] {
+            results.extend(stream.push(chunk));
+        }
+        results.extend(stream.finish());
+        let results: Vec<_> = results.into_iter().map(|(m, _)| spans(m)).collect();
+        assert_eq!(results, vec![(4, 17, 0, 2)]);
+
+        let result = builder.try_build(vec![("fox", 0), ("The a", 1)]);
+        assert_eq!(result.err(), Some(BuildError::EmptyPattern { pattern: 1 }));
+
+        #[cfg(feature = "serialize")]
+        {
+            let loaded = SimpleFinder::from_bytes(&finder.to_bytes().unwrap(), [0]).unwrap();
+            let results: Vec<_> = loaded.find_all(haystack).map(|(m, _)| spans(m)).collect();
+            assert_eq!(results, vec![(4, 19, 0, 2)]);
+        }
+    }
+
+    #[test]
+    fn test_token_transform() {
+        fn stem(token: &str) -> Cow<'_, str> {
+            let stem = ["ning", "ing", "s"]
+                .iter()
+                .find_map(|suffix| token.strip_suffix(suffix))
+                .unwrap_or(token);
+            Cow::Borrowed(stem)
+        }
+
+        let finder = SimpleFinderBuilder::new()
+            .case_insensitive(true)
+            .token_transform(stem)
+            .build(vec![("run", 0), ("bark dogs", 1)]);
+
+        // tokens that were lowercased are transformed too
+        let haystack = "dogs RUNNING, barking dog";
+        let results: Vec<_> = finder
+            .find_all(haystack)
+            .map(|(m, &d)| (&haystack[m.start()..m.end()], d))
+            .collect();
+        assert_eq!(results, vec![("RUNNING", 0), ("barking dog", 1)]);
+        assert!(!finder.is_match("runner"));
+
+        #[cfg(feature = "serialize")]
+        assert_eq!(
+            finder.to_bytes().err(),
+            Some(SerializeError::TokenTransform)
+        );
+    }
+
+    #[test]
+    fn test_count_graphemes() {
+        // a family emoji joined with zero width joiners is five chars, but
+        // one grapheme cluster
+        let family = "\u{1f468}\u{200d}\u{1f469}\u{200d}\u{1f467}";
+        let haystack = format!("{} e\u{301}e cafe tea", family);
+        let patterns = vec![("cafe tea", 0)];
+        let spans = |m: Match| (m.start(), m.end(), m.byte_start(), m.byte_end());
+
+        let finder = SimpleFinder::new(patterns.clone());
+        let results: Vec<_> = finder.find_all(&haystack).map(|(m, _)| spans(m)).collect();
+        assert_eq!(results, vec![(10, 18, 24, 32)]);
+
+        let mut builder = SimpleFinderBuilder::new();
+        builder.count_graphemes(true);
+        let finder = builder.build(patterns.clone());
+        let results: Vec<_> = finder.find_all(&haystack).map(|(m, _)| spans(m)).collect();
+        assert_eq!(results, vec![(5, 13, 24, 32)]);
+        assert_eq!(
+            finder.find_first(&haystack).map(|(m, _)| spans(m)),
+            Some((5, 13, 24, 32))
+        );
+
+        let mut stream = finder.stream();
+        let mut results = Vec::new();
+        for c in haystack.chars() {
+            results.extend(stream.push(&c.to_string()));
+        }
+        results.extend(stream.finish());
+        let results: Vec<_> = results.into_iter().map(|(m, _)| spans(m)).collect();
+        assert_eq!(results, vec![(5, 13, 24, 32)]);
+
+        // the emoji is a token of its own, spanning the whole cluster
+        let finder = builder.build(vec![(family, 0)]);
+        let results: Vec<_> = finder.find_all(&haystack).map(|(m, _)| spans(m)).collect();
+        assert_eq!(results, vec![(0, 1, 0, 18)]);
+    }
+
+    #[test]
+    fn test_join_apostrophes() {
+        let patterns = vec![
+            ("don't", 0),
+            ("rock'n'roll", 1),
+            ("don", 2),
+            ("t", 3),
+            ("'tis", 4),
+            ("dogs'", 5),
+            ("can\u{2019}t", 6),
+        ];
+        let haystack = "don't stop rock'n'roll, 'tis the dogs' don ' t can\u{2019}t";
+
+        let finder = SimpleFinderBuilder::new()
+            .join_apostrophes(true)
+            .build(patterns.clone());
+        let results: Vec<_> = finder
+            .find_all(haystack)
+            .map(|(m, &d)| (&haystack[m.byte_start()..m.byte_end()], d))
+            .collect();
+        assert_eq!(
+            results,
+            vec![
+                ("don't", 0),
+                ("rock'n'roll", 1),
+                ("'tis", 4),
+                ("dogs'", 5),
+                ("don", 2),
+                ("t", 3),
+                ("can\u{2019}t", 6),
+            ]
+        );
+
+        let mut stream = finder.stream();
+        let mut streamed = Vec::new();
+        for c in haystack.chars() {
+            streamed.extend(stream.push(&c.to_string()));
+        }
+        streamed.extend(stream.finish());
+        let streamed: Vec<_> = streamed
+            .into_iter()
+            .map(|(m, &d)| (&haystack[m.byte_start()..m.byte_end()], d))
+            .collect();
+        assert_eq!(streamed, results);
+
+        // without joining, the words either side of an apostrophe match
+        let finder = SimpleFinder::new(patterns);
+        let results: Vec<_> = finder.find_all("don't").map(|(_, &d)| d).collect();
+        assert_eq!(results, vec![2, 0, 3]);
+    }
+
+    #[test]
+    fn test_join_hyphens() {
+        let patterns = vec![
+            ("state-of-the-art", 0),
+            ("state", 1),
+            ("2020-2021", 2),
+            ("2020", 3),
+            ("foo", 4),
+            ("-", 5),
+        ];
+        let haystack = "state-of-the-art, -foo foo- 2020-2021 and 2020 - 2021";
+
+        let finder = SimpleFinderBuilder::new()
+            .join_hyphens(true)
+            .build(patterns.clone());
+        let results: Vec<_> = finder
+            .find_all(haystack)
+            .map(|(m, &d)| (m.start(), m.end(), d))
+            .collect();
+        assert_eq!(
+            results,
+            vec![
+                (0, 16, 0),
+                (18, 19, 5),
+                (19, 22, 4),
+                (23, 26, 4),
+                (26, 27, 5),
+                (28, 37, 2),
+                (42, 46, 3),
+                (47, 48, 5),
+            ]
+        );
+
+        let mut stream = finder.stream();
+        let mut streamed = Vec::new();
+        for c in haystack.chars() {
+            streamed.extend(stream.push(&c.to_string()));
+        }
+        streamed.extend(stream.finish());
+        let streamed: Vec<_> = streamed
+            .into_iter()
+            .map(|(m, &d)| (m.start(), m.end(), d))
+            .collect();
+        assert_eq!(streamed, results);
+
+        // hyphens aren't joined unless asked for
+        let finder = SimpleFinder::new(patterns);
+        assert!(finder.find_all("state-of-the-art").any(|(_, &d)| d == 1));
+    }
+
+    #[test]
+    fn test_join_digit_groups() {
+        let patterns = vec![
+            ("1,000.50", 0),
+            ("3.14", 1),
+            ("000", 2),
+            ("3", 3),
+            ("14.", 4),
+        ];
+        let haystack = "paid 1,000.50 for 3.14. 3 . 14 and 1,000 or 1, 000";
+
+        let finder = SimpleFinderBuilder::new()
+            .join_digit_groups(true)
+            .build(patterns.clone());
+        let results: Vec<_> = finder
+            .find_all(haystack)
+            .map(|(m, &d)| (&haystack[m.start()..m.end()], m.start(), d))
+            .collect();
+        assert_eq!(
+            results,
+            vec![
+                ("1,000.50", 5, 0),
+                ("3.14", 18, 1),
+                ("3", 24, 3),
+                ("000", 47, 2),
+            ]
+        );
+
+        let mut stream = finder.stream();
+        let mut streamed = Vec::new();
+        for c in haystack.chars() {
+            streamed.extend(stream.push(&c.to_string()));
+        }
+        streamed.extend(stream.finish());
+        let streamed: Vec<_> = streamed
+            .into_iter()
+            .map(|(m, &d)| (&haystack[m.start()..m.end()], m.start(), d))
+            .collect();
+        assert_eq!(streamed, results);
+
+        // digits aren't grouped unless asked for
+        let finder = SimpleFinder::new(patterns);
+        let results: Vec<_> = finder.find_all("3.14.").map(|(_, &d)| d).collect();
+        assert_eq!(results, vec![3, 1, 4]);
+    }
+
+    #[test]
+    fn test_find_in_range() {
+        let finder = SimpleFinder::new(vec![
+            ("foo", 123),
+            ("bar", 234),
+            ("baz", 345),
+            ("bar baz", 456),
+        ]);
+
+        // "foo bar baz": foo is 0..3, bar is 4..7, baz is 8..11
+        let haystack = "foo bar baz";
+
+        let inside: Vec<_> = finder
+            .find_in_range(haystack, 0..7)
+            .map(|(m, &d)| (m.start(), m.end(), d))
+            .collect();
+        assert_eq!(inside, vec![(0, 3, 123), (4, 7, 234)]);
+
+        // "bar baz" straddles the end of 4..10, and "baz" lies outside of it
+        let straddling: Vec<_> = finder
+            .find_in_range(haystack, 4..10)
+            .map(|(m, &d)| (m.start(), m.end(), d))
+            .collect();
+        assert_eq!(straddling, vec![(4, 7, 234)]);
+
+        let outside: Vec<_> = finder.find_in_range(haystack, 12..20).collect();
+        assert!(outside.is_empty());
+    }
+
+    #[test]
+    fn test_compact_match() {
+        assert_eq!(std::mem::size_of::<CompactMatch>(), 12);
+
+        let finder = SimpleFinder::new(vec![("lol lol_", 0), ("lol lol", 2)]);
+        let haystack = "lol lol lol lol_";
+
+        let matches: Vec<_> = finder.find_all(haystack).map(|(m, _)| m).collect();
+        let compact: Vec<_> = finder
+            .find_all_compact(haystack)
+            .collect::<Result<_, _>>()
+            .unwrap();
+
+        assert_eq!(matches.len(), compact.len());
+        let expanded = CompactMatch::to_matches(&compact, haystack);
+        for ((m, c), e) in matches.into_iter().zip(compact).zip(expanded) {
+            assert_eq!((m.pattern(), m.start(), m.end()), (c.pattern(), c.start(), c.end()));
+            // token indices aren't kept by a compact match
+            assert_eq!(
+                c.to_match(haystack),
+                Match {
+                    token_len: 0,
+                    token_end: 0,
+                    ..m
+                }
+            );
+            assert_eq!(c.to_match(haystack), e);
+        }
+
+        // the finder's overlapping setting is followed
+        let finder = SimpleFinderBuilder::new()
+            .overlapping(false)
+            .build(vec![("lol lol_", 0), ("lol lol", 2)]);
+        let expected: Vec<_> = finder
+            .find_all(haystack)
+            .map(|(m, _)| CompactMatch::try_from(&m).unwrap())
+            .collect();
+        let compact: Vec<_> = finder
+            .find_all_compact(haystack)
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(compact, expected);
+        assert_eq!(compact.len(), 2);
+
+        // multibyte chars, and offsets shared by several matches
+        let finder = SimpleFinder::new(vec![("ünï", 0), ("ünï çø", 1), ("çø", 2)]);
+        let haystack = "☃ ünï çø ünï";
+        let compact: Vec<_> = finder
+            .find_all_compact(haystack)
+            .map(Result::unwrap)
+            .collect();
+        let expected: Vec<_> = compact.iter().map(|c| c.to_match(haystack)).collect();
+        assert_eq!(CompactMatch::to_matches(&compact, haystack), expected);
+        assert_eq!(expected.len(), 4);
+
+        let far = Match {
+            pattern: 0,
+            len: 2,
+            end: u32::MAX as usize + 1,
+            byte_len: 2,
+            byte_end: u32::MAX as usize + 1,
+            token_len: 1,
+            token_end: 1,
+        };
+        assert!(CompactMatch::try_from(&far).is_err());
+        let near = Match {
+            end: u32::MAX as usize,
+            ..far
+        };
+        assert_eq!(
+            CompactMatch::try_from(&near).unwrap().end(),
+            u32::MAX as usize
+        );
+    }
+
+    #[test]
+    fn test_from_literals() {
+        use regex::RegexSet;
+
+        let haystack = "the quick brown fox jumps over the lazy dog";
+        let literals = ["quick brown", "fox", "lazy cat", "the", "ump"];
+        let set = RegexSet::new(
+            literals
+                .iter()
+                .map(|s| format!(r"\b(?:{})\b", regex::escape(s))),
+        )
+        .unwrap();
+        let finder = SimpleFinder::from_literals(literals);
+
+        let mut matched: Vec<usize> = finder.find_all_unique(haystack).into_iter().collect();
+        matched.sort_unstable();
+        assert_eq!(
+            matched,
+            set.matches(haystack).into_iter().collect::<Vec<_>>()
+        );
+        assert_eq!(matched, vec![0, 1, 3]);
+
+        let finder = SimpleFinder::from_literals(vec![String::from("c++")]);
+        assert!(finder.is_match("written in c++ mostly"));
+    }
+
+    #[test]
+    fn test_pattern_match() {
+        let finder = SimpleFinder::new(vec![("foo", ()), ("bar", ()), ("baz", ())]);
+        let haystack = "bar foo bar foo";
+
+        let matched: HashSet<PatternMatch> = finder
+            .find_all(haystack)
+            .map(|(m, _)| m.by_pattern())
+            .collect();
+        let mut patterns: Vec<usize> = matched.iter().map(|m| m.0.pattern()).collect();
+        patterns.sort_unstable();
+        assert_eq!(patterns, vec![0, 1]);
+        assert!(matched.contains(&1) && !matched.contains(&2));
+        assert_eq!(matched.get(&0).unwrap().0.start(), 4);
+
+        let (first, second): (Vec<_>, Vec<_>) = finder
+            .find_all(haystack)
+            .map(|(m, _)| m)
+            .filter(|m| m.pattern() == 1)
+            .partition(|m| m.start() == 0);
+        assert_ne!(first, second);
+        assert_eq!(
+            first[0].clone().by_pattern(),
+            second[0].clone().by_pattern()
+        );
+    }
+
+    #[test]
+    fn test_max_pattern_len() {
+        let patterns = vec![
+            ("apple", ()),
+            ("apple pie", ()),
+            ("a slice of apple pie", ()),
+            ("crumble, with custard", ()),
+        ];
+        let finder = SimpleFinder::new(patterns.clone());
+        assert_eq!(finder.max_pattern_len(), 5);
+        assert_eq!(finder.max_pattern_len(), finder.stats().max_pattern_len);
+
+        let finder = SimpleFinderBuilder::new()
+            .stop_words(vec!["a", "of"])
+            .build(patterns.clone());
+        assert_eq!(finder.max_pattern_len(), 4);
+
+        let finder = SimpleFinderBuilder::new().build_reverse(patterns);
+        assert_eq!(finder.max_pattern_len(), 5);
+    }
+
+    #[test]
+    fn test_find_checked() {
+        use crate::automaton::Automaton;
+
+        let patterns = vec![("foo", 0), ("foo bar", 1), ("bar", 2), ("bar baz qux", 3)];
+        let haystack = "foo bar baz foo bar baz qux bar";
+
+        for &(match_kind, overlapping, dfa) in &[
+            (MatchKind::Standard, true, false),
+            (MatchKind::Standard, false, false),
+            (MatchKind::Standard, true, true),
+            (MatchKind::LeftmostLongest, true, false),
+            (MatchKind::LeftmostFirst, true, true),
+        ] {
+            let finder = SimpleFinderBuilder::new()
+                .match_kind(match_kind)
+                .overlapping(overlapping)
+                .dfa(dfa)
+                .build(patterns.clone());
+            let expected: Vec<_> = finder.find_all(haystack).collect();
+            let results: Vec<_> = finder.find_checked(haystack).collect();
+            assert!(!results.is_empty());
+            assert_eq!(results, expected, "{:?}", match_kind);
+        }
+
+        // searching from a state that doesn't exist ends the search, rather
+        // than panicking
+        let finder = SimpleFinder::new(patterns);
+        let imp = &finder.aho.imp;
+        let tokens = [Cow::Borrowed("foo")];
+        let mut state_id = imp.state_count() + 1;
+        assert_eq!(imp.next_state_checked(state_id, imp.token_id("foo")), None);
+        assert_eq!(imp.checked_find_at(&tokens, 0, &mut state_id), None);
+        assert_eq!(
+            imp.checked_overlapping_find_at(&tokens, 0, &mut state_id, &mut 0),
+            None
+        );
+        assert_eq!(
+            imp.checked_find_at(&tokens, 2, &mut imp.start_state()),
+            None
+        );
+    }
+
+    #[test]
+    fn test_next_state_or_none() {
+        use crate::automaton::Automaton;
+
+        for &dfa in &[false, true] {
+            let finder = SimpleFinderBuilder::new()
+                .dfa(dfa)
+                .build(vec![("a b", 0), ("b c", 1)]);
+            let imp = &finder.aho.imp;
+            let next = |state, token| imp.next_state_or_none(state, imp.token_id(token));
+            let start = imp.start_state();
+
+            let a = next(start, "a").unwrap();
+            let ab = next(a, "b").unwrap();
+            assert_eq!(imp.get_match(ab, 0, 2).unwrap().pattern(), 0);
+            // "a b" fails to "b", which has a transition on "c"
+            let bc = next(ab, "c").unwrap();
+            assert_eq!(imp.get_match(bc, 0, 3).unwrap().pattern(), 1);
+
+            assert_eq!(next(start, "c"), None);
+            assert_eq!(next(start, "d"), None);
+            assert_eq!(next(a, "c"), None);
+            assert_eq!(imp.next_state_checked(a, imp.token_id("c")), Some(start));
+        }
+
+        // leftmost match states fail to the dead state
+        let finder = SimpleFinderBuilder::new()
+            .match_kind(MatchKind::LeftmostFirst)
+            .build(vec![("a", 0), ("a b", 1)]);
+        let imp = &finder.aho.imp;
+        let a = imp
+            .next_state_or_none(imp.start_state(), imp.token_id("a"))
+            .unwrap();
+        assert_eq!(imp.next_state_or_none(a, imp.token_id("c")), None);
+        assert_eq!(
+            imp.next_state_checked(a, imp.token_id("c")),
+            Some(crate::state_id::dead_id())
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "is not a valid state ID")]
+    fn test_next_state_or_none_invalid() {
+        use crate::automaton::Automaton;
+
+        let finder = SimpleFinder::new(vec![("a", ())]);
+        let imp = &finder.aho.imp;
+        imp.next_state_or_none(imp.state_count(), imp.token_id("a"));
+    }
+
+    #[test]
+    fn test_find_all_longest_per_end() {
+        let finder = SimpleFinder::new(vec![
+            ("apple pie", 0),
+            ("pie", 1),
+            ("pie crust", 2),
+            ("crust", 3),
+            ("apple", 4),
+        ]);
+        let haystack = "apple pie crust, then pie";
+        let spans = |iter: SimpleFinderIter<_>| -> Vec<_> {
+            iter.map(|(m, &d)| (d, m.start(), m.end())).collect()
+        };
+
+        assert_eq!(
+            spans(finder.find_all(haystack)),
+            vec![
+                (4, 0, 5),
+                (0, 0, 9),
+                (1, 6, 9),
+                (2, 6, 15),
+                (3, 10, 15),
+                (1, 22, 25)
+            ]
+        );
+        assert_eq!(
+            spans(finder.find_all_longest_per_end(haystack)),
+            vec![(4, 0, 5), (0, 0, 9), (2, 6, 15), (1, 22, 25)]
+        );
+
+        let finder = SimpleFinderBuilder::new()
+            .overlapping(false)
+            .dfa(true)
+            .build(vec![("apple pie", 0), ("pie", 1)]);
+        assert_eq!(
+            spans(finder.find_all_longest_per_end("apple pie")),
+            vec![(0, 0, 9)]
+        );
+
+        // patterns of the same tokens are equally long, so both are reported
+        let finder = SimpleFinder::new(vec![("apple pie", 0), ("pie", 1), ("apple  pie", 2)]);
+        assert_eq!(
+            spans(finder.find_all_longest_per_end("apple pie")),
+            vec![(0, 0, 9), (2, 0, 9)]
+        );
+    }
+
+    #[test]
+    fn test_matched_pattern_set() {
+        let patterns = vec![
+            ("foo", ()),
+            ("foo bar", ()),
+            ("bar", ()),
+            ("qux", ()),
+            ("baz", ()),
+        ];
+        let haystack = "foo bar baz foo";
+
+        for &(match_kind, overlapping) in &[
+            (MatchKind::Standard, true),
+            (MatchKind::Standard, false),
+            (MatchKind::LeftmostLongest, true),
+        ] {
+            let finder = SimpleFinderBuilder::new()
+                .match_kind(match_kind)
+                .overlapping(overlapping)
+                .build(patterns.clone());
+            let mut expected = vec![false; patterns.len()];
+            for (m, _) in finder.find_all(haystack) {
+                expected[m.pattern()] = true;
+            }
+            assert_eq!(finder.matched_pattern_set(haystack), expected);
+        }
+
+        let finder = SimpleFinder::new(patterns);
+        assert_eq!(
+            finder.matched_pattern_set(haystack),
+            vec![true, true, true, false, true]
+        );
+        assert_eq!(finder.matched_pattern_set(""), vec![false; 5]);
+    }
+
+    #[test]
+    fn test_dense_depth() {
+        let patterns = vec![
+            ("foo", 0),
+            ("foo bar", 1),
+            ("foo bar baz", 2),
+            ("bar baz", 3),
+            ("baz qux quux", 4),
+        ];
+        let haystack = "foo bar baz qux quux foo bar";
+        let finder = SimpleFinder::new(patterns.clone());
+        let expected: Vec<_> = finder.find_all(haystack).map(|(m, &d)| (m, d)).collect();
+
+        let mut dense_state_counts = vec![];
+        for depth in 0..5 {
+            for &dfa in &[false, true] {
+                let finder = SimpleFinderBuilder::new()
+                    .dense_depth(depth)
+                    .dfa(dfa)
+                    .build(patterns.clone());
+                let results: Vec<_> = finder.find_all(haystack).map(|(m, &d)| (m, d)).collect();
+                assert_eq!(results, expected, "dense_depth {}", depth);
+                if !dfa {
+                    dense_state_counts.push(finder.stats().dense_state_count);
+                }
+            }
+        }
+        // the start state along with the fail and dead states, then three
+        // states at each depth but the last
+        assert_eq!(dense_state_counts, vec![0, 3, 6, 9, 11]);
+    }
+
+    #[test]
+    fn test_start_next_state() {
+        use crate::automaton::Automaton;
+
+        let patterns = vec![("foo bar", 0), ("bar", 1), ("baz qux", 2), ("qux", 3)];
+        let tokens = ["foo", "bar", "baz", "qux", "quux", ","];
+
+        for &(match_kind, dfa) in &[
+            (MatchKind::Standard, false),
+            (MatchKind::Standard, true),
+            (MatchKind::LeftmostFirst, false),
+            (MatchKind::LeftmostLongest, true),
+        ] {
+            let mut finder = SimpleFinderBuilder::new()
+                .match_kind(match_kind)
+                .dfa(dfa)
+                .build(patterns.clone());
+            let check = |finder: &SimpleFinder<_>| {
+                let imp = &finder.aho.imp;
+                let start = imp.start_state();
+                for token in &tokens {
+                    assert_eq!(
+                        imp.start_next_state(token),
+                        imp.next_state_checked(start, imp.token_id(token)).unwrap(),
+                        "{:?} {:?}",
+                        match_kind,
+                        token
+                    );
+                }
+            };
+            check(&finder);
+            finder.extend(vec![("quux", 4)]);
+            check(&finder);
+            assert_eq!(
+                finder.find_all("foo bar, baz qux quux").count(),
+                finder.find_checked("foo bar, baz qux quux").count()
+            );
+        }
+    }
+
+    #[test]
+    fn test_find_overlapping_owned() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<SimpleFinderIter<'static, 'static, u32>>();
+        assert_send_sync::<FindOverlappingIntoIter<'static, u32>>();
+
+        let finder = SimpleFinderBuilder::new()
+            .case_insensitive(true)
+            .overlapping(false)
+            .build(vec![("foo", 0), ("foo bar", 1), ("bar", 2)]);
+        let haystack = String::from("FOO BAR, bar foo");
+        let expected: Vec<_> =
+            SimpleFinder::new_case_insensitive(vec![("foo", 0), ("foo bar", 1), ("bar", 2)])
+                .find_all(&haystack)
+                .map(|(m, &d)| (m, d))
+                .collect();
+
+        let iter = finder.find_overlapping_owned(haystack.clone());
+        let (results, haystack) = std::thread::scope(|scope| {
+            scope
+                .spawn(move || {
+                    let mut iter = iter;
+                    let results: Vec<_> = iter.by_ref().map(|(m, &d)| (m, d)).collect();
+                    (results, iter.into_haystack())
+                })
+                .join()
+                .unwrap()
+        });
+        assert_eq!(results, expected);
+        let texts: Vec<_> = results
+            .iter()
+            .map(|(m, _)| &haystack[m.byte_start()..m.byte_end()])
+            .collect();
+        assert_eq!(texts, vec!["FOO", "FOO BAR", "BAR", "bar", "foo"]);
+    }
+
+    #[test]
+    fn test_find_in_tokens() {
+        let patterns = vec![
+            ("new", 0),
+            ("new york", 1),
+            ("york city", 2),
+            ("\u{1F600}", 3),
+        ];
+        // a segmenter might split differently to the default tokenizer
+        let tokens = ["New", "York", "City", ",", "\u{1F600}", "new"];
+        let spans = |matches: Vec<Match>| -> Vec<_> {
+            matches
+                .iter()
+                .map(|m| (m.pattern(), m.token_start(), m.token_end()))
+                .collect()
+        };
+
+        let finder = SimpleFinderBuilder::new()
+            .case_insensitive(true)
+            .build(patterns.clone());
+        assert_eq!(
+            spans(finder.find_in_tokens(&tokens)),
+            vec![(0, 0, 1), (1, 0, 2), (2, 1, 3), (3, 4, 5), (0, 5, 6)]
+        );
+        let from_all_tokens: Vec<_> = finder.find_all_tokens(&tokens).map(|(m, _)| m).collect();
+        assert_eq!(finder.find_in_tokens(&tokens), from_all_tokens);
+
+        let finder = SimpleFinderBuilder::new()
+            .case_insensitive(true)
+            .overlapping(false)
+            .build(patterns.clone());
+        assert_eq!(
+            spans(finder.find_in_tokens(&tokens)),
+            vec![(0, 0, 1), (2, 1, 3), (3, 4, 5), (0, 5, 6)]
+        );
+
+        let finder = SimpleFinderBuilder::new()
+            .case_insensitive(true)
+            .match_kind(MatchKind::LeftmostLongest)
+            .stop_words(vec![","])
+            .build(patterns);
+        assert_eq!(
+            spans(finder.find_in_tokens(&tokens)),
+            vec![(1, 0, 2), (3, 3, 4), (0, 4, 5)]
+        );
+        assert!(finder.find_in_tokens(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_find_all_unique_by() {
+        let finder = SimpleFinderBuilder::new()
+            .case_insensitive(true)
+            .build(vec![
+                ("fox", String::from("animal")),
+                ("dog", String::from("animal")),
+                ("quick", String::from("speed")),
+                ("slow", String::from("speed")),
+                ("red", String::from("colour")),
+            ]);
+        let haystack = "The quick brown fox jumps over the lazy DOG";
+
+        let categories: HashSet<&str> = finder.find_all_unique_by(haystack, String::as_str);
+        let mut categories: Vec<_> = categories.into_iter().collect();
+        categories.sort_unstable();
+        assert_eq!(categories, vec!["animal", "speed"]);
+
+        let lengths = finder.find_all_unique_by(haystack, |d| d.len());
+        assert_eq!(lengths.len(), 2);
+        assert!(finder
+            .find_all_unique_by("nothing", String::as_str)
+            .is_empty());
+    }
+
+    #[test]
+    fn test_non_overlapping() {
+        let finder = SimpleFinder::new(vec![
+            ("lol lol_", 0),
+            ("lol lol", 2),
+        ]);
+
+        let results: Vec<_> = finder
+            .find_all("lol lol lol lol_")
+            .non_overlapping()
+            .collect();
+        assert_eq!(
+            results,
+            vec![
+                (
+                    Match {
+                        pattern: 1,
+                        len: 7,
+                        end: 7,
+                        byte_len: 7,
+                        byte_end: 7,
+                        token_len: 2,
+                        token_end: 2
+                    },
+                    &2
+                ),
+                (
+                    Match {
+                        pattern: 0,
+                        len: 8,
+                        end: 16,
+                        byte_len: 8,
+                        byte_end: 16,
+                        token_len: 2,
+                        token_end: 4
+                    },
+                    &0
+                )
+            ]
+        );
+    }
+
+    #[test]
+    fn test_fold_width() {
+        let finder = SimpleFinderBuilder::new()
+            .fold_width(true)
+            .build(vec![("ABC", 0), ("123", 1), ("カタカナ", 2)]);
+
+        let results: Vec<_> = finder
+            .find_all("ＡＢＣ　１２３, ｶﾀｶﾅ")
+            .map(|(m, &d)| (m.start(), m.end(), d))
+            .collect();
+        assert_eq!(results, vec![(0, 3, 0), (4, 7, 1), (9, 13, 2)]);
+
+        let unfolded = SimpleFinder::new(vec![("ABC", 0), ("123", 1)]);
+        assert_eq!(unfolded.find_all("ＡＢＣ １２３").count(), 0);
+    }
+
+    #[test]
+    fn test_first_tokens() {
+        let finder = SimpleFinder::new(vec![
+            ("foo", 123),
+            ("bar", 234),
+            ("baz", 345),
+            ("bar baz", 456),
+        ]);
+
+        let expected: HashSet<_> = vec!["foo", "bar", "baz"].into_iter().collect();
+        assert_eq!(finder.first_tokens(), expected);
+    }
+
+    #[test]
+    fn test_chunked_search() {
+        let finder = SimpleFinder::new(vec![("foo", 0), ("bar baz", 1)]);
+
+        let mut search = finder.chunked_search();
+        let mut results = search.push_chunk("foo bar b");
+        results.extend(search.push_chunk("az foo"));
+        results.extend(search.finish());
+
+        let results: Vec<_> = results
+            .into_iter()
+            .map(|(m, &d)| (m.start(), m.end(), m.byte_start(), m.byte_end(), d))
+            .collect();
+        assert_eq!(
+            results,
+            vec![(0, 3, 0, 3, 0), (4, 11, 4, 11, 1), (12, 15, 12, 15, 0)]
+        );
+    }
+
+    #[test]
+    fn test_nonoverlapping() {
+        let finder = SimpleFinder::new(vec![
+            ("foo", 123),
+            ("bar", 234),
+            ("baz", 345),
+            ("bar baz", 456),
+        ]);
+
+        let results: Vec<_> = finder
+            .find_all_nonoverlapping("foo bar baz foobar foo'bar foo,bar")
+            .map(|(m, &d)| (m.start(), m.end(), d))
+            .collect();
+        assert_eq!(
+            results,
+            vec![
+                (0, 3, 123),
+                (4, 7, 234),
+                (8, 11, 345),
+                (19, 22, 123),
+                (23, 26, 234),
+                (27, 30, 123),
+                (31, 34, 234),
+            ]
+        );
+
+        let finder = SimpleFinder::new(vec![
+            ("lol lol_", 0),
+            ("lol lol", 2),
+        ]);
+
+        let nonoverlapping: Vec<_> = finder.find_all_nonoverlapping("lol lol lol lol_").collect();
+        let adapted: Vec<_> = finder.find_all("lol lol lol lol_").non_overlapping().collect();
+        assert_eq!(nonoverlapping, adapted);
+    }
+
+    #[test]
+    fn test_byte_offsets() {
+        let finder = SimpleFinder::new(vec![("café", 0), ("crème brûlée", 1)]);
+        let haystack = "un café et une crème brûlée";
+
+        let results: Vec<_> = finder
+            .find_all(haystack)
+            .map(|(m, _)| {
+                (
+                    (m.start(), m.end()),
+                    &haystack[m.byte_start()..m.byte_end()],
+                )
+            })
+            .collect();
+        assert_eq!(
+            results,
+            vec![((3, 7), "café"), ((15, 27), "crème brûlée")]
+        );
+    }
+
+    #[test]
+    fn test_case_insensitive() {
+        let finder = SimpleFinder::new_case_insensitive(vec![
+            ("foo", 0),
+            ("σοφία", 1),
+            ("İSTANBUL", 2),
+        ]);
+
+        let haystack = "Foo FOO foo ΣΟΦΊΑ İstanbul";
+        let results: Vec<_> = finder
+            .find_all(haystack)
+            .map(|(m, &d)| (&haystack[m.byte_start()..m.byte_end()], m.start(), d))
+            .collect();
+        assert_eq!(
+            results,
+            vec![
+                ("Foo", 0, 0),
+                ("FOO", 4, 0),
+                ("foo", 8, 0),
+                ("ΣΟΦΊΑ", 12, 1),
+                ("İstanbul", 18, 2),
+            ]
+        );
+
+        let sensitive = SimpleFinder::new(vec![("foo", 0)]);
+        assert_eq!(sensitive.find_all("Foo FOO").count(), 0);
+    }
+
+    #[test]
+    fn test_case_insensitive_unique() {
+        let finder = SimpleFinder::new_case_insensitive(vec![("foo", 0), ("Foo BAR", 1)]);
+        let expected: HashSet<_> = vec![0].into_iter().collect();
+        assert_eq!(finder.find_all_unique("FOO foo FoO"), expected);
+
+        let expected: HashSet<_> = vec![0, 1].into_iter().collect();
+        assert_eq!(finder.find_all_unique("fOO bar"), expected);
+
+        let sensitive = SimpleFinder::new(vec![("foo", 0)]);
+        assert_eq!(sensitive.find_all_unique("FOO FoO"), HashSet::new());
+    }
+
+    #[test]
+    fn test_builder() {
+        let patterns = vec![("bar", 0), ("bar baz", 1), ("BAZ", 2)];
+
+        let results: Vec<_> = SimpleFinderBuilder::new()
+            .case_insensitive(true)
+            .overlapping(false)
+            .build(patterns.clone())
+            .find_all("Bar baz baz")
+            .map(|(m, &d)| (m.start(), m.end(), d))
+            .collect();
+        assert_eq!(results, vec![(0, 3, 0), (4, 7, 2), (8, 11, 2)]);
+
+        let results: Vec<_> = SimpleFinderBuilder::new()
+            .build(patterns)
+            .find_all("bar baz BAZ")
+            .map(|(m, &d)| (m.start(), m.end(), d))
+            .collect();
+        assert_eq!(results, vec![(0, 3, 0), (0, 7, 1), (8, 11, 2)]);
+    }
+
+    #[test]
+    fn test_state_id_width() {
+        use crate::nfa::AnyAutomaton;
+
+        let patterns: Vec<_> = (0..100).map(|i| (format!("foo{} bar{}", i, i), i)).collect();
+        let patterns = || patterns.iter().map(|(p, d)| (p.as_str(), *d));
+
+        let wide = SimpleFinder::new_with_state_id::<usize, _>(patterns()).unwrap();
+        let narrow = SimpleFinder::new_with_state_id::<u16, _>(patterns()).unwrap();
+
+        // the forced representation is used, rather than the narrowest
+        let width = |finder: &SimpleFinder<_>| match finder.aho.imp {
+            AnyAutomaton::U8(_) => 1,
+            AnyAutomaton::U16(_) => 2,
+            AnyAutomaton::U32(_) => 4,
+            AnyAutomaton::Usize(_) => core::mem::size_of::<usize>(),
+        };
+        assert_eq!(width(&wide), core::mem::size_of::<usize>());
+        assert_eq!(width(&narrow), 2);
+
+        // transitions are keyed by interned token IDs, so narrowing the state
+        // ID halves the size of each transition
+        assert!(narrow.heap_bytes() < wide.heap_bytes());
+        assert_eq!(
+            narrow.find_all("foo42 bar42").collect::<Vec<_>>(),
+            wide.find_all("foo42 bar42").collect::<Vec<_>>()
+        );
+
+        // 128 patterns of two unique tokens need 258 states, including the
+        // fail and start states, which is more than a u8 can identify
+        let patterns: Vec<_> = (0..128).map(|i| (format!("foo{} bar{}", i, i), i)).collect();
+        let overflowed = SimpleFinder::new_with_state_id::<u8, _>(
+            patterns.iter().map(|(p, d)| (p.as_str(), *d)),
+        );
+        assert_eq!(overflowed.err(), Some(BuildError::StateIdOverflow { max: 255 }));
+    }
+
+    #[test]
+    fn test_auto_state_id() {
+        use crate::nfa::AnyAutomaton;
+
+        let patterns: Vec<_> = (0..300).map(|i| (format!("foo{}", i), i)).collect();
+        let finder = SimpleFinder::new(patterns.iter().map(|(p, d)| (p.as_str(), *d)));
+        assert!(matches!(finder.aho.imp, AnyAutomaton::U16(_)));
+        assert_eq!(
+            finder
+                .find_all("foo299")
+                .map(|(_, &d)| d)
+                .collect::<Vec<_>>(),
+            vec![299]
+        );
+
+        let finder = SimpleFinder::new(patterns.iter().take(10).map(|(p, d)| (p.as_str(), *d)));
+        assert!(matches!(finder.aho.imp, AnyAutomaton::U8(_)));
+    }
+
+    #[test]
+    fn test_try_new() {
+        let finder = SimpleFinder::try_new(vec![("foo bar", 0)]).unwrap();
+        assert_eq!(finder.find_all("foo bar").count(), 1);
+
+        let built = SimpleFinderBuilder::new()
+            .case_insensitive(true)
+            .try_build(vec![("foo bar", 0)])
+            .unwrap();
+        assert_eq!(built.find_all("FOO BAR").count(), 1);
+    }
+
+    #[test]
+    fn test_find_all_with_text() {
+        let finder = SimpleFinder::new_case_insensitive(vec![("foo bar", 0), ("ｂａｚ", 1)]);
+
+        let found: Vec<_> = finder
+            .find_all_with_text("Ünï FOO Bar baz")
+            .map(|(_, d, text)| (*d, text))
+            .collect();
+        assert_eq!(found, vec![(0, "FOO Bar")]);
+
+        let finder = SimpleFinderBuilder::new()
+            .fold_width(true)
+            .case_insensitive(true)
+            .build(vec![("foo bar", 0), ("baz", 1)]);
+        let found: Vec<_> = finder
+            .find_all_with_text("ＦＯＯ Bar, BAZ")
+            .map(|(_, d, text)| (*d, text))
+            .collect();
+        assert_eq!(found, vec![(0, "ＦＯＯ Bar"), (1, "BAZ")]);
+    }
+
+    #[test]
+    fn test_pattern_lookup() {
+        let finder = SimpleFinder::new(vec![("foo bar", 0), ("Baz", 1)]);
+
+        let found: Vec<_> = finder
+            .find_all("foo bar baz")
+            .map(|(m, _)| finder.pattern(m.pattern()))
+            .collect();
+        assert_eq!(found, vec![Some("foo bar")]);
+        assert_eq!(finder.pattern(1), Some("Baz"));
+        assert_eq!(finder.pattern(2), None);
+
+        let finder = SimpleFinderBuilder::new()
+            .store_patterns(false)
+            .build(vec![("foo bar", 0)]);
+        assert_eq!(finder.pattern(0), None);
+    }
+
+    #[test]
+    fn test_replace_all() {
+        let finder =
+            SimpleFinder::new(vec![("foo", 0), ("foo bar", 1), ("bar baz", 2), ("qux", 3)]);
+
+        // "foo bar" wins over "foo" as it's longer, and "bar baz" is dropped
+        // as it overlaps it
+        let replaced = finder.replace_all("a foo bar baz qux", |_, d| format!("<{}>", d));
+        assert_eq!(replaced, "a <1> baz <3>");
+
+        // adjacent matches and a match at the very end
+        let replaced = finder.replace_all("qux,qux", |_, _| "ŭňĭçøđë".to_string());
+        assert_eq!(replaced, "ŭňĭçøđë,ŭňĭçøđë");
+
+        // a match starting exactly where the one before it ends
+        let touching = SimpleFinder::new(vec![("foo,", 0), ("bar", 1)]);
+        let replaced = touching.replace_all("foo,bar", |_, d| format!("<{}>", d));
+        assert_eq!(replaced, "<0><1>");
+
+        // multibyte haystack text around the replaced spans
+        let replaced = finder.replace_all("ünï foo ☃", |m, _| "★".repeat(m.end() - m.start()));
+        assert_eq!(replaced, "ünï ★★★ ☃");
+
+        assert_eq!(
+            finder.replace_all("nothing here", |_, _| unreachable!()),
+            "nothing here"
+        );
+    }
+
+    #[test]
+    fn test_leftmost_longest() {
+        let finder = SimpleFinderBuilder::new()
+            .match_kind(MatchKind::LeftmostLongest)
+            .build(vec![("bar", 0), ("bar baz", 1), ("baz qux", 2), ("qux", 3)]);
+        let found = |haystack| {
+            finder
+                .find_all(haystack)
+                .map(|(m, d)| (*d, m.start(), m.end()))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(found("bar baz"), vec![(1, 0, 7)]);
+        // the failure transition out of "bar" must not forget its match
+        assert_eq!(found("bar lol"), vec![(0, 0, 3)]);
+        // "baz qux" is dropped as it overlaps "bar baz", which starts first
+        assert_eq!(found("bar baz qux"), vec![(1, 0, 7), (3, 8, 11)]);
+        assert_eq!(found("lol baz qux bar"), vec![(2, 4, 11), (0, 12, 15)]);
+        assert_eq!(found("bar bar baz"), vec![(0, 0, 3), (1, 4, 11)]);
+
+        let nonoverlapping: Vec<_> = finder
+            .find_all_nonoverlapping("bar baz qux")
+            .map(|(_, d)| *d)
+            .collect();
+        assert_eq!(nonoverlapping, vec![1, 3]);
+        assert_eq!(finder.replace_all("a bar baz", |_, d| d.to_string()), "a 1");
+    }
+
+    #[test]
+    fn test_leftmost_longest_suffixes() {
+        // "b c d" fails over to "c d" after "b c", where the longer pattern
+        // starting at "b" must still win over "c d"
+        let finder = SimpleFinderBuilder::new()
+            .match_kind(MatchKind::LeftmostLongest)
+            .build(vec![("a b c d", 0), ("b", 1), ("b c d", 2), ("c d", 3)]);
+
+        let found: Vec<_> = finder
+            .find_all("a b c e b c d")
+            .map(|(m, d)| (*d, m.start()))
+            .collect();
+        assert_eq!(found, vec![(1, 2), (2, 8)]);
+
+        // "c" is only seen through the failure transition of "a c", but the
+        // search must still report it once "a c a a" fails
+        let finder = SimpleFinderBuilder::new()
+            .match_kind(MatchKind::LeftmostLongest)
+            .build(vec![("a c a a", 0), ("c", 1)]);
+
+        let found: Vec<_> = finder
+            .find_all("a c a c")
+            .map(|(m, d)| (*d, m.start()))
+            .collect();
+        assert_eq!(found, vec![(1, 2), (1, 6)]);
+    }
+
+    #[test]
+    fn test_leftmost_first() {
+        let found = |patterns: Vec<(&str, usize)>, haystack| {
+            SimpleFinderBuilder::new()
+                .match_kind(MatchKind::LeftmostFirst)
+                .build(patterns)
+                .find_all(haystack)
+                .map(|(m, d)| (*d, m.start(), m.end()))
+                .collect::<Vec<_>>()
+        };
+
+        // the pattern given first wins at a given start position, so
+        // reordering the patterns changes which one is reported
+        assert_eq!(
+            found(vec![("bar", 0), ("bar baz", 1)], "bar baz"),
+            vec![(0, 0, 3)]
+        );
+        assert_eq!(
+            found(vec![("bar baz", 1), ("bar", 0)], "bar baz"),
+            vec![(1, 0, 7)]
+        );
+        assert_eq!(
+            found(vec![("bar baz", 1), ("bar", 0)], "bar qux"),
+            vec![(0, 0, 3)]
+        );
+
+        // but a match starting earlier always wins
+        assert_eq!(
+            found(vec![("baz qux", 0), ("bar baz", 1)], "bar baz qux"),
+            vec![(1, 0, 7)]
+        );
+        assert_eq!(
+            found(vec![("a b c d", 0), ("b", 1), ("c d", 2)], "a b c e c d"),
+            vec![(1, 2, 3), (2, 8, 11)]
+        );
+    }
+
+    #[test]
+    fn test_is_match() {
+        let finder = SimpleFinder::new(vec![("foo bar", 0), ("baz", 1)]);
+
+        assert!(finder.is_match("lol foo bar lol"));
+        assert!(finder.is_match("baz"));
+        assert!(!finder.is_match("foo lol bar foobar"));
+        assert!(!finder.is_match(""));
+
+        let finder = SimpleFinderBuilder::new()
+            .case_insensitive(true)
+            .match_kind(MatchKind::LeftmostFirst)
+            .build(vec![("foo bar", 0), ("foo", 1)]);
+        assert!(finder.is_match("lol FOO lol"));
+        assert!(!finder.is_match("lol bar lol"));
+    }
+
+    #[test]
+    fn test_find_anchored() {
+        for &dfa in &[false, true] {
+            let finder = SimpleFinderBuilder::new().dfa(dfa).build(vec![
+                ("foo bar", 0),
+                ("bar", 1),
+                ("foo", 2),
+            ]);
+            let found = |haystack| {
+                finder
+                    .find_anchored(haystack)
+                    .map(|(m, &d)| (d, m.start(), m.end()))
+            };
+
+            assert_eq!(found("foo bar"), Some((0, 0, 7)));
+            assert_eq!(found("  foo  bar "), Some((0, 2, 10)));
+            assert_eq!(found("foo, bar"), None);
+            assert_eq!(found("foo"), Some((2, 0, 3)));
+            assert_eq!(found("bar"), Some((1, 0, 3)));
+            assert_eq!(found("foo bar baz"), None);
+            assert_eq!(found("lol foo bar"), None);
+            assert_eq!(found("foo foo bar"), None);
+            assert_eq!(found(""), None);
+        }
+    }
+
+    #[test]
+    fn test_step() {
+        let finder = SimpleFinder::new(vec![("foo bar", 0), ("bar", 1), ("foo", 2)]);
+        fn found<'a>(matches: impl Iterator<Item = (Match, &'a i32)>) -> Vec<(i32, usize, usize)> {
+            matches.map(|(m, &d)| (d, m.start(), m.end())).collect()
+        }
+
+        let mut state = finder.start_state().unwrap();
+        assert_eq!(found(finder.step(&mut state, "lol")), vec![]);
+        assert_eq!(found(finder.step(&mut state, "FOO")), vec![]);
+        assert_eq!(found(finder.step(&mut state, "foo")), vec![(2, 2, 3)]);
+
+        // resuming a snapshot gives the same matches as carrying on
+        let snapshot = state;
+        assert_eq!(
+            found(finder.step(&mut state, "bar")),
+            vec![(0, 2, 4), (1, 3, 4)]
+        );
+        let mut resumed = snapshot;
+        assert_eq!(
+            found(finder.step(&mut resumed, "bar")),
+            vec![(0, 2, 4), (1, 3, 4)]
+        );
+        assert_eq!(resumed, state);
+        assert_eq!(state.tokens_consumed(), 4);
+
+        // stepping through the tokens of a haystack finds what find_all does
+        let haystack = "foo bar lol bar foo";
+        let mut state = finder.start_state().unwrap();
+        let stepped: Vec<_> = haystack
+            .split(' ')
+            .flat_map(|token| found(finder.step(&mut state, token)))
+            .collect();
+        let tokens: Vec<_> = haystack.split(' ').collect();
+        let expected: Vec<_> = finder
+            .find_all_tokens(&tokens)
+            .map(|(m, &d)| (d, m.start(), m.end()))
+            .collect();
+        assert_eq!(stepped, expected);
+
+        let leftmost = SimpleFinderBuilder::new()
+            .match_kind(MatchKind::LeftmostFirst)
+            .build(vec![("foo", 0)]);
+        assert_eq!(leftmost.start_state(), None);
+    }
+
+    #[test]
+    fn test_reverse() {
+        let found = |matches: Vec<(Match, &i32)>| {
+            matches
+                .into_iter()
+                .map(|(m, &d)| (d, m.start(), m.end()))
+                .collect::<Vec<_>>()
+        };
+
+        // patterns that read the same either way, found by both searches
+        let patterns = vec![("a b a", 0), ("b", 1), ("a b b a", 2)];
+        let haystack = "a b a b b a b a";
+        let forward = SimpleFinder::new(patterns.clone());
+        let reverse = SimpleFinderBuilder::new().build_reverse(patterns.clone());
+        let mut expected = found(forward.find_all(haystack).collect());
+        let reversed = found(reverse.find_all(haystack).collect());
+        assert!(reversed.windows(2).all(|w| w[0].1 >= w[1].1));
+        expected.sort_by_key(|&(d, start, _)| (std::cmp::Reverse(start), d));
+        let mut sorted = reversed.clone();
+        sorted.sort_by_key(|&(d, start, _)| (std::cmp::Reverse(start), d));
+        assert_eq!(sorted, expected);
+
+        // the first match reported is the one nearest the end
+        let reverse = SimpleFinderBuilder::new()
+            .match_kind(MatchKind::LeftmostLongest)
+            .build_reverse(vec![
+                ("sent from my phone", 0),
+                ("my phone", 1),
+                ("thanks", 2),
+            ]);
+        let haystack = "thanks, my phone broke\n\nthanks\nsent from my phone";
+        assert_eq!(
+            found(reverse.find_all(haystack).collect()),
+            vec![(0, 31, 49), (2, 24, 30), (1, 8, 16), (2, 0, 6)]
+        );
+    }
+
+    #[test]
+    fn test_find_first() {
+        let finder = SimpleFinder::new(vec![("foo bar", 0), ("bar", 1), ("café", 2)]);
+        let found = |haystack| {
+            finder
+                .find_first(haystack)
+                .map(|(m, &d)| (d, m.start(), m.end()))
+        };
+
+        assert_eq!(found("lol foo bar lol bar"), Some((0, 4, 11)));
+        assert_eq!(found("lol foo lol bar"), Some((1, 12, 15)));
+        assert_eq!(found("lol"), None);
+        assert_eq!(found(""), None);
+        assert_eq!(finder.find_first("日本 café").unwrap().0.byte_start(), 7);
+        for haystack in &["foo bar", "a foo café bar", "bar foo bar", "foo foo"] {
+            assert_eq!(
+                finder.find_first(haystack),
+                finder.find_all(haystack).next(),
+                "{:?}",
+                haystack
+            );
+        }
+
+        // a leftmost search may report a match ending later
+        let finder = SimpleFinderBuilder::new()
+            .match_kind(MatchKind::LeftmostLongest)
+            .build(vec![("foo bar baz", 0), ("bar", 1)]);
+        let (m, _) = finder.find_first("foo bar baz").unwrap();
+        assert_eq!((m.pattern(), m.start(), m.end()), (0, 0, 11));
+    }
+
+    #[test]
+    fn test_extend() {
+        let patterns = vec![("foo", 0), ("bar baz", 1), ("qux", 2)];
+        let added = vec![("baz", 3), ("foo bar", 4), ("qux", 5)];
+        let haystack = "foo bar baz qux foo baz";
+
+        for &kind in &[
+            MatchKind::Standard,
+            MatchKind::LeftmostLongest,
+            MatchKind::LeftmostFirst,
+        ] {
+            for &dfa in &[false, true] {
+                let builder = {
+                    let mut builder = SimpleFinderBuilder::new();
+                    builder.match_kind(kind).dfa(dfa).prefilter(true);
+                    builder
+                };
+                let mut finder = builder.build(patterns.clone());
+                finder.extend(added.clone());
+                let all = builder.build(patterns.iter().chain(&added).cloned());
+
+                let found = |finder: &SimpleFinder<i32>| {
+                    finder
+                        .find_all(haystack)
+                        .map(|(m, &d)| (m.pattern(), d, m.start(), m.end()))
+                        .collect::<Vec<_>>()
+                };
+                assert_eq!(found(&finder), found(&all), "{:?}", kind);
+                assert_eq!(finder.pattern_count(), 6);
+                assert_eq!(finder.pattern(4), Some("foo bar"));
+                // the old patterns are still found along with the new ones
+                assert!(found(&finder).iter().any(|&(_, d, _, _)| d == 0));
+                assert!(found(&finder).iter().any(|&(_, d, _, _)| d >= 3));
+            }
+        }
+
+        // adding patterns can need a wider state ID representation
+        let mut finder = SimpleFinder::new(vec![("a", ())]);
+        let many: Vec<_> = (0..300).map(|i| format!("w{}", i)).collect();
+        finder.extend(many.iter().map(|p| (p.as_str(), ())));
+        assert_eq!(finder.find_all("a w299 w5").count(), 3);
+
+        assert!(finder.try_extend(vec![("  ", ())]).is_err());
+        assert_eq!(finder.pattern_count(), 301);
+    }
+
+    #[test]
+    fn test_merge() {
+        let fruit = SimpleFinder::new(vec![("apple", "fruit"), ("blood orange", "fruit")]);
+        let colour = SimpleFinder::new(vec![("orange", "colour"), ("red", "colour")]);
+        let merged = fruit.merge(colour);
+
+        let found: Vec<_> = merged
+            .find_all("a red apple and a blood orange")
+            .map(|(m, &d)| (m.pattern(), d, m.start(), m.end()))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (3, "colour", 2, 5),
+                (0, "fruit", 6, 11),
+                (1, "fruit", 18, 30),
+                (2, "colour", 24, 30),
+            ]
+        );
+        assert_eq!(merged.pattern_count(), 4);
+        assert_eq!(merged.pattern(2), Some("orange"));
+
+        // merging gives the same automaton as building from both sets of
+        // patterns, for every match kind
+        let patterns = vec![("a b", 0), ("b", 1), ("a b c", 2), ("c", 3)];
+        let other = vec![("b c", 4), ("a", 5), ("a b", 6), ("a b c d", 7)];
+        let haystack = "a b c d a b";
+        for &kind in &[
+            MatchKind::Standard,
+            MatchKind::LeftmostLongest,
+            MatchKind::LeftmostFirst,
+        ] {
+            for &dfa in &[false, true] {
+                let mut builder = SimpleFinderBuilder::new();
+                builder.match_kind(kind).dfa(dfa);
+                let merged = builder
+                    .build(patterns.clone())
+                    .merge(builder.build(other.clone()));
+                let all = builder.build(patterns.iter().chain(&other).cloned());
+
+                let found = |finder: &SimpleFinder<i32>| {
+                    finder
+                        .find_all(haystack)
+                        .map(|(m, &d)| (m.pattern(), d, m.start(), m.end()))
+                        .collect::<Vec<_>>()
+                };
+                assert_eq!(found(&merged), found(&all), "{:?}", kind);
+                assert_eq!(merged.pattern_count(), 8);
+            }
+        }
+
+        // the patterns of a case-insensitive finder wouldn't match as they did
+        let insensitive = SimpleFinder::new_case_insensitive(vec![("apple", 1)]);
+        let result = SimpleFinder::new(vec![("red", 0)]).try_merge(insensitive);
+        assert_eq!(result.err(), Some(BuildError::NormalizerMismatch));
+
+        let merged = SimpleFinderBuilder::new()
+            .stop_words(vec!["the"])
+            .build(vec![("red", 0)])
+            .try_merge(
+                SimpleFinderBuilder::new()
+                    .stop_words(vec!["the"])
+                    .build(vec![("the apple", 1)]),
+            )
+            .unwrap();
+        assert_eq!(merged.find_all("the red apple").count(), 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "failed to merge finders")]
+    fn test_merge_mismatched_normalizers() {
+        let stops = SimpleFinderBuilder::new()
+            .stop_words(vec!["the"])
+            .build(vec![("apple", 1)]);
+        SimpleFinder::new(vec![("red", 0)]).merge(stops);
+    }
+
+    #[test]
+    fn test_count_matches() {
+        let patterns = vec![("foo", 0), ("foo bar", 1), ("bar", 2), ("baz", 3)];
+        let haystack = "foo bar foo, bar baz foo bar";
+
+        let finder = SimpleFinder::new(patterns.clone());
+        assert_eq!(
+            finder.count_matches(haystack),
+            finder.find_all(haystack).count()
+        );
+        assert_eq!(finder.count_matches(haystack), 9);
+
+        let counts = finder.count_matches_per_pattern(haystack);
+        assert_eq!(counts.get(&0), Some(&3));
+        assert_eq!(counts.get(&1), Some(&2));
+        assert_eq!(counts.get(&2), Some(&3));
+        assert_eq!(counts.get(&3), Some(&1));
+        assert_eq!(finder.count_matches("nothing"), 0);
+
+        for kind in &[MatchKind::LeftmostLongest, MatchKind::LeftmostFirst] {
+            let finder = SimpleFinderBuilder::new()
+                .match_kind(*kind)
+                .build(patterns.clone());
+            assert_eq!(
+                finder.count_matches(haystack),
+                finder.find_all(haystack).count()
+            );
+        }
+        let finder = SimpleFinderBuilder::new()
+            .overlapping(false)
+            .build(patterns);
+        assert_eq!(
+            finder.count_matches(haystack),
+            finder.find_all(haystack).count()
+        );
+    }
+
+    #[test]
+    fn test_stream() {
+        let finder = SimpleFinder::new(vec![("foo", 0), ("bar baz", 1), ("baz qux", 2)]);
+        let haystack = "foo bar baz qux ☃ foo";
+
+        // every way of splitting the haystack in two gives the same matches
+        let expected: Vec<_> = finder.find_all(haystack).map(|(m, &d)| (m, d)).collect();
+        for (split, _) in haystack.char_indices() {
+            let mut stream = finder.stream();
+            let mut results = stream.push(&haystack[..split]);
+            results.extend(stream.push(&haystack[split..]));
+            results.extend(stream.finish());
+
+            let results: Vec<_> = results.into_iter().map(|(m, &d)| (m, d)).collect();
+            assert_eq!(results, expected, "split at {}", split);
+        }
+
+        let mut stream = finder.stream();
+        let mut results = Vec::new();
+        for c in "bar baz qux".chars() {
+            results.extend(stream.push(&c.to_string()));
+        }
+        results.extend(stream.finish());
+        let results: Vec<_> = results
+            .into_iter()
+            .map(|(m, &d)| (m.start(), m.end(), d))
+            .collect();
+        assert_eq!(results, vec![(0, 7, 1), (4, 11, 2)]);
+    }
+
+    #[test]
+    fn test_find_all_tokens() {
+        let finder = SimpleFinder::new_case_insensitive(vec![("run fast", 0), ("fast", 1)]);
+
+        // tokens from a stemmer, which the finder's tokenizer would not produce
+        let tokens = ["they", "Run", "fast", "er"];
+        let results: Vec<_> = finder
+            .find_all_tokens(&tokens)
+            .map(|(m, &d)| (m.start(), m.end(), m.byte_start(), m.byte_end(), d))
+            .collect();
+        assert_eq!(results, vec![(1, 3, 1, 3, 0), (2, 3, 2, 3, 1)]);
+    }
+
+    #[test]
+    fn test_custom_tokenizer() {
+        // splits on spaces only, so punctuation stays part of each token
+        struct SpaceTokenizer;
+
+        impl Tokenizer for SpaceTokenizer {
+            fn tokenize<'t>(&self, text: &'t str) -> Box<dyn Iterator<Item = (u32, &'t str)> + 't> {
+                let mut offset = 0;
+                Box::new(text.split(' ').filter_map(move |token| {
+                    let start = offset;
+                    offset += token.chars().count() as u32 + 1;
+                    Some((start, token)).filter(|_| !token.is_empty())
+                }))
+            }
+        }
+
+        let finder = SimpleFinderBuilder::new()
+            .tokenizer(SpaceTokenizer)
+            .build(vec![("c++ rocks", 0), ("c", 1)]);
+
+        let results: Vec<_> = finder
+            .find_all("I think  c++ rocks, c")
+            .map(|(m, &d)| (m.start(), m.end(), d))
+            .collect();
+        assert_eq!(results, vec![(20, 21, 1)]);
+
+        let results: Vec<_> = finder
+            .find_all("ünï c++ rocks")
+            .map(|(m, &d)| (m.start(), m.end(), m.byte_start(), d))
+            .collect();
+        assert_eq!(results, vec![(4, 13, 6, 0)]);
+
+        // tokens that aren't subslices of the text have no offsets in it, so
+        // they're skipped
+        struct ForeignTokenizer;
+
+        impl Tokenizer for ForeignTokenizer {
+            fn tokenize<'t>(&self, text: &'t str) -> Box<dyn Iterator<Item = (u32, &'t str)> + 't> {
+                Box::new(
+                    SpaceTokenizer
+                        .tokenize(text)
+                        .flat_map(|(idx, token)| vec![(idx, "!"), (idx, token)]),
+                )
+            }
+        }
+
+        let finder = SimpleFinderBuilder::new()
+            .tokenizer(ForeignTokenizer)
+            .build(vec![("c++ rocks", 0)]);
+        let haystack = "so c++ rocks";
+        let results: Vec<_> = finder
+            .find_all(haystack)
+            .map(|(m, &d)| (m.start(), m.end(), m.byte_start(), d))
+            .collect();
+        assert_eq!(results, vec![(3, 12, 3, 0)]);
+        assert!(finder.is_match(haystack));
+        let first = finder
+            .find_first(haystack)
+            .map(|(m, &d)| (m.start(), m.end(), d));
+        assert_eq!(first, Some((3, 12, 0)));
+        let mut stream = finder.stream();
+        let mut results = stream.push("so c++ ");
+        results.extend(stream.push("rocks"));
+        results.extend(stream.finish());
+        let results: Vec<_> = results
+            .into_iter()
+            .map(|(m, &d)| (m.start(), m.end(), d))
+            .collect();
+        assert_eq!(results, vec![(3, 12, 0)]);
+    }
+
+    #[test]
+    fn test_keep_whitespace() {
+        let finder = SimpleFinderBuilder::new()
+            .keep_whitespace(true)
+            .build(vec![("foo  bar", 0), ("baz qux", 1)]);
+
+        assert_eq!(finder.find_all("foo bar").count(), 0);
+        assert_eq!(finder.find_all("baz  qux").count(), 0);
+        let results: Vec<_> = finder
+            .find_all("foo  bar baz qux")
+            .map(|(m, &d)| (m.start(), m.end(), d))
+            .collect();
+        assert_eq!(results, vec![(0, 8, 0), (9, 16, 1)]);
+
+        // spacing is ignored by default
+        let finder = SimpleFinder::new(vec![("foo  bar", 0)]);
+        assert_eq!(finder.find_all("foo bar").count(), 1);
+    }
+
+    #[test]
+    fn test_word_characters() {
+        let finder = SimpleFinderBuilder::new()
+            .word_characters(|c| c == '#' || c == '@' || is_word_character(c))
+            .build(vec![("#foo", 0), ("foo", 1), ("@bar baz", 2)]);
+
+        let results: Vec<_> = finder
+            .find_all("#foo foo #foo! @bar baz")
+            .map(|(m, &d)| (m.start(), m.end(), d))
+            .collect();
+        assert_eq!(results, vec![(0, 4, 0), (5, 8, 1), (9, 13, 0), (15, 23, 2)]);
+        assert_eq!(finder.find_all("@foo").count(), 0);
+    }
+
+    #[test]
+    fn test_normalize_nfc() {
+        let patterns = vec![("café noir", 0), ("a ≠ b", 1)];
+        let haystack = "un cafe\u{301} noir, a =\u{338} b";
+
+        let finder = SimpleFinder::new(patterns.clone());
+        assert_eq!(finder.find_all(haystack).count(), 0);
+
+        let finder = SimpleFinderBuilder::new()
+            .normalize(Normalization::Nfc)
+            .build(patterns);
+        let results: Vec<_> = finder
+            .find_all(haystack)
+            .map(|(m, &d)| {
+                (
+                    &haystack[m.byte_start()..m.byte_end()],
+                    m.start(),
+                    m.end(),
+                    d,
+                )
+            })
+            .collect();
+        assert_eq!(
+            results,
+            vec![("cafe\u{301} noir", 3, 13, 0), ("a =\u{338} b", 15, 21, 1)]
+        );
+        assert!(finder.is_match("cafe\u{301} noir"));
+    }
+
+    #[test]
+    fn test_empty_pattern() {
+        for pattern in &["", "  \t"] {
+            let result = SimpleFinder::try_new(vec![("foo", 0), (*pattern, 1)]);
+            assert_eq!(result.err(), Some(BuildError::EmptyPattern { pattern: 1 }));
+        }
+
+        let result = SimpleFinderBuilder::new()
+            .match_kind(MatchKind::LeftmostLongest)
+            .build_with_state_id::<u8, _, _>(vec![("", ())]);
+        assert_eq!(result.err(), Some(BuildError::EmptyPattern { pattern: 0 }));
+    }
+
+    #[test]
+    fn test_symbol_only_patterns() {
+        let patterns = vec![("foo", 0), ("@", 1), ("a @ b", 2)];
+        let haystack = "foo@bar a @ b";
+
+        let finder = SimpleFinder::new(patterns.clone());
+        let results: Vec<_> = finder
+            .find_all(haystack)
+            .map(|(m, &d)| (m.start(), d))
+            .collect();
+        assert_eq!(results, vec![(0, 0), (3, 1), (10, 1), (8, 2)]);
+
+        let mut builder = SimpleFinderBuilder::new();
+        builder.reject_symbol_only_patterns(true);
+        let result = builder.try_build(patterns);
+        assert_eq!(
+            result.err(),
+            Some(BuildError::SymbolOnlyPattern { pattern: 1 })
+        );
+        let result = builder.try_build(vec![("foo", 0), ("", 1), ("...", 2)]);
+        assert_eq!(result.err(), Some(BuildError::EmptyPattern { pattern: 1 }));
+
+        let mut finder = builder.build(vec![("foo", 0), ("a @ b", 2)]);
+        let results: Vec<_> = finder
+            .find_all(haystack)
+            .map(|(m, &d)| (m.start(), d))
+            .collect();
+        assert_eq!(results, vec![(0, 0), (8, 2)]);
+        let result = finder.try_extend(vec![("bar", 3), ("@ !", 4)]);
+        assert_eq!(
+            result.err(),
+            Some(BuildError::SymbolOnlyPattern { pattern: 1 })
+        );
+        assert_eq!(finder.pattern_count(), 2);
+
+        #[cfg(feature = "serialize")]
+        {
+            let mut loaded = SimpleFinder::from_bytes(&finder.to_bytes().unwrap(), [0, 2]).unwrap();
+            let result = loaded.try_extend(vec![("@", 1)]);
+            assert_eq!(
+                result.err(),
+                Some(BuildError::SymbolOnlyPattern { pattern: 0 })
+            );
+        }
+    }
+
+    #[cfg(feature = "trace")]
+    #[test]
+    fn test_explain() {
+        let patterns = vec![("new york city", 0), ("york", 1)];
+        let haystack = "New York town, new york city";
+        for &dfa in &[false, true] {
+            let finder = SimpleFinderBuilder::new()
+                .case_insensitive(true)
+                .dfa(dfa)
+                .build(patterns.clone());
+            let steps = finder.explain(haystack);
+
+            let tokens: Vec<_> = steps.iter().map(|s| s.token()).collect();
+            assert_eq!(
+                tokens,
+                vec!["new", "york", "town", ",", "new", "york", "city"]
+            );
+            let depths: Vec<_> = steps.iter().map(|s| s.depth()).collect();
+            assert_eq!(depths, vec![1, 2, 0, 0, 1, 2, 3]);
+            assert_eq!((steps[1].byte_start(), steps[1].byte_end()), (4, 8));
+            let start = steps[0].from();
+            assert_eq!(steps[2].to(), start);
+            for pair in steps.windows(2) {
+                assert_eq!(pair[0].to(), pair[1].from());
+            }
+
+            let matches: Vec<_> = steps
+                .iter()
+                .flat_map(|s| {
+                    s.matches()
+                        .iter()
+                        .map(|m| (m.pattern(), m.start(), m.end()))
+                })
+                .collect();
+            let expected: Vec<_> = finder
+                .find_all(haystack)
+                .map(|(m, _)| (m.pattern(), m.start(), m.end()))
+                .collect();
+            assert_eq!(matches, expected);
+
+            if !dfa {
+                // "new york" has no transition on "town", so it fails to
+                // "york" and then to the start state
+                let york = steps[1].failures();
+                assert!(york.is_empty());
+                let town = steps[2].failures();
+                assert_eq!(town.len(), 2);
+                assert_eq!(town[1], start);
+            }
+        }
+    }
+
+    #[test]
+    fn test_dedup_patterns() {
+        let patterns = vec![("foo bar", 0), ("Foo, bar", 1), ("foo  bar", 2)];
+
+        let finder = SimpleFinder::new(patterns.clone());
+        let results: Vec<_> = finder.find_all("foo bar").map(|(_, &d)| d).collect();
+        assert_eq!(results, vec![0, 2]);
+
+        let finder = SimpleFinderBuilder::new()
+            .case_insensitive(true)
+            .dedup_patterns(true)
+            .build(patterns);
+        let results: Vec<_> = finder.find_all("FOO bar").map(|(_, &d)| d).collect();
+        assert_eq!(results, vec![0]);
+        assert_eq!(finder.pattern_count(), 3);
+    }
+
+    /// Builds a random sentence of 1 to `max_len` tokens from `vocab`,
+    /// returning it along with its tokens.
+    fn random_sentence<R: rand::Rng>(
+        rng: &mut R,
+        vocab: &[&'static str],
+        max_len: usize,
+    ) -> (String, Vec<&'static str>) {
+        let spaces = [" ", "  ", "\t", " \n "];
+        let len = rng.gen_range(1, max_len + 1);
+        let tokens: Vec<_> = (0..len)
+            .map(|_| vocab[rng.gen_range(0, vocab.len())])
+            .collect();
+
+        let mut sentence = String::new();
+        for (i, token) in tokens.iter().enumerate() {
+            if i > 0 {
+                sentence.push_str(spaces[rng.gen_range(0, spaces.len())]);
+            }
+            sentence.push_str(token);
+        }
+        (sentence, tokens)
+    }
+
+    #[test]
+    fn test_offsets_fuzz() {
+        use rand::{rngs::SmallRng, Rng, SeedableRng};
+
+        let vocab = ["a", "bb", "é", "日本", "x_1", ".", "?!", "ÿÿÿ"];
+        let mut rng = SmallRng::from_seed([7; 16]);
+
+        for _ in 0..500 {
+            let num_patterns = rng.gen_range(1, 5);
+            let patterns: Vec<_> = (0..num_patterns)
+                .map(|_| random_sentence(&mut rng, &vocab, 3))
+                .collect();
+            let (haystack, tokens) = random_sentence(&mut rng, &vocab, 8);
+
+            // the char and byte span of each haystack token, found by hand
+            let mut spans = Vec::new();
+            let mut byte_pos = 0;
+            for token in &tokens {
+                byte_pos += haystack[byte_pos..].find(token).unwrap();
+                let char_pos = haystack[..byte_pos].chars().count();
+                spans.push((
+                    char_pos,
+                    char_pos + token.chars().count(),
+                    byte_pos,
+                    byte_pos + token.len(),
+                ));
+                byte_pos += token.len();
+            }
+
+            let mut expected = Vec::new();
+            for (id, (_, pattern)) in patterns.iter().enumerate() {
+                for start in 0..tokens.len() {
+                    let end = start + pattern.len();
+                    if end <= tokens.len() && tokens[start..end] == pattern[..] {
+                        let (char_start, _, byte_start, _) = spans[start];
+                        let (_, char_end, _, byte_end) = spans[end - 1];
+                        expected.push((id, char_start, char_end, byte_start, byte_end));
+                    }
+                }
+            }
+            expected.sort();
+
+            let finder = SimpleFinder::new(
+                patterns
+                    .iter()
+                    .enumerate()
+                    .map(|(id, (pattern, _))| (pattern.as_str(), id)),
+            );
+            let mut results: Vec<_> = finder
+                .find_all(&haystack)
+                .map(|(m, &id)| (id, m.start(), m.end(), m.byte_start(), m.byte_end()))
+                .collect();
+            results.sort();
+
+            assert_eq!(
+                results, expected,
+                "patterns {:?} in {:?}",
+                patterns, haystack
+            );
+        }
+    }
+
+    #[test]
+    fn test_prefilter() {
+        use rand::{rngs::SmallRng, Rng, SeedableRng};
+
+        let vocab = ["a", "ab", "b", "ba", "abc", "é", "."];
+        let mut rng = SmallRng::from_seed([3; 16]);
+
+        for _ in 0..300 {
+            let num_patterns = rng.gen_range(1, 4);
+            let patterns: Vec<_> = (0..num_patterns)
+                .map(|_| random_sentence(&mut rng, &vocab, 3).0)
+                .collect();
+            let (haystack, _) = random_sentence(&mut rng, &vocab, 10);
+
+            for &kind in &[
+                MatchKind::Standard,
+                MatchKind::LeftmostLongest,
+                MatchKind::LeftmostFirst,
+            ] {
+                for &overlapping in &[false, true] {
+                    let find = |prefilter| {
+                        let finder = SimpleFinderBuilder::new()
+                            .match_kind(kind)
+                            .overlapping(overlapping)
+                            .prefilter(prefilter)
+                            .build(patterns.iter().map(|p| (p.as_str(), ())));
+                        let matches: Vec<_> = finder.find_all(&haystack).map(|(m, _)| m).collect();
+                        (matches, finder.is_match(&haystack))
+                    };
+                    assert_eq!(
+                        find(true),
+                        find(false),
+                        "patterns {:?} in {:?}",
+                        patterns,
+                        haystack
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_dfa() {
+        use rand::{rngs::SmallRng, Rng, SeedableRng};
+
+        // enough tokens that failure chains are long, and states copy many
+        // transitions from the states they fail to
+        let vocab = ["a", "b", "c", "d", "e", "f", "g", "h", "i", "j", "k", "l"];
+        let mut rng = SmallRng::from_seed([4; 16]);
+
+        for _ in 0..300 {
+            let num_patterns = rng.gen_range(1, 60);
+            let patterns: Vec<_> = (0..num_patterns)
+                .map(|_| {
+                    let tokens = rng.gen_range(3, vocab.len());
+                    random_sentence(&mut rng, &vocab[..tokens], 4).0
+                })
+                .collect();
+            let (haystack, _) = random_sentence(&mut rng, &vocab, 40);
+
+            for &kind in &[
+                MatchKind::Standard,
+                MatchKind::LeftmostLongest,
+                MatchKind::LeftmostFirst,
+            ] {
+                for &overlapping in &[false, true] {
+                    let find = |dfa| {
+                        let finder = SimpleFinderBuilder::new()
+                            .match_kind(kind)
+                            .overlapping(overlapping)
+                            .dfa(dfa)
+                            .build(patterns.iter().map(|p| (p.as_str(), ())));
+                        // a DFA falls back at most once per token
+                        #[cfg(feature = "trace")]
+                        if dfa {
+                            let steps = finder.explain(&haystack);
+                            assert!(steps.iter().all(|step| step.failures().len() <= 1));
+                        }
+                        let matches: Vec<_> = finder.find_all(&haystack).map(|(m, _)| m).collect();
+                        (matches, finder.count_matches(&haystack))
+                    };
+                    assert_eq!(
+                        find(true),
+                        find(false),
+                        "patterns {:?} in {:?}",
+                        patterns,
+                        haystack
+                    );
+                }
+            }
+        }
+
+        // "y x" fails to "x", which has many transitions, but on "z" a DFA
+        // falls straight back to the start state rather than through "x"
+        #[cfg(feature = "trace")]
+        {
+            let mut patterns: Vec<_> = vocab[..9].iter().map(|t| format!("x {}", t)).collect();
+            patterns.push(String::from("y x w"));
+            let finder = SimpleFinderBuilder::new()
+                .dfa(true)
+                .build(patterns.iter().map(|p| (p.as_str(), ())));
+            let steps = finder.explain("y x z");
+            assert_eq!(steps[2].failures(), &[2]);
+            assert_eq!(steps[2].to(), 2);
+        }
+    }
+
+    #[test]
+    fn test_sparse_transitions_fuzz() {
+        use rand::{rngs::SmallRng, Rng, SeedableRng};
+
+        // a large vocabulary, so that deep states have many transitions
+        let vocab = [
+            "a", "b", "c", "d", "e", "f", "g", "h", "i", "j", "k", "l", "m", "n", "o", "p", "q",
+            "r", "s", "t", "u", "v", "w", "x", "y", "z",
+        ];
+        let mut rng = SmallRng::from_seed([7; 16]);
+
+        for _ in 0..200 {
+            let num_patterns = rng.gen_range(1, 200);
+            let patterns: Vec<_> = (0..num_patterns)
+                .map(|_| {
+                    // a shared first token gives the states after it many
+                    // transitions in whatever order the patterns add them
+                    let (rest, mut tokens) = random_sentence(&mut rng, &vocab, 3);
+                    tokens.insert(0, vocab[0]);
+                    (format!("{} {}", vocab[0], rest), tokens)
+                })
+                .collect();
+            // mostly the patterns themselves, so that there's plenty to find
+            let mut tokens = vec![];
+            for _ in 0..rng.gen_range(1, 30) {
+                if rng.gen() {
+                    tokens.extend(&patterns[rng.gen_range(0, patterns.len())].1);
+                } else {
+                    tokens.push(vocab[rng.gen_range(0, vocab.len())]);
+                }
+            }
+            let haystack = tokens.join(" ");
+
+            // every window of the haystack's tokens equal to a pattern
+            let mut expected = vec![];
+            for end in 1..=tokens.len() {
+                for (id, (_, pattern)) in patterns.iter().enumerate() {
+                    if pattern.len() <= end && tokens[end - pattern.len()..end] == pattern[..] {
+                        expected.push((id, end - pattern.len(), end));
+                    }
+                }
+            }
+            expected.sort();
+
+            for &dfa in &[false, true] {
+                let finder = SimpleFinderBuilder::new().dfa(dfa).build(
+                    patterns
+                        .iter()
+                        .enumerate()
+                        .map(|(i, (p, _))| (p.as_str(), i)),
+                );
+                // every token is a single byte followed by a space
+                let mut found: Vec<_> = finder
+                    .find_all(&haystack)
+                    .map(|(m, &id)| (id, m.byte_start() / 2, m.byte_end() / 2 + 1))
+                    .collect();
+                found.sort();
+                assert_eq!(found, expected, "patterns {:?} in {:?}", patterns, haystack);
+            }
+        }
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn test_find_all_parallel() {
+        use rand::{rngs::SmallRng, SeedableRng};
+
+        let vocab = ["a", "bb", "日本"];
+        let mut rng = SmallRng::from_seed([5; 16]);
+        let patterns: Vec<_> = (0..20)
+            .map(|_| random_sentence(&mut rng, &vocab, 4).0)
+            .collect();
+        // long enough to be split into several parts, whose seams matches
+        // will straddle
+        let mut haystack = String::new();
+        let mut num_tokens = 0;
+        while num_tokens < 50_000 {
+            let (sentence, tokens) = random_sentence(&mut rng, &vocab, 1000);
+            haystack.push_str(&sentence);
+            haystack.push(' ');
+            num_tokens += tokens.len();
+        }
+
+        for &(match_kind, overlapping) in &[
+            (MatchKind::Standard, true),
+            (MatchKind::Standard, false),
+            (MatchKind::LeftmostLongest, true),
+        ] {
+            let finder = SimpleFinderBuilder::new()
+                .match_kind(match_kind)
+                .overlapping(overlapping)
+                .build(patterns.iter().map(|p| (p.as_str(), ())));
+            let expected: Vec<_> = finder.find_all(&haystack).collect();
+            assert!(!expected.is_empty());
+            assert_eq!(finder.find_all_parallel(&haystack), expected);
+        }
+    }
+
+    #[cfg(all(feature = "rayon", feature = "serialize"))]
+    #[test]
+    fn test_parallel_build() {
+        use rand::{rngs::SmallRng, Rng, SeedableRng};
+
+        let vocab = ["a", "bb", "é", "日本", "."];
+        let mut rng = SmallRng::from_seed([9; 16]);
+
+        for _ in 0..100 {
+            let num_patterns = rng.gen_range(1, 30);
+            let patterns: Vec<_> = (0..num_patterns)
+                .map(|_| random_sentence(&mut rng, &vocab, 4).0)
+                .collect();
+
+            for &kind in &[
+                MatchKind::Standard,
+                MatchKind::LeftmostLongest,
+                MatchKind::LeftmostFirst,
+            ] {
+                for &dedup in &[false, true] {
+                    let build = |parallel| {
+                        SimpleFinderBuilder::new()
+                            .match_kind(kind)
+                            .dedup_patterns(dedup)
+                            .parallel_build(parallel)
+                            .build(patterns.iter().map(|p| (p.as_str(), ())))
+                    };
+                    let (serial, parallel) = (build(false), build(true));
+                    assert_eq!(
+                        serial.to_bytes().unwrap(),
+                        parallel.to_bytes().unwrap(),
+                        "patterns {:?}",
+                        patterns
+                    );
+                    assert_eq!(serial.heap_bytes(), parallel.heap_bytes());
+                }
+            }
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_match() {
+        let finder = SimpleFinder::new(vec![("café", ())]);
+        let m = finder.find_all("un café").next().unwrap().0;
+
+        let json = serde_json::to_string(&m).unwrap();
+        assert_eq!(
+            json,
+            r#"{"pattern":0,"start":3,"end":7,"byte_start":3,"byte_end":8,"token_start":1,"token_end":2}"#
+        );
+        assert_eq!(serde_json::from_str::<Match>(&json).unwrap(), m);
+
+        // matches serialized without token indices still deserialize
+        let old = r#"{"pattern":0,"start":3,"end":7,"byte_start":3,"byte_end":8}"#;
+        let old = serde_json::from_str::<Match>(old).unwrap();
+        assert_eq!((old.start(), old.byte_end(), old.token_end()), (3, 8, 0));
+
+        let backwards = r#"{"pattern":0,"start":7,"end":3,"byte_start":3,"byte_end":8}"#;
+        assert!(serde_json::from_str::<Match>(backwards).is_err());
+    }
+
+    #[cfg(feature = "serialize")]
+    #[test]
+    fn test_to_bytes() {
+        let patterns = vec![("foo bar", 0), ("bar", 1), ("ＢＡＺ", 2), ("café", 3)];
+        let haystack = "Foo bar baz cafe\u{301}";
+
+        for &(kind, dfa) in &[
+            (MatchKind::Standard, false),
+            (MatchKind::LeftmostLongest, false),
+            (MatchKind::Standard, true),
+        ] {
+            let finder = SimpleFinderBuilder::new()
+                .case_insensitive(true)
+                .fold_width(true)
+                .normalize(Normalization::Nfc)
+                .match_kind(kind)
+                .dfa(dfa)
+                .build(patterns.clone());
+            let bytes = finder.to_bytes().unwrap();
+
+            let restored = SimpleFinder::from_bytes(&bytes, 0..4).unwrap();
+            let expected: Vec<_> = finder.find_all(haystack).collect();
+            assert_eq!(restored.find_all(haystack).collect::<Vec<_>>(), expected);
+            assert_eq!(restored.pattern(2), Some("ＢＡＺ"));
+            assert_eq!(restored.to_bytes().unwrap(), bytes);
+
+            // each pattern needs exactly one item of data
+            for data in &[0..3, 0..5] {
+                let result = SimpleFinder::from_bytes(&bytes, data.clone());
+                assert_eq!(
+                    result.err(),
+                    Some(SerializeError::DataCountMismatch {
+                        patterns: 4,
+                        data: data.len()
+                    })
+                );
+            }
+
+            // every truncation of the bytes is rejected, rather than panicking
+            for len in 0..bytes.len() {
+                let result = SimpleFinder::from_bytes(&bytes[..len], 0..4);
+                assert_eq!(result.err(), Some(SerializeError::InvalidFormat));
+            }
+        }
+
+        let finder = SimpleFinderBuilder::new()
+            .word_characters(|c| c == '#')
+            .build(patterns);
+        assert_eq!(
+            finder.to_bytes().err(),
+            Some(SerializeError::CustomTokenizer)
+        );
+    }
+}
+
+#[cfg(test)]
+mod proptests {
+    use super::*;
+    use proptest::prelude::*;
+    use std::{
+        format,
+        string::{String, ToString},
+        vec,
+        vec::Vec,
+    };
+
+    /// Words, punctuation, and whitespace, mixed with chars that change
+    /// length when normalized or case folded, combining marks, emoji joined
+    /// into one grapheme cluster, and wide chars.
+    const ALPHABET: &str = "[abc01 ,.'\\-é\u{301}İß😀\u{200d}\u{1f3fd}ｶﾞ１Ａ中\t]";
+
+    fn text(max_len: usize) -> impl Strategy<Value = String> {
+        proptest::string::string_regex(&format!("{}{{0,{}}}", ALPHABET, max_len)).unwrap()
+    }
+
+    /// Builds a finder with the options picked by the bits of `options`.
+    fn builder(options: u16) -> SimpleFinderBuilder {
+        let bit = |n: u16| options & (1 << n) != 0;
+        let mut builder = SimpleFinderBuilder::new();
+        builder
+            .case_insensitive(bit(0))
+            .fold_width(bit(1))
+            .normalize(if bit(2) {
+                Normalization::Nfc
+            } else {
+                Normalization::None
+            })
+            .count_graphemes(bit(3))
+            .join_apostrophes(bit(4))
+            .join_hyphens(bit(5))
+            .join_digit_groups(bit(6))
+            .keep_whitespace(bit(7))
+            .dfa(bit(8))
+            .overlapping(!bit(9))
+            .match_kind(match (bit(10), bit(11)) {
+                (false, _) => MatchKind::Standard,
+                (true, false) => MatchKind::LeftmostLongest,
+                (true, true) => MatchKind::LeftmostFirst,
+            });
+        if bit(12) {
+            builder.stop_words(["a", ","]);
+        }
+        builder
+    }
+
+    /// Checks that a match lies within the haystack on char boundaries, and
+    /// that its char offsets agree with its byte offsets, unless they count
+    /// grapheme clusters.
+    fn check_match(haystack: &str, m: &Match, graphemes: bool) {
+        assert!(m.start() <= m.end());
+        assert!(m.token_start() < m.token_end());
+        let text = haystack.get(m.byte_start()..m.byte_end());
+        assert!(text.is_some(), "{:?} doesn't slice {:?}", m, haystack);
+        if !graphemes {
+            let chars = |end| haystack[..end].chars().count();
+            assert_eq!(
+                (m.start(), m.end()),
+                (chars(m.byte_start()), chars(m.byte_end()))
+            );
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn search_never_panics(
+            patterns in prop::collection::vec(text(8), 1..6),
+            filler in prop::collection::vec(text(6), 0..6),
+            options in any::<u16>(),
+        ) {
+            // the haystack interleaves the patterns with filler, so that
+            // there's something to find
+            let haystack: String = filler
+                .iter()
+                .zip(patterns.iter().cycle())
+                .flat_map(|(f, p)| vec![f.as_str(), p.as_str()])
+                .collect();
+            let builder = builder(options);
+            let graphemes = builder.normalizer.graphemes;
+            let finder = match builder.try_build(patterns.iter().map(|p| (p.as_str(), ()))) {
+                Ok(finder) => finder,
+                // patterns without any tokens are rejected
+                Err(_) => return Ok(()),
+            };
+
+            let matches: Vec<_> = finder.find_all(&haystack).map(|(m, _)| m).collect();
+            for m in &matches {
+                check_match(&haystack, m, graphemes);
+            }
+            let checked: Vec<_> = finder.find_checked(&haystack).map(|(m, _)| m).collect();
+            prop_assert_eq!(&checked, &matches);
+            if let Some((m, _)) = finder.find_first(&haystack) {
+                check_match(&haystack, &m, graphemes);
+            }
+            prop_assert_eq!(finder.is_match(&haystack), !matches.is_empty());
+            finder.replace_all(&haystack, |_, _| "x".into());
+
+            if !finder.aho.match_kind().is_leftmost() {
+                let mut stream = finder.stream();
+                let mut streamed = Vec::new();
+                for c in haystack.chars() {
+                    streamed.extend(stream.push(&c.to_string()));
+                }
+                streamed.extend(stream.finish());
+                for (m, _) in &streamed {
+                    check_match(&haystack, m, graphemes);
+                }
+            }
+
+            let reverse = builder.build_reverse(patterns.iter().map(|p| (p.as_str(), ())));
+            for (m, _) in reverse.find_all(&haystack) {
+                check_match(&haystack, &m, graphemes);
+            }
+        }
     }
 }
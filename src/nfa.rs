@@ -1,36 +1,299 @@
-use std::{
+use alloc::{
+    borrow::{Cow, ToOwned},
+    boxed::Box,
+    collections::VecDeque,
+    vec,
+    vec::Vec,
+};
+use core::{
     cmp,
-    collections::{HashMap, VecDeque},
+    hash::{BuildHasherDefault, Hash, Hasher},
     mem::size_of,
 };
 
 use crate::{
-    automaton::Automaton,
-    state_id::{fail_id, usize_to_state_id, StateID},
-    Match,
+    automaton::{Automaton, TokenSource},
+    collections::HashMap,
+    dfa::DFA,
+    error::BuildError,
+    heap_size::map_heap_bytes,
+    parts::{AutomatonParts, StateParts},
+    prefilter::Prefilter,
+    state_id::{dead_id, fail_id, usize_to_state_id, StateID},
+    Match, MatchKind,
+};
+#[cfg(feature = "serialize")]
+use crate::{
+    error::SerializeError,
+    serialize::{Reader, Writer},
 };
 
-type PatternID = usize;
-type PatternLength = usize;
+pub(crate) type PatternID = usize;
+pub(crate) type PatternLength = usize;
 
-#[derive(Clone)]
-pub(crate) struct NFA<S> {
-    start_id: S,
-    max_pattern_len: usize,
-    pattern_count: usize,
-    heap_bytes: usize,
-    states: Vec<State<S>>,
+/// The identifier of a token interned by an NFA.
+///
+/// States and their transitions only ever see these identifiers, so only
+/// the interner, and the start transitions and prefilter that look tokens up
+/// before interning them, depend on the `Token` type.
+pub(crate) type TokenID = u32;
+
+/// The identifier of every token that the NFA hasn't interned. No state has a
+/// transition on it, as no pattern contains it.
+pub(crate) const UNKNOWN_TOKEN: TokenID = u32::MAX;
+
+/// A token that patterns and haystacks are split into, which an NFA interns
+/// to label its transitions with.
+///
+/// Text is split into `str` tokens, and byte strings into `[u8]` tokens, so
+/// that both are searched by the same automaton.
+pub(crate) trait Token: Eq + Hash + Sync + ToOwned<Owned: Send + Sync> + 'static {
+    /// Returns the bytes of the token, which the prefilter looks at.
+    fn as_bytes(&self) -> &[u8];
+
+    /// Copies the token, to be kept by an interner.
+    fn to_boxed(&self) -> Box<Self>;
 }
 
-impl<S: StateID> NFA<S> {
+impl Token for str {
+    fn as_bytes(&self) -> &[u8] {
+        str::as_bytes(self)
+    }
+
+    fn to_boxed(&self) -> Box<str> {
+        self.into()
+    }
+}
+
+impl Token for [u8] {
+    fn as_bytes(&self) -> &[u8] {
+        self
+    }
+
+    fn to_boxed(&self) -> Box<[u8]> {
+        self.into()
+    }
+}
+
+/// Copies a map keyed by boxed tokens, which can't be cloned for every
+/// `Token` type.
+pub(crate) fn clone_token_map<T: Token + ?Sized, V: Copy>(
+    map: &HashMap<Box<T>, V>,
+) -> HashMap<Box<T>, V> {
+    map.iter()
+        .map(|(token, &value)| (token.to_boxed(), value))
+        .collect()
+}
+
+/// Hashes token IDs with a single multiplication.
+///
+/// Token IDs are assigned by the NFA rather than taken from the haystack, so
+/// SipHash's resistance to collision attacks is wasted on them.
+#[derive(Default)]
+pub(crate) struct TokenIDHasher(u64);
+
+impl Hasher for TokenIDHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.write_u32(b as u32);
+        }
+    }
+
+    fn write_u32(&mut self, n: u32) {
+        self.0 = (self.0.rotate_left(5) ^ n as u64).wrapping_mul(0x517c_c1b7_2722_0a95);
+    }
+}
+
+pub(crate) type TokenMap<S> = HashMap<TokenID, S, BuildHasherDefault<TokenIDHasher>>;
+
+/// Returns the heap memory used by a token interner.
+pub(crate) fn tokens_heap_bytes<T: Token + ?Sized, V>(tokens: &HashMap<Box<T>, V>) -> usize {
+    map_heap_bytes(tokens)
+        + tokens
+            .keys()
+            .map(|token| token.as_bytes().len())
+            .sum::<usize>()
+}
+
+pub(crate) struct NFA<S, T: Token + ?Sized = str> {
+    pub(crate) start_id: S,
+    pub(crate) max_pattern_len: usize,
+    pub(crate) pattern_count: usize,
+    pub(crate) heap_bytes: usize,
+    /// Every token in the patterns, so that transitions can be labelled by
+    /// integer identifiers rather than tokens.
+    pub(crate) tokens: HashMap<Box<T>, TokenID>,
+    /// Used to skip over tokens that can't start a match while in the start
+    /// state, when enabled.
+    pub(crate) prefilter: Option<Prefilter>,
+    /// The transitions out of the start state by token, for searches to
+    /// look up the first token of each match with. This is left out of NFAs
+    /// that are never searched, such as the tries returned by `into_trie`.
+    pub(crate) start_transitions: Option<StartTransitions<S, T>>,
+    pub(crate) states: Vec<State<S>>,
+}
+
+impl<S: StateID, T: Token + ?Sized> Clone for NFA<S, T> {
+    fn clone(&self) -> NFA<S, T> {
+        NFA {
+            start_id: self.start_id,
+            max_pattern_len: self.max_pattern_len,
+            pattern_count: self.pattern_count,
+            heap_bytes: self.heap_bytes,
+            tokens: clone_token_map(&self.tokens),
+            prefilter: self.prefilter.clone(),
+            start_transitions: self.start_transitions.clone(),
+            states: self.states.clone(),
+        }
+    }
+}
+
+impl<S: StateID, T: Token + ?Sized> NFA<S, T> {
     pub(crate) fn heap_bytes(&self) -> usize {
         self.heap_bytes
     }
 
+    pub(crate) fn max_pattern_len(&self) -> usize {
+        self.max_pattern_len
+    }
+
     pub(crate) fn pattern_count(&self) -> usize {
         self.pattern_count
     }
 
+    /// Follows failure transitions from `current` to the first state with a
+    /// transition on `input`, returning where that transition leads, or the
+    /// state at the end of the chain if no state on it has one.
+    ///
+    /// `current` must be a valid state ID.
+    #[inline(always)]
+    unsafe fn follow_failures(&self, mut current: S, input: TokenID) -> Result<S, S> {
+        loop {
+            let state = self.states.get_unchecked(current.to_usize());
+            let next = state.next_state(input);
+            if next != fail_id() {
+                return Ok(next);
+            }
+
+            // the start and dead states are the only states that fail to
+            // themselves
+            if state.fail == current {
+                return Err(current);
+            }
+            current = state.fail;
+        }
+    }
+
+    /// Returns the ID and length of every pattern that can match, found in
+    /// the state its last token leads to, in no particular order.
+    pub(crate) fn pattern_lens(&self) -> impl Iterator<Item = (PatternID, PatternLength)> + '_ {
+        // matches copied from the states a state fails to are shorter than
+        // its depth
+        self.states.iter().flat_map(|state| {
+            state
+                .matches
+                .iter()
+                .copied()
+                .filter(move |&(_, len)| len == state.depth)
+        })
+    }
+
+    /// Returns the tokens that label the transitions out of the start state,
+    /// i.e. the first token of every non-empty pattern.
+    pub(crate) fn start_tokens(&self) -> impl Iterator<Item = &T> {
+        let start = self.start();
+        self.tokens
+            .iter()
+            .filter(move |&(_, &id)| start.next_state(id) != fail_id())
+            .map(|(token, _)| &**token)
+    }
+
+    pub(crate) fn state_count(&self) -> usize {
+        self.states.len()
+    }
+
+    pub(crate) fn max_out_degree(&self) -> usize {
+        self.states
+            .iter()
+            .map(|state| state.trans.len())
+            .max()
+            .unwrap_or(0)
+    }
+
+    pub(crate) fn dense_state_count(&self) -> usize {
+        self.states
+            .iter()
+            .filter(|state| matches!(state.trans, Transitions::Dense(_)))
+            .count()
+    }
+
+    fn calculate_heap_bytes(&self) -> usize {
+        tokens_heap_bytes(&self.tokens)
+            + self
+                .start_transitions
+                .as_ref()
+                .map_or(0, StartTransitions::heap_bytes)
+            + self.states.capacity() * size_of::<State<S>>()
+            + self.states.iter().map(State::heap_bytes).sum::<usize>()
+    }
+
+    /// Frees the spare capacity left over from building this NFA.
+    pub(crate) fn shrink_to_fit(&mut self) {
+        self.tokens.shrink_to_fit();
+        if let Some(start_transitions) = &mut self.start_transitions {
+            start_transitions.shrink_to_fit();
+        }
+        self.states.shrink_to_fit();
+        for state in &mut self.states {
+            state.trans.shrink_to_fit();
+            state.matches.shrink_to_fit();
+        }
+        self.heap_bytes = self.calculate_heap_bytes();
+    }
+
+    /// Panics if the NFA isn't well formed: every stored transition must
+    /// lead to a state other than the fail state, and every failure
+    /// transition to a shallower state, so that following them from any
+    /// state ends at the start state, or at the dead state for a leftmost
+    /// match kind.
+    ///
+    /// This is checked whenever an NFA is built in a debug build, to catch
+    /// bugs in its construction before they show up as missed matches or
+    /// searches that never end.
+    #[cfg(debug_assertions)]
+    pub(crate) fn debug_validate(&self) {
+        // the fail and dead states are never searched from
+        let dead: S = dead_id();
+        for (i, state) in self.states.iter().enumerate().skip(dead.to_usize() + 1) {
+            for (token, next) in state.trans.iter() {
+                assert!(
+                    next != fail_id() && self.is_valid(next),
+                    "state {} has a transition on token {} to invalid state {}",
+                    i,
+                    token,
+                    next.to_usize()
+                );
+            }
+
+            let mut current = i;
+            while current != self.start_id.to_usize() && current != dead.to_usize() {
+                let state = &self.states[current];
+                let fail = state.fail.to_usize();
+                assert!(
+                    self.states[fail].depth < state.depth,
+                    "state {} fails to state {}, which isn't shallower",
+                    current,
+                    fail
+                );
+                current = fail;
+            }
+        }
+    }
+
     fn state(&self, id: S) -> &State<S> {
         &self.states[id.to_usize()]
     }
@@ -43,7 +306,7 @@ impl<S: StateID> NFA<S> {
         self.state(self.start_id)
     }
 
-    fn iter_transitions_mut(&mut self, id: S) -> IterTransitionsMut<'_, S> {
+    fn iter_transitions_mut(&mut self, id: S) -> IterTransitionsMut<'_, S, T> {
         IterTransitionsMut::new(self, id)
     }
 
@@ -57,20 +320,30 @@ impl<S: StateID> NFA<S> {
         self.copy_matches(start_id, dst);
     }
 
-    fn add_sparse_state(&mut self) -> Option<S> {
-        let trans = Transitions(HashMap::new());
-        let id = usize_to_state_id(self.states.len())?;
+    fn add_dense_state(&mut self, depth: usize) -> Result<S, BuildError> {
+        self.push_state(Transitions::Dense(TokenMap::default()), depth)
+    }
+
+    fn add_sparse_state(&mut self, depth: usize) -> Result<S, BuildError> {
+        self.push_state(Transitions::Sparse(vec![]), depth)
+    }
+
+    fn push_state(&mut self, trans: Transitions<S>, depth: usize) -> Result<S, BuildError> {
+        let id =
+            usize_to_state_id(self.states.len()).ok_or_else(BuildError::state_id_overflow::<S>)?;
         self.states.push(State {
             trans,
             fail: self.start_id,
+            depth,
             matches: vec![],
         });
-        Some(id)
+        Ok(id)
     }
 }
 
-impl<S: StateID> Automaton for NFA<S> {
+impl<S: StateID, T: Token + ?Sized> Automaton for NFA<S, T> {
     type ID = S;
+    type Token = T;
 
     fn start_state(&self) -> S {
         self.start_id
@@ -93,6 +366,10 @@ impl<S: StateID> Automaton for NFA<S> {
             pattern: id,
             len,
             end,
+            byte_len: len,
+            byte_end: end,
+            token_len: len,
+            token_end: end,
         })
     }
 
@@ -100,34 +377,88 @@ impl<S: StateID> Automaton for NFA<S> {
         self.states[id.to_usize()].matches.len()
     }
 
-    unsafe fn next_state_unchecked(&self, mut current: S, input: &str) -> S {
+    fn token_id(&self, token: &T) -> TokenID {
+        self.tokens.get(token).copied().unwrap_or(UNKNOWN_TOKEN)
+    }
+
+    fn prefilter(&self) -> Option<&Prefilter> {
+        self.prefilter.as_ref()
+    }
+
+    fn start_next_state(&self, token: &T) -> S {
+        match &self.start_transitions {
+            Some(start) => start.next_state(token),
+            None => unsafe { self.next_state_unchecked(self.start_id, self.token_id(token)) },
+        }
+    }
+
+    fn next_state_checked(&self, mut current: S, input: TokenID) -> Option<S> {
+        // every failure transition leads to a shallower state, so a valid
+        // automaton never follows as many as it has states
+        for _ in 0..self.states.len() {
+            let state = self.states.get(current.to_usize())?;
+            let next = state.next_state(input);
+            if next != fail_id() {
+                return Some(next).filter(|&next| self.is_valid(next));
+            }
+            if state.fail == current {
+                return Some(current);
+            }
+            current = state.fail;
+        }
+        None
+    }
+
+    unsafe fn next_state_unchecked(&self, current: S, input: TokenID) -> S {
+        // the start and dead states stay put on any input they have no
+        // transition for
+        self.follow_failures(current, input)
+            .unwrap_or_else(|end| end)
+    }
+
+    fn next_state_or_none(&self, current: S, input: TokenID) -> Option<S> {
+        assert!(
+            self.is_valid(current),
+            "{} is not a valid state ID",
+            current.to_usize()
+        );
+        unsafe { self.follow_failures(current, input) }.ok()
+    }
+
+    #[cfg(feature = "trace")]
+    fn traced_next_state(&self, mut current: S, input: TokenID, failures: &mut Vec<S>) -> S {
         loop {
-            let state = self.states.get_unchecked(current.to_usize());
+            let state = &self.states[current.to_usize()];
             let next = state.next_state(input);
             if next != fail_id() {
                 return next;
             }
-
-            // if next is fail_id and the current state is the start state, we
-            // loop back to the start
-            if current == self.start_state() {
-                return self.start_state();
+            if state.fail == current {
+                return current;
             }
             current = state.fail;
+            failures.push(current);
         }
     }
+
+    fn state_depth(&self, id: S) -> usize {
+        self.states[id.to_usize()].depth
+    }
 }
 
 #[derive(Clone)]
 pub(crate) struct State<S> {
-    trans: Transitions<S>,
-    fail: S,
-    matches: Vec<(PatternID, PatternLength)>,
+    pub(crate) trans: Transitions<S>,
+    pub(crate) fail: S,
+    /// The number of transitions from the start state to this state.
+    pub(crate) depth: usize,
+    pub(crate) matches: Vec<(PatternID, PatternLength)>,
 }
 
 impl<S: StateID> State<S> {
     fn heap_bytes(&self) -> usize {
-        self.trans.heap_bytes() + (self.matches.len() * size_of::<(PatternID, PatternLength)>())
+        self.trans.heap_bytes()
+            + (self.matches.capacity() * size_of::<(PatternID, PatternLength)>())
     }
 
     fn add_match(&mut self, i: PatternID, len: PatternLength) {
@@ -138,47 +469,192 @@ impl<S: StateID> State<S> {
         !self.matches.is_empty()
     }
 
-    fn next_state(&self, input: &str) -> S {
+    fn get_longest_match_len(&self) -> Option<usize> {
+        // Why is this true? Because the first match in any matching state
+        // will always correspond to the match added to it during trie
+        // construction (since when we copy matches due to failure transitions,
+        // we always append them). Therefore, it follows that the first match
+        // must always be longest since any subsequent match must be from a
+        // failure transition, and a failure transition by construction points
+        // to a proper suffix. A proper suffix is, by definition, smaller.
+        self.matches.first().map(|&(_, len)| len)
+    }
+
+    fn next_state(&self, input: TokenID) -> S {
         self.trans.next_state(input)
     }
 
-    fn set_next_state(&mut self, input: &str, next: S) {
+    fn set_next_state(&mut self, input: TokenID, next: S) {
         self.trans.set_next_state(input, next);
     }
 }
 
+/// The transitions out of a state.
+///
+/// States near the start state can have a transition for nearly every token
+/// that begins a pattern, so they're hashed. Deeper states rarely have more
+/// than a couple of transitions, which are kept in a list sorted by token,
+/// quicker to search than to hash and taking less memory.
+///
+/// Every change to a sparse list must keep it sorted, as `next_state`
+/// binary searches it.
 #[derive(Clone)]
-struct Transitions<S>(pub HashMap<String, S>);
+pub(crate) enum Transitions<S> {
+    Dense(TokenMap<S>),
+    Sparse(Vec<(TokenID, S)>),
+}
 
 impl<S: StateID> Transitions<S> {
-    fn heap_bytes(&self) -> usize {
-        self.0.capacity() * size_of::<(String, S)>() + self.0.keys().map(|s| s.len()).sum::<usize>()
+    pub(crate) fn heap_bytes(&self) -> usize {
+        match self {
+            Transitions::Dense(dense) => map_heap_bytes(dense),
+            Transitions::Sparse(sparse) => sparse.capacity() * size_of::<(TokenID, S)>(),
+        }
+    }
+
+    pub(crate) fn shrink_to_fit(&mut self) {
+        match self {
+            Transitions::Dense(dense) => dense.shrink_to_fit(),
+            Transitions::Sparse(sparse) => sparse.shrink_to_fit(),
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        match self {
+            Transitions::Dense(dense) => dense.len(),
+            Transitions::Sparse(sparse) => sparse.len(),
+        }
+    }
+
+    pub(crate) fn iter(&self) -> Box<dyn Iterator<Item = (TokenID, S)> + '_> {
+        match self {
+            Transitions::Dense(dense) => Box::new(dense.iter().map(|(&token, &id)| (token, id))),
+            Transitions::Sparse(sparse) => Box::new(sparse.iter().cloned()),
+        }
+    }
+
+    pub(crate) fn next_state(&self, input: TokenID) -> S {
+        match self {
+            Transitions::Dense(dense) => dense.get(&input).cloned().unwrap_or_else(fail_id),
+            Transitions::Sparse(sparse) => sparse
+                .binary_search_by_key(&input, |&(token, _)| token)
+                .map_or_else(|_| fail_id(), |i| sparse[i].1),
+        }
+    }
+
+    pub(crate) fn set_next_state(&mut self, input: TokenID, next: S) {
+        match self {
+            Transitions::Dense(dense) => {
+                dense.insert(input, next);
+            }
+            Transitions::Sparse(sparse) => {
+                match sparse.binary_search_by_key(&input, |&(token, _)| token) {
+                    Ok(i) => sparse[i].1 = next,
+                    Err(i) => sparse.insert(i, (input, next)),
+                }
+            }
+        }
+    }
+
+    /// Relabels each transition with the token and state identifiers
+    /// returned by `f`, keeping the representation.
+    fn map<T>(self, f: impl Fn(TokenID, S) -> (TokenID, T)) -> Transitions<T> {
+        match self {
+            Transitions::Dense(dense) => {
+                Transitions::Dense(dense.into_iter().map(|(token, id)| f(token, id)).collect())
+            }
+            Transitions::Sparse(sparse) => {
+                let mut sparse: Vec<_> =
+                    sparse.into_iter().map(|(token, id)| f(token, id)).collect();
+                // relabelled tokens may no longer be in order
+                sparse.sort_unstable_by_key(|&(token, _)| token);
+                Transitions::Sparse(sparse)
+            }
+        }
+    }
+}
+
+/// The transitions out of the start state, keyed by token rather than by
+/// token identifier.
+///
+/// A search spends most of its time in the start state, where looking up a
+/// token's identifier and then the transition on it hashes twice. This
+/// takes one lookup, at the cost of a copy of every token that starts a
+/// pattern.
+pub(crate) struct StartTransitions<S, T: Token + ?Sized = str> {
+    next: HashMap<Box<T>, S>,
+    /// The state reached on any token without a transition, which is the
+    /// start state itself unless it fails to the dead state.
+    otherwise: S,
+}
+
+impl<S: StateID, T: Token + ?Sized> Clone for StartTransitions<S, T> {
+    fn clone(&self) -> StartTransitions<S, T> {
+        StartTransitions {
+            next: clone_token_map(&self.next),
+            otherwise: self.otherwise,
+        }
+    }
+}
+
+impl<S: StateID, T: Token + ?Sized> StartTransitions<S, T> {
+    pub(crate) fn new<A: Automaton<ID = S, Token = T>>(
+        automaton: &A,
+        tokens: &HashMap<Box<T>, TokenID>,
+    ) -> StartTransitions<S, T> {
+        let start = automaton.start_state();
+        let next_state = |token| {
+            automaton
+                .next_state_checked(start, token)
+                .expect("the start state is valid")
+        };
+        let otherwise = next_state(UNKNOWN_TOKEN);
+        let next = tokens
+            .iter()
+            .map(|(token, &id)| (token.to_boxed(), next_state(id)))
+            .filter(|&(_, next)| next != otherwise)
+            .collect();
+        StartTransitions { next, otherwise }
+    }
+
+    #[inline(always)]
+    pub(crate) fn next_state(&self, token: &T) -> S {
+        self.next.get(token).copied().unwrap_or(self.otherwise)
     }
 
-    fn next_state(&self, input: &str) -> S {
-        self.0.get(input).cloned().unwrap_or_else(fail_id)
+    pub(crate) fn heap_bytes(&self) -> usize {
+        tokens_heap_bytes(&self.next)
+    }
+
+    pub(crate) fn shrink_to_fit(&mut self) {
+        self.next.shrink_to_fit();
     }
 
-    fn set_next_state(&mut self, input: &str, next: S) {
-        self.0.insert(input.to_owned(), next);
+    fn map<U>(self, f: impl Fn(S) -> U) -> StartTransitions<U, T> {
+        StartTransitions {
+            next: self
+                .next
+                .into_iter()
+                .map(|(token, id)| (token, f(id)))
+                .collect(),
+            otherwise: f(self.otherwise),
+        }
     }
 }
 
-struct IterTransitionsMut<'a, S: StateID + 'a> {
-    nfa: &'a mut NFA<S>,
+struct IterTransitionsMut<'a, S: StateID + 'a, T: Token + ?Sized> {
+    nfa: &'a mut NFA<S, T>,
     state_id: S,
     cur: usize,
-    keys: Vec<String>,
+    keys: Vec<TokenID>,
 }
 
-impl<'a, S: StateID> IterTransitionsMut<'a, S> {
-    fn new(nfa: &'a mut NFA<S>, state_id: S) -> IterTransitionsMut<'a, S> {
+impl<'a, S: StateID, T: Token + ?Sized> IterTransitionsMut<'a, S, T> {
+    fn new(nfa: &'a mut NFA<S, T>, state_id: S) -> IterTransitionsMut<'a, S, T> {
         let keys = nfa.states[state_id.to_usize()]
             .trans
-            .0
-            .keys()
-            .cloned()
-            // .map(|s| s.as_ref())
+            .iter()
+            .map(|(token, _)| token)
             .collect();
 
         IterTransitionsMut {
@@ -189,95 +665,299 @@ impl<'a, S: StateID> IterTransitionsMut<'a, S> {
         }
     }
 
-    fn nfa(&mut self) -> &mut NFA<S> {
+    fn nfa(&mut self) -> &mut NFA<S, T> {
         self.nfa
     }
 }
 
-impl<'a, S: StateID> Iterator for IterTransitionsMut<'a, S> {
-    type Item = (String, S);
+impl<'a, S: StateID, T: Token + ?Sized> Iterator for IterTransitionsMut<'a, S, T> {
+    type Item = (TokenID, S);
 
-    fn next(&mut self) -> Option<(String, S)> {
+    fn next(&mut self) -> Option<(TokenID, S)> {
         let trans = &self.nfa.states[self.state_id.to_usize()].trans;
-        if self.cur >= trans.0.len() {
+        if self.cur >= trans.len() {
             return None;
         }
         let i = self.cur;
-        let key = &self.keys[i];
+        let key = self.keys[i];
         self.cur += 1;
-        Some((key.to_owned(), trans.0[key]))
+        Some((key, trans.next_state(key)))
+    }
+}
+
+/// The depth below which states use hashed transitions by default.
+pub(crate) const DEFAULT_DENSE_DEPTH: usize = 2;
+
+/// The options that an NFA is compiled with.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct NfaOptions {
+    pub(crate) match_kind: MatchKind,
+    /// Whether a pattern with the same tokens as an earlier one is merged
+    /// into it, rather than being reported as a match of its own.
+    pub(crate) dedup_patterns: bool,
+    /// States shallower than this use hashed transitions, and deeper states
+    /// use a list of transitions.
+    pub(crate) dense_depth: usize,
+    /// Whether a pattern without any word tokens is rejected.
+    pub(crate) reject_symbol_only: bool,
+    /// The most tokens a pattern may have, if limited.
+    pub(crate) max_match_tokens: Option<usize>,
+    /// Whether searches skip tokens that can't start a pattern while in the
+    /// start state.
+    pub(crate) prefilter: bool,
+    /// Whether the NFA is converted to a DFA once it's built.
+    pub(crate) dfa: bool,
+    /// Whether the trie is built on several threads.
+    #[cfg(feature = "rayon")]
+    pub(crate) parallel: bool,
+}
+
+impl Default for NfaOptions {
+    fn default() -> NfaOptions {
+        NfaOptions {
+            match_kind: MatchKind::default(),
+            dedup_patterns: false,
+            dense_depth: DEFAULT_DENSE_DEPTH,
+            reject_symbol_only: false,
+            max_match_tokens: None,
+            prefilter: false,
+            dfa: false,
+            #[cfg(feature = "rayon")]
+            parallel: false,
+        }
     }
 }
 
-struct Compiler<S: StateID> {
-    nfa: NFA<S>,
+struct Compiler<S: StateID, T: Token + ?Sized> {
+    match_kind: MatchKind,
+    dedup_patterns: bool,
+    dense_depth: usize,
+    prefilter: bool,
+    nfa: NFA<S, T>,
 }
 
-impl<S: StateID> Compiler<S> {
-    fn new() -> Option<Compiler<S>> {
-        Some(Compiler {
+impl<S: StateID, T: Token + ?Sized> Compiler<S, T> {
+    fn new(options: NfaOptions) -> Result<Compiler<S, T>, BuildError> {
+        Ok(Compiler {
+            match_kind: options.match_kind,
+            dedup_patterns: options.dedup_patterns,
+            dense_depth: options.dense_depth,
+            prefilter: options.prefilter,
             nfa: NFA {
-                start_id: usize_to_state_id(1)?,
+                start_id: usize_to_state_id(2).ok_or_else(BuildError::state_id_overflow::<S>)?,
                 max_pattern_len: 0,
                 pattern_count: 0,
                 heap_bytes: 0,
+                tokens: HashMap::new(),
+                prefilter: None,
+                start_transitions: None,
                 states: vec![],
             },
         })
     }
 
-    fn compile<'a, I>(mut self, patterns: I) -> Option<NFA<S>>
-    where
-        I: IntoIterator<Item = &'a str>,
-    {
-        use crate::word_split_trait::WordBoundarySplitter;
+    fn compile(mut self, patterns: &[Vec<Cow<'_, T>>]) -> Result<NFA<S, T>, BuildError> {
+        self.add_initial_states()?;
+        self.build_trie(patterns)?;
+        Ok(self.finish())
+    }
 
-        self.add_state()?; // the fail state, which is never entered
-        self.add_state()?; // the start state
-        let patterns: Vec<Vec<_>> = patterns
-            .into_iter()
-            .map(|p| p.unicode_words_and_syms().collect())
+    /// Like `compile`, but builds the tries of the patterns in parallel,
+    /// split up by their first token, as patterns starting with different
+    /// tokens never share a state other than the start state.
+    ///
+    /// The states and tokens are then numbered in the order that `compile`
+    /// would add them, so that the NFA is identical to the one it builds.
+    #[cfg(feature = "rayon")]
+    fn compile_parallel(mut self, patterns: &[Vec<Cow<'_, T>>]) -> Result<NFA<S, T>, BuildError> {
+        use rayon::prelude::*;
+        use std::{collections::hash_map::DefaultHasher, hash::Hash};
+
+        // an empty pattern matches in the start state, which isn't part of
+        // any pattern's trie
+        if patterns.iter().any(Vec::is_empty) {
+            return self.compile(patterns);
+        }
+        self.add_initial_states()?;
+        self.nfa.pattern_count = patterns.len();
+        self.nfa.max_pattern_len = patterns.iter().map(Vec::len).max().unwrap_or(0);
+
+        // patterns are split into a few groups per thread by their first
+        // token, as a trie per first token costs more to merge than it saves
+        let num_groups = rayon::current_num_threads() * 4;
+        let group_of: Vec<usize> = patterns
+            .par_iter()
+            .map(|pat| {
+                let mut hasher = DefaultHasher::new();
+                pat[0].hash(&mut hasher);
+                hasher.finish() as usize % num_groups
+            })
+            .collect();
+        let mut groups = vec![vec![]; num_groups];
+        for (pati, group) in group_of.into_iter().enumerate() {
+            groups[group].push(pati);
+        }
+        let tries: Vec<SubTrie<'_, T>> = groups
+            .into_par_iter()
+            .map(|ids| SubTrie::build(&self, patterns, &ids))
+            .collect();
+
+        // tokens are interned in the order that `build_trie` first walks
+        // over them, and each token is first walked over in only one trie
+        let mut tokens: Vec<_> = tries
+            .iter()
+            .flat_map(|trie| trie.tokens.iter().map(|&(token, at)| (at, token)))
             .collect();
-        self.build_trie(&patterns)?;
-        self.fill_failure_transitions_standard();
+        tokens.par_sort_unstable_by_key(|&(at, _)| at);
+        for (_, token) in tokens {
+            self.intern(token)?;
+        }
+        let interned = &self.nfa.tokens;
+        let token_ids: Vec<Vec<TokenID>> = tries
+            .par_iter()
+            .map(|trie| {
+                trie.tokens
+                    .iter()
+                    .map(|&(token, _)| interned[token])
+                    .collect()
+            })
+            .collect();
+
+        // likewise, states are added in the order `build_trie` creates them
+        let mut created: Vec<_> = tries
+            .iter()
+            .enumerate()
+            .flat_map(|(t, trie)| {
+                let created_at = trie.created_at.iter().enumerate().skip(1);
+                created_at.map(move |(local, &at)| (at, t, local))
+            })
+            .collect();
+        created.par_sort_unstable_by_key(|&(at, _, _)| at);
+        let start_id = self.nfa.start_id;
+        let mut ids: Vec<Vec<S>> = tries
+            .iter()
+            .map(|trie| vec![start_id; trie.states.len()])
+            .collect();
+        for (_, t, local) in created {
+            ids[t][local] = self.add_state(tries[t].states[local].depth)?;
+        }
+
+        let states: Vec<Vec<State<S>>> = tries
+            .into_par_iter()
+            .zip(&token_ids)
+            .zip(&ids)
+            .map(|((trie, token_ids), ids)| {
+                let states = trie.states.into_iter().map(|state| State {
+                    trans: state
+                        .trans
+                        .map(|token, id| (token_ids[token as usize], ids[id])),
+                    fail: start_id,
+                    depth: state.depth,
+                    matches: state.matches,
+                });
+                states.collect()
+            })
+            .collect();
+
+        let mut start_trans = vec![];
+        for (states, ids) in states.into_iter().zip(&ids) {
+            for (local, state) in states.into_iter().enumerate() {
+                if local == 0 {
+                    start_trans.extend(state.trans.iter().map(|(token, next)| (next, token)));
+                } else {
+                    *self.nfa.state_mut(ids[local]) = state;
+                }
+            }
+        }
+        start_trans.sort_unstable();
+        for (next, token) in start_trans {
+            self.nfa.state_mut(start_id).set_next_state(token, next);
+        }
+        Ok(self.finish())
+    }
+
+    /// Adds the fail, dead and start states, which every NFA has.
+    fn add_initial_states(&mut self) -> Result<(), BuildError> {
+        self.add_state(0)?; // the fail state, which is never entered
+        let dead = self.add_state(0)?; // the dead state, only used for leftmost
+        self.nfa.state_mut(dead).fail = dead;
+        self.add_state(0)?; // the start state
+        Ok(())
+    }
+
+    /// Fills in the failure transitions of the trie, completing the NFA.
+    fn finish(mut self) -> NFA<S, T> {
+        if self.match_kind.is_leftmost() {
+            self.fill_failure_transitions_leftmost();
+        } else {
+            self.fill_failure_transitions_standard();
+        }
+        if self.prefilter {
+            self.nfa.prefilter = Some(Prefilter::new(self.nfa.start_tokens().map(Token::as_bytes)));
+        }
+        self.nfa.start_transitions = Some(StartTransitions::new(&self.nfa, &self.nfa.tokens));
         self.calculate_size();
-        Some(self.nfa)
+        #[cfg(debug_assertions)]
+        self.nfa.debug_validate();
+        self.nfa
     }
 
     /// This sets up the initial prefix trie that makes up the Aho-Corasick
     /// automaton. Effectively, it creates the basic structure of the
     /// automaton, where every pattern given has a path from the start state to
     /// the end of the pattern.
-    fn build_trie(&mut self, patterns: &[Vec<&str>]) -> Option<()> {
-        for (pati, pat) in patterns.iter().enumerate() {
-            self.nfa.max_pattern_len = cmp::max(self.nfa.max_pattern_len, pat.len());
-            self.nfa.pattern_count += 1;
+    fn build_trie(&mut self, patterns: &[Vec<Cow<'_, T>>]) -> Result<(), BuildError> {
+        for pat in patterns {
+            // patterns added to an existing NFA are numbered after its own
+            self.add_pattern(self.nfa.pattern_count, pat)?;
+        }
+        Ok(())
+    }
 
-            let mut prev = self.nfa.start_id;
-            let mut saw_match = false;
-            for &b in pat.iter() {
-                saw_match = saw_match || self.nfa.state(prev).is_match();
-                // If the transition from prev using the current byte already
-                // exists, then just move through it. Otherwise, add a new
-                // state. We track the depth here so that we can determine
-                // how to represent transitions. States near the start state
-                // use a dense representation that uses more memory but is
-                // faster. Other states use a sparse representation that uses
-                // less memory but is slower.
-                let next = self.nfa.state(prev).next_state(b);
-                if next != fail_id() {
-                    prev = next;
-                } else {
-                    let next = self.add_state()?;
-                    self.nfa.state_mut(prev).set_next_state(b, next);
-                    prev = next;
-                }
+    /// Adds the path of a pattern with the given identifier to the trie,
+    /// counting every identifier up to it as a pattern.
+    fn add_pattern(&mut self, pati: PatternID, pat: &[Cow<'_, T>]) -> Result<(), BuildError> {
+        self.nfa.max_pattern_len = cmp::max(self.nfa.max_pattern_len, pat.len());
+        self.nfa.pattern_count = cmp::max(self.nfa.pattern_count, pati + 1);
+
+        let mut prev = self.nfa.start_id;
+        let mut saw_match = false;
+        for (depth, token) in pat.iter().enumerate() {
+            saw_match = saw_match || self.nfa.state(prev).is_match();
+            if self.match_kind.is_leftmost_first() && saw_match {
+                // An earlier pattern is a prefix of this one, and will
+                // always be preferred over it, so this pattern can never
+                // match. Skip to the next pattern immediately. This
+                // avoids incorrectly adding a match after this loop
+                // terminates.
+                return Ok(());
+            }
+            // If the transition from prev using the current byte already
+            // exists, then just move through it. Otherwise, add a new
+            // state. We track the depth here so that we can determine
+            // how to represent transitions. States near the start state
+            // use a dense representation that uses more memory but is
+            // faster. Other states use a sparse representation that uses
+            // less memory but is slower.
+            let b = self.intern(token)?;
+            let next = self.nfa.state(prev).next_state(b);
+            if next != fail_id() {
+                prev = next;
+            } else {
+                let next = self.add_state(depth + 1)?;
+                self.nfa.state_mut(prev).set_next_state(b, next);
+                prev = next;
             }
-            // Once the pattern has been added, log the match in the final
-            // state that it reached.
-            self.nfa.state_mut(prev).add_match(pati, pat.len());
         }
-        Some(())
+        // Every match logged so far in the final state is of a pattern
+        // that ends there, and so has the same tokens as this one.
+        if self.dedup_patterns && self.nfa.state(prev).is_match() {
+            return Ok(());
+        }
+        // Once the pattern has been added, log the match in the final
+        // state that it reached.
+        self.nfa.state_mut(prev).add_match(pati, pat.len());
+        Ok(())
     }
 
     /// This routine creates failure transitions according to the standard
@@ -381,14 +1061,22 @@ impl<S: StateID> Compiler<S> {
     /// We don't actually use recursion to implement this, but instead, use a
     /// breadth first search of the automaton. Our base case is the start
     /// state, whose failure transition is just a transition to itself.
+    ///
+    /// This is only used for `MatchKind::Standard`, which reports every
+    /// match, so copying the matches of each failure state is always right
+    /// here. The leftmost match kinds, including leftmost-first, whose
+    /// precedence would be broken by surfacing the matches of later patterns
+    /// through failure transitions, are built with
+    /// `fill_failure_transitions_leftmost` instead.
     fn fill_failure_transitions_standard(&mut self) {
+        debug_assert!(!self.match_kind.is_leftmost());
         // Initialize the queue for breadth first search with all transitions
         // out of the start state. We handle the start state specially because
         // we only want to follow non-self transitions. If we followed self
         // transitions, then this would never terminate.
         let mut queue = VecDeque::new();
-        queue.extend(self.nfa.start().trans.0.iter().filter_map(|(_, id)| {
-            if *id != self.nfa.start_id {
+        queue.extend(self.nfa.start().trans.iter().filter_map(|(_, id)| {
+            if id != self.nfa.start_id {
                 Some(id)
             } else {
                 None
@@ -401,14 +1089,19 @@ impl<S: StateID> Compiler<S> {
                 queue.push_back(next);
 
                 let mut fail = it.nfa().state(id).fail;
-                while it.nfa().state(fail).next_state(&b) == fail_id() {
+                while it.nfa().state(fail).next_state(b) == fail_id() {
                     let new_fail = it.nfa().state(fail).fail;
                     if new_fail == fail {
                         break;
                     }
                     fail = new_fail;
                 }
-                fail = it.nfa().state(fail).next_state(&b);
+                fail = it.nfa().state(fail).next_state(b);
+                // the start state has no transition on a token that no
+                // pattern starts with, so its suffix is the empty one
+                if fail == fail_id() {
+                    fail = it.nfa().start_id;
+                }
                 it.nfa().state_mut(next).fail = fail;
                 it.nfa().copy_matches(fail, next);
             }
@@ -425,13 +1118,215 @@ impl<S: StateID> Compiler<S> {
         }
     }
 
+    /// This routine is just like fill_failure_transitions_standard, except it
+    /// adds failure transitions in a way that preserves leftmost match
+    /// semantics (for both leftmost-first and leftmost-longest).
+    ///
+    /// The algorithms are so similar that it would be possible to write it
+    /// generically. But doing so without overhead would require a bit of
+    /// ceremony, so we just copy it and add in the extra leftmost logic.
+    /// Moreover, the standard algorithm above is so simple that it feels like
+    /// crazy to jam in even more complexity.
+    ///
+    /// This algorithm is necessary for correct leftmost searching because
+    /// the standard failure transitions can lead the search back to a state
+    /// that has already forgotten about a match it saw earlier. For example,
+    /// with the patterns `bar` and `bar baz` over the tokens `bar baz`, the
+    /// search must report `bar baz`, but over `bar qux` it must still report
+    /// `bar`, even though the failure transition out of the state for `bar`
+    /// leads back to the start state. To fix this, failure transitions that
+    /// would drop a match that's already been seen are sent to the dead
+    /// state instead, where the search stops and reports the last match.
+    ///
+    /// Matches are measured in tokens, so "leftmost" refers to the first
+    /// token of a match and "longest" to the number of tokens it spans.
+    fn fill_failure_transitions_leftmost(&mut self) {
+        /// Represents an item in our queue of states to process.
+        ///
+        /// Fundamentally, this queue serves the same purpose as the queue
+        /// for filling failure transitions using the standard formulation.
+        /// In the leftmost case, though, we need to track a bit more
+        /// information. See comments below.
+        #[derive(Clone, Copy, Debug)]
+        struct QueuedState<S> {
+            /// The id of the state to visit.
+            id: S,
+            /// The depth at which the first match was observed in the path
+            /// to this state. Note that this corresponds to the depth at
+            /// which the beginning of the match was detected. If no match
+            /// has been seen, then this is None.
+            match_at_depth: Option<usize>,
+        }
+
+        impl<S: StateID> QueuedState<S> {
+            /// Create a queued state corresponding to the given NFA's start
+            /// state.
+            fn start<T: Token + ?Sized>(nfa: &NFA<S, T>) -> QueuedState<S> {
+                let match_at_depth = if nfa.start().is_match() {
+                    Some(0)
+                } else {
+                    None
+                };
+                QueuedState {
+                    id: nfa.start_id,
+                    match_at_depth,
+                }
+            }
+
+            /// Return the next state to queue up. The given id must be a state
+            /// corresponding to a single transition from this queued state.
+            fn next_queued_state<T: Token + ?Sized>(
+                &self,
+                nfa: &NFA<S, T>,
+                id: S,
+            ) -> QueuedState<S> {
+                let match_at_depth = self.next_match_at_depth(nfa, id);
+                QueuedState { id, match_at_depth }
+            }
+
+            /// Return the earliest depth at which a match has occurred for
+            /// the given state. The given state must correspond to a single
+            /// transition from this queued state.
+            fn next_match_at_depth<T: Token + ?Sized>(
+                &self,
+                nfa: &NFA<S, T>,
+                next: S,
+            ) -> Option<usize> {
+                // This is a little tricky. If the previous state has already
+                // seen a match or if `next` isn't a match state, then nothing
+                // needs to change since a later state cannot find an earlier
+                // match.
+                match self.match_at_depth {
+                    Some(x) => return Some(x),
+                    None if nfa.state(next).is_match() => {}
+                    None => return None,
+                }
+                let depth =
+                    nfa.state(next).depth - nfa.state(next).get_longest_match_len().unwrap() + 1;
+                Some(depth)
+            }
+        }
+
+        let mut queue: VecDeque<QueuedState<S>> = VecDeque::new();
+        let start = QueuedState::start(&self.nfa);
+        let start_trans: Vec<S> = self.nfa.start().trans.iter().map(|(_, id)| id).collect();
+        for next_id in start_trans {
+            queue.push_back(start.next_queued_state(&self.nfa, next_id));
+            // If a state immediately following the start state is a match
+            // state, then we never want to follow its failure transition
+            // since the failure transition necessarily leads back to the
+            // start state, which we never want to do for leftmost matching
+            // after a match has been found.
+            //
+            // N.B. This is a special case of the more general handling
+            // found below.
+            if self.nfa.state(next_id).is_match() {
+                self.nfa.state_mut(next_id).fail = dead_id();
+            }
+        }
+        // If the start state is itself a match state, then the empty pattern
+        // matches at the start of every search, and the search must stop
+        // rather than looping back to the start state.
+        if start.match_at_depth.is_some() {
+            let start_id = self.nfa.start_id;
+            self.nfa.state_mut(start_id).fail = dead_id();
+        }
+        while let Some(item) = queue.pop_front() {
+            let mut any_trans = false;
+            let mut it = self.nfa.iter_transitions_mut(item.id);
+            while let Some((b, next_id)) = it.next() {
+                any_trans = true;
+
+                let next = item.next_queued_state(it.nfa(), next_id);
+
+                // Find the failure state for next. Same as standard.
+                let mut fail = it.nfa().state(item.id).fail;
+                while it.nfa().state(fail).next_state(b) == fail_id() {
+                    let new_fail = it.nfa().state(fail).fail;
+                    if new_fail == fail {
+                        break;
+                    }
+                    fail = new_fail;
+                }
+                fail = it.nfa().state(fail).next_state(b);
+                if fail == fail_id() {
+                    fail = it.nfa().start_id;
+                }
+
+                // This is the key difference from the standard formulation.
+                // Namely, if we've seen a match, then we only want a failure
+                // transition if the failure transition preserves the match
+                // we've seen. In general, this is not true of all failure
+                // transitions since they can point back to any suffix of what
+                // we've seen so far. Instead, we only want to point back to
+                // suffixes that contain any match we've seen.
+                //
+                // We achieve this by comparing the depth of the failure
+                // transition with the number of states between this state
+                // and the beginning of the earliest match detected. If the
+                // depth of the failure state is smaller than this difference,
+                // then it cannot contain the match. If it's bigger or equal
+                // to the difference, then it necessarily includes the match
+                // we've seen since all failure transitions correspond to a
+                // suffix.
+                //
+                // If we've determined that we don't want the failure
+                // transition, then we set this state's failure transition to
+                // the dead state. In other words, when a search hits this
+                // state, it will not continue and correctly stop. (N.B. A
+                // dead state is different than a fail state. A dead state
+                // MUST be preceded by a match and acts as a sentinel to search
+                // routines to terminate.)
+                if let Some(match_depth) = next.match_at_depth {
+                    let fail_depth = it.nfa().state(fail).depth;
+                    let next_depth = it.nfa().state(next.id).depth;
+                    if next_depth - match_depth + 1 > fail_depth {
+                        it.nfa().state_mut(next.id).fail = dead_id();
+                        queue.push_back(next);
+                        continue;
+                    }
+                }
+                it.nfa().state_mut(next.id).fail = fail;
+                it.nfa().copy_matches(fail, next.id);
+
+                // Queue up the next state. This is done after copying the
+                // matches of the failure state, since those are matches the
+                // search has seen by the time it reaches `next` too, and so
+                // they must be kept by the failure transitions of the states
+                // that follow it.
+                queue.push_back(item.next_queued_state(it.nfa(), next_id));
+            }
+            // If there are no transitions for this state and if it's a match
+            // state, then we must set its failure transition to the dead
+            // state since we never want it to restart the search.
+            if !any_trans && it.nfa().state(item.id).is_match() {
+                it.nfa().state_mut(item.id).fail = dead_id();
+            }
+            // We don't need to copy empty matches from the start state here
+            // because that's only necessary for overlapping matches and
+            // leftmost match kinds don't support overlapping matches.
+        }
+    }
+
+    /// Returns the identifier of `token`, giving it a new one if it hasn't
+    /// been seen before.
+    fn intern(&mut self, token: &T) -> Result<TokenID, BuildError> {
+        if let Some(&id) = self.nfa.tokens.get(token) {
+            return Ok(id);
+        }
+        let id = self.nfa.tokens.len() as TokenID;
+        if id == UNKNOWN_TOKEN {
+            return Err(BuildError::TooManyTokens {
+                max: UNKNOWN_TOKEN as usize,
+            });
+        }
+        self.nfa.tokens.insert(token.to_boxed(), id);
+        Ok(id)
+    }
+
     /// Computes the total amount of heap used by this NFA in bytes.
     fn calculate_size(&mut self) {
-        let mut size = 0;
-        for state in &self.nfa.states {
-            size += state.heap_bytes();
-        }
-        self.nfa.heap_bytes = size;
+        self.nfa.heap_bytes = self.nfa.calculate_heap_bytes();
     }
 
     /// Add a new state to the underlying NFA with the given depth. The depth
@@ -439,16 +1334,126 @@ impl<S: StateID> Compiler<S> {
     ///
     /// If adding the new state would overflow the chosen state ID
     /// representation, then this returns an error.
-    fn add_state(&mut self) -> Option<S> {
-        self.nfa.add_sparse_state()
+    fn add_state(&mut self, depth: usize) -> Result<S, BuildError> {
+        if depth < self.dense_depth {
+            self.nfa.add_dense_state(depth)
+        } else {
+            self.nfa.add_sparse_state(depth)
+        }
     }
 }
 
-pub(crate) fn build_nfa<'a, I, S: StateID>(patterns: I) -> Option<NFA<S>>
-where
-    I: IntoIterator<Item = &'a str>,
-{
-    Compiler::new()?.compile(patterns)
+/// Builds an NFA from patterns that have already been tokenized and
+/// normalized.
+pub(crate) fn build_nfa<S: StateID, T: Token + ?Sized>(
+    patterns: &[Vec<Cow<'_, T>>],
+    options: NfaOptions,
+) -> Result<NFA<S, T>, BuildError> {
+    #[cfg(feature = "rayon")]
+    {
+        if options.parallel {
+            return build_nfa_parallel(patterns, options);
+        }
+    }
+    Compiler::new(options)?.compile(patterns)
+}
+
+/// Builds the same NFA as `build_nfa`, building the trie of the patterns
+/// starting with each token in parallel.
+#[cfg(feature = "rayon")]
+pub(crate) fn build_nfa_parallel<S: StateID, T: Token + ?Sized>(
+    patterns: &[Vec<Cow<'_, T>>],
+    options: NfaOptions,
+) -> Result<NFA<S, T>, BuildError> {
+    Compiler::new(options)?.compile_parallel(patterns)
+}
+
+/// The trie of the patterns that start with some set of tokens, built
+/// independently of the others for `Compiler::compile_parallel`.
+///
+/// Its transitions are labelled with its own token and state identifiers,
+/// and its first state stands in for the start state.
+#[cfg(feature = "rayon")]
+struct SubTrie<'p, T: Token + ?Sized> {
+    states: Vec<State<usize>>,
+    /// The pattern and depth at which `build_trie` would create each state.
+    created_at: Vec<(PatternID, usize)>,
+    /// Each token, along with the pattern and position at which it's first
+    /// walked over.
+    tokens: Vec<(&'p T, (PatternID, usize))>,
+}
+
+#[cfg(feature = "rayon")]
+impl<'p, T: Token + ?Sized> SubTrie<'p, T> {
+    /// Builds the trie of the given patterns in the same way as
+    /// `Compiler::build_trie`.
+    fn build<S: StateID>(
+        compiler: &Compiler<S, T>,
+        patterns: &'p [Vec<Cow<'_, T>>],
+        ids: &[PatternID],
+    ) -> SubTrie<'p, T> {
+        let mut trie = SubTrie {
+            states: vec![],
+            created_at: vec![],
+            tokens: vec![],
+        };
+        let mut interned: HashMap<&'p T, TokenID> = HashMap::new();
+        let root = trie.add_state(compiler, 0, (0, 0));
+
+        'patterns: for &pati in ids {
+            let pat = &patterns[pati];
+            let mut prev = root;
+            let mut saw_match = false;
+            for (depth, token) in pat.iter().enumerate() {
+                saw_match = saw_match || trie.states[prev].is_match();
+                if compiler.match_kind.is_leftmost_first() && saw_match {
+                    continue 'patterns;
+                }
+                let token: &'p T = token;
+                let tokens = &mut trie.tokens;
+                let b = *interned.entry(token).or_insert_with(|| {
+                    tokens.push((token, (pati, depth)));
+                    (tokens.len() - 1) as TokenID
+                });
+                // the root is never the target of a transition, so it
+                // doubles as the fail state
+                let next = trie.states[prev].next_state(b);
+                if next != fail_id::<usize>() {
+                    prev = next;
+                } else {
+                    let next = trie.add_state(compiler, depth + 1, (pati, depth + 1));
+                    trie.states[prev].set_next_state(b, next);
+                    prev = next;
+                }
+            }
+            if compiler.dedup_patterns && trie.states[prev].is_match() {
+                continue;
+            }
+            trie.states[prev].add_match(pati, pat.len());
+        }
+        trie
+    }
+
+    fn add_state<S: StateID>(
+        &mut self,
+        compiler: &Compiler<S, T>,
+        depth: usize,
+        created_at: (PatternID, usize),
+    ) -> usize {
+        let trans = if depth < compiler.dense_depth {
+            Transitions::Dense(TokenMap::default())
+        } else {
+            Transitions::Sparse(vec![])
+        };
+        self.states.push(State {
+            trans,
+            fail: fail_id(),
+            depth,
+            matches: vec![],
+        });
+        self.created_at.push(created_at);
+        self.states.len() - 1
+    }
 }
 
 /// Safely return two mutable borrows to two different locations in the given
@@ -465,3 +1470,908 @@ fn get_two_mut<T>(xs: &mut [T], i: usize, j: usize) -> (&mut T, &mut T) {
         (&mut after[0], &mut before[j])
     }
 }
+
+impl<S: StateID, T: Token + ?Sized> NFA<S, T> {
+    /// Strips the failure transitions from this NFA, and the matches they
+    /// copied, leaving the trie of its patterns with `usize` state IDs, so
+    /// that more patterns can be added to it.
+    fn into_trie(self) -> NFA<usize, T> {
+        let (start, dead) = (self.start_id.to_usize(), dead_id::<usize>());
+        let states = self
+            .states
+            .into_iter()
+            .enumerate()
+            .map(|(id, mut state)| {
+                // a state's own matches are of patterns ending there, and
+                // so are exactly as long as it is deep
+                let depth = state.depth;
+                state
+                    .matches
+                    .retain(|&(_, len)| len == depth && id != fail_id::<usize>());
+                State {
+                    trans: state.trans.map(|token, id| (token, id.to_usize())),
+                    fail: if id == dead { dead } else { start },
+                    depth,
+                    matches: state.matches,
+                }
+            })
+            .collect();
+
+        NFA {
+            start_id: start,
+            max_pattern_len: self.max_pattern_len,
+            pattern_count: self.pattern_count,
+            heap_bytes: 0,
+            tokens: self.tokens,
+            prefilter: None,
+            start_transitions: None,
+            states,
+        }
+    }
+}
+
+impl<T: Token + ?Sized> NFA<usize, T> {
+    /// Returns the tokens of every pattern in the trie of this NFA, which
+    /// must have no failure transitions, ordered by pattern identifier.
+    ///
+    /// Patterns that were never added to the trie, as they could never
+    /// match, are left out.
+    fn patterns(&self) -> Vec<(PatternID, Vec<Cow<'_, T>>)> {
+        let mut names = vec![None; self.tokens.len()];
+        for (token, &id) in &self.tokens {
+            names[id as usize] = Some(&**token);
+        }
+
+        let mut patterns = vec![];
+        let mut stack = vec![(self.start_id, vec![])];
+        while let Some((id, path)) = stack.pop() {
+            let state = &self.states[id];
+            for &(pattern, _) in &state.matches {
+                patterns.push((pattern, path.clone()));
+            }
+            for (token, next) in state.trans.iter() {
+                let mut path = path.clone();
+                // every token a transition is labelled with is interned
+                path.push(Cow::Borrowed(names[token as usize].unwrap()));
+                stack.push((next, path));
+            }
+        }
+        patterns.sort_by_key(|&(pattern, _)| pattern);
+        patterns
+    }
+
+    /// Returns true if every state of this NFA can be identified by `S`.
+    fn fits<S: StateID>(&self) -> bool {
+        usize_to_state_id::<S>(self.states.len().saturating_sub(1)).is_some()
+    }
+
+    /// Converts this NFA to one using a narrower state ID representation,
+    /// returning an error if it has more states than `S` can identify.
+    fn narrow<S: StateID>(self) -> Result<NFA<S, T>, BuildError> {
+        if !self.fits::<S>() {
+            return Err(BuildError::state_id_overflow::<S>());
+        }
+
+        let states = self
+            .states
+            .into_iter()
+            .map(|state| State {
+                trans: state.trans.map(|token, id| (token, S::from_usize(id))),
+                fail: S::from_usize(state.fail),
+                depth: state.depth,
+                matches: state.matches,
+            })
+            .collect();
+
+        let mut nfa = NFA {
+            start_id: S::from_usize(self.start_id),
+            max_pattern_len: self.max_pattern_len,
+            pattern_count: self.pattern_count,
+            heap_bytes: 0,
+            tokens: self.tokens,
+            prefilter: self.prefilter,
+            start_transitions: self.start_transitions.map(|start| start.map(S::from_usize)),
+            states,
+        };
+        nfa.heap_bytes = nfa.calculate_heap_bytes();
+        Ok(nfa)
+    }
+}
+
+impl NFA<usize> {
+    /// Returns true if following failure transitions from any state ends at
+    /// the start state, or at the dead state, rather than looping forever,
+    /// as every other state fails to a shallower one.
+    ///
+    /// Every failure transition must be a valid state ID.
+    fn failures_are_shallower(states: &[State<usize>], start_id: usize) -> bool {
+        let dead = dead_id::<usize>();
+        (0..states.len()).all(|id| {
+            let state = &states[id];
+            match id {
+                // the fail state is never entered
+                _ if id == fail_id::<usize>() => true,
+                _ if id == dead => state.fail == dead,
+                _ if id == start_id => state.fail == start_id || state.fail == dead,
+                _ => states[state.fail].depth < state.depth,
+            }
+        })
+    }
+
+    /// Returns why a search of the given states could report a match that
+    /// starts before the haystack, if it could.
+    ///
+    /// This can't happen as long as every transition leads one token deeper,
+    /// or to the start or dead state at depth 0, and every match spans at
+    /// least one token and at most the depth of its state. Every transition
+    /// must be a valid state ID.
+    fn check_depths(states: &[State<usize>], start_id: usize) -> Result<(), &'static str> {
+        let dead = dead_id::<usize>();
+        if states[start_id].depth != 0 || states[dead].depth != 0 {
+            return Err("the start and dead states must have depth 0");
+        }
+        for state in states {
+            for (_, next) in state.trans.iter() {
+                if next != start_id && next != dead && states[next].depth != state.depth + 1 {
+                    return Err("a transition skips a depth");
+                }
+            }
+            for &(_, len) in &state.matches {
+                if len == 0 {
+                    return Err("a match is empty");
+                }
+                if len > state.depth {
+                    return Err("a match is longer than the path to its state");
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Fills in the tables built from the states of a loaded NFA, which
+    /// aren't stored with it.
+    fn with_search_tables(mut self, prefilter: bool) -> NFA<usize> {
+        if prefilter {
+            self.prefilter = Some(Prefilter::new(self.start_tokens().map(str::as_bytes)));
+        }
+        self.start_transitions = Some(StartTransitions::new(&self, &self.tokens));
+        self.heap_bytes = self.calculate_heap_bytes();
+        self
+    }
+
+    /// Builds an NFA from the parts returned by `NFA::into_parts`, checking
+    /// that every state ID and token they refer to exists, that they can't
+    /// loop forever, and that no match can start before the haystack.
+    pub(crate) fn from_parts(
+        parts: AutomatonParts,
+        options: NfaOptions,
+    ) -> Result<NFA<usize>, BuildError> {
+        let invalid = |reason| Err(BuildError::invalid_parts(reason));
+        let num_states = parts.states.len();
+        if num_states < 3 || parts.start_id != 2 {
+            return invalid("the start state must be state 2");
+        }
+        if parts.tokens.len() > UNKNOWN_TOKEN as usize {
+            return Err(BuildError::TooManyTokens {
+                max: UNKNOWN_TOKEN as usize,
+            });
+        }
+        let mut tokens = HashMap::with_capacity(parts.tokens.len());
+        for (id, token) in parts.tokens.into_iter().enumerate() {
+            if tokens
+                .insert(token.into_boxed_str(), id as TokenID)
+                .is_some()
+            {
+                return invalid("a token is listed twice");
+            }
+        }
+
+        let mut states = Vec::with_capacity(num_states);
+        for state in parts.states {
+            if state.fail >= num_states {
+                return invalid("a failure transition leads to a missing state");
+            }
+            let mut trans = if state.depth < options.dense_depth {
+                Transitions::Dense(TokenMap::default())
+            } else {
+                Transitions::Sparse(vec![])
+            };
+            for (token, next) in state.transitions {
+                if token >= tokens.len() {
+                    return invalid("a transition is labelled with a missing token");
+                }
+                if next >= num_states || next == fail_id::<usize>() {
+                    return invalid("a transition leads to a missing state");
+                }
+                trans.set_next_state(token as TokenID, next);
+            }
+            for &(pattern, len) in &state.matches {
+                if pattern >= parts.pattern_count {
+                    return invalid("a match is of a missing pattern");
+                }
+                if len > parts.max_pattern_len {
+                    return invalid("a match is longer than the patterns");
+                }
+            }
+            states.push(State {
+                trans,
+                fail: state.fail,
+                depth: state.depth,
+                matches: state.matches,
+            });
+        }
+        if let Err(reason) = NFA::check_depths(&states, parts.start_id) {
+            return invalid(reason);
+        }
+        if !NFA::failures_are_shallower(&states, parts.start_id) {
+            return invalid("a failure transition leads to a state that isn't shallower");
+        }
+
+        let nfa = NFA {
+            start_id: parts.start_id,
+            max_pattern_len: parts.max_pattern_len,
+            pattern_count: parts.pattern_count,
+            heap_bytes: 0,
+            tokens,
+            prefilter: None,
+            start_transitions: None,
+            states,
+        };
+        Ok(nfa.with_search_tables(options.prefilter))
+    }
+}
+
+impl<S: StateID> NFA<S> {
+    /// Returns the states, tokens, and counts of this NFA as plain data,
+    /// with its tokens listed in order of their identifiers.
+    pub(crate) fn into_parts(self) -> AutomatonParts {
+        let mut tokens: Vec<_> = self.tokens.into_iter().collect();
+        tokens.sort_by_key(|&(_, id)| id);
+        let states = self
+            .states
+            .into_iter()
+            .map(|state| {
+                let mut transitions: Vec<_> = state
+                    .trans
+                    .iter()
+                    .map(|(token, next)| (token as usize, next.to_usize()))
+                    .collect();
+                transitions.sort_unstable();
+                StateParts {
+                    fail: state.fail.to_usize(),
+                    depth: state.depth,
+                    transitions,
+                    matches: state.matches,
+                }
+            })
+            .collect();
+
+        AutomatonParts {
+            states,
+            start_id: self.start_id.to_usize(),
+            pattern_count: self.pattern_count,
+            max_pattern_len: self.max_pattern_len,
+            tokens: tokens
+                .into_iter()
+                .map(|(token, _)| token.into_string())
+                .collect(),
+        }
+    }
+}
+
+/// An NFA or DFA using whichever state ID representation it was built with.
+///
+/// This implements `Automaton` with `usize` state IDs, converting to and from
+/// the underlying representation, so that searches can be written once for
+/// every representation. The searches themselves dispatch to the underlying
+/// automaton once per call rather than once per token.
+pub(crate) enum AnyAutomaton<T: Token + ?Sized = str> {
+    U8(Imp<u8, T>),
+    U16(Imp<u16, T>),
+    U32(Imp<u32, T>),
+    Usize(Imp<usize, T>),
+}
+
+impl<T: Token + ?Sized> Clone for AnyAutomaton<T> {
+    fn clone(&self) -> AnyAutomaton<T> {
+        match self {
+            AnyAutomaton::U8(imp) => AnyAutomaton::U8(imp.clone()),
+            AnyAutomaton::U16(imp) => AnyAutomaton::U16(imp.clone()),
+            AnyAutomaton::U32(imp) => AnyAutomaton::U32(imp.clone()),
+            AnyAutomaton::Usize(imp) => AnyAutomaton::Usize(imp.clone()),
+        }
+    }
+}
+
+/// An NFA, or the DFA it was converted to.
+pub(crate) enum Imp<S, T: Token + ?Sized = str> {
+    Nfa(NFA<S, T>),
+    Dfa(DFA<S, T>),
+}
+
+impl<S: StateID, T: Token + ?Sized> Clone for Imp<S, T> {
+    fn clone(&self) -> Imp<S, T> {
+        match self {
+            Imp::Nfa(nfa) => Imp::Nfa(nfa.clone()),
+            Imp::Dfa(dfa) => Imp::Dfa(dfa.clone()),
+        }
+    }
+}
+
+impl<S: StateID, T: Token + ?Sized> Imp<S, T> {
+    fn new(nfa: NFA<S, T>, dfa: bool) -> Imp<S, T> {
+        if dfa {
+            Imp::Dfa(DFA::new(nfa))
+        } else {
+            Imp::Nfa(nfa)
+        }
+    }
+
+    /// Returns the trie of the patterns of this automaton.
+    fn into_trie(self) -> NFA<usize, T> {
+        match self {
+            Imp::Nfa(nfa) => nfa.into_trie(),
+            Imp::Dfa(dfa) => dfa.into_trie().into_trie(),
+        }
+    }
+}
+
+macro_rules! forward {
+    ($self:expr, $nfa:ident => $e:expr) => {
+        match $self {
+            AnyAutomaton::U8(Imp::Nfa($nfa)) => $e,
+            AnyAutomaton::U8(Imp::Dfa($nfa)) => $e,
+            AnyAutomaton::U16(Imp::Nfa($nfa)) => $e,
+            AnyAutomaton::U16(Imp::Dfa($nfa)) => $e,
+            AnyAutomaton::U32(Imp::Nfa($nfa)) => $e,
+            AnyAutomaton::U32(Imp::Dfa($nfa)) => $e,
+            AnyAutomaton::Usize(Imp::Nfa($nfa)) => $e,
+            AnyAutomaton::Usize(Imp::Dfa($nfa)) => $e,
+        }
+    };
+}
+
+/// Runs `f` with `id` converted to the representation used by an NFA,
+/// writing the possibly updated identifier back afterwards.
+fn with_id<S: StateID, R>(id: &mut usize, f: impl FnOnce(&mut S) -> R) -> R {
+    let mut narrow = S::from_usize(*id);
+    let result = f(&mut narrow);
+    *id = narrow.to_usize();
+    result
+}
+
+impl<T: Token + ?Sized> AnyAutomaton<T> {
+    /// Builds an NFA using the narrowest state ID representation that can
+    /// identify all of its states.
+    ///
+    /// Narrower representations shrink every transition and failure link, so
+    /// a dictionary of a few hundred patterns is stored with `u16` state IDs
+    /// rather than `usize`.
+    pub(crate) fn build(
+        patterns: &[Vec<Cow<'_, T>>],
+        options: NfaOptions,
+    ) -> Result<AnyAutomaton<T>, BuildError> {
+        AnyAutomaton::from_nfa(build_nfa(patterns, options)?, options.dfa)
+    }
+
+    /// Adds patterns to this automaton, returning an automaton using the
+    /// narrowest state ID representation that can identify all of its
+    /// states, which may be wider than the one it used before.
+    pub(crate) fn extend(
+        self,
+        patterns: &[Vec<Cow<'_, T>>],
+        options: NfaOptions,
+    ) -> Result<AnyAutomaton<T>, BuildError> {
+        let mut compiler = Compiler::new(options)?;
+        compiler.nfa = self.into_trie();
+        compiler.build_trie(patterns)?;
+        AnyAutomaton::from_nfa(compiler.finish(), options.dfa)
+    }
+
+    /// Adds the patterns of `other` to this automaton, numbered after the
+    /// patterns it already has, returning an automaton using the narrowest
+    /// state ID representation that can identify all of its states.
+    pub(crate) fn merge(
+        self,
+        other: &AnyAutomaton<T>,
+        options: NfaOptions,
+    ) -> Result<AnyAutomaton<T>, BuildError> {
+        let other_count = other.pattern_count();
+        let other = other.clone().into_trie();
+
+        let mut compiler = Compiler::new(options)?;
+        compiler.nfa = self.into_trie();
+        let offset = compiler.nfa.pattern_count;
+        for (pattern, tokens) in other.patterns() {
+            compiler.add_pattern(offset + pattern, &tokens)?;
+        }
+        compiler.nfa.pattern_count = offset + other_count;
+        AnyAutomaton::from_nfa(compiler.finish(), options.dfa)
+    }
+
+    fn into_trie(self) -> NFA<usize, T> {
+        match self {
+            AnyAutomaton::U8(imp) => imp.into_trie(),
+            AnyAutomaton::U16(imp) => imp.into_trie(),
+            AnyAutomaton::U32(imp) => imp.into_trie(),
+            AnyAutomaton::Usize(imp) => imp.into_trie(),
+        }
+    }
+
+    /// Stores an NFA using the narrowest state ID representation that can
+    /// identify all of its states, converting it to a DFA if `dfa` is set.
+    fn from_nfa(nfa: NFA<usize, T>, dfa: bool) -> Result<AnyAutomaton<T>, BuildError> {
+        Ok(if nfa.fits::<u8>() {
+            AnyAutomaton::U8(Imp::new(nfa.narrow()?, dfa))
+        } else if nfa.fits::<u16>() {
+            AnyAutomaton::U16(Imp::new(nfa.narrow()?, dfa))
+        } else if nfa.fits::<u32>() {
+            AnyAutomaton::U32(Imp::new(nfa.narrow()?, dfa))
+        } else {
+            AnyAutomaton::Usize(Imp::new(nfa, dfa))
+        })
+    }
+
+    /// Builds an NFA using the state ID representation `S`, returning an
+    /// error if it cannot identify all of the states needed.
+    pub(crate) fn build_with_state_id<S: StateID>(
+        patterns: &[Vec<Cow<'_, T>>],
+        options: NfaOptions,
+    ) -> Result<AnyAutomaton<T>, BuildError> {
+        let nfa: NFA<usize, T> = build_nfa(patterns, options)?;
+
+        // state ID representations are distinguished by their range, with
+        // `u64` being stored as `usize`, as `u64` is only a `StateID` on
+        // 64-bit targets
+        let (max_id, dfa) = (S::max_id(), options.dfa);
+        Ok(if max_id == u8::MAX as usize {
+            AnyAutomaton::U8(Imp::new(nfa.narrow()?, dfa))
+        } else if max_id == u16::MAX as usize {
+            AnyAutomaton::U16(Imp::new(nfa.narrow()?, dfa))
+        } else if max_id == u32::MAX as usize {
+            AnyAutomaton::U32(Imp::new(nfa.narrow()?, dfa))
+        } else {
+            AnyAutomaton::Usize(Imp::new(nfa, dfa))
+        })
+    }
+
+    pub(crate) fn heap_bytes(&self) -> usize {
+        forward!(self, nfa => nfa.heap_bytes())
+    }
+
+    pub(crate) fn shrink_to_fit(&mut self) {
+        forward!(self, nfa => nfa.shrink_to_fit())
+    }
+
+    #[cfg(feature = "serialize")]
+    pub(crate) fn is_dfa(&self) -> bool {
+        matches!(
+            self,
+            AnyAutomaton::U8(Imp::Dfa(_))
+                | AnyAutomaton::U16(Imp::Dfa(_))
+                | AnyAutomaton::U32(Imp::Dfa(_))
+                | AnyAutomaton::Usize(Imp::Dfa(_))
+        )
+    }
+
+    pub(crate) fn max_pattern_len(&self) -> usize {
+        forward!(self, nfa => nfa.max_pattern_len())
+    }
+
+    pub(crate) fn pattern_count(&self) -> usize {
+        forward!(self, nfa => nfa.pattern_count())
+    }
+
+    pub(crate) fn state_count(&self) -> usize {
+        forward!(self, nfa => nfa.state_count())
+    }
+
+    pub(crate) fn max_out_degree(&self) -> usize {
+        forward!(self, nfa => nfa.max_out_degree())
+    }
+
+    pub(crate) fn dense_state_count(&self) -> usize {
+        forward!(self, nfa => nfa.dense_state_count())
+    }
+
+    pub(crate) fn start_tokens(&self) -> Box<dyn Iterator<Item = &T> + '_> {
+        forward!(self, nfa => Box::new(nfa.start_tokens()))
+    }
+
+    pub(crate) fn pattern_lens(&self) -> Box<dyn Iterator<Item = (PatternID, PatternLength)> + '_> {
+        forward!(self, nfa => Box::new(nfa.pattern_lens()))
+    }
+}
+
+impl AnyAutomaton {
+    /// Returns the NFA of this automaton as plain data, building it again
+    /// from the patterns of a DFA, which doesn't keep its failure
+    /// transitions.
+    pub(crate) fn into_parts(self, options: NfaOptions) -> AutomatonParts {
+        match self {
+            AnyAutomaton::U8(Imp::Nfa(nfa)) => nfa.into_parts(),
+            AnyAutomaton::U16(Imp::Nfa(nfa)) => nfa.into_parts(),
+            AnyAutomaton::U32(Imp::Nfa(nfa)) => nfa.into_parts(),
+            AnyAutomaton::Usize(Imp::Nfa(nfa)) => nfa.into_parts(),
+            dfa => {
+                let options = NfaOptions {
+                    dfa: false,
+                    ..options
+                };
+                // the NFA has as many states as the DFA, so always fits
+                match dfa.extend(&[], options) {
+                    Ok(nfa) => nfa.into_parts(options),
+                    Err(err) => panic!("failed to rebuild the NFA: {}", err),
+                }
+            }
+        }
+    }
+
+    /// Builds an automaton from the parts returned by
+    /// `AnyAutomaton::into_parts`, using the narrowest state ID
+    /// representation that can identify all of its states.
+    pub(crate) fn from_parts(
+        parts: AutomatonParts,
+        options: NfaOptions,
+    ) -> Result<AnyAutomaton, BuildError> {
+        AnyAutomaton::from_nfa(NFA::from_parts(parts, options)?, options.dfa)
+    }
+}
+
+impl<T: Token + ?Sized> Automaton for AnyAutomaton<T> {
+    type ID = usize;
+    type Token = T;
+
+    fn start_state(&self) -> usize {
+        forward!(self, nfa => nfa.start_state().to_usize())
+    }
+
+    fn is_valid(&self, id: usize) -> bool {
+        forward!(self, nfa => id < nfa.state_count())
+    }
+
+    fn is_match_state(&self, id: usize) -> bool {
+        forward!(self, nfa => nfa.is_match_state(StateID::from_usize(id)))
+    }
+
+    fn get_match(&self, id: usize, match_index: usize, end: usize) -> Option<Match> {
+        forward!(self, nfa => nfa.get_match(StateID::from_usize(id), match_index, end))
+    }
+
+    fn match_count(&self, id: usize) -> usize {
+        forward!(self, nfa => nfa.match_count(StateID::from_usize(id)))
+    }
+
+    fn token_id(&self, token: &T) -> TokenID {
+        forward!(self, nfa => nfa.token_id(token))
+    }
+
+    fn prefilter(&self) -> Option<&Prefilter> {
+        forward!(self, nfa => nfa.prefilter())
+    }
+
+    fn start_next_state(&self, token: &T) -> usize {
+        forward!(self, nfa => nfa.start_next_state(token).to_usize())
+    }
+
+    unsafe fn next_state_unchecked(&self, current: usize, input: TokenID) -> usize {
+        forward!(self, nfa => nfa.next_state_unchecked(StateID::from_usize(current), input).to_usize())
+    }
+
+    fn next_state_or_none(&self, current: usize, input: TokenID) -> Option<usize> {
+        // a state ID too wide for the automaton's representation would be
+        // truncated to another state
+        assert!(
+            self.is_valid(current),
+            "{} is not a valid state ID",
+            current
+        );
+        forward!(self, nfa => {
+            nfa.next_state_or_none(StateID::from_usize(current), input).map(StateID::to_usize)
+        })
+    }
+
+    #[cfg(feature = "trace")]
+    fn traced_next_state(
+        &self,
+        current: usize,
+        input: TokenID,
+        failures: &mut Vec<usize>,
+    ) -> usize {
+        assert!(
+            self.is_valid(current),
+            "{} is not a valid state ID",
+            current
+        );
+        forward!(self, nfa => {
+            let mut narrow = Vec::new();
+            let next = nfa.traced_next_state(StateID::from_usize(current), input, &mut narrow);
+            failures.extend(narrow.into_iter().map(StateID::to_usize));
+            next.to_usize()
+        })
+    }
+
+    fn state_depth(&self, id: usize) -> usize {
+        assert!(self.is_valid(id), "{} is not a valid state ID", id);
+        forward!(self, nfa => nfa.state_depth(StateID::from_usize(id)))
+    }
+
+    fn next_state_checked(&self, current: usize, input: TokenID) -> Option<usize> {
+        // a state ID too wide for the automaton's representation would be
+        // truncated to another state
+        if !self.is_valid(current) {
+            return None;
+        }
+        forward!(self, nfa => {
+            nfa.next_state_checked(StateID::from_usize(current), input).map(StateID::to_usize)
+        })
+    }
+
+    fn standard_find_at(
+        &self,
+        haystack: &[Cow<'_, T>],
+        at: usize,
+        state_id: &mut usize,
+    ) -> Option<Match> {
+        forward!(self, nfa => with_id(state_id, |id| nfa.standard_find_at(haystack, at, id)))
+    }
+
+    fn overlapping_find_at(
+        &self,
+        haystack: &[Cow<'_, T>],
+        at: usize,
+        state_id: &mut usize,
+        match_index: &mut usize,
+    ) -> Option<Match> {
+        forward!(self, nfa => with_id(state_id, |id| {
+            nfa.overlapping_find_at(haystack, at, id, match_index)
+        }))
+    }
+
+    fn leftmost_find_at(&self, haystack: &[Cow<'_, T>], at: usize) -> Option<Match> {
+        forward!(self, nfa => nfa.leftmost_find_at(haystack, at))
+    }
+
+    fn checked_find_at(
+        &self,
+        haystack: &[Cow<'_, T>],
+        at: usize,
+        state_id: &mut usize,
+    ) -> Option<Match> {
+        if !self.is_valid(*state_id) {
+            return None;
+        }
+        forward!(self, nfa => with_id(state_id, |id| nfa.checked_find_at(haystack, at, id)))
+    }
+
+    fn checked_overlapping_find_at(
+        &self,
+        haystack: &[Cow<'_, T>],
+        at: usize,
+        state_id: &mut usize,
+        match_index: &mut usize,
+    ) -> Option<Match> {
+        if !self.is_valid(*state_id) {
+            return None;
+        }
+        forward!(self, nfa => with_id(state_id, |id| {
+            nfa.checked_overlapping_find_at(haystack, at, id, match_index)
+        }))
+    }
+
+    fn checked_leftmost_find_at(&self, haystack: &[Cow<'_, T>], at: usize) -> Option<Match> {
+        forward!(self, nfa => nfa.checked_leftmost_find_at(haystack, at))
+    }
+
+    fn anchored_find<F>(&self, haystack: &[Cow<'_, T>], accept: F) -> Option<Match>
+    where
+        F: FnMut(&Match) -> bool,
+    {
+        forward!(self, nfa => nfa.anchored_find(haystack, accept))
+    }
+
+    fn first_match_in<S>(&self, tokens: S) -> Option<Match>
+    where
+        S: TokenSource<T>,
+    {
+        forward!(self, nfa => nfa.first_match_in(tokens))
+    }
+}
+
+/// Writes a token interner, in order of the tokens' identifiers, which are
+/// assigned in the order they were interned.
+#[cfg(feature = "serialize")]
+pub(crate) fn write_tokens(w: &mut Writer, tokens: &HashMap<Box<str>, TokenID>) {
+    let mut tokens: Vec<_> = tokens.iter().collect();
+    tokens.sort_by_key(|&(_, &id)| id);
+    w.write_usize(tokens.len());
+    for (token, _) in tokens {
+        w.write_str(token);
+    }
+}
+
+/// Reads a token interner written by `write_tokens`.
+#[cfg(feature = "serialize")]
+pub(crate) fn read_tokens(
+    r: &mut Reader<'_>,
+) -> Result<HashMap<Box<str>, TokenID>, SerializeError> {
+    let num_tokens = r.read_len(8)?;
+    let mut tokens = HashMap::with_capacity(num_tokens);
+    for id in 0..num_tokens {
+        if tokens.insert(r.read_str()?.into(), id as TokenID).is_some()
+            || id as TokenID == UNKNOWN_TOKEN
+        {
+            return Err(SerializeError::InvalidFormat);
+        }
+    }
+    Ok(tokens)
+}
+
+#[cfg(feature = "serialize")]
+impl<S: StateID> NFA<S> {
+    fn write_to(&self, w: &mut Writer) {
+        w.write_usize(self.start_id.to_usize());
+        w.write_usize(self.max_pattern_len);
+        w.write_usize(self.pattern_count);
+        w.write_bool(self.prefilter.is_some());
+        write_tokens(w, &self.tokens);
+
+        w.write_usize(self.states.len());
+        for state in &self.states {
+            w.write_usize(state.fail.to_usize());
+            w.write_usize(state.depth);
+
+            // sorted so that the same automaton always serializes the same
+            let mut trans: Vec<_> = state.trans.iter().collect();
+            if let Transitions::Dense(_) = state.trans {
+                trans.sort();
+            }
+            w.write_bool(matches!(state.trans, Transitions::Dense(_)));
+            w.write_usize(trans.len());
+            for (token, next) in trans {
+                w.write_usize(token as usize);
+                w.write_usize(next.to_usize());
+            }
+
+            w.write_usize(state.matches.len());
+            for &(pattern, len) in &state.matches {
+                w.write_usize(pattern);
+                w.write_usize(len);
+            }
+        }
+    }
+}
+
+#[cfg(feature = "serialize")]
+impl NFA<usize> {
+    /// Reads an NFA written by `NFA::write_to`, checking that every state ID
+    /// it refers to exists.
+    fn read_from(r: &mut Reader<'_>) -> Result<NFA<usize>, SerializeError> {
+        let start_id = r.read_usize()?;
+        let max_pattern_len = r.read_usize()?;
+        let pattern_count = r.read_usize()?;
+        let prefilter = r.read_bool()?;
+        let tokens = read_tokens(r)?;
+        let num_tokens = tokens.len();
+
+        // each state is at least its four integers
+        let num_states = r.read_len(32)?;
+        let valid = |id: usize| {
+            if id < num_states {
+                Ok(id)
+            } else {
+                Err(SerializeError::InvalidFormat)
+            }
+        };
+
+        let mut states = Vec::with_capacity(num_states);
+        for _ in 0..num_states {
+            let fail = valid(r.read_usize()?)?;
+            let depth = r.read_usize()?;
+
+            let mut trans = if r.read_bool()? {
+                Transitions::Dense(TokenMap::default())
+            } else {
+                Transitions::Sparse(vec![])
+            };
+            let num_trans = r.read_len(16)?;
+            for _ in 0..num_trans {
+                let token = r.read_usize()?;
+                if token >= num_tokens {
+                    return Err(SerializeError::InvalidFormat);
+                }
+                trans.set_next_state(token as TokenID, valid(r.read_usize()?)?);
+            }
+
+            let num_matches = r.read_len(16)?;
+            let mut matches = Vec::with_capacity(num_matches);
+            for _ in 0..num_matches {
+                let (pattern, len) = (r.read_usize()?, r.read_usize()?);
+                // searches keep no more tokens than the longest pattern spans
+                if pattern >= pattern_count || len > max_pattern_len {
+                    return Err(SerializeError::InvalidFormat);
+                }
+                matches.push((pattern, len));
+            }
+
+            states.push(State {
+                trans,
+                fail,
+                depth,
+                matches,
+            });
+        }
+
+        // the fail, dead, and start states always exist
+        if states.len() < 3 || start_id != 2 {
+            return Err(SerializeError::InvalidFormat);
+        }
+        // following failure transitions must always end at the start or dead
+        // state, or searches could loop forever
+        if !NFA::failures_are_shallower(&states, start_id) {
+            return Err(SerializeError::InvalidFormat);
+        }
+        // nor may a match start before the haystack
+        if NFA::check_depths(&states, start_id).is_err() {
+            return Err(SerializeError::InvalidFormat);
+        }
+
+        let nfa = NFA {
+            start_id,
+            max_pattern_len,
+            pattern_count,
+            heap_bytes: 0,
+            tokens,
+            prefilter: None,
+            start_transitions: None,
+            states,
+        };
+        Ok(nfa.with_search_tables(prefilter))
+    }
+}
+
+#[cfg(feature = "serialize")]
+impl AnyAutomaton {
+    pub(crate) fn write_to(&self, w: &mut Writer) {
+        let width = match self {
+            AnyAutomaton::U8(_) => 1,
+            AnyAutomaton::U16(_) => 2,
+            AnyAutomaton::U32(_) => 4,
+            AnyAutomaton::Usize(_) => 8,
+        };
+        w.write_u8(width);
+        let dfa = match self {
+            AnyAutomaton::U8(imp) => matches!(imp, Imp::Dfa(_)),
+            AnyAutomaton::U16(imp) => matches!(imp, Imp::Dfa(_)),
+            AnyAutomaton::U32(imp) => matches!(imp, Imp::Dfa(_)),
+            AnyAutomaton::Usize(imp) => matches!(imp, Imp::Dfa(_)),
+        };
+        w.write_bool(dfa);
+        forward!(self, nfa => nfa.write_to(w))
+    }
+
+    /// Reads an automaton written by `AnyAutomaton::write_to`, using the same
+    /// state ID representation it was written with.
+    pub(crate) fn read_from(r: &mut Reader<'_>) -> Result<AnyAutomaton, SerializeError> {
+        let width = r.read_u8()?;
+        if r.read_bool()? {
+            return Ok(match width {
+                1 => AnyAutomaton::U8(Imp::Dfa(DFA::read_from(r)?)),
+                2 => AnyAutomaton::U16(Imp::Dfa(DFA::read_from(r)?)),
+                4 => AnyAutomaton::U32(Imp::Dfa(DFA::read_from(r)?)),
+                8 => AnyAutomaton::Usize(Imp::Dfa(DFA::read_from(r)?)),
+                _ => return Err(SerializeError::InvalidFormat),
+            });
+        }
+
+        let nfa = NFA::read_from(r)?;
+        let narrowed = match width {
+            1 => nfa.narrow().map(|nfa| AnyAutomaton::U8(Imp::Nfa(nfa))),
+            2 => nfa.narrow().map(|nfa| AnyAutomaton::U16(Imp::Nfa(nfa))),
+            4 => nfa.narrow().map(|nfa| AnyAutomaton::U32(Imp::Nfa(nfa))),
+            8 => Ok(AnyAutomaton::Usize(Imp::Nfa(nfa))),
+            _ => return Err(SerializeError::InvalidFormat),
+        };
+        narrowed.map_err(|_| SerializeError::InvalidFormat)
+    }
+}
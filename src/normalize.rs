@@ -0,0 +1,520 @@
+use alloc::{borrow::Cow, boxed::Box, string::String, sync::Arc, vec::Vec};
+use core::{fmt, iter::Peekable};
+
+use unicode_normalization::{
+    char::{canonical_combining_class, compose},
+    is_nfc, UnicodeNormalization,
+};
+use unicode_segmentation::{GraphemeIndices, UnicodeSegmentation};
+
+use crate::{
+    collections::HashSet,
+    word_split_trait::{self, is_word_character},
+};
+
+/// Halfwidth CJK punctuation and katakana, `U+FF61` to `U+FF9F`, mapped to
+/// their fullwidth forms.
+///
+/// The halfwidth voiced sound marks map to the combining marks, as in NFKC,
+/// but are not composed with the preceding kana.
+const HALFWIDTH_KATAKANA: [char; 63] = [
+    '。', '「', '」', '、', '・', 'ヲ', 'ァ', 'ィ', 'ゥ', 'ェ', 'ォ', 'ャ', 'ュ', 'ョ', 'ッ', 'ー',
+    'ア', 'イ', 'ウ', 'エ', 'オ', 'カ', 'キ', 'ク', 'ケ', 'コ', 'サ', 'シ', 'ス', 'セ', 'ソ', 'タ',
+    'チ', 'ツ', 'テ', 'ト', 'ナ', 'ニ', 'ヌ', 'ネ', 'ノ', 'ハ', 'ヒ', 'フ', 'ヘ', 'ホ', 'マ', 'ミ',
+    'ム', 'メ', 'モ', 'ヤ', 'ユ', 'ヨ', 'ラ', 'リ', 'ル', 'レ', 'ロ', 'ワ', 'ン', '\u{3099}',
+    '\u{309A}',
+];
+
+/// Maps a fullwidth ASCII variant, the ideographic space, or a halfwidth
+/// katakana to its canonical width, returning `None` if `c` has no other
+/// width form.
+fn fold_char_width(c: char) -> Option<char> {
+    match c {
+        '\u{FF01}'..='\u{FF5E}' => core::char::from_u32(c as u32 - 0xFEE0),
+        '\u{3000}' => Some(' '),
+        '\u{FF61}'..='\u{FF9F}' => Some(HALFWIDTH_KATAKANA[(c as u32 - 0xFF61) as usize]),
+        _ => None,
+    }
+}
+
+/// A Unicode normalization form that patterns and haystacks are converted to
+/// before they're split into tokens, set with
+/// `SimpleFinderBuilder::normalize`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum Normalization {
+    /// Compare text as given, so that text in different normalization forms
+    /// never matches.
+    #[default]
+    None,
+    /// Convert text to Normalization Form C, composing characters, so that a
+    /// decomposed `"e\u{301}"` matches a precomposed `"é"`.
+    Nfc,
+}
+
+/// A transform applied to each token after it's normalized, set with
+/// `SimpleFinderBuilder::token_transform`.
+pub(crate) type TokenTransform = Arc<dyn Fn(&str) -> Cow<'_, str> + Send + Sync>;
+
+/// The transformations applied to each token before it is fed to the
+/// automaton.
+///
+/// The same normalizer must be used for both the patterns and the haystack,
+/// as tokens are only compared after normalization. Match offsets are always
+/// computed from the un-normalized tokens.
+#[derive(Clone, Default)]
+pub(crate) struct Normalizer {
+    pub(crate) fold_width: bool,
+    pub(crate) case_insensitive: bool,
+    pub(crate) form: Normalization,
+    /// Whether the char offsets of matches count grapheme clusters instead.
+    pub(crate) graphemes: bool,
+    /// Tokens that are dropped rather than fed to the automaton, already
+    /// normalized.
+    pub(crate) stop_words: HashSet<Box<str>>,
+    /// Symbols removed from the words they join, and dropped where they
+    /// don't join words, longest first.
+    pub(crate) strip_symbols: Arc<[Box<str>]>,
+    pub(crate) transform: Option<TokenTransform>,
+}
+
+impl Normalizer {
+    pub(crate) fn normalize<'t>(&self, token: &'t str) -> Cow<'t, str> {
+        self.normalize_folding(token, self.case_insensitive)
+    }
+
+    /// Like `normalize`, but writes a token that normalizing changes to
+    /// `scratch` rather than a new `String`, for searches that look each
+    /// token up as it's read, without keeping it. Returns true if the token
+    /// was written to `scratch`, or false if it's unchanged.
+    ///
+    /// A token that only needs its case folded is folded straight into
+    /// `scratch`, so that no token allocates once it's grown to fit the
+    /// longest.
+    pub(crate) fn normalize_into(&self, token: &str, scratch: &mut String) -> bool {
+        let only_folds_case = self.form == Normalization::None
+            && !self.fold_width
+            && self.strip_symbols.is_empty()
+            && self.transform.is_none();
+        if only_folds_case {
+            return self.case_insensitive && word_split_trait::fold_case(token, scratch);
+        }
+        match self.normalize(token) {
+            Cow::Borrowed(normalized) if normalized == token => false,
+            normalized => {
+                scratch.clear();
+                scratch.push_str(&normalized);
+                true
+            }
+        }
+    }
+
+    /// Like `normalize`, but never folds case, for comparing a token with
+    /// the tokens of a case-sensitive pattern.
+    pub(crate) fn normalize_keeping_case<'t>(&self, token: &'t str) -> Cow<'t, str> {
+        self.normalize_folding(token, false)
+    }
+
+    fn normalize_folding<'t>(&self, token: &'t str, fold_case: bool) -> Cow<'t, str> {
+        let mut token = Cow::Borrowed(token);
+
+        // text is converted before it's tokenized, but tokens given directly
+        // have to be converted on their own
+        if self.form == Normalization::Nfc && !is_nfc(&token) {
+            token = token.nfc().collect::<String>().into();
+        }
+
+        if self.fold_width && token.chars().any(|c| fold_char_width(c).is_some()) {
+            token = token
+                .chars()
+                .map(|c| fold_char_width(c).unwrap_or(c))
+                .collect::<String>()
+                .into();
+        }
+
+        // only a token with words in it can have symbols joined into it, and
+        // the longest symbols go first, so none are left half stripped
+        if !self.strip_symbols.is_empty()
+            && !self.strips(&token)
+            && token.chars().any(is_word_character)
+            && self
+                .strip_symbols
+                .iter()
+                .any(|symbol| token.contains(&**symbol))
+        {
+            let mut stripped = token.into_owned();
+            for symbol in self.strip_symbols.iter() {
+                stripped = stripped.replace(&**symbol, "");
+            }
+            token = stripped.into();
+        }
+
+        if fold_case {
+            let mut folded = String::new();
+            if word_split_trait::fold_case(&token, &mut folded) {
+                token = folded.into();
+            }
+        }
+
+        if let Some(transform) = &self.transform {
+            token = match token {
+                Cow::Borrowed(token) => transform(token),
+                Cow::Owned(token) => transform(&token).into_owned().into(),
+            };
+        }
+
+        token
+    }
+
+    /// Returns whether a normalized token is fed to the automaton, rather
+    /// than dropped as a stop word.
+    #[inline]
+    pub(crate) fn keeps(&self, token: &str) -> bool {
+        (self.stop_words.is_empty() || !self.stop_words.contains(token)) && !self.strips(token)
+    }
+
+    /// Returns true if `token` is one of the symbols to strip.
+    #[inline]
+    fn strips(&self, token: &str) -> bool {
+        self.strip_symbols.iter().any(|symbol| **symbol == *token)
+    }
+
+    /// Returns true if this normalizes and drops tokens just as `other` does.
+    ///
+    /// Whether offsets count grapheme clusters isn't compared, as it doesn't
+    /// change the tokens, and token transforms are only the same if they're
+    /// the same function.
+    pub(crate) fn normalizes_like(&self, other: &Normalizer) -> bool {
+        let same_transform = match (&self.transform, &other.transform) {
+            (None, None) => true,
+            (Some(transform), Some(other)) => Arc::ptr_eq(transform, other),
+            _ => false,
+        };
+        self.fold_width == other.fold_width
+            && self.case_insensitive == other.case_insensitive
+            && self.form == other.form
+            && self.stop_words == other.stop_words
+            && self.strip_symbols == other.strip_symbols
+            && same_transform
+    }
+
+    /// Returns a counter of the grapheme clusters in `text`, if the char
+    /// offsets of matches count grapheme clusters.
+    pub(crate) fn grapheme_counter<'t>(&self, text: &'t str) -> Option<GraphemeCounter<'t>> {
+        if self.graphemes {
+            Some(GraphemeCounter::new(text))
+        } else {
+            None
+        }
+    }
+
+    /// Converts a whole text to this normalizer's normalization form, ready
+    /// to be tokenized.
+    pub(crate) fn normalize_text<'t>(&self, text: &'t str) -> NormalizedText<'t> {
+        match self.form {
+            Normalization::Nfc if !is_nfc(text) => nfc_with_offsets(text),
+            _ => NormalizedText {
+                text: Cow::Borrowed(text),
+                segments: Vec::new(),
+            },
+        }
+    }
+}
+
+impl fmt::Debug for Normalizer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Normalizer")
+            .field("fold_width", &self.fold_width)
+            .field("case_insensitive", &self.case_insensitive)
+            .field("form", &self.form)
+            .field("graphemes", &self.graphemes)
+            .field("stop_words", &self.stop_words)
+            .field("strip_symbols", &self.strip_symbols)
+            .field("transform", &self.transform.is_some())
+            .finish()
+    }
+}
+
+/// Converts spans of bytes in a text to the spans of grapheme clusters that
+/// cover them.
+///
+/// The text is segmented as the spans are converted, so they must be given
+/// in order, with no span starting before the end of the one before it.
+pub(crate) struct GraphemeCounter<'t> {
+    text: &'t str,
+    graphemes: Peekable<GraphemeIndices<'t>>,
+    /// The number of grapheme clusters starting before the last offset
+    /// converted.
+    count: u32,
+    /// The byte offset of the last of those clusters.
+    last_start: usize,
+}
+
+impl<'t> GraphemeCounter<'t> {
+    fn new(text: &'t str) -> GraphemeCounter<'t> {
+        GraphemeCounter {
+            text,
+            graphemes: text.grapheme_indices(true).peekable(),
+            count: 0,
+            last_start: 0,
+        }
+    }
+
+    /// Returns the span of grapheme clusters covering a span of bytes.
+    ///
+    /// A span starting or ending inside a cluster is widened to cover it.
+    pub(crate) fn span(&mut self, (byte_start, byte_end): (usize, usize)) -> (u32, u32) {
+        let (start, _) = self.cluster_at(byte_start);
+        (start, self.count_before(byte_end))
+    }
+
+    /// Returns the index and the byte offset of the grapheme cluster that
+    /// `byte_idx` lies in, or of the end of the text.
+    pub(crate) fn cluster_at(&mut self, byte_idx: usize) -> (u32, usize) {
+        let count = self.count_before(byte_idx);
+        let on_boundary = match self.graphemes.peek() {
+            Some(&(idx, _)) => idx == byte_idx,
+            None => byte_idx >= self.text.len(),
+        };
+        if on_boundary {
+            (count, byte_idx)
+        } else {
+            (count - 1, self.last_start)
+        }
+    }
+
+    fn count_before(&mut self, byte_idx: usize) -> u32 {
+        while let Some((idx, _)) = self.graphemes.next_if(|&(idx, _)| idx < byte_idx) {
+            self.count += 1;
+            self.last_start = idx;
+        }
+        self.count
+    }
+}
+
+/// A run of a normalized text, along with the char and byte offsets it
+/// starts at in both the normalized and the original text.
+#[derive(Clone, Copy, Debug)]
+struct Segment {
+    char_start: u32,
+    byte_start: usize,
+    orig_char_start: u32,
+    orig_byte_start: usize,
+    /// Whether normalizing changed this run, in which case offsets inside
+    /// it have no counterpart in the original text.
+    changed: bool,
+}
+
+/// A text converted to a normalization form, which can map offsets in the
+/// normalized text back to the original.
+pub(crate) struct NormalizedText<'t> {
+    text: Cow<'t, str>,
+    /// The runs of the text, ending with an empty run at the end of the text.
+    /// This is empty if normalizing didn't change the text.
+    segments: Vec<Segment>,
+}
+
+impl<'t> NormalizedText<'t> {
+    pub(crate) fn as_str(&self) -> &str {
+        &self.text
+    }
+
+    /// Returns the original text if normalizing didn't change it, so that
+    /// slices of the normalized text can be taken from the original.
+    pub(crate) fn unchanged(&self) -> Option<&'t str> {
+        match self.text {
+            Cow::Borrowed(text) => Some(text),
+            Cow::Owned(_) => None,
+        }
+    }
+
+    /// Maps a span of chars and the span of bytes it covers in the normalized
+    /// text to the spans they came from in the original text.
+    ///
+    /// A span that starts or ends inside a run that normalizing changed is
+    /// widened to cover that whole run.
+    pub(crate) fn to_original(
+        &self,
+        (char_start, char_end): (u32, u32),
+        (byte_start, byte_end): (usize, usize),
+    ) -> ((u32, u32), (usize, usize)) {
+        if self.segments.is_empty() {
+            return ((char_start, char_end), (byte_start, byte_end));
+        }
+
+        // the run containing the start, and the run containing the last
+        // byte before the end
+        let first = self
+            .segments
+            .partition_point(|s| s.byte_start <= byte_start)
+            - 1;
+        let last = self
+            .segments
+            .partition_point(|s| s.byte_start < byte_end)
+            .saturating_sub(1);
+        let (first_seg, last_seg) = (self.segments[first], self.segments[last]);
+
+        let (orig_char_start, orig_byte_start) = if first_seg.changed {
+            (first_seg.orig_char_start, first_seg.orig_byte_start)
+        } else {
+            (
+                first_seg.orig_char_start + (char_start - first_seg.char_start),
+                first_seg.orig_byte_start + (byte_start - first_seg.byte_start),
+            )
+        };
+
+        let (orig_char_end, orig_byte_end) = if last_seg.changed {
+            let next = self.segments[last + 1];
+            (next.orig_char_start, next.orig_byte_start)
+        } else {
+            (
+                last_seg.orig_char_start + (char_end - last_seg.char_start),
+                last_seg.orig_byte_start + (byte_end - last_seg.byte_start),
+            )
+        };
+
+        (
+            (orig_char_start, orig_char_end),
+            (orig_byte_start, orig_byte_end),
+        )
+    }
+}
+
+/// Converts `text` to NFC, keeping track of where each part of the result
+/// came from.
+///
+/// The text is split into runs that can be normalized independently, each
+/// starting with a character that doesn't combine with anything before it,
+/// and the result is the concatenation of the normalized runs.
+fn nfc_with_offsets(text: &str) -> NormalizedText<'_> {
+    let mut normalized = String::with_capacity(text.len());
+    let mut segments: Vec<Segment> = Vec::new();
+    let mut char_len = 0;
+    let mut orig_char_len = 0;
+
+    let mut push_run = |run: &str, orig_byte_start: usize, segments: &mut Vec<Segment>| {
+        let run_nfc: String = run.nfc().collect();
+        let changed = run_nfc != run;
+        let segment = Segment {
+            char_start: char_len,
+            byte_start: normalized.len(),
+            orig_char_start: orig_char_len,
+            orig_byte_start,
+            changed,
+        };
+
+        // unchanged runs are merged, so that only the changes are recorded
+        match segments.last() {
+            Some(last) if !last.changed && !changed => {}
+            _ => segments.push(segment),
+        }
+
+        char_len += run_nfc.chars().count() as u32;
+        orig_char_len += run.chars().count() as u32;
+        normalized.push_str(&run_nfc);
+    };
+
+    let mut run_start = 0;
+    for (idx, c) in text.char_indices().skip(1) {
+        if canonical_combining_class(c) != 0 {
+            continue;
+        }
+
+        // a starter can still compose with the end of the run before it, as
+        // with hangul jamo
+        let composes = text[run_start..idx]
+            .nfc()
+            .last()
+            .is_some_and(|last| compose(last, c).is_some());
+        if !composes {
+            push_run(&text[run_start..idx], run_start, &mut segments);
+            run_start = idx;
+        }
+    }
+    push_run(&text[run_start..], run_start, &mut segments);
+
+    segments.push(Segment {
+        char_start: char_len,
+        byte_start: normalized.len(),
+        orig_char_start: orig_char_len,
+        orig_byte_start: text.len(),
+        changed: false,
+    });
+
+    NormalizedText {
+        text: Cow::Owned(normalized),
+        segments,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_width_folding() {
+        let normalizer = Normalizer {
+            fold_width: true,
+            ..Normalizer::default()
+        };
+
+        assert_eq!(normalizer.normalize("ＡＢＣ１２３"), "ABC123");
+        assert_eq!(normalizer.normalize("ｶﾀｶﾅ"), "カタカナ");
+        assert_eq!(normalizer.normalize("abc"), "abc");
+        assert!(match normalizer.normalize("abc") {
+            Cow::Borrowed(_) => true,
+            Cow::Owned(_) => false,
+        });
+
+        let mut scratch = String::new();
+        assert!(normalizer.normalize_into("ｶﾀｶﾅ", &mut scratch));
+        assert_eq!(scratch, "カタカナ");
+        assert!(!normalizer.normalize_into("abc", &mut scratch));
+    }
+
+    #[test]
+    fn check_case_folding() {
+        let normalizer = Normalizer {
+            case_insensitive: true,
+            ..Normalizer::default()
+        };
+
+        assert_eq!(normalizer.normalize("FoO"), "foo");
+        assert_eq!(normalizer.normalize("ΣΟΦΊΑ"), "σοφία");
+        assert_eq!(normalizer.normalize("İ"), "i\u{307}");
+        assert!(match normalizer.normalize("foo") {
+            Cow::Borrowed(_) => true,
+            Cow::Owned(_) => false,
+        });
+
+        // a token only needing its case folded reuses the scratch buffer
+        let mut scratch = String::with_capacity(16);
+        let capacity = scratch.capacity();
+        assert!(normalizer.normalize_into("FoO", &mut scratch));
+        assert_eq!(scratch, "foo");
+        assert!(normalizer.normalize_into("BAR", &mut scratch));
+        assert_eq!(scratch, "bar");
+        assert_eq!(scratch.capacity(), capacity);
+        assert!(!normalizer.normalize_into("baz", &mut scratch));
+        assert_eq!(scratch, "bar");
+    }
+
+    #[test]
+    fn check_nfc_offsets() {
+        let normalizer = Normalizer {
+            form: Normalization::Nfc,
+            ..Normalizer::default()
+        };
+
+        let text = normalizer.normalize_text("ab e\u{301}e\u{301} c");
+        assert_eq!(text.as_str(), "ab éé c");
+        // "ab", "éé", and "c" map back to their decomposed spans
+        assert_eq!(text.to_original((0, 2), (0, 2)), ((0, 2), (0, 2)));
+        assert_eq!(text.to_original((3, 5), (3, 7)), ((3, 7), (3, 9)));
+        assert_eq!(text.to_original((6, 7), (8, 9)), ((8, 9), (10, 11)));
+        // a span ending inside a changed run covers all of it
+        assert_eq!(text.to_original((3, 4), (3, 5)), ((3, 5), (3, 6)));
+
+        assert!(normalizer.normalize_text("café").unchanged().is_some());
+        assert_eq!(normalizer.normalize("cafe\u{301}"), "café");
+    }
+}
@@ -0,0 +1,47 @@
+use alloc::{string::String, vec::Vec};
+
+/// The automaton of a finder, as plain data, returned by
+/// `SimpleFinder::into_parts` and restored with
+/// `SimpleFinderBuilder::try_build_from_parts`.
+///
+/// This lets finders be stored in any format, such as with `serde` when the
+/// `serde` feature is enabled, without building them again. The parts are
+/// checked when a finder is restored from them, so that a corrupted or
+/// hand-made automaton is an error rather than a search that never ends.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AutomatonParts {
+    /// The states of the automaton, indexed by their identifiers.
+    ///
+    /// State `0` is never entered, and is what missing transitions lead
+    /// to. State `1` is the dead state, which searches stop in.
+    pub states: Vec<StateParts>,
+    /// The identifier of the start state, which is always `2`.
+    pub start_id: usize,
+    /// The number of patterns, whose identifiers are below it.
+    pub pattern_count: usize,
+    /// The number of tokens in the longest pattern.
+    pub max_pattern_len: usize,
+    /// The normalized tokens of the patterns, indexed by the token
+    /// identifiers labelling transitions.
+    pub tokens: Vec<String>,
+}
+
+/// A state of an `AutomatonParts`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct StateParts {
+    /// The state to try next when this one has no transition on a token,
+    /// which must be shallower than it.
+    pub fail: usize,
+    /// The number of tokens from the start state to this one.
+    pub depth: usize,
+    /// The transitions out of this state, as pairs of a token identifier
+    /// and the state it leads to, sorted by token. Each leads to a state one
+    /// deeper than this one, or to the start or dead state.
+    pub transitions: Vec<(usize, usize)>,
+    /// The matches ending in this state, as pairs of a pattern identifier
+    /// and the number of tokens in the pattern, which is at least one and
+    /// at most the depth of this state.
+    pub matches: Vec<(usize, usize)>,
+}
@@ -0,0 +1,48 @@
+/// A cheap test of whether a token could be the first token of a pattern,
+/// used to skip over tokens in the start state without looking them up.
+///
+/// This records the first byte and the length of every first token, so it
+/// never rejects a first token, but may accept tokens that aren't one.
+#[derive(Clone, Debug)]
+pub(crate) struct Prefilter {
+    first_bytes: [u64; 4],
+    /// Bit `n` is set if a first token is `n` bytes long, with every length
+    /// of at least 63 bytes sharing the last bit.
+    lengths: u64,
+}
+
+impl Prefilter {
+    pub(crate) fn new<'a, I>(first_tokens: I) -> Prefilter
+    where
+        I: IntoIterator<Item = &'a [u8]>,
+    {
+        let mut prefilter = Prefilter {
+            first_bytes: [0; 4],
+            lengths: 0,
+        };
+        for token in first_tokens {
+            if let Some(&b) = token.first() {
+                prefilter.first_bytes[b as usize / 64] |= 1 << (b % 64);
+            }
+            prefilter.lengths |= length_bit(token);
+        }
+        prefilter
+    }
+
+    /// Returns false if the bytes of the given token are certainly not the first token of
+    /// any pattern.
+    #[inline(always)]
+    pub(crate) fn may_start(&self, token: &[u8]) -> bool {
+        if self.lengths & length_bit(token) == 0 {
+            return false;
+        }
+        match token.first() {
+            Some(&b) => self.first_bytes[b as usize / 64] & (1 << (b % 64)) != 0,
+            None => true,
+        }
+    }
+}
+
+fn length_bit(token: &[u8]) -> u64 {
+    1 << token.len().min(63)
+}
@@ -0,0 +1,63 @@
+use crate::{ahocorasick::AhoCorasick, collections::HashMap, Match};
+
+/// A finder that searches a haystack from its end, created by
+/// `SimpleFinderBuilder::build_reverse`.
+///
+/// Its automaton is built from the patterns with their tokens reversed, so
+/// matches are found by the token they start at, and reported in descending
+/// order of their start. This suits looking for phrases at the end of a
+/// document, such as a signature, where a search can stop at the first
+/// match it reports.
+///
+/// The offsets of the matches are the same as a forward search would report.
+/// With a leftmost `MatchKind`, matches are chosen from the right instead:
+/// the match ending last is reported, picking the longest or the earliest
+/// added pattern of those ending at the same token.
+pub struct ReverseFinder<D> {
+    pub(crate) aho: AhoCorasick,
+    pub(crate) data: HashMap<usize, D>,
+    pub(crate) overlapping: bool,
+}
+
+impl<D> ReverseFinder<D> {
+    /// Find all matches in `haystack`, starting from its end.
+    ///
+    /// Unless the finder was built with `SimpleFinderBuilder::overlapping`
+    /// disabled, or with a leftmost `MatchKind`, this reports overlapping
+    /// matches.
+    pub fn find_all<'a: 'b, 'b>(
+        &'a self,
+        haystack: &'b str,
+    ) -> impl Iterator<Item = (Match, &'a D)> + 'b {
+        self.aho
+            .find_reverse_iter(haystack, self.overlapping)
+            .filter_map(move |m| {
+                let data = self.data.get(&m.pattern)?;
+
+                Some((m, data))
+            })
+    }
+
+    /// Returns the number of patterns that the finder was built from.
+    pub fn pattern_count(&self) -> usize {
+        self.aho.pattern_count()
+    }
+
+    /// Returns the number of tokens in the longest pattern.
+    pub fn max_pattern_len(&self) -> usize {
+        self.aho.max_pattern_len()
+    }
+
+    /// Returns the approximate amount of heap memory used by the finder's
+    /// automaton, in bytes.
+    pub fn heap_bytes(&self) -> usize {
+        self.aho.heap_bytes()
+    }
+
+    /// Frees the spare capacity left in the finder, as
+    /// `SimpleFinder::shrink_to_fit` does.
+    pub fn shrink_to_fit(&mut self) {
+        self.aho.shrink_to_fit();
+        self.data.shrink_to_fit();
+    }
+}
@@ -0,0 +1,121 @@
+use alloc::{borrow::ToOwned, string::String, vec::Vec};
+
+use crate::error::SerializeError;
+
+/// Written at the start of every serialized finder, followed by the format
+/// version.
+const MAGIC: &[u8; 8] = b"lacbd\0nf";
+const VERSION: u64 = 1;
+
+/// Writes a finder in a simple little-endian binary format, where integers
+/// are written as 8 bytes regardless of their type, and strings as their
+/// length followed by their bytes.
+pub(crate) struct Writer {
+    bytes: Vec<u8>,
+}
+
+impl Writer {
+    pub(crate) fn new() -> Writer {
+        let mut writer = Writer { bytes: Vec::new() };
+        writer.bytes.extend_from_slice(MAGIC);
+        writer.write_usize(VERSION as usize);
+        writer
+    }
+
+    pub(crate) fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+
+    pub(crate) fn write_u8(&mut self, n: u8) {
+        self.bytes.push(n);
+    }
+
+    pub(crate) fn write_bool(&mut self, b: bool) {
+        self.write_u8(b as u8);
+    }
+
+    pub(crate) fn write_usize(&mut self, n: usize) {
+        self.bytes.extend_from_slice(&(n as u64).to_le_bytes());
+    }
+
+    pub(crate) fn write_str(&mut self, s: &str) {
+        self.write_usize(s.len());
+        self.bytes.extend_from_slice(s.as_bytes());
+    }
+}
+
+/// Reads a finder written by a `Writer`, validating everything read, as the
+/// bytes may come from anywhere.
+pub(crate) struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    /// Starts reading a serialized finder, checking that it was written in
+    /// this version of the format.
+    pub(crate) fn new(bytes: &'a [u8]) -> Result<Reader<'a>, SerializeError> {
+        let mut reader = Reader { bytes };
+        if reader.take(MAGIC.len())? != MAGIC || reader.read_usize()? != VERSION as usize {
+            return Err(SerializeError::InvalidFormat);
+        }
+        Ok(reader)
+    }
+
+    /// Checks that every byte has been read.
+    pub(crate) fn finish(self) -> Result<(), SerializeError> {
+        if self.bytes.is_empty() {
+            Ok(())
+        } else {
+            Err(SerializeError::InvalidFormat)
+        }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], SerializeError> {
+        if self.bytes.len() < len {
+            return Err(SerializeError::InvalidFormat);
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    pub(crate) fn read_u8(&mut self) -> Result<u8, SerializeError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub(crate) fn read_bool(&mut self) -> Result<bool, SerializeError> {
+        match self.read_u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(SerializeError::InvalidFormat),
+        }
+    }
+
+    pub(crate) fn read_usize(&mut self) -> Result<usize, SerializeError> {
+        let mut buf = [0; 8];
+        buf.copy_from_slice(self.take(8)?);
+        let n = u64::from_le_bytes(buf);
+        if n > usize::MAX as u64 {
+            return Err(SerializeError::InvalidFormat);
+        }
+        Ok(n as usize)
+    }
+
+    /// Reads a length, checking that at least `min_item_size` bytes remain
+    /// for each item, so that a corrupt length can't cause a huge allocation.
+    pub(crate) fn read_len(&mut self, min_item_size: usize) -> Result<usize, SerializeError> {
+        let len = self.read_usize()?;
+        if len.saturating_mul(min_item_size) > self.bytes.len() {
+            return Err(SerializeError::InvalidFormat);
+        }
+        Ok(len)
+    }
+
+    pub(crate) fn read_str(&mut self) -> Result<String, SerializeError> {
+        let len = self.read_len(1)?;
+        let bytes = self.take(len)?;
+        core::str::from_utf8(bytes)
+            .map(str::to_owned)
+            .map_err(|_| SerializeError::InvalidFormat)
+    }
+}
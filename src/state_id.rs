@@ -1,4 +1,4 @@
-use std::hash::Hash;
+use core::hash::Hash;
 
 // pretty much copied from
 // https://github.com/BurntSushi/aho-corasick/blob/f166d2e63d0d7a41339b5e7f8c939dd4196f92f0/src/state_id.rs
@@ -18,8 +18,12 @@ pub(crate) fn fail_id<S: StateID>() -> S {
     S::from_usize(0)
 }
 
+pub(crate) fn dead_id<S: StateID>() -> S {
+    S::from_usize(1)
+}
+
 mod private {
-    pub(crate) trait Sealed {}
+    pub trait Sealed {}
     impl Sealed for u8 {}
     impl Sealed for u16 {}
     impl Sealed for u32 {}
@@ -27,10 +31,19 @@ mod private {
     impl Sealed for usize {}
 }
 
-// for explanation of invariants, check:
-// https://github.com/BurntSushi/aho-corasick/blob/f166d2e63d0d7a41339b5e7f8c939dd4196f92f0/src/state_id.rs#L60
-pub(crate) unsafe trait StateID:
-    private::Sealed + Clone + Copy + Eq + Hash + PartialEq + PartialOrd + Ord
+/// The representation of a state identifier in an automaton.
+///
+/// This is implemented for `u8`, `u16`, `u32`, `u64` and `usize`. Narrower
+/// representations use less memory, but limit the number of states, and so
+/// the number and length of patterns, that an automaton can have.
+///
+/// # Safety
+///
+/// This trait is sealed and cannot be implemented outside of this crate. For
+/// an explanation of the invariants implementations uphold, check:
+/// https://github.com/BurntSushi/aho-corasick/blob/f166d2e63d0d7a41339b5e7f8c939dd4196f92f0/src/state_id.rs#L60
+pub unsafe trait StateID:
+    private::Sealed + Clone + Copy + Eq + Hash + PartialEq + PartialOrd + Ord + Send + Sync
 {
     fn from_usize(n: usize) -> Self;
 
@@ -52,7 +65,7 @@ unsafe impl StateID for usize {
 
     #[inline]
     fn max_id() -> usize {
-        ::std::usize::MAX
+        ::core::usize::MAX
     }
 }
 
@@ -69,7 +82,7 @@ unsafe impl StateID for u8 {
 
     #[inline]
     fn max_id() -> usize {
-        ::std::u8::MAX as usize
+        ::core::u8::MAX as usize
     }
 }
 
@@ -86,7 +99,7 @@ unsafe impl StateID for u16 {
 
     #[inline]
     fn max_id() -> usize {
-        ::std::u16::MAX as usize
+        ::core::u16::MAX as usize
     }
 }
 
@@ -104,7 +117,7 @@ unsafe impl StateID for u32 {
 
     #[inline]
     fn max_id() -> usize {
-        ::std::u32::MAX as usize
+        ::core::u32::MAX as usize
     }
 }
 
@@ -122,6 +135,6 @@ unsafe impl StateID for u64 {
 
     #[inline]
     fn max_id() -> usize {
-        ::std::u64::MAX as usize
+        ::core::u64::MAX as usize
     }
 }
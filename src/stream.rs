@@ -0,0 +1,156 @@
+use alloc::{borrow::ToOwned, string::String, vec::Vec};
+
+use crate::{ahocorasick::remap_offsets, automaton::Automaton, Match, SimpleFinder};
+
+/// A search over a haystack that arrives as a stream of chunks, created by
+/// `SimpleFinder::stream`.
+///
+/// The state of the automaton is carried from one chunk to the next, so each
+/// token is only searched once, and memory use doesn't grow with the length
+/// of the stream. Chunks may be split anywhere, including in the middle of a
+/// word; the last token of each chunk is buffered until the next chunk shows
+/// whether it continues.
+///
+/// Matches are reported as the token that completes them is consumed, with
+/// overlapping matches included. Their offsets are char and byte offsets from
+/// the start of the stream, not the start of the chunk.
+pub struct StreamFinder<'a, D> {
+    finder: &'a SimpleFinder<D>,
+    state_id: usize,
+    /// Text that has not yet been searched, starting with the buffered token.
+    pending: String,
+    /// The char offset of the start of `pending` in the stream, counted in
+    /// grapheme clusters if the finder counts them.
+    pending_offset: u32,
+    /// The byte offset of the start of `pending` in the stream.
+    pending_byte_offset: usize,
+    /// The length of the start of `pending` that has already been searched,
+    /// which is kept so that a grapheme cluster continued by the next chunk
+    /// is counted from its start.
+    pending_skip: usize,
+    /// The spans of the last few tokens searched, which is as far back as a
+    /// match ending in the next chunk can start.
+    context_spans: Vec<(u32, u32)>,
+    context_byte_spans: Vec<(usize, usize)>,
+    /// The number of tokens in the stream before those in `context_spans`.
+    context_token_offset: usize,
+}
+
+impl<'a, D> StreamFinder<'a, D> {
+    pub(crate) fn new(finder: &'a SimpleFinder<D>) -> Self {
+        StreamFinder {
+            finder,
+            state_id: finder.aho.imp.start_state(),
+            pending: String::new(),
+            pending_offset: 0,
+            pending_byte_offset: 0,
+            pending_skip: 0,
+            context_spans: Vec::new(),
+            context_byte_spans: Vec::new(),
+            context_token_offset: 0,
+        }
+    }
+
+    /// Feed the next chunk of the stream, returning the matches that it
+    /// completes.
+    pub fn push(&mut self, chunk: &str) -> Vec<(Match, &'a D)> {
+        self.pending.push_str(chunk);
+        self.search(false)
+    }
+
+    /// Signal the end of the stream, returning any matches ending in the
+    /// final token.
+    pub fn finish(mut self) -> Vec<(Match, &'a D)> {
+        self.search(true)
+    }
+
+    fn search(&mut self, finish: bool) -> Vec<(Match, &'a D)> {
+        let text = core::mem::take(&mut self.pending);
+        let skip = core::mem::take(&mut self.pending_skip);
+        let mut tokens: Vec<_> = self
+            .finder
+            .aho
+            .tokenizer()
+            .tokenize_spans(&text)
+            .filter(|&(_, byte_idx, _)| byte_idx >= skip)
+            .collect();
+
+        // the last token may be continued by the next chunk, so keep it
+        // around until we know where it ends
+        let mut held = if finish { None } else { tokens.pop() };
+        // a word followed by a joiner, such as an apostrophe, may yet be
+        // joined to the word after it
+        if let (Some((_, _, joiner)), Some(&(_, _, word))) = (held, tokens.last()) {
+            if self.finder.aho.tokenizer().may_join(word, joiner) {
+                held = tokens.pop();
+            }
+        }
+
+        let mut spans = core::mem::take(&mut self.context_spans);
+        let mut byte_spans = core::mem::take(&mut self.context_byte_spans);
+        let fsm = &self.finder.aho.imp;
+        let mut matches = Vec::new();
+        let mut graphemes = self.finder.aho.normalizer().grapheme_counter(&text);
+        let mut scratch = String::new();
+        for &(idx, byte_idx, s) in &tokens {
+            let token = if self.finder.aho.normalizer().normalize_into(s, &mut scratch) {
+                scratch.as_str()
+            } else {
+                s
+            };
+            if !self.finder.aho.normalizer().keeps(token) {
+                continue;
+            }
+            let token = fsm.token_id(token);
+            self.state_id = unsafe { fsm.next_state_unchecked_no_fail(self.state_id, token) };
+
+            let (start, end) = match &mut graphemes {
+                Some(graphemes) => graphemes.span((byte_idx, byte_idx + s.len())),
+                None => (idx, idx + s.chars().count() as u32),
+            };
+            spans.push((self.pending_offset + start, self.pending_offset + end));
+            let byte_start = self.pending_byte_offset + byte_idx;
+            byte_spans.push((byte_start, byte_start + s.len()));
+
+            // every match of the state is reported before moving on to the
+            // next token, so there's no match index to carry between chunks
+            let end = spans.len();
+            for match_index in 0..fsm.match_count(self.state_id) {
+                let m = match fsm.get_match(self.state_id, match_index, end) {
+                    Some(m) => m,
+                    None => break,
+                };
+                if let Some(data) = self.finder.data.get(&m.pattern()) {
+                    let mut m = remap_offsets(&spans, &byte_spans, m);
+                    m.token_end += self.context_token_offset;
+                    matches.push((m, data));
+                }
+            }
+        }
+
+        let keep = self.finder.aho.max_pattern_len().saturating_sub(1);
+        let skip = spans.len().saturating_sub(keep);
+        self.context_spans = spans.split_off(skip);
+        self.context_byte_spans = byte_spans.split_off(skip);
+        self.context_token_offset += skip;
+
+        // the text from `keep` on is searched again along with the next chunk
+        let (keep, keep_offset) = match (held, &mut graphemes) {
+            (held, Some(graphemes)) => {
+                // the grapheme cluster that the held token starts in may be
+                // continued by the next chunk too, so it's kept whole
+                let split = held.map_or(text.len(), |(_, byte_idx, _)| byte_idx);
+                let (count, start) = graphemes.cluster_at(split.min(text.len().saturating_sub(1)));
+                self.pending_skip = split - start;
+                (start, count)
+            }
+            (Some((idx, byte_idx, _)), None) => (byte_idx, idx),
+            (None, None) => (text.len(), text.chars().count() as u32),
+        };
+        self.pending = text[keep..].to_owned();
+        self.pending_offset += keep_offset;
+        self.pending_byte_offset += keep;
+
+        matches
+    }
+}
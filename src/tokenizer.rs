@@ -0,0 +1,299 @@
+use alloc::{boxed::Box, sync::Arc};
+use core::{fmt, iter::Peekable};
+
+use crate::word_split_trait::{is_word_character, AsciiWordsAndSymsIndices, WordBoundarySplitter};
+
+/// Decides whether a character is part of a word, for the `DefaultTokenizer`.
+pub(crate) type WordClassifier = Arc<dyn Fn(char) -> bool + Send + Sync>;
+
+/// Splits text into the tokens that patterns are matched against.
+///
+/// A finder uses the same tokenizer for its patterns and for every haystack
+/// it searches, as a pattern can only match where the haystack splits into
+/// the same tokens.
+pub trait Tokenizer: Send + Sync {
+    /// Returns the tokens of `text`, each paired with the char offset in
+    /// `text` that it starts at.
+    ///
+    /// Each token must be a subslice of `text`, and the tokens must be given
+    /// in order without overlapping, as match offsets are computed from
+    /// where the tokens lie in the haystack. Tokens that aren't subslices of
+    /// `text` are skipped.
+    fn tokenize<'t>(&self, text: &'t str) -> Box<dyn Iterator<Item = (u32, &'t str)> + 't>;
+}
+
+/// The tokenizer used unless another is given to
+/// `SimpleFinderBuilder::tokenizer`.
+///
+/// This splits text into runs of word characters and runs of other
+/// non-whitespace characters, discarding whitespace, so `"foo, bar"` becomes
+/// `"foo"`, `","`, and `"bar"`. Word characters are those matched by `\w`,
+/// as decided by `is_word_character`, unless a custom classifier is given to
+/// `SimpleFinderBuilder::word_characters`.
+///
+/// Text that is entirely ASCII is split by classifying its bytes directly,
+/// which is much cheaper than the unicode path.
+#[derive(Clone, Default)]
+pub struct DefaultTokenizer {
+    pub(crate) keep_whitespace: bool,
+    pub(crate) is_word: Option<WordClassifier>,
+    pub(crate) join_apostrophes: bool,
+    pub(crate) join_hyphens: bool,
+    pub(crate) join_digit_groups: bool,
+    /// Whether tokens that are neither words nor whitespace are dropped, as
+    /// whitespace is unless it's kept.
+    pub(crate) skip_symbols: bool,
+    /// Symbols that join the words either side of them, and are then
+    /// stripped from the joined token by the `Normalizer`.
+    pub(crate) strip_symbols: Arc<[Box<str>]>,
+}
+
+impl Tokenizer for DefaultTokenizer {
+    fn tokenize<'t>(&self, text: &'t str) -> Box<dyn Iterator<Item = (u32, &'t str)> + 't> {
+        let mut tokens = self.split(text);
+        if self.join_apostrophes
+            || self.join_hyphens
+            || self.join_digit_groups
+            || !self.strip_symbols.is_empty()
+        {
+            tokens = Box::new(JoinWords {
+                text,
+                tokens: tokens.peekable(),
+                pending: None,
+                tokenizer: self.clone(),
+            });
+        }
+        // symbols are only dropped once they've had the chance to join words
+        if self.skip_symbols {
+            let tokenizer = self.clone();
+            tokens = Box::new(tokens.filter(move |&(_, token)| !tokenizer.is_symbol(token)));
+        }
+        tokens
+    }
+}
+
+impl DefaultTokenizer {
+    fn is_word(&self, token: &str) -> bool {
+        token.chars().next().is_some_and(|c| match &self.is_word {
+            Some(is_word) => is_word(c),
+            None => is_word_character(c),
+        })
+    }
+
+    fn is_symbol(&self, token: &str) -> bool {
+        !self.is_word(token) && !token.starts_with(char::is_whitespace)
+    }
+
+    /// Returns true if `token` may join the words either side of it,
+    /// depending on what those words are.
+    fn is_joiner(&self, token: &str) -> bool {
+        if self.strips(token) {
+            return true;
+        }
+        match token {
+            "'" | "\u{2019}" => self.join_apostrophes,
+            "-" | "\u{2010}" => self.join_hyphens,
+            "," | "." => self.join_digit_groups,
+            _ => false,
+        }
+    }
+
+    /// Returns true if the joiner `joiner` joins the words `left` and
+    /// `right`.
+    fn joins(&self, left: &str, joiner: &str, right: &str) -> bool {
+        if self.strips(joiner) {
+            return self.is_word(right);
+        }
+        match joiner {
+            "," | "." => is_digits(left) && is_digits(right),
+            _ => self.is_word(right),
+        }
+    }
+
+    fn strips(&self, token: &str) -> bool {
+        self.strip_symbols.iter().any(|symbol| **symbol == *token)
+    }
+
+    /// Splits text between runs of word characters, whitespace, and other
+    /// characters.
+    fn split<'t>(&self, text: &'t str) -> Box<dyn Iterator<Item = (u32, &'t str)> + 't> {
+        match (&self.is_word, self.keep_whitespace) {
+            // ASCII text can be split bytewise, skipping the unicode tables
+            (None, keep_whitespace) if text.is_ascii() => {
+                Box::new(AsciiWordsAndSymsIndices::new(text, keep_whitespace))
+            }
+            (None, false) => Box::new(text.unicode_words_and_syms_indices()),
+            (None, true) => Box::new(text.unicode_words_syms_and_spaces_indices()),
+            (Some(is_word), false) => {
+                let is_word = is_word.clone();
+                Box::new(text.unicode_words_and_syms_indices_by(move |c| is_word(c)))
+            }
+            (Some(is_word), true) => {
+                let is_word = is_word.clone();
+                Box::new(text.unicode_words_syms_and_spaces_indices_by(move |c| is_word(c)))
+            }
+        }
+    }
+}
+
+impl fmt::Debug for DefaultTokenizer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DefaultTokenizer")
+            .field("keep_whitespace", &self.keep_whitespace)
+            .field("custom_word_characters", &self.is_word.is_some())
+            .field("join_apostrophes", &self.join_apostrophes)
+            .field("join_hyphens", &self.join_hyphens)
+            .field("join_digit_groups", &self.join_digit_groups)
+            .field("skip_symbols", &self.skip_symbols)
+            .field("strip_symbols", &self.strip_symbols)
+            .finish()
+    }
+}
+
+/// Joins words separated by a lone apostrophe or hyphen into a single token,
+/// so that `"don't"` is one token rather than `"don"`, `"'"`, and `"t"`, and
+/// likewise digits separated by a comma or a decimal point, and words
+/// separated by a symbol to strip.
+///
+/// Only a joiner directly between two words is joined, so those leading or
+/// trailing a word, or next to whitespace, are tokens of their own.
+struct JoinWords<'t> {
+    text: &'t str,
+    tokens: Peekable<Box<dyn Iterator<Item = (u32, &'t str)> + 't>>,
+    /// A token taken from `tokens` that couldn't be joined, to be yielded
+    /// next.
+    pending: Option<(u32, &'t str)>,
+    tokenizer: DefaultTokenizer,
+}
+
+impl<'t> JoinWords<'t> {
+    /// Returns the text spanning from the start of `left` to the end of
+    /// `right`, which must both be tokens of the text, with `left` first.
+    fn join(&self, left: &str, right: &str) -> &'t str {
+        let start = left.as_ptr() as usize - self.text.as_ptr() as usize;
+        let end = right.as_ptr() as usize + right.len() - self.text.as_ptr() as usize;
+        &self.text[start..end]
+    }
+}
+
+impl<'t> Iterator for JoinWords<'t> {
+    type Item = (u32, &'t str);
+
+    fn next(&mut self) -> Option<(u32, &'t str)> {
+        let (idx, mut token) = self.pending.take().or_else(|| self.tokens.next())?;
+        if !self.tokenizer.is_word(token) {
+            return Some((idx, token));
+        }
+
+        // the last word joined into `token`, which decides what it can be
+        // joined to next
+        let mut last = token;
+        while let Some(&(_, joiner)) = self.tokens.peek() {
+            if !adjacent(last, joiner) || !self.tokenizer.is_joiner(joiner) {
+                break;
+            }
+            let joiner = self.tokens.next().unwrap();
+            match self.tokens.peek().copied() {
+                Some((_, word))
+                    if adjacent(joiner.1, word) && self.tokenizer.joins(last, joiner.1, word) =>
+                {
+                    token = self.join(token, word);
+                    last = word;
+                    self.tokens.next();
+                }
+                _ => {
+                    self.pending = Some(joiner);
+                    break;
+                }
+            }
+        }
+        Some((idx, token))
+    }
+}
+
+fn is_digits(token: &str) -> bool {
+    token.bytes().all(|b| b.is_ascii_digit())
+}
+
+/// Returns the byte offset of `token` in `text`, or `None` if `token` isn't a
+/// subslice of `text`.
+pub(crate) fn subslice_offset(text: &str, token: &str) -> Option<usize> {
+    let offset = (token.as_ptr() as usize).checked_sub(text.as_ptr() as usize)?;
+    if offset.checked_add(token.len())? > text.len() {
+        return None;
+    }
+    Some(offset)
+}
+
+/// Returns true if `right` starts where `left` ends, with no whitespace
+/// between them.
+fn adjacent(left: &str, right: &str) -> bool {
+    left.as_ptr() as usize + left.len() == right.as_ptr() as usize
+}
+
+/// A tokenizer shared between a builder and the finders it builds.
+///
+/// The `DefaultTokenizer` is kept by value, so that its configuration can be
+/// inspected, such as when serializing a finder.
+#[derive(Clone)]
+pub(crate) enum SharedTokenizer {
+    Default(DefaultTokenizer),
+    Custom(Arc<dyn Tokenizer>),
+}
+
+impl SharedTokenizer {
+    pub(crate) fn new<T: Tokenizer + 'static>(tokenizer: T) -> SharedTokenizer {
+        SharedTokenizer::Custom(Arc::new(tokenizer))
+    }
+
+    pub(crate) fn tokenize<'t>(&self, text: &'t str) -> impl Iterator<Item = (u32, &'t str)> + 't {
+        self.tokenize_spans(text)
+            .map(|(idx, _, token)| (idx, token))
+    }
+
+    /// Returns the tokens of `text`, each paired with the char and byte
+    /// offsets in `text` that it starts at.
+    ///
+    /// A custom tokenizer may give tokens that aren't subslices of `text`
+    /// despite its contract, and as they have no offset in `text` they're
+    /// skipped.
+    pub(crate) fn tokenize_spans<'t>(
+        &self,
+        text: &'t str,
+    ) -> impl Iterator<Item = (u32, usize, &'t str)> + 't {
+        let tokens = match self {
+            SharedTokenizer::Default(tokenizer) => tokenizer.tokenize(text),
+            SharedTokenizer::Custom(tokenizer) => tokenizer.tokenize(text),
+        };
+        tokens.filter_map(move |(idx, token)| Some((idx, subslice_offset(text, token)?, token)))
+    }
+
+    /// Returns true if `token` is a word, rather than a symbol or
+    /// whitespace.
+    pub(crate) fn is_word(&self, token: &str) -> bool {
+        match self {
+            SharedTokenizer::Default(tokenizer) => tokenizer.is_word(token),
+            SharedTokenizer::Custom(_) => token.chars().next().is_some_and(is_word_character),
+        }
+    }
+
+    /// Returns true if the adjacent tokens `left` and `right` may be joined
+    /// into one token once the text after them is known.
+    pub(crate) fn may_join(&self, left: &str, right: &str) -> bool {
+        match self {
+            SharedTokenizer::Default(tokenizer) => {
+                adjacent(left, right) && tokenizer.is_joiner(right)
+            }
+            SharedTokenizer::Custom(_) => false,
+        }
+    }
+}
+
+impl fmt::Debug for SharedTokenizer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SharedTokenizer::Default(tokenizer) => tokenizer.fmt(f),
+            SharedTokenizer::Custom(_) => f.write_str("Tokenizer"),
+        }
+    }
+}
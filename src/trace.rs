@@ -0,0 +1,83 @@
+use alloc::{string::String, vec::Vec};
+
+use crate::Match;
+
+/// One token of a haystack consumed by `SimpleFinder::explain`, and the
+/// states of the automaton that consuming it went through.
+///
+/// State identifiers are only meaningful within the finder that produced
+/// them. The start state is the `from` state of the first step.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TraceStep {
+    pub(crate) token: String,
+    pub(crate) start: usize,
+    pub(crate) end: usize,
+    pub(crate) byte_start: usize,
+    pub(crate) byte_end: usize,
+    pub(crate) from: usize,
+    pub(crate) failures: Vec<usize>,
+    pub(crate) to: usize,
+    pub(crate) depth: usize,
+    pub(crate) matches: Vec<Match>,
+}
+
+impl TraceStep {
+    /// Returns the token consumed, after normalization.
+    pub fn token(&self) -> &str {
+        &self.token
+    }
+
+    /// Returns the char offset of the start of the token in the haystack.
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    /// Returns the char offset of the end of the token in the haystack,
+    /// exclusive.
+    pub fn end(&self) -> usize {
+        self.end
+    }
+
+    /// Returns the byte offset of the start of the token in the haystack.
+    pub fn byte_start(&self) -> usize {
+        self.byte_start
+    }
+
+    /// Returns the byte offset of the end of the token in the haystack,
+    /// exclusive.
+    pub fn byte_end(&self) -> usize {
+        self.byte_end
+    }
+
+    /// Returns the state the automaton was in before consuming the token.
+    pub fn from(&self) -> usize {
+        self.from
+    }
+
+    /// Returns the states jumped to through failure transitions, in order,
+    /// because the state before had no transition on the token.
+    ///
+    /// A DFA has fewer failure transitions to follow than an NFA, since some
+    /// are replaced by direct transitions when it is built.
+    pub fn failures(&self) -> &[usize] {
+        &self.failures
+    }
+
+    /// Returns the state the automaton was in after consuming the token.
+    pub fn to(&self) -> usize {
+        self.to
+    }
+
+    /// Returns how many tokens of a pattern the state after this token has
+    /// matched, or `0` if it matched none and the automaton is back in its
+    /// start state.
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Returns the matches ending at this token, including overlapping
+    /// matches.
+    pub fn matches(&self) -> &[Match] {
+        &self.matches
+    }
+}
@@ -1,4 +1,5 @@
-use std::iter::{Filter, Map};
+use alloc::string::String;
+use core::iter::{Filter, Map};
 
 // pub fn split_unicode_word_and_syms(s: &str) -> Vec<(usize, &str)> {
 //     let chars_and_stuff: Vec<_> = s.char_indices()
@@ -34,11 +35,12 @@ use std::iter::{Filter, Map};
 //     results
 // }
 
-struct UnicodeWordBoundaries<'a> {
+struct UnicodeWordBoundaries<'a, F> {
     s: &'a str,
+    is_word: F,
 }
 
-impl<'a> Iterator for UnicodeWordBoundaries<'a> {
+impl<'a, F: Fn(char) -> bool> Iterator for UnicodeWordBoundaries<'a, F> {
     type Item = &'a str;
 
     #[inline]
@@ -48,7 +50,7 @@ impl<'a> Iterator for UnicodeWordBoundaries<'a> {
         }
 
         let mut c_it = self.s.char_indices()
-                             .map(|(idx, c)| ((is_word_character(c), c.is_whitespace()), idx))
+                             .map(|(idx, c)| (((self.is_word)(c), c.is_whitespace()), idx))
                              .peekable();
 
         while let (Some(((lhs_is_word, lhs_is_ws), _)),
@@ -63,14 +65,26 @@ impl<'a> Iterator for UnicodeWordBoundaries<'a> {
 
         // if we got here, it means that the string had no word boundaries in it
         let empty_s = &self.s[0..0];
-        let s = std::mem::replace(&mut self.s, empty_s);
+        let s = core::mem::replace(&mut self.s, empty_s);
 
         Some(s)
     }
 }
 
+/// An iterator over the tokens of a string, created by
+/// `WordBoundarySplitter::unicode_words_and_syms`.
+///
+/// ```
+/// use lacbd::WordBoundarySplitter;
+///
+/// let tokens: Vec<_> = "aaa.bbb,ccc".unicode_words_and_syms().collect();
+/// assert_eq!(tokens, vec!["aaa", ".", "bbb", ",", "ccc"]);
+/// ```
 pub struct UnicodeWordsAndSyms<'a> {
-    inner: Filter<Map<UnicodeWordBoundaries<'a>, fn(&str) -> &str>, fn(&&str) -> bool>,
+    inner: Filter<
+        Map<UnicodeWordBoundaries<'a, fn(char) -> bool>, fn(&str) -> &str>,
+        fn(&&str) -> bool,
+    >,
 }
 
 impl<'a> Iterator for UnicodeWordsAndSyms<'a> {
@@ -82,21 +96,21 @@ impl<'a> Iterator for UnicodeWordsAndSyms<'a> {
     }
 }
 
-struct UnicodeWordsAndSymsIndicesInner<'a> {
+struct UnicodeWordsAndSymsIndicesInner<'a, F> {
     current_offset: u32,
-    inner: UnicodeWordBoundaries<'a>,
+    inner: UnicodeWordBoundaries<'a, F>,
 }
 
-impl<'a> UnicodeWordsAndSymsIndicesInner<'a> {
-    fn new(init: &'a str) -> Self {
+impl<'a, F> UnicodeWordsAndSymsIndicesInner<'a, F> {
+    fn new(init: &'a str, is_word: F) -> Self {
         UnicodeWordsAndSymsIndicesInner {
             current_offset: 0,
-            inner: UnicodeWordBoundaries { s: init },
+            inner: UnicodeWordBoundaries { s: init, is_word },
         }
     }
 }
 
-impl<'a> Iterator for UnicodeWordsAndSymsIndicesInner<'a> {
+impl<'a, F: Fn(char) -> bool> Iterator for UnicodeWordsAndSymsIndicesInner<'a, F> {
     type Item = (u32, &'a str);
 
     #[inline]
@@ -110,14 +124,40 @@ impl<'a> Iterator for UnicodeWordsAndSymsIndicesInner<'a> {
     }
 }
 
-pub struct UnicodeWordsAndSymsIndices<'a> {
+/// An iterator over the tokens of a string and the char offsets they start
+/// at, created by `WordBoundarySplitter::unicode_words_and_syms_indices`.
+///
+/// This is how the `DefaultTokenizer` splits patterns and haystacks.
+///
+/// ```
+/// use lacbd::WordBoundarySplitter;
+///
+/// let tokens: Vec<_> = "aaa.bbb,ccc".unicode_words_and_syms_indices().collect();
+/// assert_eq!(tokens, vec![(0, "aaa"), (3, "."), (4, "bbb"), (7, ","), (8, "ccc")]);
+/// ```
+pub struct UnicodeWordsAndSymsIndices<'a, F = fn(char) -> bool> {
     inner: Filter<
-        Map<UnicodeWordsAndSymsIndicesInner<'a>, fn((u32, &str)) -> (u32, &str)>,
+        Map<UnicodeWordsAndSymsIndicesInner<'a, F>, fn((u32, &str)) -> (u32, &str)>,
         fn(&(u32, &str)) -> bool,
     >,
 }
 
-impl<'a> Iterator for UnicodeWordsAndSymsIndices<'a> {
+impl<'a, F: Fn(char) -> bool> Iterator for UnicodeWordsAndSymsIndices<'a, F> {
+    type Item = (u32, &'a str);
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next()
+    }
+}
+
+/// Like `UnicodeWordsAndSymsIndices`, but also yields each run of whitespace
+/// as a token of its own.
+pub struct UnicodeWordsSymsAndSpacesIndices<'a, F = fn(char) -> bool> {
+    inner: UnicodeWordsAndSymsIndicesInner<'a, F>,
+}
+
+impl<'a, F: Fn(char) -> bool> Iterator for UnicodeWordsSymsAndSpacesIndices<'a, F> {
     type Item = (u32, &'a str);
 
     #[inline]
@@ -133,9 +173,14 @@ fn is_word_byte(c: u8) -> bool {
     }
 }
 
-fn is_word_character(c: char) -> bool {
+/// Returns true if `c` is a word character, using the Unicode definition of
+/// `\w` from Perl and regular expressions.
+///
+/// This is how the `DefaultTokenizer` classifies characters unless it's
+/// given a custom classifier.
+pub fn is_word_character(c: char) -> bool {
     use crate::unicode_tables::perl_word::PERL_WORD;
-    use std::cmp::Ordering;
+    use core::cmp::Ordering;
 
     if c <= 0x7F as char && is_word_byte(c as u8) {
         return true;
@@ -154,10 +199,137 @@ fn is_word_character(c: char) -> bool {
         .is_ok()
 }
 
+/// Lowercases `token` into `scratch` for case-insensitive comparison,
+/// returning false, and leaving `scratch` as it was, if it's lowercase
+/// already.
+///
+/// ASCII tokens are lowercased byte by byte, reusing the capacity of
+/// `scratch` so that no token allocates once it's grown to fit the longest.
+/// Other tokens fall back to `str::to_lowercase`, which lowercases a final
+/// sigma as it should.
+pub(crate) fn fold_case(token: &str, scratch: &mut String) -> bool {
+    if token.is_ascii() {
+        if !token.bytes().any(|b| b.is_ascii_uppercase()) {
+            return false;
+        }
+        scratch.clear();
+        scratch.push_str(token);
+        scratch.make_ascii_lowercase();
+        return true;
+    }
+
+    if token.chars().all(|c| c.to_lowercase().eq(Some(c))) {
+        return false;
+    }
+    *scratch = token.to_lowercase();
+    true
+}
+
+/// The classes of byte that `AsciiWordsAndSymsIndices` splits between.
+#[derive(Clone, Copy, PartialEq)]
+enum AsciiClass {
+    Word,
+    Space,
+    Sym,
+}
+
+fn ascii_class(b: u8) -> AsciiClass {
+    match b {
+        // the ASCII characters for which `char::is_whitespace` holds
+        b' ' | b'\t' | b'\n' | b'\x0B' | b'\x0C' | b'\r' => AsciiClass::Space,
+        b if is_word_byte(b) => AsciiClass::Word,
+        _ => AsciiClass::Sym,
+    }
+}
+
+/// Splits ASCII text the same way as `UnicodeWordsAndSymsIndices` (or
+/// `UnicodeWordsSymsAndSpacesIndices` when keeping whitespace) with the
+/// default word characters, but classifies bytes directly instead of
+/// decoding and looking up each char.
+///
+/// As every char is one byte, the yielded char offsets are byte offsets.
+pub(crate) struct AsciiWordsAndSymsIndices<'a> {
+    s: &'a str,
+    offset: usize,
+    keep_whitespace: bool,
+}
+
+impl<'a> AsciiWordsAndSymsIndices<'a> {
+    /// `s` must be ASCII.
+    pub(crate) fn new(s: &'a str, keep_whitespace: bool) -> Self {
+        debug_assert!(s.is_ascii());
+        AsciiWordsAndSymsIndices {
+            s,
+            offset: 0,
+            keep_whitespace,
+        }
+    }
+}
+
+impl<'a> Iterator for AsciiWordsAndSymsIndices<'a> {
+    type Item = (u32, &'a str);
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        let bytes = self.s.as_bytes();
+
+        while self.offset < bytes.len() {
+            let start = self.offset;
+            let class = ascii_class(bytes[start]);
+            let len = bytes[start..]
+                .iter()
+                .position(|&b| ascii_class(b) != class)
+                .unwrap_or(bytes.len() - start);
+            self.offset += len;
+
+            if class != AsciiClass::Space || self.keep_whitespace {
+                return Some((start as u32, &self.s[start..self.offset]));
+            }
+        }
+
+        None
+    }
+}
+
+/// Splits text into tokens in the same way as the `DefaultTokenizer`, so that
+/// haystacks can be pre-tokenized and the tokens a pattern must match can be
+/// inspected.
+///
+/// Text is split between runs of word characters, runs of whitespace, and
+/// runs of other characters, with the whitespace discarded.
 pub trait WordBoundarySplitter {
+    /// Returns the tokens of this text.
     fn unicode_words_and_syms(&self) -> UnicodeWordsAndSyms;
 
+    /// Returns the tokens of this text, each paired with the char offset it
+    /// starts at.
     fn unicode_words_and_syms_indices(&self) -> UnicodeWordsAndSymsIndices;
+
+    /// Like `unicode_words_and_syms_indices`, but keeping each run of
+    /// whitespace as a token.
+    ///
+    /// ```
+    /// use lacbd::WordBoundarySplitter;
+    ///
+    /// let tokens: Vec<_> = "aaa. bbb".unicode_words_syms_and_spaces_indices().collect();
+    /// assert_eq!(tokens, vec![(0, "aaa"), (3, "."), (4, " "), (5, "bbb")]);
+    /// ```
+    fn unicode_words_syms_and_spaces_indices(&self) -> UnicodeWordsSymsAndSpacesIndices<'_>;
+
+    /// Like `unicode_words_and_syms_indices`, but with `is_word` deciding
+    /// which characters are word characters.
+    fn unicode_words_and_syms_indices_by<F>(&self, is_word: F) -> UnicodeWordsAndSymsIndices<'_, F>
+    where
+        F: Fn(char) -> bool;
+
+    /// Like `unicode_words_syms_and_spaces_indices`, but with `is_word`
+    /// deciding which characters are word characters.
+    fn unicode_words_syms_and_spaces_indices_by<F>(
+        &self,
+        is_word: F,
+    ) -> UnicodeWordsSymsAndSpacesIndices<'_, F>
+    where
+        F: Fn(char) -> bool;
 }
 
 impl WordBoundarySplitter for str {
@@ -167,13 +339,27 @@ impl WordBoundarySplitter for str {
         }
 
         UnicodeWordsAndSyms {
-            inner: UnicodeWordBoundaries { s: self }
+            inner: UnicodeWordBoundaries {
+                s: self,
+                is_word: is_word_character as fn(char) -> bool,
+            }
                 .map(str::trim as fn(&str) -> &str)
                 .filter(is_not_empty),
         }
     }
 
     fn unicode_words_and_syms_indices(&self) -> UnicodeWordsAndSymsIndices {
+        self.unicode_words_and_syms_indices_by(is_word_character as fn(char) -> bool)
+    }
+
+    fn unicode_words_syms_and_spaces_indices(&self) -> UnicodeWordsSymsAndSpacesIndices<'_> {
+        self.unicode_words_syms_and_spaces_indices_by(is_word_character as fn(char) -> bool)
+    }
+
+    fn unicode_words_and_syms_indices_by<F>(&self, is_word: F) -> UnicodeWordsAndSymsIndices<'_, F>
+    where
+        F: Fn(char) -> bool,
+    {
         fn trim((idx, s): (u32, &str)) -> (u32, &str) {
             // keep idx correct
 
@@ -190,16 +376,31 @@ impl WordBoundarySplitter for str {
         }
 
         UnicodeWordsAndSymsIndices {
-            inner: UnicodeWordsAndSymsIndicesInner::new(self)
+            inner: UnicodeWordsAndSymsIndicesInner::new(self, is_word)
                 .map(trim as fn ((u32, &str)) -> (u32, &str))
                 .filter(is_not_empty as fn(&(u32, &str)) -> bool),
         }
     }
+
+    fn unicode_words_syms_and_spaces_indices_by<F>(
+        &self,
+        is_word: F,
+    ) -> UnicodeWordsSymsAndSpacesIndices<'_, F>
+    where
+        F: Fn(char) -> bool,
+    {
+        // boundaries are placed between whitespace and everything else, so
+        // each whitespace run is already a segment of its own
+        UnicodeWordsSymsAndSpacesIndices {
+            inner: UnicodeWordsAndSymsIndicesInner::new(self, is_word),
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::{prelude::v1::*, vec};
 
     #[test]
     fn check_boundary_splits() {
@@ -210,4 +411,44 @@ mod tests {
             vec!["aaa", ".", "bbb", ",", "ccc", "\'", "ddd", "@", "eee"]
         );
     }
+
+    #[test]
+    fn check_fold_case() {
+        let mut scratch = String::new();
+        assert!(!fold_case("hello, world", &mut scratch));
+        assert!(fold_case("Hello", &mut scratch));
+        assert_eq!(scratch, "hello");
+        // the buffer is reused rather than appended to
+        assert!(fold_case("ABC", &mut scratch));
+        assert_eq!(scratch, "abc");
+        assert!(!fold_case("café", &mut scratch));
+        assert_eq!(scratch, "abc");
+        assert!(fold_case("CAFÉ", &mut scratch));
+        assert_eq!(scratch, "café");
+        assert!(fold_case("ΟΔΟΣ", &mut scratch));
+        assert_eq!(scratch, "οδος");
+    }
+
+    #[test]
+    fn check_ascii_splits() {
+        let text = "foo  bar,\tbaz?! qux_1\x0B\x0Cend ";
+        assert_eq!(
+            AsciiWordsAndSymsIndices::new(text, false).collect::<Vec<_>>(),
+            text.unicode_words_and_syms_indices().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            AsciiWordsAndSymsIndices::new(text, true).collect::<Vec<_>>(),
+            text.unicode_words_syms_and_spaces_indices().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn check_whitespace_splits() {
+        assert_eq!(
+            "foo  bar,\tbaz"
+                .unicode_words_syms_and_spaces_indices()
+                .collect::<Vec<_>>(),
+            vec![(0, "foo"), (3, "  "), (5, "bar"), (8, ","), (9, "\t"), (10, "baz")]
+        );
+    }
 }